@@ -0,0 +1,14 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Embedded by `sqlx::migrate!`, which can't tell cargo to rebuild when they change
+    println!("cargo:rerun-if-changed=migrations");
+    // No system protoc needed
+    env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("auth_uservice_descriptor.bin"))
+        .compile(&["proto/auth_uservice/v1/auth_uservice.proto"], &["proto"])?;
+    Ok(())
+}
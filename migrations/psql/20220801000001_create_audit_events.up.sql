@@ -0,0 +1,28 @@
+CREATE TABLE IF NOT EXISTS audit_events (
+    seq BIGSERIAL PRIMARY KEY,
+    id UUID NOT NULL UNIQUE,
+    occurred_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    event_type VARCHAR NOT NULL,
+    actor_id UUID,
+    subject_id UUID,
+    details VARCHAR NOT NULL,
+    -- Every event is chained to the previous one, so two concurrent appends can't both succeed
+    prev_hash VARCHAR NOT NULL UNIQUE,
+    hash VARCHAR NOT NULL UNIQUE
+);
+
+CREATE INDEX IF NOT EXISTS audit_events_subject_id ON audit_events (subject_id);
+
+CREATE OR REPLACE FUNCTION audit_events_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_no_update_delete
+    BEFORE UPDATE OR DELETE ON audit_events
+    FOR EACH ROW EXECUTE FUNCTION audit_events_append_only();
+
+CREATE TRIGGER audit_events_no_truncate
+    BEFORE TRUNCATE ON audit_events
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_events_append_only();
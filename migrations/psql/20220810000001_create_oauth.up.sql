@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS oauth_clients (
+    id VARCHAR PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    -- JSON array, redirects are only made to these exact URIs
+    redirect_uris VARCHAR NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS oauth_authorization_codes (
+    -- SHA-256 of the code, the code itself is only known to the client
+    code_hash VARCHAR PRIMARY KEY,
+    client_id VARCHAR NOT NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+    user_id UUID NOT NULL,
+    redirect_uri VARCHAR NOT NULL,
+    scope VARCHAR,
+    code_challenge VARCHAR NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Argon2 hash of the secret of confidential clients, public clients have none
+ALTER TABLE oauth_clients ADD COLUMN IF NOT EXISTS secret_hash VARCHAR;
+-- Space separated scopes the client may be granted
+ALTER TABLE oauth_clients ADD COLUMN IF NOT EXISTS allowed_scopes VARCHAR NOT NULL DEFAULT '';
@@ -0,0 +1,5 @@
+-- Outstanding codes live for a minute at most, dropping them spares making up login times
+DELETE FROM oauth_authorization_codes;
+-- OpenID Connect nonce of the authorization request, echoed in the ID token
+ALTER TABLE oauth_authorization_codes ADD COLUMN IF NOT EXISTS nonce VARCHAR;
+ALTER TABLE oauth_authorization_codes ADD COLUMN IF NOT EXISTS auth_time TIMESTAMP WITH TIME ZONE NOT NULL;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+    -- `jti` claim of the revoked access token
+    jti UUID PRIMARY KEY,
+    -- Expiry of the token, past which it's rejected anyway and can be forgotten
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS oauth_device_authorizations (
+    -- SHA-256 of the device code, the code itself is only known to the device
+    device_code_hash VARCHAR PRIMARY KEY,
+    -- Code the user enters on the verification page, without the separator
+    user_code VARCHAR NOT NULL UNIQUE,
+    client_id VARCHAR NOT NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+    scope VARCHAR,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    -- Minimum seconds between polls of the device, raised when it polls too fast
+    interval_secs BIGINT NOT NULL,
+    last_polled_at TIMESTAMP WITH TIME ZONE,
+    -- pending, approved or denied
+    status VARCHAR NOT NULL,
+    -- User who approved or denied the request
+    user_id UUID
+);
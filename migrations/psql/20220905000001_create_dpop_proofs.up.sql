@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS dpop_proofs (
+    -- SHA-256 of the `jti` claim of a DPoP proof already used
+    jti_hash VARCHAR PRIMARY KEY,
+    -- Past which the proof is too old to be accepted anyway and can be forgotten
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
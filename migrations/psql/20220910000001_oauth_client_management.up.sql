@@ -0,0 +1,10 @@
+-- Space separated grant types the client may use
+ALTER TABLE oauth_clients ADD COLUMN IF NOT EXISTS grant_types VARCHAR NOT NULL DEFAULT '';
+-- The grants existing clients could use so far
+UPDATE oauth_clients SET grant_types = 'urn:ietf:params:oauth:grant-type:device_code'
+    || CASE WHEN redirect_uris <> '[]' THEN ' authorization_code' ELSE '' END
+    || CASE WHEN secret_hash IS NOT NULL
+        THEN ' client_credentials urn:ietf:params:oauth:grant-type:token-exchange' ELSE '' END;
+-- Argon2 hash of the secret replaced by the last rotation, accepted until it expires
+ALTER TABLE oauth_clients ADD COLUMN IF NOT EXISTS previous_secret_hash VARCHAR;
+ALTER TABLE oauth_clients ADD COLUMN IF NOT EXISTS previous_secret_expires_at TIMESTAMP WITH TIME ZONE;
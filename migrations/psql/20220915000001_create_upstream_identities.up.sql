@@ -0,0 +1,25 @@
+-- Accounts of users at upstream OpenID Connect providers, a user may have several
+CREATE TABLE IF NOT EXISTS linked_identities (
+    -- ID of the provider in the settings
+    provider VARCHAR NOT NULL,
+    -- `sub` of the user at the provider
+    subject VARCHAR NOT NULL,
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    email VARCHAR,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (provider, subject)
+);
+CREATE INDEX IF NOT EXISTS linked_identities_user_id ON linked_identities (user_id);
+
+-- Sign-ins at upstream providers in progress, from the redirect to the provider to its callback
+CREATE TABLE IF NOT EXISTS upstream_logins (
+    -- SHA-256 of the state, the state itself is only known to the browser
+    state_hash VARCHAR PRIMARY KEY,
+    provider VARCHAR NOT NULL,
+    nonce VARCHAR NOT NULL,
+    -- PKCE verifier of the code issued by the provider
+    code_verifier VARCHAR NOT NULL,
+    -- JSON of the authorization request to resume once signed in
+    authorize_params VARCHAR NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Users whose access tokens were all revoked at once, e.g. on password change
+CREATE TABLE IF NOT EXISTS revoked_user_tokens (
+    user_id UUID PRIMARY KEY,
+    -- Tokens issued up to then, by their `iat` claim, are revoked
+    issued_before TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS users (
+    id BLOB PRIMARY KEY,
+    username TEXT NOT NULL,
+    password_hash TEXT NOT NULL,
+    email TEXT,
+    created_at DATETIME,
+    last_login DATETIME
+);
@@ -0,0 +1,2 @@
+ALTER TABLE oauth_clients DROP COLUMN allowed_scopes;
+ALTER TABLE oauth_clients DROP COLUMN secret_hash;
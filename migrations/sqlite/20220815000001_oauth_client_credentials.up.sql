@@ -0,0 +1,4 @@
+-- Argon2 hash of the secret of confidential clients, public clients have none
+ALTER TABLE oauth_clients ADD COLUMN secret_hash TEXT;
+-- Space separated scopes the client may be granted
+ALTER TABLE oauth_clients ADD COLUMN allowed_scopes TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,17 @@
+-- Outstanding codes live for a minute at most, dropping them spares making up login times.
+-- SQLite can't add a NOT NULL column without a default, so the table is recreated.
+DROP TABLE oauth_authorization_codes;
+
+CREATE TABLE oauth_authorization_codes (
+    -- SHA-256 of the code, the code itself is only known to the client
+    code_hash TEXT PRIMARY KEY,
+    client_id TEXT NOT NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+    user_id BLOB NOT NULL,
+    redirect_uri TEXT NOT NULL,
+    scope TEXT,
+    code_challenge TEXT NOT NULL,
+    expires_at DATETIME NOT NULL,
+    -- OpenID Connect nonce of the authorization request, echoed in the ID token
+    nonce TEXT,
+    auth_time DATETIME NOT NULL
+);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS oauth_device_authorizations (
+    -- SHA-256 of the device code, the code itself is only known to the device
+    device_code_hash TEXT PRIMARY KEY,
+    -- Code the user enters on the verification page, without the separator
+    user_code TEXT NOT NULL UNIQUE,
+    client_id TEXT NOT NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+    scope TEXT,
+    expires_at DATETIME NOT NULL,
+    -- Minimum seconds between polls of the device, raised when it polls too fast
+    interval_secs INTEGER NOT NULL,
+    last_polled_at DATETIME,
+    -- pending, approved or denied
+    status TEXT NOT NULL,
+    -- User who approved or denied the request
+    user_id BLOB
+);
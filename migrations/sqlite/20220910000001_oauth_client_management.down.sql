@@ -0,0 +1,3 @@
+ALTER TABLE oauth_clients DROP COLUMN previous_secret_expires_at;
+ALTER TABLE oauth_clients DROP COLUMN previous_secret_hash;
+ALTER TABLE oauth_clients DROP COLUMN grant_types;
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS upstream_logins;
+DROP TABLE IF EXISTS linked_identities;
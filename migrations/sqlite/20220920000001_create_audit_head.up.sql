@@ -0,0 +1,16 @@
+-- Latest audit event, moved with every append, so events removed from the end of the chain are
+-- noticed
+CREATE TABLE IF NOT EXISTS audit_head (
+    id INTEGER PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+    seq INTEGER NOT NULL,
+    hash TEXT NOT NULL
+);
+
+INSERT INTO audit_head (seq, hash)
+SELECT
+    COALESCE(MAX(seq), 0),
+    COALESCE(
+        (SELECT hash FROM audit_events ORDER BY seq DESC LIMIT 1),
+        '0000000000000000000000000000000000000000000000000000000000000000'
+    )
+FROM audit_events;
@@ -0,0 +1,40 @@
+use actix_web::web;
+use actix_web::web::ServiceConfig;
+use actix_web::Route;
+
+use crate::api::version::mark_deprecated;
+use crate::api::version::Deprecation;
+use crate::services::audit::get_audit_chain_verification;
+use crate::services::audit::get_audit_events;
+use crate::services::user::delete_user;
+use crate::services::user::get_user_by_id;
+use crate::services::user::post_user;
+
+/// Routes served at the root before `/v1`, with their payloads of the time. They are still those
+/// of v1: should v1 change one, the former handler and DTOs are kept here. Routes added since are
+/// v1 only.
+fn resources() -> Vec<(&'static str, Vec<Route>)> {
+    vec![
+        (
+            "/users/{user_id}",
+            vec![web::get().to(get_user_by_id), web::delete().to(delete_user)],
+        ),
+        ("/users", vec![web::post().to(post_user)]),
+        ("/admin/audit", vec![web::get().to(get_audit_events)]),
+        (
+            "/admin/audit/verify",
+            vec![web::get().to(get_audit_chain_verification)],
+        ),
+    ]
+}
+
+/// Registers each route on its own rather than in a scope: at the root, a scope would catch, and
+/// mark deprecated, every request no other route matches.
+pub fn configure(cfg: &mut ServiceConfig, deprecation: Option<Deprecation>) {
+    for (path, routes) in resources() {
+        let resource = routes
+            .into_iter()
+            .fold(web::resource(path), |resource, route| resource.route(route));
+        cfg.service(resource.wrap_fn(move |req, srv| mark_deprecated(req, srv, deprecation)));
+    }
+}
@@ -0,0 +1,60 @@
+use actix_web::web;
+use actix_web::web::ServiceConfig;
+
+use crate::api::version::mark_deprecated;
+use crate::api::version::ApiVersion;
+use crate::api::version::Deprecation;
+use crate::services::audit::get_audit_chain_verification;
+use crate::services::audit::get_audit_events;
+use crate::services::clients::delete_client;
+use crate::services::clients::get_client;
+use crate::services::clients::get_clients;
+use crate::services::clients::post_client;
+use crate::services::clients::post_client_secret;
+use crate::services::clients::put_client;
+use crate::services::upstream::delete_user_identity;
+use crate::services::upstream::get_user_identities;
+use crate::services::upstream::post_user_identity;
+use crate::services::user::delete_user;
+use crate::services::user::get_user_by_id;
+use crate::services::user::post_user;
+
+/// Serves the handlers of `services` and the DTOs of `models` as they are, under `/v1`.
+pub fn configure(cfg: &mut ServiceConfig, deprecation: Option<Deprecation>) {
+    cfg.service(
+        web::scope(ApiVersion::V1.prefix())
+            .wrap_fn(move |req, srv| mark_deprecated(req, srv, deprecation))
+            .route("/users/{user_id}", web::get().to(get_user_by_id))
+            .route("/users", web::post().to(post_user))
+            .route("/users/{user_id}", web::delete().to(delete_user))
+            .route("/admin/audit", web::get().to(get_audit_events))
+            .route(
+                "/admin/audit/verify",
+                web::get().to(get_audit_chain_verification),
+            )
+            .route("/admin/clients", web::get().to(get_clients))
+            .route("/admin/clients", web::post().to(post_client))
+            .route("/admin/clients/{client_id}", web::get().to(get_client))
+            .route("/admin/clients/{client_id}", web::put().to(put_client))
+            .route(
+                "/admin/clients/{client_id}",
+                web::delete().to(delete_client),
+            )
+            .route(
+                "/admin/clients/{client_id}/secret",
+                web::post().to(post_client_secret),
+            )
+            .route(
+                "/admin/users/{user_id}/identities",
+                web::get().to(get_user_identities),
+            )
+            .route(
+                "/admin/users/{user_id}/identities",
+                web::post().to(post_user_identity),
+            )
+            .route(
+                "/admin/users/{user_id}/identities/{provider}/{subject}",
+                web::delete().to(delete_user_identity),
+            ),
+    );
+}
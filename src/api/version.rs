@@ -0,0 +1,123 @@
+use std::future::Future;
+
+use actix_web::dev::Service;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::HeaderName;
+use actix_web::http::header::HeaderValue;
+use actix_web::http::header::LINK;
+use actix_web::web::ServiceConfig;
+use chrono::DateTime;
+use chrono::Utc;
+
+use crate::api::legacy;
+use crate::api::v1;
+use crate::settings::ApiSettings;
+use crate::settings::LEGACY_DEPRECATED_SINCE;
+
+pub static DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+pub static SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// Versions of the REST API, served side by side under their own prefix.
+///
+/// Each version has its own module registering its routes, with the handlers and DTOs it serves.
+/// A version which changes payloads gets new ones, the previous versions keep theirs until their
+/// sunset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiVersion {
+    /// The unversioned routes at the root, kept for clients predating `/v1`
+    Legacy,
+    V1,
+}
+
+impl ApiVersion {
+    pub const ALL: [Self; 2] = [Self::V1, Self::Legacy];
+    pub const LATEST: Self = Self::V1;
+
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Self::Legacy => "",
+            Self::V1 => "/v1",
+        }
+    }
+
+    /// Registers the routes of the version, marking their responses if it's deprecated.
+    pub fn configure(&self, cfg: &mut ServiceConfig, deprecation: Option<Deprecation>) {
+        match self {
+            Self::Legacy => legacy::configure(cfg, deprecation),
+            Self::V1 => v1::configure(cfg, deprecation),
+        }
+    }
+
+    /// `None` for versions which are not deprecated.
+    pub fn deprecation(&self, settings: &ApiSettings) -> Option<Deprecation> {
+        match self {
+            Self::Legacy => Some(Deprecation {
+                since: LEGACY_DEPRECATED_SINCE,
+                sunset: settings.legacy_sunset,
+                successor: Self::LATEST,
+            }),
+            Self::V1 => None,
+        }
+    }
+
+    pub fn is_enabled(&self, settings: &ApiSettings) -> bool {
+        match self {
+            Self::Legacy => settings.legacy_routes,
+            Self::V1 => true,
+        }
+    }
+}
+
+/// Registers every enabled version.
+pub fn configure(cfg: &mut ServiceConfig, settings: &ApiSettings) {
+    for version in ApiVersion::ALL {
+        if version.is_enabled(settings) {
+            version.configure(cfg, version.deprecation(settings));
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deprecation {
+    /// Unix time the version was deprecated at
+    pub since: i64,
+    /// When the version stops being served
+    pub sunset: Option<DateTime<Utc>>,
+    pub successor: ApiVersion,
+}
+
+/// Adds the RFC 9745 `Deprecation`, RFC 8594 `Sunset` and `successor-version` link headers to
+/// the responses of deprecated versions.
+pub fn mark_deprecated<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+    deprecation: Option<Deprecation>,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let path = req.path().to_string();
+    let fut = srv.call(req);
+    async move {
+        let mut res = fut.await?;
+        let deprecation = match deprecation {
+            Some(deprecation) => deprecation,
+            None => return Ok(res),
+        };
+        let headers = res.headers_mut();
+        let since = format!("@{}", deprecation.since);
+        headers.insert(DEPRECATION.clone(), HeaderValue::from_str(&since)?);
+        if let Some(sunset) = deprecation.sunset {
+            let sunset = sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+            headers.insert(SUNSET.clone(), HeaderValue::from_str(&sunset)?);
+        }
+        let link = format!(
+            "<{}{}>; rel=\"successor-version\"",
+            deprecation.successor.prefix(),
+            path
+        );
+        headers.insert(LINK, HeaderValue::from_str(&link)?);
+        Ok(res)
+    }
+}
@@ -0,0 +1,148 @@
+use std::future::ready;
+use std::future::Future;
+use std::future::Ready;
+use std::pin::Pin;
+
+use actix_web::dev::Payload;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::web::Data;
+use actix_web::FromRequest;
+use actix_web::HttpRequest;
+use ring::constant_time::verify_slices_are_equal;
+
+use crate::crypto::TokenSigner;
+use crate::errors::oauth::OAuthError;
+use crate::errors::user::UserServiceError;
+use crate::models::token::AccessTokenClaims;
+use crate::repositories::oauth::OAuthRepo;
+use crate::services::dpop;
+use crate::services::dpop::DPOP_HEADER;
+use crate::services::token;
+use crate::settings::AdminSettings;
+use crate::settings::TokenSettings;
+
+/// Extractor admitting only requests bearing the admin API token.
+pub struct AdminAuth;
+
+impl FromRequest for AdminAuth {
+    type Error = UserServiceError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let given = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        ready(match req.app_data::<Data<AdminSettings>>().zip(given) {
+            Some((settings, given)) if is_admin_token(settings, given) => Ok(Self),
+            _ => Err(UserServiceError::Forbidden),
+        })
+    }
+}
+
+/// Compared in constant time, no token is the admin one when none is configured.
+pub fn is_admin_token(settings: &AdminSettings, given: &str) -> bool {
+    settings.api_token.as_deref().is_some_and(|expected| {
+        verify_slices_are_equal(expected.as_bytes(), given.as_bytes()).is_ok()
+    })
+}
+
+/// Extractor of a valid, unrevoked access token issued by the service, given as
+/// `Authorization: Bearer`, or as `Authorization: DPoP` with a proof of possession of the key
+/// DPoP-bound tokens are bound to.
+pub struct BearerToken(pub AccessTokenClaims);
+
+impl FromRequest for BearerToken {
+    type Error = OAuthError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let authorization = req
+                .headers()
+                .get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split_once(' '));
+            let (token, dpop) = match authorization {
+                Some((scheme, token)) if scheme.eq_ignore_ascii_case("Bearer") => {
+                    (token.trim(), false)
+                }
+                Some((scheme, token)) if scheme.eq_ignore_ascii_case(DPOP_HEADER) => {
+                    (token.trim(), true)
+                }
+                _ => return Err(OAuthError::InvalidToken("Missing access token".into())),
+            };
+            let result = verify(&req, token, dpop).await.map(Self);
+            match dpop {
+                true => result.map_err(|error| OAuthError::DpopChallenge(Box::new(error))),
+                false => result,
+            }
+        })
+    }
+}
+
+async fn verify(
+    req: &HttpRequest,
+    token: &str,
+    dpop: bool,
+) -> Result<AccessTokenClaims, OAuthError> {
+    let token_signer = req.app_data::<Data<TokenSigner>>();
+    let oauth_repo = req.app_data::<Data<dyn OAuthRepo>>();
+    let (token_signer, oauth_repo) = token_signer
+        .zip(oauth_repo)
+        .ok_or(OAuthError::ServerError)?;
+    let claims = token::verify_access_token(token_signer, oauth_repo.as_ref(), token)
+        .await?
+        .ok_or_else(|| {
+            OAuthError::InvalidToken("Invalid, expired or revoked access token".into())
+        })?;
+    // Exchanged tokens restricted to a downstream service aren't good for our own API
+    if claims
+        .aud
+        .as_deref()
+        .is_some_and(|aud| aud != token_signer.issuer())
+    {
+        return Err(OAuthError::InvalidToken(
+            "The access token is for another audience".into(),
+        ));
+    }
+    match (dpop, &claims.cnf) {
+        (false, None) => {}
+        (false, Some(_)) => {
+            return Err(OAuthError::DpopChallenge(Box::new(
+                OAuthError::InvalidToken(
+                    "DPoP-bound access tokens must be given with a DPoP proof".into(),
+                ),
+            )))
+        }
+        (true, None) => {
+            return Err(OAuthError::InvalidToken(
+                "The access token isn't DPoP-bound".into(),
+            ))
+        }
+        (true, Some(cnf)) => {
+            let proof = dpop::proof_header(req)?
+                .ok_or_else(|| OAuthError::InvalidDpopProof("Missing DPoP proof".into()))?;
+            let token_settings = req
+                .app_data::<Data<TokenSettings>>()
+                .ok_or(OAuthError::ServerError)?;
+            let jkt = dpop::verify_proof(
+                oauth_repo.as_ref(),
+                token_signer,
+                token_settings,
+                req,
+                proof,
+                Some(token),
+            )
+            .await?;
+            if jkt != cnf.jkt {
+                return Err(OAuthError::InvalidDpopProof(
+                    "The DPoP proof isn't signed with the key the token is bound to".into(),
+                ));
+            }
+        }
+    }
+    Ok(claims)
+}
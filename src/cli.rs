@@ -0,0 +1,250 @@
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Result;
+use clap::Parser;
+use clap::Subcommand;
+use uuid::Uuid;
+
+use crate::crypto::PasswordHasher;
+use crate::models::audit::AuditEventRespDto;
+use crate::models::audit::AuditEventType;
+use crate::models::audit::AuditQuery;
+use crate::models::oauth::ClientRegistrationDto;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::migrations::MigrationState;
+use crate::repositories::migrations::SchemaMigrator;
+use crate::repositories::oauth::OAuthRepo;
+use crate::services;
+
+#[derive(Parser, Debug)]
+#[clap(version, about)]
+pub struct Cli {
+    /// TOML config file [default: auth-uservice.toml if present]
+    #[clap(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Address the HTTP server binds to
+    #[clap(long, global = true)]
+    pub bind_address: Option<String>,
+    /// Address the gRPC server binds to
+    #[clap(long, global = true)]
+    pub grpc_bind_address: Option<String>,
+    /// Database URL, the backend is selected by its scheme (`postgres:` or `sqlite:`)
+    #[clap(long, global = true)]
+    pub db_url: Option<String>,
+    /// Maximum number of pooled database connections
+    #[clap(long, global = true)]
+    pub db_max_connections: Option<u32>,
+    /// Log filter directives, e.g. `info,sqlx=warn`
+    #[clap(long, global = true)]
+    pub log_level: Option<String>,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// Settings given as flags, which take precedence over every other configuration source.
+    pub fn settings_overrides(&self) -> Vec<(&'static str, String)> {
+        [
+            ("server.bind_address", self.bind_address.clone()),
+            ("server.grpc_bind_address", self.grpc_bind_address.clone()),
+            ("database.url", self.db_url.clone()),
+            (
+                "database.max_connections",
+                self.db_max_connections.map(|n| n.to_string()),
+            ),
+            ("log.level", self.log_level.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.map(|value| (key, value)))
+        .collect()
+    }
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Run the HTTP server (default)
+    Serve {
+        /// Apply pending migrations before starting instead of refusing to start
+        #[clap(long)]
+        migrate: bool,
+    },
+    /// Manage the database schema
+    Migrate {
+        #[clap(subcommand)]
+        action: MigrateAction,
+    },
+    /// Inspect the audit log
+    Audit {
+        #[clap(subcommand)]
+        action: AuditAction,
+    },
+    /// Manage the OAuth clients
+    Client {
+        #[clap(subcommand)]
+        action: ClientAction,
+    },
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Self::Serve { migrate: false }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum MigrateAction {
+    /// Apply all pending migrations
+    Up,
+    /// Revert applied migrations, by default only the latest one
+    Down {
+        /// Revert every migration newer than this version (0 reverts all)
+        #[clap(long)]
+        target: Option<i64>,
+    },
+    /// List migrations and whether they are applied
+    Status,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum AuditAction {
+    /// Print events as JSON lines, oldest first
+    List {
+        #[clap(long)]
+        event_type: Option<AuditEventType>,
+        #[clap(long)]
+        subject_id: Option<Uuid>,
+        /// Only events recorded after this sequence number
+        #[clap(long)]
+        after_seq: Option<i64>,
+        #[clap(long)]
+        limit: Option<u32>,
+    },
+    /// Check no event was altered, removed or inserted since it was recorded
+    Verify,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum ClientAction {
+    /// Register a client and print it as JSON, with its secret if confidential
+    Create {
+        #[clap(long)]
+        name: String,
+        /// URI the client may be redirected to after authorization, repeatable
+        #[clap(long = "redirect-uri")]
+        redirect_uris: Vec<String>,
+        /// Scope the client may be granted, repeatable
+        #[clap(long = "scope")]
+        allowed_scopes: Vec<String>,
+        /// Issue a secret, allowing the client credentials grant
+        #[clap(long)]
+        confidential: bool,
+        /// Grant type the client may use, repeatable, by default every one it can use
+        #[clap(long = "grant-type")]
+        grant_types: Vec<String>,
+    },
+    /// Print clients as JSON lines
+    List,
+}
+
+pub async fn client(
+    oauth_repo: &dyn OAuthRepo,
+    passwd_hasher: &PasswordHasher,
+    action: ClientAction,
+) -> Result<()> {
+    match action {
+        ClientAction::Create {
+            name,
+            redirect_uris,
+            allowed_scopes,
+            confidential,
+            grant_types,
+        } => {
+            let registration = ClientRegistrationDto {
+                name,
+                redirect_uris,
+                allowed_scopes,
+                confidential,
+                grant_types,
+            };
+            let client =
+                services::oauth::register_client(oauth_repo, passwd_hasher, registration).await?;
+            println!("{}", serde_json::to_string(&client)?);
+        }
+        ClientAction::List => {
+            for client in oauth_repo.list_clients().await? {
+                println!("{}", serde_json::to_string(&client)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn audit(audit_repo: &dyn AuditRepo, action: AuditAction) -> Result<()> {
+    match action {
+        AuditAction::List {
+            event_type,
+            subject_id,
+            after_seq,
+            limit,
+        } => {
+            let query = AuditQuery {
+                event_type,
+                subject_id,
+                after_seq,
+                limit,
+                ..Default::default()
+            };
+            for event in audit_repo.list_events(&query).await? {
+                println!(
+                    "{}",
+                    serde_json::to_string(&AuditEventRespDto::from(event))?
+                );
+            }
+        }
+        AuditAction::Verify => {
+            let report = services::audit::verify_chain(audit_repo).await?;
+            println!("{}", serde_json::to_string(&report)?);
+            if !report.valid {
+                bail!("Audit log chain is broken");
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn migrate(migrator: &dyn SchemaMigrator, action: MigrateAction) -> Result<()> {
+    match action {
+        MigrateAction::Up => {
+            migrator.migrate_up().await?;
+            log::info!("Database schema is up to date");
+        }
+        MigrateAction::Down { target } => {
+            let target = match target {
+                Some(target) => target,
+                None => {
+                    let applied: Vec<_> = migrator
+                        .migration_status()
+                        .await?
+                        .into_iter()
+                        .filter(|m| m.state != MigrationState::Pending)
+                        .map(|m| m.version)
+                        .collect();
+                    match applied.as_slice() {
+                        [] => bail!("No migrations applied, nothing to revert"),
+                        [.., previous, _] => *previous,
+                        [_] => 0,
+                    }
+                }
+            };
+            migrator.migrate_down(target).await?;
+            log::info!("Database schema reverted to version {}", target);
+        }
+        MigrateAction::Status => {
+            for m in migrator.migration_status().await? {
+                println!("{:<16} {:<8?} {}", m.version, m.state, m.description);
+            }
+        }
+    }
+    Ok(())
+}
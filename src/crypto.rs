@@ -1,28 +1,63 @@
+use std::fs;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
 use anyhow::Result;
 use argon2::Config;
 use argon2::ThreadMode;
 use argon2::Variant;
 use argon2::Version;
+use jsonwebtoken::jwk::AlgorithmParameters;
+use jsonwebtoken::jwk::Jwk;
+use jsonwebtoken::Algorithm;
+use jsonwebtoken::DecodingKey;
+use jsonwebtoken::EncodingKey;
+use jsonwebtoken::Header;
+use jsonwebtoken::Validation;
 use rand::Rng;
+use ring::digest;
+use ring::hmac;
+use ring::rand::SystemRandom;
+use ring::signature::Ed25519KeyPair;
+use ring::signature::KeyPair;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::metrics::METRICS;
+use crate::settings::Argon2Settings;
+use crate::settings::TokenSettings;
+
+/// DPoP nonces are good for the window they were made in and the next one
+const DPOP_NONCE_WINDOW_SECS: i64 = 300;
 
 pub struct PasswordHasher(Config<'static>);
 
 impl PasswordHasher {
     pub fn new() -> Self {
+        Self::with_settings(&Argon2Settings::default())
+    }
+
+    pub fn with_settings(settings: &Argon2Settings) -> Self {
         Self(Config {
             variant: Variant::Argon2id,
             version: Version::Version13,
-            mem_cost: 1024,
-            time_cost: 3,
-            lanes: 4,
+            mem_cost: settings.mem_cost,
+            time_cost: settings.time_cost,
+            lanes: settings.lanes,
             thread_mode: ThreadMode::Sequential,
             secret: &[],
             ad: &[],
-            hash_length: 32,
+            hash_length: settings.hash_length,
         })
     }
 
+    #[tracing::instrument(skip_all)]
     pub fn hash_password(&self, password_raw: &str) -> Result<String> {
+        let _timer = METRICS
+            .password_hash_duration
+            .with_label_values(&["hash"])
+            .start_timer();
         let salt = rand::thread_rng().gen::<[u8; 8]>();
         Ok(argon2::hash_encoded(
             password_raw.as_bytes(),
@@ -31,7 +66,12 @@ impl PasswordHasher {
         )?)
     }
 
+    #[tracing::instrument(skip_all)]
     pub fn verify_password(&self, password_raw: &str, password_hash: &str) -> Result<bool> {
+        let _timer = METRICS
+            .password_hash_duration
+            .with_label_values(&["verify"])
+            .start_timer();
         Ok(argon2::verify_encoded(
             password_hash,
             password_raw.as_bytes(),
@@ -44,3 +84,178 @@ impl Default for PasswordHasher {
         Self::new()
     }
 }
+
+/// 256 random bits, base64url encoded, for the codes and secrets handed out to clients.
+pub fn random_token() -> String {
+    base64::encode_config(
+        rand::thread_rng().gen::<[u8; 32]>(),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+/// Consonants only, so codes are easy to type and can't spell words, per RFC 8628 section 6.1
+pub const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+pub const USER_CODE_LEN: usize = 8;
+
+/// Random code for users to type, without separator, about 34 bits.
+pub fn random_user_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..USER_CODE_LEN)
+        .map(|_| USER_CODE_ALPHABET[rng.gen_range(0..USER_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// SHA-256 of a token, hex encoded, stored in place of the token itself.
+pub fn token_hash(token: &str) -> String {
+    digest::digest(&digest::SHA256, token.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Ed25519 key pair signing and verifying the JWTs issued by the service.
+pub struct TokenSigner {
+    kid: String,
+    issuer: String,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    public_key: Vec<u8>,
+    nonce_key: hmac::Key,
+}
+
+impl TokenSigner {
+    pub fn from_settings(settings: &TokenSettings) -> Result<Self> {
+        let pkcs8 = match &settings.signing_key_file {
+            Some(path) => {
+                let file = fs::read(path)
+                    .with_context(|| format!("Failed to read signing key {}", path.display()))?;
+                let pem = pem::parse(file)
+                    .with_context(|| format!("Invalid PEM in signing key {}", path.display()))?;
+                if pem.tag != "PRIVATE KEY" {
+                    bail!("Signing key {} is not a PKCS#8 private key", path.display());
+                }
+                pem.contents
+            }
+            None => {
+                log::warn!("No token signing key configured, using an ephemeral key");
+                Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+                    .map_err(|_| anyhow!("Failed to generate signing key"))?
+                    .as_ref()
+                    .to_vec()
+            }
+        };
+        Self::from_pkcs8(&pkcs8, &settings.issuer)
+    }
+
+    pub fn from_pkcs8(pkcs8: &[u8], issuer: &str) -> Result<Self> {
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8)
+            .map_err(|err| anyhow!("Invalid Ed25519 signing key: {}", err))?;
+        let public_key = key_pair.public_key().as_ref().to_vec();
+        Ok(Self {
+            kid: jwk_thumbprint(&public_key),
+            issuer: issuer.to_string(),
+            encoding_key: EncodingKey::from_ed_der(pkcs8),
+            decoding_key: DecodingKey::from_ed_der(&public_key),
+            public_key,
+            // Derived from the signing key, so every instance sharing it accepts the same nonces
+            nonce_key: hmac::Key::new(
+                hmac::HMAC_SHA256,
+                digest::digest(&digest::SHA256, &[b"dpop-nonce:", pkcs8].concat()).as_ref(),
+            ),
+        })
+    }
+
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String> {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.kid.clone());
+        Ok(jsonwebtoken::encode(&header, claims, &self.encoding_key)?)
+    }
+
+    /// Checks the signature, expiry and issuer of `token`.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<T> {
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_issuer(&[&self.issuer]);
+        Ok(jsonwebtoken::decode(token, &self.decoding_key, &validation)?.claims)
+    }
+
+    /// Server-provided nonce for DPoP proofs, `<window>.<MAC of the window>`.
+    pub fn dpop_nonce(&self, now: i64) -> String {
+        let window = (now / DPOP_NONCE_WINDOW_SECS).to_string();
+        let tag = hmac::sign(&self.nonce_key, window.as_bytes());
+        format!(
+            "{}.{}",
+            window,
+            base64::encode_config(tag, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    pub fn verify_dpop_nonce(&self, nonce: &str, now: i64) -> bool {
+        let current = now / DPOP_NONCE_WINDOW_SECS;
+        let Some((window, tag)) = nonce.split_once('.') else {
+            return false;
+        };
+        let Ok(tag) = base64::decode_config(tag, base64::URL_SAFE_NO_PAD) else {
+            return false;
+        };
+        window
+            .parse::<i64>()
+            .is_ok_and(|window| window == current || window + 1 == current)
+            && hmac::verify(&self.nonce_key, window.as_bytes(), &tag).is_ok()
+    }
+}
+
+/// Base64url SHA-256, as in the `ath` claim of DPoP proofs and JWK thumbprints.
+pub fn sha256_base64url(data: &[u8]) -> String {
+    base64::encode_config(
+        digest::digest(&digest::SHA256, data),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+/// RFC 7638 thumbprint of a public key, such as the one DPoP proofs are signed with.
+pub fn public_jwk_thumbprint(jwk: &Jwk) -> Result<String> {
+    // Members in lexicographic order, without whitespace
+    let string = |value: &str| serde_json::to_string(value);
+    let canonical = match &jwk.algorithm {
+        AlgorithmParameters::EllipticCurve(params) => format!(
+            r#"{{"crv":{},"kty":"EC","x":{},"y":{}}}"#,
+            serde_json::to_string(&params.curve)?,
+            string(&params.x)?,
+            string(&params.y)?
+        ),
+        AlgorithmParameters::OctetKeyPair(params) => format!(
+            r#"{{"crv":{},"kty":"OKP","x":{}}}"#,
+            serde_json::to_string(&params.curve)?,
+            string(&params.x)?
+        ),
+        AlgorithmParameters::RSA(params) => format!(
+            r#"{{"e":{},"kty":"RSA","n":{}}}"#,
+            string(&params.e)?,
+            string(&params.n)?
+        ),
+        AlgorithmParameters::OctetKey(_) => bail!("Symmetric keys have no public thumbprint"),
+    };
+    Ok(sha256_base64url(canonical.as_bytes()))
+}
+
+/// RFC 7638 JWK thumbprint of an Ed25519 public key, used as the key ID.
+fn jwk_thumbprint(public_key: &[u8]) -> String {
+    let jwk = format!(
+        r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#,
+        base64::encode_config(public_key, base64::URL_SAFE_NO_PAD)
+    );
+    sha256_base64url(jwk.as_bytes())
+}
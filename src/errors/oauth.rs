@@ -0,0 +1,254 @@
+use actix_web::http::header::CacheControl;
+use actix_web::http::header::CacheDirective;
+use actix_web::http::header::LOCATION;
+use actix_web::http::header::WWW_AUTHENTICATE;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use actix_web::ResponseError;
+use thiserror::Error;
+use url::Url;
+
+use crate::errors::user::log_err;
+use crate::errors::user::UserServiceError;
+use crate::models::oauth::OAuthErrorDto;
+use crate::services::dpop::DPOP_NONCE_HEADER;
+use crate::services::dpop::DPOP_SIGNING_ALGS;
+
+/// Errors of the OAuth endpoints, reported in the RFC 6749 format rather than as problems, as
+/// OAuth clients expect.
+#[derive(Error, Debug)]
+pub enum OAuthError {
+    #[error("{0}")]
+    InvalidRequest(String),
+    #[error("Client authentication failed")]
+    InvalidClient,
+    #[error("{0}")]
+    InvalidGrant(String),
+    #[error("The client is not allowed to use this grant")]
+    UnauthorizedClient,
+    #[error("Scope not allowed for the client: {0}")]
+    InvalidScope(String),
+    #[error("Unsupported grant type: {0}")]
+    UnsupportedGrantType(String),
+    #[error("Unsupported response type: {0}")]
+    UnsupportedResponseType(String),
+    #[error("The user hasn't approved the device yet")]
+    AuthorizationPending,
+    #[error("Polling too fast, wait {0} seconds between requests")]
+    SlowDown(i64),
+    #[error("The user denied the request")]
+    AccessDenied,
+    #[error("The device code expired")]
+    ExpiredToken,
+    #[error("Audience not allowed: {0}")]
+    InvalidTarget(String),
+    #[error("{0}")]
+    InvalidToken(String),
+    #[error("The access token lacks the {0} scope")]
+    InsufficientScope(String),
+    #[error("{0}")]
+    InvalidDpopProof(String),
+    /// Carries the nonce the client must use
+    #[error("The DPoP proof must carry the nonce of the DPoP-Nonce header")]
+    UseDpopNonce(String),
+    /// Failure to access a resource with a DPoP-bound token, challenged with the DPoP scheme
+    #[error(transparent)]
+    DpopChallenge(Box<OAuthError>),
+    #[error("{0}")]
+    InvalidRedirectUri(String),
+    #[error("{0}")]
+    InvalidClientMetadata(String),
+    #[error("Internal server error")]
+    ServerError,
+}
+
+impl OAuthError {
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            Self::InvalidRequest(_) => "invalid_request",
+            Self::InvalidClient => "invalid_client",
+            Self::InvalidGrant(_) => "invalid_grant",
+            Self::UnauthorizedClient => "unauthorized_client",
+            Self::InvalidScope(_) => "invalid_scope",
+            Self::UnsupportedGrantType(_) => "unsupported_grant_type",
+            Self::UnsupportedResponseType(_) => "unsupported_response_type",
+            Self::AuthorizationPending => "authorization_pending",
+            Self::SlowDown(_) => "slow_down",
+            Self::AccessDenied => "access_denied",
+            Self::ExpiredToken => "expired_token",
+            Self::InvalidTarget(_) => "invalid_target",
+            Self::InvalidToken(_) => "invalid_token",
+            Self::InsufficientScope(_) => "insufficient_scope",
+            Self::InvalidDpopProof(_) => "invalid_dpop_proof",
+            Self::UseDpopNonce(_) => "use_dpop_nonce",
+            Self::DpopChallenge(error) => error.error_code(),
+            Self::InvalidRedirectUri(_) => "invalid_redirect_uri",
+            Self::InvalidClientMetadata(_) => "invalid_client_metadata",
+            Self::ServerError => "server_error",
+        }
+    }
+
+    pub fn to_dto(&self) -> OAuthErrorDto {
+        OAuthErrorDto {
+            error: self.error_code().to_string(),
+            error_description: self.to_string(),
+        }
+    }
+}
+
+impl From<UserServiceError> for OAuthError {
+    fn from(_: UserServiceError) -> Self {
+        Self::ServerError
+    }
+}
+
+/// Invalid settings of a client to register or update.
+#[derive(Error, Debug)]
+pub enum ClientRegistrationError {
+    #[error("Invalid redirect URI: {0}")]
+    InvalidRedirectUri(String),
+    #[error("{0}")]
+    InvalidMetadata(String),
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl From<ClientRegistrationError> for OAuthError {
+    fn from(error: ClientRegistrationError) -> Self {
+        match error {
+            ClientRegistrationError::InvalidRedirectUri(_) => {
+                Self::InvalidRedirectUri(error.to_string())
+            }
+            ClientRegistrationError::InvalidMetadata(description) => {
+                Self::InvalidClientMetadata(description)
+            }
+            ClientRegistrationError::Internal(error) => {
+                log_err(error);
+                Self::ServerError
+            }
+        }
+    }
+}
+
+impl From<ClientRegistrationError> for UserServiceError {
+    fn from(error: ClientRegistrationError) -> Self {
+        match error {
+            ClientRegistrationError::Internal(error) => {
+                log_err(error);
+                Self::UnknownInternal
+            }
+            _ => Self::InvalidClientFields(error.to_string()),
+        }
+    }
+}
+
+impl ResponseError for OAuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidClient | Self::InvalidToken(_) | Self::DpopChallenge(_) => {
+                StatusCode::UNAUTHORIZED
+            }
+            Self::InsufficientScope(_) => StatusCode::FORBIDDEN,
+            Self::ServerError => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status_code = self.status_code();
+        match self {
+            // Expected every few seconds while a device waits for its user
+            Self::AuthorizationPending | Self::SlowDown(_) => {
+                tracing::debug!(code = self.error_code(), "Sending OAuth error response")
+            }
+            _ => tracing::error!(
+                status = status_code.as_u16(),
+                code = self.error_code(),
+                error = %self,
+                "Sending OAuth error response"
+            ),
+        }
+        let mut response = HttpResponse::build(status_code);
+        response.insert_header(CacheControl(vec![CacheDirective::NoStore]));
+        match self {
+            Self::InvalidClient => {
+                response.insert_header((WWW_AUTHENTICATE, r#"Basic realm="oauth""#));
+            }
+            // RFC 6750 challenge of the resources protected by access tokens
+            Self::InvalidToken(_) | Self::InsufficientScope(_) => {
+                response.insert_header((
+                    WWW_AUTHENTICATE,
+                    format!(r#"Bearer realm="oauth", error="{}""#, self.error_code()),
+                ));
+            }
+            Self::DpopChallenge(error) => {
+                response.insert_header((
+                    WWW_AUTHENTICATE,
+                    format!(
+                        r#"DPoP algs="{}", error="{}""#,
+                        DPOP_SIGNING_ALGS.join(" "),
+                        self.error_code()
+                    ),
+                ));
+                if let Self::UseDpopNonce(nonce) = error.as_ref() {
+                    response.insert_header((DPOP_NONCE_HEADER, nonce.as_str()));
+                }
+            }
+            Self::UseDpopNonce(nonce) => {
+                response.insert_header((DPOP_NONCE_HEADER, nonce.as_str()));
+            }
+            _ => {}
+        }
+        response.json(self.to_dto())
+    }
+}
+
+/// Failure of an authorization request. Once the client and redirect URI are known to be valid,
+/// errors are reported to the client by redirecting back to it; before that the redirect URI
+/// can't be trusted and the error is shown to the user instead.
+#[derive(Error, Debug)]
+pub enum AuthorizeError {
+    #[error(transparent)]
+    Direct(#[from] OAuthError),
+    #[error("{error}")]
+    Redirect {
+        redirect_uri: Url,
+        state: Option<String>,
+        error: OAuthError,
+    },
+}
+
+impl ResponseError for AuthorizeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Direct(error) => error.status_code(),
+            Self::Redirect { .. } => StatusCode::FOUND,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Self::Direct(error) => error.error_response(),
+            Self::Redirect {
+                redirect_uri,
+                state,
+                error,
+            } => {
+                let mut location = redirect_uri.clone();
+                {
+                    let mut query = location.query_pairs_mut();
+                    query
+                        .append_pair("error", error.error_code())
+                        .append_pair("error_description", &error.to_string());
+                    if let Some(state) = state {
+                        query.append_pair("state", state);
+                    }
+                }
+                tracing::info!(code = error.error_code(), error = %error, "Authorization request rejected");
+                HttpResponse::Found()
+                    .insert_header((LOCATION, location.as_str()))
+                    .finish()
+            }
+        }
+    }
+}
@@ -2,8 +2,16 @@ use actix_web::http::StatusCode;
 use actix_web::web::Json;
 use actix_web::HttpResponse;
 use actix_web::ResponseError;
-use serde_json::json;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Map;
+use serde_json::Value;
 use thiserror::Error;
+use utoipa::ToSchema;
+use validator::ValidationErrors;
+use validator::ValidationErrorsKind;
+
+use crate::request_id;
 
 pub type UserServiceResult<T> = Result<Json<T>, UserServiceError>;
 
@@ -19,37 +27,194 @@ pub enum UserServiceError {
     InvalidUserFields(#[from] validator::ValidationErrors),
     #[error("Access denied")]
     Forbidden,
+    #[error("Invalid username or password")]
+    InvalidCredentials,
+    #[error("No OAuth client found for given ID: {0}")]
+    NoClientForId(String),
+    #[error("Client fields invalid: {0}")]
+    InvalidClientFields(String),
+    #[error("No linked identity found for given provider and subject: {0}")]
+    NoIdentityForUser(String),
+    #[error("Identity fields invalid: {0}")]
+    InvalidIdentityFields(String),
+    #[error("Identity already linked")]
+    IdentityTaken,
     #[error("Unknown internal server error")]
     UnknownInternal,
 }
 
+#[derive(Error, Debug)]
+pub enum UserRepoError {
+    #[error("Username already exists: {0}")]
+    UsernameConflict(String),
+    #[error("Identity already linked: {0}")]
+    IdentityConflict(String),
+}
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// RFC 7807 body of every error response.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct ProblemDetailsDto {
+    /// `urn:auth-uservice:problem:<code>`
+    #[serde(rename = "type")]
+    pub type_: String,
+    #[schema(example = "Username taken")]
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// Stable identifier of the error, for clients to branch on
+    #[schema(example = "username_taken")]
+    pub code: String,
+    /// ID of the request, to be quoted when reporting the error
+    pub request_id: Option<String>,
+    /// Failed validations, for `invalid_user_fields`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldErrorDto>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct FieldErrorDto {
+    /// Dotted path of the field, e.g. `email`
+    pub field: String,
+    /// Failed `validator` rule, e.g. `length`
+    pub code: String,
+    /// Rule parameters, e.g. `{"min": 3, "max": 30}`
+    #[schema(value_type = Object)]
+    pub params: Map<String, Value>,
+}
+
+impl UserServiceError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NoUserForId(_) => "no_user_for_id",
+            Self::InvalidId(_) => "invalid_id",
+            Self::UsernameTaken => "username_taken",
+            Self::InvalidUserFields(_) => "invalid_user_fields",
+            Self::Forbidden => "forbidden",
+            Self::InvalidCredentials => "invalid_credentials",
+            Self::NoClientForId(_) => "no_client_for_id",
+            Self::InvalidClientFields(_) => "invalid_client_fields",
+            Self::NoIdentityForUser(_) => "no_identity_for_user",
+            Self::InvalidIdentityFields(_) => "invalid_identity_fields",
+            Self::IdentityTaken => "identity_taken",
+            Self::UnknownInternal => "internal",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Self::NoUserForId(_) => "User not found",
+            Self::InvalidId(_) => "Invalid ID",
+            Self::UsernameTaken => "Username taken",
+            Self::InvalidUserFields(_) => "Invalid user fields",
+            Self::Forbidden => "Access denied",
+            Self::InvalidCredentials => "Invalid credentials",
+            Self::NoClientForId(_) => "OAuth client not found",
+            Self::InvalidClientFields(_) => "Invalid client fields",
+            Self::NoIdentityForUser(_) => "Linked identity not found",
+            Self::InvalidIdentityFields(_) => "Invalid identity fields",
+            Self::IdentityTaken => "Identity already linked",
+            Self::UnknownInternal => "Internal server error",
+        }
+    }
+
+    /// Message safe to log, validation errors leaving out the rejected values: those may be
+    /// passwords.
+    pub fn detail(&self) -> String {
+        match self {
+            Self::InvalidUserFields(_) => "One or more fields are invalid".to_string(),
+            _ => self.to_string(),
+        }
+    }
+
+    pub fn to_problem(&self) -> ProblemDetailsDto {
+        ProblemDetailsDto {
+            type_: format!("urn:auth-uservice:problem:{}", self.code()),
+            title: self.title().to_string(),
+            status: self.status_code().as_u16(),
+            detail: self.detail(),
+            code: self.code().to_string(),
+            request_id: request_id::current().map(|id| id.to_string()),
+            errors: match self {
+                Self::InvalidUserFields(errors) => field_errors(errors),
+                _ => Vec::new(),
+            },
+        }
+    }
+}
+
+/// Flattens nested validation errors, sorted by field. The rejected value is left out, it may be
+/// a password.
+pub fn field_errors(errors: &ValidationErrors) -> Vec<FieldErrorDto> {
+    let mut flat = Vec::new();
+    collect_field_errors(errors, "", &mut flat);
+    flat.sort_by(|a, b| a.field.cmp(&b.field).then_with(|| a.code.cmp(&b.code)));
+    flat
+}
+
+fn collect_field_errors(errors: &ValidationErrors, prefix: &str, flat: &mut Vec<FieldErrorDto>) {
+    for (field, kind) in errors.errors() {
+        let path = format!("{}{}", prefix, field);
+        match kind {
+            ValidationErrorsKind::Field(errors) => flat.extend(errors.iter().map(|error| {
+                FieldErrorDto {
+                    field: path.clone(),
+                    code: error.code.to_string(),
+                    params: error
+                        .params
+                        .iter()
+                        .filter(|(name, _)| *name != "value")
+                        .map(|(name, value)| (name.to_string(), value.clone()))
+                        .collect(),
+                }
+            })),
+            ValidationErrorsKind::Struct(errors) => {
+                collect_field_errors(errors, &format!("{}.", path), flat)
+            }
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect_field_errors(errors, &format!("{}[{}].", path, index), flat);
+                }
+            }
+        }
+    }
+}
+
 impl ResponseError for UserServiceError {
     fn status_code(&self) -> StatusCode {
         match *self {
-            Self::NoUserForId(_) => StatusCode::NOT_FOUND,
-            Self::InvalidId(_) | Self::UsernameTaken | Self::InvalidUserFields(_) => {
-                StatusCode::BAD_REQUEST
+            Self::NoUserForId(_) | Self::NoClientForId(_) | Self::NoIdentityForUser(_) => {
+                StatusCode::NOT_FOUND
             }
+            Self::InvalidId(_)
+            | Self::UsernameTaken
+            | Self::InvalidUserFields(_)
+            | Self::InvalidClientFields(_)
+            | Self::InvalidIdentityFields(_)
+            | Self::IdentityTaken => StatusCode::BAD_REQUEST,
             Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::InvalidCredentials => StatusCode::UNAUTHORIZED,
             Self::UnknownInternal => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
         let status_code = self.status_code();
-        log::error!(
-            "Sending error HTTP response: {} {}",
-            status_code,
-            self.to_string()
+        tracing::error!(
+            status = status_code.as_u16(),
+            code = self.code(),
+            error = %self.detail(),
+            "Sending error HTTP response"
         );
-        HttpResponse::build(status_code).json(json!({
-            "error": self.to_string()
-        }))
+        HttpResponse::build(status_code)
+            .content_type(PROBLEM_JSON)
+            .json(self.to_problem())
     }
 }
 
 pub fn log_err(any_err: impl Into<anyhow::Error>) -> anyhow::Error {
     let err = any_err.into();
-    log::error!("Internal Error: {:?}", err);
+    tracing::error!(error = ?err, "Internal error");
     err
 }
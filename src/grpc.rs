@@ -0,0 +1,280 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use tonic::metadata::MetadataMap;
+use tonic::metadata::MetadataValue;
+use tonic::transport::server::Router;
+use tonic::transport::Server;
+use tonic::Request;
+use tonic::Response;
+use tonic::Status;
+use uuid::Uuid;
+
+use crate::auth;
+use crate::crypto::PasswordHasher;
+use crate::crypto::TokenSigner;
+use crate::errors::user::UserServiceError;
+use crate::models::user::User;
+use crate::models::user::UserCreateReqDto;
+use crate::models::user::UserUpdateReqDto;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::user::UserRepo;
+use crate::services::oidc;
+use crate::services::token;
+use crate::services::user;
+use crate::settings::AdminSettings;
+
+pub mod pb {
+    tonic::include_proto!("auth_uservice.v1");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("auth_uservice_descriptor");
+}
+
+use pb::auth_service_server::AuthService;
+use pb::auth_service_server::AuthServiceServer;
+use pb::user_service_server::UserService;
+use pb::user_service_server::UserServiceServer;
+
+/// Error code of the failure, as in the `code` of REST problem responses
+pub const ERROR_CODE_METADATA: &str = "x-error-code";
+/// Scope letting OAuth clients change the password of the user or delete them
+pub const MANAGE_ACCOUNT_SCOPE: &str = "account:manage";
+
+impl From<UserServiceError> for Status {
+    fn from(err: UserServiceError) -> Self {
+        // Display may hold rejected values, e.g. passwords
+        let message = err.title();
+        let mut status = match err {
+            UserServiceError::NoUserForId(_)
+            | UserServiceError::NoClientForId(_)
+            | UserServiceError::NoIdentityForUser(_) => Status::not_found(message),
+            UserServiceError::InvalidId(_)
+            | UserServiceError::InvalidUserFields(_)
+            | UserServiceError::InvalidClientFields(_)
+            | UserServiceError::InvalidIdentityFields(_) => Status::invalid_argument(message),
+            UserServiceError::UsernameTaken | UserServiceError::IdentityTaken => {
+                Status::already_exists(message)
+            }
+            UserServiceError::Forbidden => Status::permission_denied(message),
+            UserServiceError::InvalidCredentials => Status::unauthenticated(message),
+            UserServiceError::UnknownInternal => Status::internal(message),
+        };
+        status
+            .metadata_mut()
+            .insert(ERROR_CODE_METADATA, MetadataValue::from_static(err.code()));
+        status
+    }
+}
+
+impl From<User> for pb::User {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id.to_string(),
+            username: user.username,
+            email: user.email,
+            created_at: user.created_at.map(|t| t.timestamp()),
+            last_login: user.last_login.map(|t| t.timestamp()),
+        }
+    }
+}
+
+/// Services of the gRPC API, sharing the repositories and hasher of the REST API.
+#[derive(Clone)]
+pub struct GrpcState {
+    pub user_repo: Arc<dyn UserRepo>,
+    pub audit_repo: Arc<dyn AuditRepo>,
+    pub oauth_repo: Arc<dyn OAuthRepo>,
+    pub passwd_hasher: Arc<PasswordHasher>,
+    pub token_signer: Arc<TokenSigner>,
+    pub access_token_ttl: Duration,
+    pub admin_settings: Arc<AdminSettings>,
+}
+
+impl GrpcState {
+    /// The user and auth services, plus reflection so clients can discover them.
+    pub fn router(self) -> Result<Router> {
+        let reflection = tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(pb::FILE_DESCRIPTOR_SET)
+            .build()?;
+        Ok(Server::builder()
+            .add_service(UserServiceServer::new(self.clone()))
+            .add_service(AuthServiceServer::new(self))
+            .add_service(reflection))
+    }
+
+    /// Admits requests whose `authorization: Bearer` metadata is the admin token, or an access
+    /// token of the user given, if any. `destructive` calls take tokens the user got by logging in
+    /// directly, or granted `account:manage`. Checked in the handlers rather than by an
+    /// interceptor, as revocation is looked up in the repository.
+    async fn authorize(
+        &self,
+        metadata: &MetadataMap,
+        user_id: Option<&str>,
+        destructive: bool,
+    ) -> Result<(), Status> {
+        let token = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or_else(|| Status::unauthenticated("Missing bearer token"))?;
+        if auth::is_admin_token(&self.admin_settings, token) {
+            return Ok(());
+        }
+        let claims =
+            token::verify_access_token(&self.token_signer, self.oauth_repo.as_ref(), token)
+                .await?
+                .ok_or_else(|| {
+                    Status::unauthenticated("Invalid, expired or revoked access token")
+                })?;
+        // Exchanged tokens restricted to a downstream service aren't good for our own API, and no
+        // DPoP proof can be given over gRPC
+        if claims
+            .aud
+            .as_deref()
+            .is_some_and(|aud| aud != self.token_signer.issuer())
+            || claims.cnf.is_some()
+        {
+            return Err(Status::unauthenticated(
+                "The access token can't be used with this API",
+            ));
+        }
+        // Neither services acting for the user nor staff impersonating them manage the account
+        if claims.act.is_some()
+            || destructive
+                && claims.client_id.is_some()
+                && !oidc::has_scope(claims.scope.as_deref(), MANAGE_ACCOUNT_SCOPE)
+        {
+            return Err(UserServiceError::Forbidden.into());
+        }
+        match user_id {
+            Some(user_id)
+                if Uuid::try_parse(&claims.sub).ok() == Some(user::parse_user_id(user_id)?) =>
+            {
+                Ok(())
+            }
+            _ => Err(UserServiceError::Forbidden.into()),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl UserService for GrpcState {
+    #[tracing::instrument(skip_all)]
+    async fn create_user(
+        &self,
+        request: Request<pb::CreateUserRequest>,
+    ) -> Result<Response<pb::CreateUserResponse>, Status> {
+        self.authorize(request.metadata(), None, false).await?;
+        let request = request.into_inner();
+        let user_id = user::create_user(
+            self.user_repo.as_ref(),
+            self.audit_repo.as_ref(),
+            &self.passwd_hasher,
+            UserCreateReqDto {
+                username: request.username,
+                password_raw: request.password,
+                email: request.email,
+            },
+        )
+        .await?;
+        Ok(Response::new(pb::CreateUserResponse {
+            id: user_id.to_string(),
+        }))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_user(
+        &self,
+        request: Request<pb::GetUserRequest>,
+    ) -> Result<Response<pb::User>, Status> {
+        let user = user::find_user(self.user_repo.as_ref(), &request.into_inner().id).await?;
+        Ok(Response::new(user.into()))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn update_user(
+        &self,
+        request: Request<pb::UpdateUserRequest>,
+    ) -> Result<Response<pb::User>, Status> {
+        let destructive = request.get_ref().password.is_some();
+        self.authorize(request.metadata(), Some(&request.get_ref().id), destructive)
+            .await?;
+        let request = request.into_inner();
+        let user = user::update_user(
+            self.user_repo.as_ref(),
+            self.audit_repo.as_ref(),
+            self.oauth_repo.as_ref(),
+            &self.passwd_hasher,
+            &request.id,
+            UserUpdateReqDto {
+                password_raw: request.password,
+                email: request.email,
+            },
+        )
+        .await?;
+        Ok(Response::new(user.into()))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn delete_user(
+        &self,
+        request: Request<pb::DeleteUserRequest>,
+    ) -> Result<Response<pb::DeleteUserResponse>, Status> {
+        self.authorize(request.metadata(), Some(&request.get_ref().id), true)
+            .await?;
+        user::remove_user(self.audit_repo.as_ref(), &request.into_inner().id).await?;
+        Ok(Response::new(pb::DeleteUserResponse {}))
+    }
+}
+
+#[tonic::async_trait]
+impl AuthService for GrpcState {
+    #[tracing::instrument(skip_all)]
+    async fn authenticate(
+        &self,
+        request: Request<pb::AuthenticateRequest>,
+    ) -> Result<Response<pb::AuthenticateResponse>, Status> {
+        let request = request.into_inner();
+        let token = token::authenticate(
+            self.user_repo.as_ref(),
+            self.audit_repo.as_ref(),
+            &self.passwd_hasher,
+            &self.token_signer,
+            self.access_token_ttl,
+            &request.username,
+            &request.password,
+        )
+        .await?;
+        Ok(Response::new(pb::AuthenticateResponse {
+            access_token: token.access_token,
+            token_type: token.token_type,
+            expires_in: token.expires_in,
+        }))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn introspect_token(
+        &self,
+        request: Request<pb::IntrospectTokenRequest>,
+    ) -> Result<Response<pb::IntrospectTokenResponse>, Status> {
+        let introspection = token::introspect(
+            &self.token_signer,
+            self.oauth_repo.as_ref(),
+            &request.into_inner().token,
+        )
+        .await?;
+        let claims = introspection.claims;
+        Ok(Response::new(pb::IntrospectTokenResponse {
+            active: introspection.active,
+            sub: claims.as_ref().map(|c| c.sub.clone()),
+            username: claims.as_ref().and_then(|c| c.username.clone()),
+            iss: claims.as_ref().map(|c| c.iss.clone()),
+            exp: claims.as_ref().map(|c| c.exp),
+            iat: claims.as_ref().map(|c| c.iat),
+        }))
+    }
+}
@@ -0,0 +1,148 @@
+use std::future::Future;
+use std::time::Instant;
+
+use actix_web::dev::Service;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use prometheus::Encoder;
+use prometheus::HistogramOpts;
+use prometheus::HistogramVec;
+use prometheus::IntCounterVec;
+use prometheus::IntGauge;
+use prometheus::IntGaugeVec;
+use prometheus::Opts;
+use prometheus::Registry;
+use prometheus::TextEncoder;
+
+use crate::repositories::database::PoolStats;
+
+pub static METRICS: Lazy<Metrics> =
+    Lazy::new(|| Metrics::new().expect("Metric definitions are valid"));
+
+/// Buckets for database queries, from sub-millisecond lookups up to slow statements.
+const QUERY_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub password_hash_duration: HistogramVec,
+    pub logins: IntCounterVec,
+    pub db_pool_connections: IntGaugeVec,
+    pub db_pool_max_connections: IntGauge,
+    pub db_query_duration: HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> Result<Self> {
+        let metrics = Self {
+            registry: Registry::new(),
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests served"),
+                &["method", "route", "status"],
+            )?,
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+                &["method", "route", "status"],
+            )?,
+            password_hash_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "password_hash_duration_seconds",
+                    "Time spent hashing or verifying passwords with argon2",
+                )
+                .buckets(vec![
+                    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+                ]),
+                &["operation"],
+            )?,
+            logins: IntCounterVec::new(
+                Opts::new("logins_total", "Login attempts by result"),
+                &["result"],
+            )?,
+            db_pool_connections: IntGaugeVec::new(
+                Opts::new(
+                    "db_pool_connections",
+                    "Pooled database connections by state",
+                ),
+                &["state"],
+            )?,
+            db_pool_max_connections: IntGauge::new(
+                "db_pool_max_connections",
+                "Maximum number of pooled database connections",
+            )?,
+            db_query_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "db_query_duration_seconds",
+                    "Latency of user repository calls",
+                )
+                .buckets(QUERY_BUCKETS.to_vec()),
+                &["method"],
+            )?,
+        };
+        let registry = &metrics.registry;
+        registry.register(Box::new(metrics.http_requests.clone()))?;
+        registry.register(Box::new(metrics.http_request_duration.clone()))?;
+        registry.register(Box::new(metrics.password_hash_duration.clone()))?;
+        registry.register(Box::new(metrics.logins.clone()))?;
+        registry.register(Box::new(metrics.db_pool_connections.clone()))?;
+        registry.register(Box::new(metrics.db_pool_max_connections.clone()))?;
+        registry.register(Box::new(metrics.db_query_duration.clone()))?;
+        Ok(metrics)
+    }
+
+    pub fn record_login(&self, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.logins.with_label_values(&[result]).inc();
+    }
+
+    pub fn set_pool_stats(&self, stats: PoolStats) {
+        let in_use = stats.size.saturating_sub(stats.idle);
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(stats.idle.into());
+        self.db_pool_connections
+            .with_label_values(&["in_use"])
+            .set(in_use.into());
+        self.db_pool_max_connections.set(stats.max_size.into());
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn encode(&self) -> Result<String> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+}
+
+/// Middleware for `App::wrap_fn` counting requests and their latency. Requests are labelled by
+/// route pattern (e.g. `/users/{user_id}`) rather than path to keep the label cardinality bounded.
+pub fn track_request<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let fut = srv.call(req);
+    async move {
+        let res = fut.await?;
+        let route = res
+            .request()
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".into());
+        let status = res.status();
+        let labels = [method.as_str(), route.as_str(), status.as_str()];
+        METRICS.http_requests.with_label_values(&labels).inc();
+        METRICS
+            .http_request_duration
+            .with_label_values(&labels)
+            .observe(start.elapsed().as_secs_f64());
+        Ok(res)
+    }
+}
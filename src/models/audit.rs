@@ -0,0 +1,263 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use chrono::DateTime;
+use chrono::SubsecRound;
+use chrono::Utc;
+use derive_builder::Builder;
+use ring::digest;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// `prev_hash` of the first event of the chain
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEventType {
+    UserCreated,
+    LoginSucceeded,
+    LoginFailed,
+    PasswordChanged,
+    UserDeleted,
+    UserImpersonated,
+}
+
+impl AuditEventType {
+    pub const ALL: [Self; 6] = [
+        Self::UserCreated,
+        Self::LoginSucceeded,
+        Self::LoginFailed,
+        Self::PasswordChanged,
+        Self::UserDeleted,
+        Self::UserImpersonated,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UserCreated => "user_created",
+            Self::LoginSucceeded => "login_succeeded",
+            Self::LoginFailed => "login_failed",
+            Self::PasswordChanged => "password_changed",
+            Self::UserDeleted => "user_deleted",
+            Self::UserImpersonated => "user_impersonated",
+        }
+    }
+}
+
+impl fmt::Display for AuditEventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AuditEventType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.as_str() == s)
+            .ok_or_else(|| anyhow!("Unknown audit event type: {}", s))
+    }
+}
+
+impl TryFrom<String> for AuditEventType {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Event to be appended to the audit log.
+#[derive(Builder, Clone, Debug, PartialEq)]
+#[builder(setter(into, strip_option))]
+pub struct NewAuditEvent {
+    pub event_type: AuditEventType,
+    #[builder(default)]
+    pub actor_id: Option<Uuid>,
+    #[builder(default)]
+    pub subject_id: Option<Uuid>,
+    #[builder(default = "json!({})")]
+    pub details: Value,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct AuditEvent {
+    pub seq: i64,
+    pub id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    #[sqlx(try_from = "String")]
+    pub event_type: AuditEventType,
+    pub actor_id: Option<Uuid>,
+    pub subject_id: Option<Uuid>,
+    /// JSON object
+    pub details: String,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEvent {
+    /// Builds the record following `prev_hash` in the chain. `seq` is assigned by the database.
+    pub fn seal(event: &NewAuditEvent, prev_hash: &str) -> Self {
+        let mut sealed = Self {
+            seq: 0,
+            id: Uuid::new_v4(),
+            // Postgres keeps microseconds, the hash must survive the roundtrip
+            occurred_at: Utc::now().trunc_subsecs(6),
+            event_type: event.event_type,
+            actor_id: event.actor_id,
+            subject_id: event.subject_id,
+            details: event.details.to_string(),
+            prev_hash: prev_hash.to_string(),
+            hash: String::new(),
+        };
+        sealed.hash = sealed.compute_hash();
+        sealed
+    }
+
+    /// SHA-256 over every field but `seq` and `hash`, hex encoded.
+    pub fn compute_hash(&self) -> String {
+        let content = json!([
+            self.id,
+            self.occurred_at.timestamp_micros(),
+            self.event_type,
+            self.actor_id,
+            self.subject_id,
+            self.details,
+            self.prev_hash,
+        ]);
+        digest::digest(&digest::SHA256, content.to_string().as_bytes())
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// Latest event of the chain, stored apart from it so events removed from its end are noticed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct AuditHead {
+    /// 0 while the chain is empty
+    pub seq: i64,
+    pub hash: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AuditChainReport {
+    pub valid: bool,
+    pub events_checked: u64,
+    /// First event whose hash or link to the previous event doesn't match, or the head if the
+    /// chain doesn't end there
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_invalid_seq: Option<i64>,
+}
+
+/// Checks events, fed in `seq` order starting from the first one ever recorded, form an unbroken
+/// chain.
+#[derive(Debug)]
+pub struct ChainVerifier {
+    prev_seq: i64,
+    prev_hash: String,
+    events_checked: u64,
+    first_invalid_seq: Option<i64>,
+}
+
+impl Default for ChainVerifier {
+    fn default() -> Self {
+        Self {
+            prev_seq: 0,
+            prev_hash: GENESIS_HASH.to_string(),
+            events_checked: 0,
+            first_invalid_seq: None,
+        }
+    }
+}
+
+impl ChainVerifier {
+    /// Returns `false` once an event breaks the chain, later events are not checked.
+    pub fn check(&mut self, event: &AuditEvent) -> bool {
+        if self.first_invalid_seq.is_some() {
+            return false;
+        }
+        self.events_checked += 1;
+        if event.prev_hash != self.prev_hash || event.hash != event.compute_hash() {
+            self.first_invalid_seq = Some(event.seq);
+            return false;
+        }
+        self.prev_seq = event.seq;
+        self.prev_hash = event.hash.clone();
+        true
+    }
+
+    /// Once every event up to the head was fed, checks the chain ends at the head. A missing head
+    /// was removed, as the chain starts with one.
+    pub fn check_head(&mut self, head: Option<&AuditHead>) -> bool {
+        if self.first_invalid_seq.is_some() {
+            return false;
+        }
+        match head {
+            Some(head) if head.seq == self.prev_seq && head.hash == self.prev_hash => true,
+            _ => {
+                self.first_invalid_seq = Some(head.map_or(self.prev_seq, |head| head.seq));
+                false
+            }
+        }
+    }
+
+    pub fn report(&self) -> AuditChainReport {
+        AuditChainReport {
+            valid: self.first_invalid_seq.is_none(),
+            events_checked: self.events_checked,
+            first_invalid_seq: self.first_invalid_seq,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct AuditQuery {
+    pub event_type: Option<AuditEventType>,
+    pub actor_id: Option<Uuid>,
+    pub subject_id: Option<Uuid>,
+    /// Only events recorded after this one
+    pub after_seq: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuditEventRespDto {
+    pub seq: i64,
+    pub id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    pub event_type: AuditEventType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject_id: Option<Uuid>,
+    pub details: Value,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl From<AuditEvent> for AuditEventRespDto {
+    fn from(event: AuditEvent) -> Self {
+        Self {
+            seq: event.seq,
+            id: event.id,
+            occurred_at: event.occurred_at,
+            event_type: event.event_type,
+            actor_id: event.actor_id,
+            subject_id: event.subject_id,
+            details: serde_json::from_str(&event.details).unwrap_or(Value::Null),
+            prev_hash: event.prev_hash,
+            hash: event.hash,
+        }
+    }
+}
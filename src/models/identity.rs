@@ -0,0 +1,29 @@
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Account of a user at an upstream OpenID Connect provider. A user may have several, each one
+/// belonging to a single user.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct LinkedIdentity {
+    /// ID of the provider in `oauth.upstream_providers`
+    pub provider: String,
+    /// `sub` of the user at the provider
+    pub subject: String,
+    pub user_id: Uuid,
+    /// As given by the provider when linked
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Identity to link to a user, by an administrator.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct LinkIdentityReqDto {
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+}
@@ -0,0 +1,496 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::telemetry::REDACTED;
+
+/// Application allowed to obtain tokens on behalf of users, or for itself if confidential.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub struct OAuthClient {
+    pub id: String,
+    pub name: String,
+    #[sqlx(try_from = "String")]
+    pub redirect_uris: RedirectUris,
+    pub created_at: DateTime<Utc>,
+    /// Hash of the secret of confidential clients, public clients have none
+    #[serde(skip)]
+    pub secret_hash: Option<String>,
+    /// Space separated scopes the client may be granted
+    pub allowed_scopes: String,
+    /// Space separated grant types the client may use
+    pub grant_types: String,
+    /// Hash of the secret replaced by the last rotation
+    #[serde(skip)]
+    pub previous_secret_hash: Option<String>,
+    /// Until when the previous secret is still accepted
+    pub previous_secret_expires_at: Option<DateTime<Utc>>,
+}
+
+impl OAuthClient {
+    pub fn is_confidential(&self) -> bool {
+        self.secret_hash.is_some()
+    }
+
+    pub fn allows_grant_type(&self, grant_type: &str) -> bool {
+        self.grant_types.split_whitespace().any(|g| g == grant_type)
+    }
+
+    /// Whether every scope of the space separated `scope` is allowed.
+    pub fn allows_scope(&self, scope: &str) -> bool {
+        scope
+            .split_whitespace()
+            .all(|scope| self.allowed_scopes.split_whitespace().any(|s| s == scope))
+    }
+}
+
+/// Client to register. Confidential clients get a secret to authenticate with. Without
+/// `grant_types`, the client may use every grant its redirect URIs and confidentiality allow.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ClientRegistrationDto {
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub allowed_scopes: Vec<String>,
+    pub confidential: bool,
+    pub grant_types: Vec<String>,
+}
+
+/// Replaces the settings of a client, which stays confidential or public.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ClientUpdateDto {
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub allowed_scopes: Vec<String>,
+    pub grant_types: Vec<String>,
+}
+
+/// Body of `POST /admin/clients/{client_id}/secret`.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct SecretRotationReqDto {
+    /// How long the replaced secret stays valid, `oauth.secret_rotation_overlap_secs` by default
+    pub overlap_secs: Option<u64>,
+}
+
+/// RFC 7591 client metadata of `POST /oauth/register`.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct DynamicRegistrationReqDto {
+    pub client_name: Option<String>,
+    pub redirect_uris: Vec<String>,
+    /// `authorization_code` if unset, as in RFC 7591
+    pub grant_types: Option<Vec<String>>,
+    /// Space separated
+    pub scope: Option<String>,
+    /// `none` for public clients, `client_secret_basic` (the default) or `client_secret_post`
+    pub token_endpoint_auth_method: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DynamicRegistrationRespDto {
+    pub client_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    pub client_id_issued_at: i64,
+    /// 0 as secrets don't expire until rotated, only given with a secret
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_secret_expires_at: Option<i64>,
+    pub client_name: String,
+    pub redirect_uris: Vec<String>,
+    pub grant_types: Vec<String>,
+    pub scope: String,
+    pub token_endpoint_auth_method: String,
+}
+
+/// Registered client, with its secret which is only ever shown here.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RegisteredClientDto {
+    #[serde(flatten)]
+    pub client: OAuthClient,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+}
+
+/// Allowlist of redirect URIs, compared as exact strings. Stored as a JSON array.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct RedirectUris(pub Vec<String>);
+
+impl RedirectUris {
+    pub fn contains(&self, redirect_uri: &str) -> bool {
+        self.0.iter().any(|allowed| allowed == redirect_uri)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::Value::from(self.0.clone()).to_string()
+    }
+}
+
+impl TryFrom<String> for RedirectUris {
+    type Error = serde_json::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        serde_json::from_str(&s)
+    }
+}
+
+/// Authorization code issued to a client, redeemable once before `expires_at`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct AuthorizationCode {
+    /// SHA-256 of the code, hex encoded
+    pub code_hash: String,
+    pub client_id: String,
+    pub user_id: Uuid,
+    pub redirect_uri: String,
+    pub scope: Option<String>,
+    /// PKCE `S256` challenge, the only method supported
+    pub code_challenge: String,
+    pub expires_at: DateTime<Utc>,
+    /// OpenID Connect nonce of the authorization request
+    pub nonce: Option<String>,
+    /// When the user logged in to approve the request
+    pub auth_time: DateTime<Utc>,
+}
+
+/// Sign-in at an upstream provider in progress, from the redirect to the provider to its
+/// callback, which takes it once before `expires_at`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct UpstreamLogin {
+    /// SHA-256 of the state, hex encoded
+    pub state_hash: String,
+    /// ID of the provider in `oauth.upstream_providers`
+    pub provider: String,
+    pub nonce: String,
+    /// PKCE verifier of the code issued by the provider
+    pub code_verifier: String,
+    /// JSON of the authorization request to resume once signed in
+    pub authorize_params: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Query of the redirect back from an upstream provider.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct UpstreamCallbackParams {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceAuthorizationStatus {
+    Pending,
+    Approved,
+    Denied,
+}
+
+impl DeviceAuthorizationStatus {
+    pub const ALL: [Self; 3] = [Self::Pending, Self::Approved, Self::Denied];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Denied => "denied",
+        }
+    }
+}
+
+impl FromStr for DeviceAuthorizationStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| anyhow!("Unknown device authorization status: {}", s))
+    }
+}
+
+impl TryFrom<String> for DeviceAuthorizationStatus {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// RFC 8628 authorization of a device, approved or denied by a user entering the user code,
+/// while the device polls with the device code.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct DeviceAuthorization {
+    /// SHA-256 of the device code, hex encoded
+    pub device_code_hash: String,
+    /// Normalized, without separator
+    pub user_code: String,
+    pub client_id: String,
+    pub scope: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub interval_secs: i64,
+    pub last_polled_at: Option<DateTime<Utc>>,
+    #[sqlx(try_from = "String")]
+    pub status: DeviceAuthorizationStatus,
+    /// User who approved or denied the request
+    pub user_id: Option<Uuid>,
+}
+
+/// Form of `POST /oauth/device_authorization`.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct DeviceAuthorizationReqDto {
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DeviceAuthorizationRespDto {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: String,
+    /// Seconds until the codes expire
+    pub expires_in: i64,
+    /// Seconds the device must wait between polls
+    pub interval: i64,
+}
+
+/// Query of `GET /oauth/device`, the user code is optional so a link can carry it.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct DeviceVerificationParams {
+    pub user_code: Option<String>,
+}
+
+/// Form of `POST /oauth/device`, `action` is `approve` or `deny`.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct DeviceVerificationFormDto {
+    pub user_code: String,
+    pub username: String,
+    pub password: String,
+    pub action: String,
+}
+
+/// Query of `GET /oauth/authorize`, also posted back by the login form. Every field is optional
+/// so missing ones are reported as OAuth errors rather than rejected by the extractor.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct AuthorizeParams {
+    pub response_type: Option<String>,
+    pub client_id: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub nonce: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct LoginFormDto {
+    #[serde(flatten)]
+    pub params: AuthorizeParams,
+    pub username: String,
+    pub password: String,
+}
+
+/// Form of `POST /oauth/token`.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct TokenReqDto {
+    pub grant_type: Option<String>,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub code_verifier: Option<String>,
+    pub scope: Option<String>,
+    pub device_code: Option<String>,
+    pub subject_token: Option<String>,
+    pub subject_token_type: Option<String>,
+    pub actor_token: Option<String>,
+    pub actor_token_type: Option<String>,
+    pub requested_token_type: Option<String>,
+    pub audience: Option<String>,
+    /// ID of the user to impersonate, in place of a subject token
+    pub requested_subject: Option<String>,
+}
+
+/// Form of `POST /oauth/introspect` and `POST /oauth/revoke`: a token, and the credentials of
+/// the client asking.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct ClientTokenReqDto {
+    pub token: Option<String>,
+    /// Ignored, access tokens are the only kind of token
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+// Hand-written so credentials, codes and verifiers never end up in logs
+impl fmt::Debug for OAuthClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OAuthClient")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("redirect_uris", &self.redirect_uris)
+            .field("created_at", &self.created_at)
+            .field("secret_hash", &self.secret_hash.as_ref().map(|_| REDACTED))
+            .field("allowed_scopes", &self.allowed_scopes)
+            .field("grant_types", &self.grant_types)
+            .field(
+                "previous_secret_hash",
+                &self.previous_secret_hash.as_ref().map(|_| REDACTED),
+            )
+            .field(
+                "previous_secret_expires_at",
+                &self.previous_secret_expires_at,
+            )
+            .finish()
+    }
+}
+
+impl fmt::Debug for DynamicRegistrationRespDto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DynamicRegistrationRespDto")
+            .field("client_id", &self.client_id)
+            .field(
+                "client_secret",
+                &self.client_secret.as_ref().map(|_| REDACTED),
+            )
+            .field("client_id_issued_at", &self.client_id_issued_at)
+            .field("client_secret_expires_at", &self.client_secret_expires_at)
+            .field("client_name", &self.client_name)
+            .field("redirect_uris", &self.redirect_uris)
+            .field("grant_types", &self.grant_types)
+            .field("scope", &self.scope)
+            .field(
+                "token_endpoint_auth_method",
+                &self.token_endpoint_auth_method,
+            )
+            .finish()
+    }
+}
+
+impl fmt::Debug for RegisteredClientDto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegisteredClientDto")
+            .field("client", &self.client)
+            .field(
+                "client_secret",
+                &self.client_secret.as_ref().map(|_| REDACTED),
+            )
+            .finish()
+    }
+}
+
+impl fmt::Debug for LoginFormDto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoginFormDto")
+            .field("params", &self.params)
+            .field("username", &self.username)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
+impl fmt::Debug for TokenReqDto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenReqDto")
+            .field("grant_type", &self.grant_type)
+            .field("code", &self.code.as_ref().map(|_| REDACTED))
+            .field("redirect_uri", &self.redirect_uri)
+            .field("client_id", &self.client_id)
+            .field(
+                "client_secret",
+                &self.client_secret.as_ref().map(|_| REDACTED),
+            )
+            .field(
+                "code_verifier",
+                &self.code_verifier.as_ref().map(|_| REDACTED),
+            )
+            .field("scope", &self.scope)
+            .field("device_code", &self.device_code.as_ref().map(|_| REDACTED))
+            .field(
+                "subject_token",
+                &self.subject_token.as_ref().map(|_| REDACTED),
+            )
+            .field("subject_token_type", &self.subject_token_type)
+            .field("actor_token", &self.actor_token.as_ref().map(|_| REDACTED))
+            .field("actor_token_type", &self.actor_token_type)
+            .field("requested_token_type", &self.requested_token_type)
+            .field("audience", &self.audience)
+            .field("requested_subject", &self.requested_subject)
+            .finish()
+    }
+}
+
+impl fmt::Debug for DeviceAuthorizationReqDto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceAuthorizationReqDto")
+            .field("client_id", &self.client_id)
+            .field(
+                "client_secret",
+                &self.client_secret.as_ref().map(|_| REDACTED),
+            )
+            .field("scope", &self.scope)
+            .finish()
+    }
+}
+
+impl fmt::Debug for DeviceAuthorizationRespDto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceAuthorizationRespDto")
+            .field("device_code", &REDACTED)
+            .field("user_code", &self.user_code)
+            .field("verification_uri", &self.verification_uri)
+            .field("expires_in", &self.expires_in)
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+impl fmt::Debug for DeviceVerificationFormDto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceVerificationFormDto")
+            .field("user_code", &self.user_code)
+            .field("username", &self.username)
+            .field("password", &REDACTED)
+            .field("action", &self.action)
+            .finish()
+    }
+}
+
+impl fmt::Debug for ClientTokenReqDto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientTokenReqDto")
+            .field("token", &self.token.as_ref().map(|_| REDACTED))
+            .field("token_type_hint", &self.token_type_hint)
+            .field("client_id", &self.client_id)
+            .field(
+                "client_secret",
+                &self.client_secret.as_ref().map(|_| REDACTED),
+            )
+            .finish()
+    }
+}
+
+/// RFC 6749 error response of the OAuth endpoints.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct OAuthErrorDto {
+    pub error: String,
+    pub error_description: String,
+}
@@ -0,0 +1,89 @@
+use serde::Deserialize;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Claims of the access tokens issued to authenticated users, or to clients for themselves.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AccessTokenClaims {
+    pub iss: String,
+    /// User ID, or the client ID for tokens of the client credentials grant
+    pub sub: String,
+    /// Unset for tokens of the client credentials grant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: Uuid,
+    /// OAuth client the token was issued to, unset for direct password authentication
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Space separated scopes granted by the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Service the token is restricted to, set by token exchange
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    /// RFC 8693 party acting on behalf of the subject, set by token exchange
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+    /// RFC 9449 key the token is bound to, whose holder must prove possession with DPoP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Confirmation {
+    /// JWK SHA-256 thumbprint
+    pub jkt: String,
+}
+
+/// RFC 8693 `act` claim, nesting the earlier actors of a delegation chain.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Actor {
+    /// User ID, or the client ID of a delegating client
+    pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Box<Actor>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AccessTokenRespDto {
+    pub access_token: String,
+    pub token_type: String,
+    /// Seconds until the token expires
+    pub expires_in: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// OpenID Connect ID token, issued when the `openid` scope was granted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+    /// RFC 8693 type of the token issued by token exchange
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issued_token_type: Option<String>,
+}
+
+/// Claims of an RFC 9449 DPoP proof, a JWT signed by the client with the key in its header.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DpopProofClaims {
+    pub jti: String,
+    /// HTTP method of the request
+    pub htm: String,
+    /// URI of the request, without query and fragment
+    pub htu: String,
+    pub iat: i64,
+    /// Hash of the access token, when proving possession to a resource
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ath: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+}
+
+/// RFC 7662 style introspection result, claims are only given for active tokens.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct IntrospectionRespDto {
+    pub active: bool,
+    #[serde(flatten)]
+    pub claims: Option<AccessTokenClaims>,
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use chrono::serde::ts_seconds_option;
 use chrono::DateTime;
 use chrono::Utc;
@@ -5,10 +7,13 @@ use derive_builder::Builder;
 use serde::Deserialize;
 use serde::Serialize;
 use sqlx::FromRow;
+use utoipa::ToSchema;
 use uuid::Uuid;
 use validator::Validate;
 
-#[derive(Builder, Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq, FromRow)]
+use crate::telemetry::REDACTED;
+
+#[derive(Builder, Serialize, Deserialize, Clone, Default, PartialEq, Eq, FromRow)]
 #[builder(setter(into, strip_option), default)]
 pub struct User {
     pub id: Uuid,
@@ -21,26 +26,81 @@ pub struct User {
     pub last_login: Option<DateTime<Utc>>,
 }
 
-#[derive(Builder, Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq, Validate)]
+// `schema` attributes mirror the `validate` ones, utoipa can't read the latter
+#[derive(Builder, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Validate, ToSchema)]
 #[builder(setter(into, strip_option), default)]
 pub struct UserCreateReqDto {
     #[validate(length(min = 3, max = 30))]
+    #[schema(min_length = 3, max_length = 30, example = "derek")]
     pub username: String,
     #[validate(length(min = 8, max = 30))]
+    #[schema(min_length = 8, max_length = 30, write_only)]
     pub password_raw: String,
     #[validate(email)]
+    #[schema(format = Email)]
     pub email: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+/// Fields left unset are not changed.
+#[derive(Builder, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Validate)]
+#[builder(setter(into, strip_option), default)]
+pub struct UserUpdateReqDto {
+    #[validate(length(min = 8, max = 30))]
+    pub password_raw: Option<String>,
+    #[validate(email)]
+    pub email: Option<String>,
+}
+
+// Hand-written so password fields never end up in logs
+impl fmt::Debug for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("User")
+            .field("id", &self.id)
+            .field("username", &self.username)
+            .field("password_hash", &REDACTED)
+            .field("email", &self.email)
+            .field("created_at", &self.created_at)
+            .field("last_login", &self.last_login)
+            .finish()
+    }
+}
+
+impl fmt::Debug for UserCreateReqDto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserCreateReqDto")
+            .field("username", &self.username)
+            .field("password_raw", &REDACTED)
+            .field("email", &self.email)
+            .finish()
+    }
+}
+
+impl fmt::Debug for UserUpdateReqDto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserUpdateReqDto")
+            .field(
+                "password_raw",
+                &self.password_raw.as_ref().map(|_| REDACTED),
+            )
+            .field("email", &self.email)
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq, ToSchema)]
 pub struct UserGetRespDto {
     pub id: Uuid,
     pub username: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(format = Email)]
     pub email: Option<String>,
+    /// Unix timestamp in seconds
     #[serde(with = "ts_seconds_option", skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<i64>)]
     pub created_at: Option<DateTime<Utc>>,
+    /// Unix timestamp in seconds
     #[serde(with = "ts_seconds_option", skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<i64>)]
     pub last_login: Option<DateTime<Utc>>,
 }
 
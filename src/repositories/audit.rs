@@ -0,0 +1,29 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::models::audit::AuditEvent;
+use crate::models::audit::AuditHead;
+use crate::models::audit::AuditQuery;
+use crate::models::audit::NewAuditEvent;
+use crate::models::user::User;
+
+/// Append-only, hash-chained log of security events.
+#[async_trait]
+pub trait AuditRepo: Send + Sync + 'static {
+    /// Seals the event with the hash of the latest one and appends it, moving the head to it.
+    async fn append_event(&self, event: &NewAuditEvent) -> Result<AuditEvent>;
+    /// Applies the change and appends its event, or does neither. Returns `false`, appending
+    /// nothing, if there is no user to update or delete.
+    async fn change_user(&self, change: UserChange<'_>, event: &NewAuditEvent) -> Result<bool>;
+    async fn get_head(&self) -> Result<Option<AuditHead>>;
+    /// Events matching `query`, oldest first.
+    async fn list_events(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>>;
+}
+
+/// Change to a user which is audited along with it.
+pub enum UserChange<'a> {
+    Create(&'a User),
+    Update(&'a User),
+    Delete(&'a Uuid),
+}
@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use anyhow::bail;
+use anyhow::Result;
+
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::database::DatabaseProbe;
+use crate::repositories::identity::IdentityRepo;
+use crate::repositories::metered::MeteredAuditRepo;
+use crate::repositories::metered::MeteredUserRepo;
+use crate::repositories::migrations::SchemaMigrator;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::psql::user::UserRepoDb;
+#[cfg(feature = "sqlite")]
+use crate::repositories::sqlite::user::UserRepoSqlite;
+use crate::repositories::user::UserRepo;
+use crate::settings::DatabaseSettings;
+
+/// Every repository of the database backend selected by the URL scheme.
+#[derive(Clone)]
+pub struct Repositories {
+    pub user_repo: Arc<dyn UserRepo>,
+    pub migrator: Arc<dyn SchemaMigrator>,
+    pub probe: Arc<dyn DatabaseProbe>,
+    pub audit_repo: Arc<dyn AuditRepo>,
+    pub oauth_repo: Arc<dyn OAuthRepo>,
+    pub identity_repo: Arc<dyn IdentityRepo>,
+}
+
+impl Repositories {
+    pub async fn init(settings: &DatabaseSettings) -> Result<Self> {
+        let scheme = settings.url.split_once(':').map(|(scheme, _)| scheme);
+        match scheme {
+            Some("postgres" | "postgresql") => Ok(Self::from_backend(Arc::new(
+                UserRepoDb::init(settings).await?,
+            ))),
+            #[cfg(feature = "sqlite")]
+            Some("sqlite") => Ok(Self::from_backend(Arc::new(
+                UserRepoSqlite::init(settings).await?,
+            ))),
+            #[cfg(not(feature = "sqlite"))]
+            Some("sqlite") => bail!("SQLite support requires the `sqlite` feature"),
+            _ => bail!(
+                "Unsupported database URL scheme: {:?}",
+                scheme.unwrap_or_default()
+            ),
+        }
+    }
+
+    fn from_backend<T>(backend: Arc<T>) -> Self
+    where
+        T: UserRepo + SchemaMigrator + DatabaseProbe + AuditRepo + OAuthRepo + IdentityRepo,
+    {
+        Self {
+            user_repo: Arc::new(MeteredUserRepo(backend.clone())),
+            migrator: backend.clone(),
+            probe: backend.clone(),
+            audit_repo: Arc::new(MeteredAuditRepo(backend.clone())),
+            oauth_repo: backend.clone(),
+            identity_repo: backend,
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::cmp::min;
+use std::future::Future;
+use std::time::Duration;
+
+use actix_web::rt::time::sleep;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::settings::DatabaseSettings;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PoolStats {
+    /// Open connections, both idle and in use
+    pub size: u32,
+    pub idle: u32,
+    pub max_size: u32,
+}
+
+#[async_trait]
+pub trait DatabaseProbe: Send + Sync + 'static {
+    /// Runs a trivial query to check the database is reachable.
+    async fn ping(&self) -> Result<()>;
+    fn pool_stats(&self) -> PoolStats;
+}
+
+/// Retries `connect` according to the retry settings, doubling the delay between attempts.
+pub async fn connect_with_retry<T, F, Fut>(settings: &DatabaseSettings, mut connect: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let max_backoff = Duration::from_millis(settings.connect_backoff_max_ms);
+    let mut backoff = Duration::from_millis(settings.connect_backoff_ms);
+    let mut attempt = 0;
+    loop {
+        match connect().await {
+            Ok(conn) => return Ok(conn),
+            Err(err) if attempt < settings.connect_retries => {
+                attempt += 1;
+                log::warn!(
+                    "Failed to connect to database (retry {}/{} in {:?}): {:#}",
+                    attempt,
+                    settings.connect_retries,
+                    backoff,
+                    err
+                );
+                sleep(backoff).await;
+                backoff = min(backoff * 2, max_backoff);
+            }
+            Err(err) => return Err(err.context("Failed to connect to database")),
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::models::identity::LinkedIdentity;
+use crate::models::user::User;
+
+/// Accounts of users at upstream OpenID Connect providers.
+#[async_trait]
+pub trait IdentityRepo: Send + Sync + 'static {
+    /// Fails if the identity is already linked, to this user or another one.
+    async fn link_identity(&self, identity: &LinkedIdentity) -> Result<()>;
+    /// Creates the user with the identity linked, or neither of them.
+    async fn create_user_with_identity(&self, user: &User, identity: &LinkedIdentity)
+        -> Result<()>;
+    async fn get_identity(&self, provider: &str, subject: &str) -> Result<Option<LinkedIdentity>>;
+    /// Identities of the user by link date.
+    async fn list_identities(&self, user_id: &Uuid) -> Result<Vec<LinkedIdentity>>;
+    /// Returns `false` if the identity isn't linked to the user.
+    async fn unlink_identity(&self, user_id: &Uuid, provider: &str, subject: &str) -> Result<bool>;
+}
@@ -0,0 +1,98 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::metrics::METRICS;
+use crate::models::audit::AuditEvent;
+use crate::models::audit::AuditHead;
+use crate::models::audit::AuditQuery;
+use crate::models::audit::NewAuditEvent;
+use crate::models::user::User;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::audit::UserChange;
+use crate::repositories::user::UserRepo;
+
+/// Wraps a `UserRepo`, recording the latency of every call and tracing it in a span.
+pub struct MeteredUserRepo(pub Arc<dyn UserRepo>);
+
+/// Wraps an `AuditRepo` likewise, as audited changes to users go through it.
+pub struct MeteredAuditRepo(pub Arc<dyn AuditRepo>);
+
+async fn timed<T>(method: &str, call: impl Future<Output = T>) -> T {
+    let start = Instant::now();
+    let result = call
+        .instrument(tracing::info_span!("db_query", otel.name = method))
+        .await;
+    METRICS
+        .db_query_duration
+        .with_label_values(&[method])
+        .observe(start.elapsed().as_secs_f64());
+    result
+}
+
+#[async_trait]
+impl UserRepo for MeteredUserRepo {
+    async fn create_user(&self, user: &User) -> Result<()> {
+        timed("create_user", self.0.create_user(user)).await
+    }
+
+    async fn get_user_by_id(&self, user_id: &Uuid) -> Result<User> {
+        timed("get_user_by_id", self.0.get_user_by_id(user_id)).await
+    }
+
+    async fn update_user_by_id(&self, user_id: &Uuid, new_user: &User) -> Result<bool> {
+        timed(
+            "update_user_by_id",
+            self.0.update_user_by_id(user_id, new_user),
+        )
+        .await
+    }
+
+    async fn delete_user_by_id(&self, user_id: &Uuid) -> Result<bool> {
+        timed("delete_user_by_id", self.0.delete_user_by_id(user_id)).await
+    }
+
+    async fn contains_user_with_username(&self, username: &str) -> Result<bool> {
+        timed(
+            "contains_user_with_username",
+            self.0.contains_user_with_username(username),
+        )
+        .await
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>> {
+        timed(
+            "get_user_by_username",
+            self.0.get_user_by_username(username),
+        )
+        .await
+    }
+
+    async fn get_password_by_id(&self, user_id: &Uuid) -> Result<String> {
+        timed("get_password_by_id", self.0.get_password_by_id(user_id)).await
+    }
+}
+
+#[async_trait]
+impl AuditRepo for MeteredAuditRepo {
+    async fn append_event(&self, event: &NewAuditEvent) -> Result<AuditEvent> {
+        timed("append_event", self.0.append_event(event)).await
+    }
+
+    async fn change_user(&self, change: UserChange<'_>, event: &NewAuditEvent) -> Result<bool> {
+        timed("change_user", self.0.change_user(change, event)).await
+    }
+
+    async fn get_head(&self) -> Result<Option<AuditHead>> {
+        timed("get_head", self.0.get_head()).await
+    }
+
+    async fn list_events(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>> {
+        timed("list_events", self.0.list_events(query)).await
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::migrate::AppliedMigration;
+use sqlx::migrate::Migrate;
+use sqlx::migrate::Migrator;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    /// Applied from a script which has been edited since, its checksum differs.
+    Modified,
+    Pending,
+    /// Applied to the database but not embedded in this binary, i.e. the schema is newer.
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+#[async_trait]
+pub trait SchemaMigrator: Send + Sync + 'static {
+    async fn migrate_up(&self) -> Result<()>;
+    /// Reverts every applied migration with a version greater than `target`.
+    async fn migrate_down(&self, target: i64) -> Result<()>;
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>>;
+
+    async fn check_schema(&self) -> Result<()> {
+        ensure_schema_current(&self.migration_status().await?)
+    }
+}
+
+pub async fn migration_status<C: Migrate + ?Sized>(
+    migrator: &Migrator,
+    conn: &mut C,
+) -> Result<Vec<MigrationStatus>> {
+    conn.ensure_migrations_table().await?;
+    if let Some(version) = conn.dirty_version().await? {
+        bail!(
+            "Migration {} is partially applied, the database needs manual repair",
+            version
+        );
+    }
+    let applied = conn.list_applied_migrations().await?;
+    Ok(compute_migration_status(migrator, &applied))
+}
+
+pub fn compute_migration_status(
+    migrator: &Migrator,
+    applied: &[AppliedMigration],
+) -> Vec<MigrationStatus> {
+    let mut applied: HashMap<_, _> = applied.iter().map(|m| (m.version, m)).collect();
+    let mut status: Vec<_> = migrator
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| MigrationStatus {
+            version: m.version,
+            description: m.description.to_string(),
+            state: match applied.remove(&m.version) {
+                Some(applied) if applied.checksum != m.checksum => MigrationState::Modified,
+                Some(_) => MigrationState::Applied,
+                None => MigrationState::Pending,
+            },
+        })
+        .collect();
+    status.extend(applied.into_keys().map(|version| MigrationStatus {
+        version,
+        description: String::new(),
+        state: MigrationState::Unknown,
+    }));
+    status.sort_by_key(|m| m.version);
+    status
+}
+
+pub fn ensure_schema_current(status: &[MigrationStatus]) -> Result<()> {
+    let versions_in = |state| {
+        status
+            .iter()
+            .filter(|m| m.state == state)
+            .map(|m| m.version)
+            .collect::<Vec<_>>()
+    };
+    let unknown = versions_in(MigrationState::Unknown);
+    if !unknown.is_empty() {
+        bail!(
+            "Database schema is newer than this binary, unknown migrations: {:?}",
+            unknown
+        );
+    }
+    let modified = versions_in(MigrationState::Modified);
+    if !modified.is_empty() {
+        bail!(
+            "Applied migrations were modified since, checksums differ: {:?}",
+            modified
+        );
+    }
+    let pending = versions_in(MigrationState::Pending);
+    if !pending.is_empty() {
+        bail!(
+            "Database schema is not up to date, pending migrations: {:?}. Run `migrate up` or \
+             start with `serve --migrate`",
+            pending
+        );
+    }
+    Ok(())
+}
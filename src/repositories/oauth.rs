@@ -0,0 +1,74 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::DateTime;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::models::oauth::AuthorizationCode;
+use crate::models::oauth::DeviceAuthorization;
+use crate::models::oauth::DeviceAuthorizationStatus;
+use crate::models::oauth::OAuthClient;
+use crate::models::oauth::UpstreamLogin;
+
+/// Registered OAuth clients, the authorization codes and device authorizations issued to them,
+/// sign-ins at upstream providers in progress, and revoked access tokens.
+#[async_trait]
+pub trait OAuthRepo: Send + Sync + 'static {
+    async fn create_client(&self, client: &OAuthClient) -> Result<()>;
+    /// Replaces every field of the client but its ID and creation date.
+    async fn update_client(&self, client: &OAuthClient) -> Result<bool>;
+    /// Deletes the client with the codes and device authorizations issued to it.
+    async fn delete_client(&self, client_id: &str) -> Result<bool>;
+    async fn get_client(&self, client_id: &str) -> Result<Option<OAuthClient>>;
+    /// Clients by creation date.
+    async fn list_clients(&self) -> Result<Vec<OAuthClient>>;
+    /// Stores the code, dropping the expired ones which were never redeemed.
+    async fn store_authorization_code(&self, code: &AuthorizationCode) -> Result<()>;
+    /// Removes the code and returns it, so concurrent redemptions can't both get it.
+    async fn take_authorization_code(&self, code_hash: &str) -> Result<Option<AuthorizationCode>>;
+    /// Stores the authorization, dropping the expired ones.
+    async fn store_device_authorization(&self, authorization: &DeviceAuthorization) -> Result<()>;
+    async fn get_device_authorization(
+        &self,
+        device_code_hash: &str,
+    ) -> Result<Option<DeviceAuthorization>>;
+    async fn get_device_authorization_by_user_code(
+        &self,
+        user_code: &str,
+    ) -> Result<Option<DeviceAuthorization>>;
+    async fn record_device_poll(
+        &self,
+        device_code_hash: &str,
+        polled_at: DateTime<Utc>,
+        interval_secs: i64,
+    ) -> Result<()>;
+    /// Approves or denies a pending authorization, returns `false` if it's no longer pending.
+    async fn decide_device_authorization(
+        &self,
+        user_code: &str,
+        status: DeviceAuthorizationStatus,
+        user_id: &Uuid,
+    ) -> Result<bool>;
+    /// Removes the authorization and returns it, so concurrent polls can't both get a token.
+    async fn take_device_authorization(
+        &self,
+        device_code_hash: &str,
+    ) -> Result<Option<DeviceAuthorization>>;
+    /// Stores the sign-in at an upstream provider, dropping the expired ones which were never
+    /// completed.
+    async fn store_upstream_login(&self, login: &UpstreamLogin) -> Result<()>;
+    /// Removes the sign-in and returns it, so a callback can't be replayed.
+    async fn take_upstream_login(&self, state_hash: &str) -> Result<Option<UpstreamLogin>>;
+    /// Adds the token ID to the revocation list until the token expires, dropping the entries
+    /// of expired tokens. Revoking a token twice is not an error.
+    async fn revoke_token(&self, jti: &Uuid, expires_at: DateTime<Utc>) -> Result<()>;
+    async fn is_token_revoked(&self, jti: &Uuid) -> Result<bool>;
+    /// Revokes every access token of the user issued before `issued_before`, replacing the
+    /// previous cutoff.
+    async fn revoke_user_tokens(&self, user_id: &Uuid, issued_before: DateTime<Utc>) -> Result<()>;
+    async fn get_user_tokens_revoked_before(&self, user_id: &Uuid)
+        -> Result<Option<DateTime<Utc>>>;
+    /// Remembers a DPoP proof until it expires, dropping the expired ones. Returns `false` if
+    /// it was already used.
+    async fn record_dpop_proof(&self, jti_hash: &str, expires_at: DateTime<Utc>) -> Result<bool>;
+}
@@ -0,0 +1,104 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::PgConnection;
+
+use crate::models::audit::AuditEvent;
+use crate::models::audit::AuditHead;
+use crate::models::audit::AuditQuery;
+use crate::models::audit::NewAuditEvent;
+use crate::models::audit::GENESIS_HASH;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::audit::UserChange;
+use crate::repositories::psql::user::delete_user;
+use crate::repositories::psql::user::insert_user;
+use crate::repositories::psql::user::update_user;
+use crate::repositories::psql::user::UserRepoDb;
+
+#[async_trait]
+impl AuditRepo for UserRepoDb {
+    async fn append_event(&self, event: &NewAuditEvent) -> Result<AuditEvent> {
+        let mut tx = self.pool.begin().await?;
+        let appended = append(&mut tx, event).await?;
+        tx.commit().await?;
+        Ok(appended)
+    }
+
+    async fn change_user(&self, change: UserChange<'_>, event: &NewAuditEvent) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let changed = match change {
+            UserChange::Create(user) => insert_user(&mut tx, user).await.map(|_| true)?,
+            UserChange::Update(user) => update_user(&mut tx, &user.id, user).await?,
+            UserChange::Delete(user_id) => delete_user(&mut tx, user_id).await?,
+        };
+        if changed {
+            append(&mut tx, event).await?;
+            tx.commit().await?;
+        }
+        Ok(changed)
+    }
+
+    async fn get_head(&self) -> Result<Option<AuditHead>> {
+        let head = sqlx::query_as("SELECT seq, hash FROM audit_head")
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(head)
+    }
+
+    async fn list_events(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>> {
+        let events = sqlx::query_as(
+            r#"
+            SELECT * FROM audit_events
+            WHERE ($1::VARCHAR IS NULL OR event_type = $1)
+            AND ($2::UUID IS NULL OR actor_id = $2)
+            AND ($3::UUID IS NULL OR subject_id = $3)
+            AND seq > $4
+            ORDER BY seq
+            LIMIT $5"#,
+        )
+        .bind(query.event_type.map(|t| t.as_str()))
+        .bind(query.actor_id)
+        .bind(query.subject_id)
+        .bind(query.after_seq.unwrap_or(0))
+        .bind(query.limit.map_or(i64::MAX, i64::from))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(events)
+    }
+}
+
+async fn append(conn: &mut PgConnection, event: &NewAuditEvent) -> Result<AuditEvent> {
+    // Appends are serialized so every event links to the one before it, reads aren't blocked
+    sqlx::query("LOCK TABLE audit_events IN EXCLUSIVE MODE")
+        .execute(&mut *conn)
+        .await?;
+    let prev_hash: Option<(String,)> =
+        sqlx::query_as("SELECT hash FROM audit_events ORDER BY seq DESC LIMIT 1")
+            .fetch_optional(&mut *conn)
+            .await?;
+    let prev_hash = prev_hash.map(|(hash,)| hash);
+    let sealed = AuditEvent::seal(event, prev_hash.as_deref().unwrap_or(GENESIS_HASH));
+    let (seq,) = sqlx::query_as(
+        r#"
+        INSERT INTO audit_events
+        (id, occurred_at, event_type, actor_id, subject_id, details, prev_hash, hash)
+        VALUES
+        ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING seq"#,
+    )
+    .bind(sealed.id)
+    .bind(sealed.occurred_at)
+    .bind(sealed.event_type.as_str())
+    .bind(sealed.actor_id)
+    .bind(sealed.subject_id)
+    .bind(&sealed.details)
+    .bind(&sealed.prev_hash)
+    .bind(&sealed.hash)
+    .fetch_one(&mut *conn)
+    .await?;
+    sqlx::query("UPDATE audit_head SET seq = $1, hash = $2")
+        .bind(seq)
+        .bind(&sealed.hash)
+        .execute(&mut *conn)
+        .await?;
+    Ok(AuditEvent { seq, ..sealed })
+}
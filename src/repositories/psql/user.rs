@@ -1,104 +1,193 @@
-use anyhow::anyhow;
+use std::str::FromStr;
+use std::time::Duration;
+
 use anyhow::Result;
 use async_trait::async_trait;
+use sqlx::migrate::Migrator;
+use sqlx::postgres::PgConnectOptions;
 use sqlx::postgres::PgPoolOptions;
+use sqlx::PgExecutor;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::errors::user::UserRepoError;
 use crate::models::user::User;
+use crate::repositories::database::connect_with_retry;
+use crate::repositories::database::DatabaseProbe;
+use crate::repositories::database::PoolStats;
+use crate::repositories::migrations::migration_status;
+use crate::repositories::migrations::MigrationStatus;
+use crate::repositories::migrations::SchemaMigrator;
 use crate::repositories::user::UserRepo;
+use crate::settings::DatabaseSettings;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!("migrations/psql");
 
-pub struct UserRepoDb(PgPool);
+pub struct UserRepoDb {
+    pub(super) pool: PgPool,
+    max_connections: u32,
+}
 
 impl UserRepoDb {
-    pub async fn init(db_url: &str) -> Result<Self> {
-        let pool = PgPoolOptions::new()
-            .max_connections(5)
-            .connect(db_url)
-            .await?;
-        Ok(Self(pool))
+    pub async fn init(settings: &DatabaseSettings) -> Result<Self> {
+        let mut options = PgConnectOptions::from_str(&settings.url)?;
+        if settings.statement_timeout_ms > 0 {
+            options = options.options([("statement_timeout", settings.statement_timeout_ms)]);
+        }
+        // `PoolOptions` is not `Clone`, so build it anew for every attempt
+        let pool_options = || {
+            PgPoolOptions::new()
+                .max_connections(settings.max_connections)
+                .min_connections(settings.min_connections)
+                .acquire_timeout(Duration::from_secs(settings.acquire_timeout_secs))
+                .idle_timeout(
+                    (settings.idle_timeout_secs > 0)
+                        .then(|| Duration::from_secs(settings.idle_timeout_secs)),
+                )
+        };
+        let pool = connect_with_retry(settings, || async {
+            Ok(pool_options().connect_with(options.clone()).await?)
+        })
+        .await?;
+        Ok(Self {
+            pool,
+            max_connections: settings.max_connections,
+        })
     }
+}
 
-    pub async fn create_table(&self) -> Result<()> {
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS users (
-                id UUID PRIMARY KEY,
-                username VARCHAR NOT NULL,
-                password_hash VARCHAR NOT NULL,
-                email VARCHAR,
-                created_at TIMESTAMP WITH TIME ZONE,
-                last_login TIMESTAMP WITH TIME ZONE
-            )"#,
-        )
-        .execute(&self.0)
-        .await?;
+#[async_trait]
+impl DatabaseProbe for UserRepoDb {
+    async fn ping(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
         Ok(())
     }
 
-    #[cfg(test)]
-    pub async fn drop_table(&self) -> Result<()> {
-        sqlx::query("DROP TABLE IF EXISTS users")
-            .execute(&self.0)
-            .await?;
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max_size: self.max_connections,
+        }
+    }
+}
+
+#[async_trait]
+impl SchemaMigrator for UserRepoDb {
+    async fn migrate_up(&self) -> Result<()> {
+        MIGRATOR.run(&self.pool).await?;
         Ok(())
     }
+
+    async fn migrate_down(&self, target: i64) -> Result<()> {
+        MIGRATOR.undo(&self.pool, target).await?;
+        Ok(())
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>> {
+        let mut conn = self.pool.acquire().await?;
+        migration_status(&MIGRATOR, &mut *conn).await
+    }
 }
 
 #[async_trait]
 impl UserRepo for UserRepoDb {
     async fn create_user(&self, user: &User) -> Result<()> {
-        sqlx::query(
-            r#"
-            INSERT INTO users 
-            (id, username, password_hash, email, created_at, last_login) 
-            VALUES 
-            ($1, $2, $3, $4, $5, $6)"#,
-        )
-        .bind(&user.id)
-        .bind(&user.username)
-        .bind(&user.password_hash)
-        .bind(&user.email)
-        .bind(&user.created_at)
-        .bind(&user.last_login)
-        .execute(&self.0)
-        .await?;
-        Ok(())
+        insert_user(&self.pool, user).await
     }
 
     async fn get_user_by_id(&self, user_id: &Uuid) -> Result<User> {
         let user = sqlx::query_as("SELECT * FROM users WHERE id = $1")
             .bind(user_id)
-            .fetch_one(&self.0)
+            .fetch_one(&self.pool)
             .await?;
         Ok(user)
     }
 
-    async fn update_user_by_id(&self, _user_id: &Uuid, _new_user: &User) -> Result<()> {
-        Err(anyhow!("todo!"))
+    async fn update_user_by_id(&self, user_id: &Uuid, new_user: &User) -> Result<bool> {
+        update_user(&self.pool, user_id, new_user).await
     }
 
-    async fn delete_user_by_id(&self, user_id: &Uuid) -> Result<()> {
-        sqlx::query("DELETE FROM users WHERE id = $1")
-            .bind(user_id)
-            .execute(&self.0)
-            .await?;
-        Ok(())
+    async fn delete_user_by_id(&self, user_id: &Uuid) -> Result<bool> {
+        delete_user(&self.pool, user_id).await
     }
 
     async fn contains_user_with_username(&self, username: &str) -> Result<bool> {
-        let user = sqlx::query("SELECT (id) FROM users WHERE username = $1")
+        let user = sqlx::query("SELECT (id) FROM users WHERE LOWER(username) = LOWER($1)")
             .bind(username)
-            .fetch_optional(&self.0)
+            .fetch_optional(&self.pool)
             .await?;
         Ok(user.is_some())
     }
 
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>> {
+        let user = sqlx::query_as("SELECT * FROM users WHERE LOWER(username) = LOWER($1)")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(user)
+    }
+
     async fn get_password_by_id(&self, user_id: &Uuid) -> Result<String> {
         let (password_hash,) = sqlx::query_as("SELECT (password_hash) FROM users WHERE id = $1")
             .bind(user_id)
-            .fetch_one(&self.0)
+            .fetch_one(&self.pool)
             .await?;
         Ok(password_hash)
     }
 }
+
+pub(crate) async fn insert_user<'e>(executor: impl PgExecutor<'e>, user: &User) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO users 
+        (id, username, password_hash, email, created_at, last_login) 
+        VALUES 
+        ($1, $2, $3, $4, $5, $6)"#,
+    )
+    .bind(user.id)
+    .bind(&user.username)
+    .bind(&user.password_hash)
+    .bind(&user.email)
+    .bind(user.created_at)
+    .bind(user.last_login)
+    .execute(executor)
+    .await
+    .map_err(|err| match err {
+        sqlx::Error::Database(db_err) if db_err.constraint() == Some("users_username_key") => {
+            UserRepoError::UsernameConflict(user.username.clone()).into()
+        }
+        err => anyhow::Error::from(err),
+    })?;
+    Ok(())
+}
+
+pub(crate) async fn update_user<'e>(
+    executor: impl PgExecutor<'e>,
+    user_id: &Uuid,
+    new_user: &User,
+) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE users SET
+        username = $2, password_hash = $3, email = $4, created_at = $5, last_login = $6
+        WHERE id = $1"#,
+    )
+    .bind(user_id)
+    .bind(&new_user.username)
+    .bind(&new_user.password_hash)
+    .bind(&new_user.email)
+    .bind(new_user.created_at)
+    .bind(new_user.last_login)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub(crate) async fn delete_user<'e>(executor: impl PgExecutor<'e>, user_id: &Uuid) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(user_id)
+        .execute(executor)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
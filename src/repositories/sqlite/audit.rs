@@ -0,0 +1,119 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::SqliteConnection;
+
+use crate::models::audit::AuditEvent;
+use crate::models::audit::AuditHead;
+use crate::models::audit::AuditQuery;
+use crate::models::audit::NewAuditEvent;
+use crate::models::audit::GENESIS_HASH;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::audit::UserChange;
+use crate::repositories::sqlite::user::delete_user;
+use crate::repositories::sqlite::user::insert_user;
+use crate::repositories::sqlite::user::update_user;
+use crate::repositories::sqlite::user::UserRepoSqlite;
+
+#[async_trait]
+impl AuditRepo for UserRepoSqlite {
+    async fn append_event(&self, event: &NewAuditEvent) -> Result<AuditEvent> {
+        let mut conn = self.pool.acquire().await?;
+        // Takes the write lock upfront, so every event links to the one before it. sqlx only
+        // starts deferred transactions, which fail instead of waiting when upgraded to write.
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut conn).await?;
+        let appended = append_locked(&mut conn, event).await;
+        end_transaction(&mut conn, appended.is_ok()).await?;
+        appended
+    }
+
+    async fn change_user(&self, change: UserChange<'_>, event: &NewAuditEvent) -> Result<bool> {
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut conn).await?;
+        let changed = change_locked(&mut conn, change, event).await;
+        end_transaction(&mut conn, matches!(changed, Ok(true))).await?;
+        changed
+    }
+
+    async fn get_head(&self) -> Result<Option<AuditHead>> {
+        let head = sqlx::query_as("SELECT seq, hash FROM audit_head")
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(head)
+    }
+
+    async fn list_events(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>> {
+        let events = sqlx::query_as(
+            r#"
+            SELECT * FROM audit_events
+            WHERE ($1 IS NULL OR event_type = $1)
+            AND ($2 IS NULL OR actor_id = $2)
+            AND ($3 IS NULL OR subject_id = $3)
+            AND seq > $4
+            ORDER BY seq
+            LIMIT $5"#,
+        )
+        .bind(query.event_type.map(|t| t.as_str()))
+        .bind(query.actor_id)
+        .bind(query.subject_id)
+        .bind(query.after_seq.unwrap_or(0))
+        .bind(query.limit.map_or(i64::MAX, i64::from))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(events)
+    }
+}
+
+async fn change_locked(
+    conn: &mut SqliteConnection,
+    change: UserChange<'_>,
+    event: &NewAuditEvent,
+) -> Result<bool> {
+    let changed = match change {
+        UserChange::Create(user) => insert_user(&mut *conn, user).await.map(|_| true)?,
+        UserChange::Update(user) => update_user(&mut *conn, &user.id, user).await?,
+        UserChange::Delete(user_id) => delete_user(&mut *conn, user_id).await?,
+    };
+    if changed {
+        append_locked(conn, event).await?;
+    }
+    Ok(changed)
+}
+
+async fn end_transaction(conn: &mut SqliteConnection, commit: bool) -> Result<()> {
+    let end = if commit { "COMMIT" } else { "ROLLBACK" };
+    sqlx::query(end).execute(conn).await?;
+    Ok(())
+}
+
+async fn append_locked(conn: &mut SqliteConnection, event: &NewAuditEvent) -> Result<AuditEvent> {
+    let prev_hash: Option<(String,)> =
+        sqlx::query_as("SELECT hash FROM audit_events ORDER BY seq DESC LIMIT 1")
+            .fetch_optional(&mut *conn)
+            .await?;
+    let prev_hash = prev_hash.map(|(hash,)| hash);
+    let sealed = AuditEvent::seal(event, prev_hash.as_deref().unwrap_or(GENESIS_HASH));
+    let (seq,) = sqlx::query_as(
+        r#"
+        INSERT INTO audit_events
+        (id, occurred_at, event_type, actor_id, subject_id, details, prev_hash, hash)
+        VALUES
+        ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING seq"#,
+    )
+    .bind(sealed.id)
+    .bind(sealed.occurred_at)
+    .bind(sealed.event_type.as_str())
+    .bind(sealed.actor_id)
+    .bind(sealed.subject_id)
+    .bind(&sealed.details)
+    .bind(&sealed.prev_hash)
+    .bind(&sealed.hash)
+    .fetch_one(&mut *conn)
+    .await?;
+    sqlx::query("UPDATE audit_head SET seq = $1, hash = $2")
+        .bind(seq)
+        .bind(&sealed.hash)
+        .execute(&mut *conn)
+        .await?;
+    Ok(AuditEvent { seq, ..sealed })
+}
@@ -0,0 +1,92 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::SqliteExecutor;
+use uuid::Uuid;
+
+use crate::errors::user::UserRepoError;
+use crate::models::identity::LinkedIdentity;
+use crate::models::user::User;
+use crate::repositories::identity::IdentityRepo;
+use crate::repositories::sqlite::user::insert_user;
+use crate::repositories::sqlite::user::UserRepoSqlite;
+
+#[async_trait]
+impl IdentityRepo for UserRepoSqlite {
+    async fn link_identity(&self, identity: &LinkedIdentity) -> Result<()> {
+        insert_identity(&self.pool, identity).await
+    }
+
+    async fn create_user_with_identity(
+        &self,
+        user: &User,
+        identity: &LinkedIdentity,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        insert_user(&mut tx, user).await?;
+        insert_identity(&mut tx, identity).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_identity(&self, provider: &str, subject: &str) -> Result<Option<LinkedIdentity>> {
+        let identity =
+            sqlx::query_as("SELECT * FROM linked_identities WHERE provider = $1 AND subject = $2")
+                .bind(provider)
+                .bind(subject)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(identity)
+    }
+
+    async fn list_identities(&self, user_id: &Uuid) -> Result<Vec<LinkedIdentity>> {
+        let identities = sqlx::query_as(
+            "SELECT * FROM linked_identities WHERE user_id = $1 ORDER BY created_at, provider, subject",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(identities)
+    }
+
+    async fn unlink_identity(&self, user_id: &Uuid, provider: &str, subject: &str) -> Result<bool> {
+        let result = sqlx::query(
+            "DELETE FROM linked_identities WHERE user_id = $1 AND provider = $2 AND subject = $3",
+        )
+        .bind(user_id)
+        .bind(provider)
+        .bind(subject)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+async fn insert_identity<'e>(
+    executor: impl SqliteExecutor<'e>,
+    identity: &LinkedIdentity,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO linked_identities
+        (provider, subject, user_id, email, created_at)
+        VALUES
+        ($1, $2, $3, $4, $5)"#,
+    )
+    .bind(&identity.provider)
+    .bind(&identity.subject)
+    .bind(identity.user_id)
+    .bind(&identity.email)
+    .bind(identity.created_at)
+    .execute(executor)
+    .await
+    .map_err(|err| match err {
+        sqlx::Error::Database(db_err)
+            if db_err.message().contains("linked_identities.provider") =>
+        {
+            UserRepoError::IdentityConflict(format!("{}/{}", identity.provider, identity.subject))
+                .into()
+        }
+        err => anyhow::Error::from(err),
+    })?;
+    Ok(())
+}
@@ -0,0 +1,320 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::DateTime;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::models::oauth::AuthorizationCode;
+use crate::models::oauth::DeviceAuthorization;
+use crate::models::oauth::DeviceAuthorizationStatus;
+use crate::models::oauth::OAuthClient;
+use crate::models::oauth::UpstreamLogin;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::sqlite::user::UserRepoSqlite;
+
+#[async_trait]
+impl OAuthRepo for UserRepoSqlite {
+    async fn create_client(&self, client: &OAuthClient) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO oauth_clients
+            (id, name, redirect_uris, created_at, secret_hash, allowed_scopes, grant_types,
+             previous_secret_hash, previous_secret_expires_at)
+            VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+        )
+        .bind(&client.id)
+        .bind(&client.name)
+        .bind(client.redirect_uris.to_json())
+        .bind(client.created_at)
+        .bind(&client.secret_hash)
+        .bind(&client.allowed_scopes)
+        .bind(&client.grant_types)
+        .bind(&client.previous_secret_hash)
+        .bind(client.previous_secret_expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn update_client(&self, client: &OAuthClient) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE oauth_clients SET
+            name = $2, redirect_uris = $3, secret_hash = $4, allowed_scopes = $5,
+            grant_types = $6, previous_secret_hash = $7, previous_secret_expires_at = $8
+            WHERE id = $1"#,
+        )
+        .bind(&client.id)
+        .bind(&client.name)
+        .bind(client.redirect_uris.to_json())
+        .bind(&client.secret_hash)
+        .bind(&client.allowed_scopes)
+        .bind(&client.grant_types)
+        .bind(&client.previous_secret_hash)
+        .bind(client.previous_secret_expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_client(&self, client_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM oauth_clients WHERE id = $1")
+            .bind(client_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_client(&self, client_id: &str) -> Result<Option<OAuthClient>> {
+        let client = sqlx::query_as("SELECT * FROM oauth_clients WHERE id = $1")
+            .bind(client_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(client)
+    }
+
+    async fn list_clients(&self) -> Result<Vec<OAuthClient>> {
+        let clients = sqlx::query_as("SELECT * FROM oauth_clients ORDER BY created_at, id")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(clients)
+    }
+
+    async fn store_authorization_code(&self, code: &AuthorizationCode) -> Result<()> {
+        sqlx::query("DELETE FROM oauth_authorization_codes WHERE expires_at < $1")
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO oauth_authorization_codes
+            (code_hash, client_id, user_id, redirect_uri, scope, code_challenge, expires_at,
+             nonce, auth_time)
+            VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+        )
+        .bind(&code.code_hash)
+        .bind(&code.client_id)
+        .bind(code.user_id)
+        .bind(&code.redirect_uri)
+        .bind(&code.scope)
+        .bind(&code.code_challenge)
+        .bind(code.expires_at)
+        .bind(&code.nonce)
+        .bind(code.auth_time)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn take_authorization_code(&self, code_hash: &str) -> Result<Option<AuthorizationCode>> {
+        let code = sqlx::query_as(
+            "DELETE FROM oauth_authorization_codes WHERE code_hash = $1 RETURNING *",
+        )
+        .bind(code_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(code)
+    }
+
+    async fn store_device_authorization(&self, authorization: &DeviceAuthorization) -> Result<()> {
+        sqlx::query("DELETE FROM oauth_device_authorizations WHERE expires_at < $1")
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO oauth_device_authorizations
+            (device_code_hash, user_code, client_id, scope, expires_at, interval_secs,
+             last_polled_at, status, user_id)
+            VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+        )
+        .bind(&authorization.device_code_hash)
+        .bind(&authorization.user_code)
+        .bind(&authorization.client_id)
+        .bind(&authorization.scope)
+        .bind(authorization.expires_at)
+        .bind(authorization.interval_secs)
+        .bind(authorization.last_polled_at)
+        .bind(authorization.status.as_str())
+        .bind(authorization.user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_device_authorization(
+        &self,
+        device_code_hash: &str,
+    ) -> Result<Option<DeviceAuthorization>> {
+        let authorization =
+            sqlx::query_as("SELECT * FROM oauth_device_authorizations WHERE device_code_hash = $1")
+                .bind(device_code_hash)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(authorization)
+    }
+
+    async fn get_device_authorization_by_user_code(
+        &self,
+        user_code: &str,
+    ) -> Result<Option<DeviceAuthorization>> {
+        let authorization =
+            sqlx::query_as("SELECT * FROM oauth_device_authorizations WHERE user_code = $1")
+                .bind(user_code)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(authorization)
+    }
+
+    async fn record_device_poll(
+        &self,
+        device_code_hash: &str,
+        polled_at: DateTime<Utc>,
+        interval_secs: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE oauth_device_authorizations
+            SET last_polled_at = $1, interval_secs = $2
+            WHERE device_code_hash = $3"#,
+        )
+        .bind(polled_at)
+        .bind(interval_secs)
+        .bind(device_code_hash)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn decide_device_authorization(
+        &self,
+        user_code: &str,
+        status: DeviceAuthorizationStatus,
+        user_id: &Uuid,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE oauth_device_authorizations
+            SET status = $1, user_id = $2
+            WHERE user_code = $3 AND status = $4"#,
+        )
+        .bind(status.as_str())
+        .bind(user_id)
+        .bind(user_code)
+        .bind(DeviceAuthorizationStatus::Pending.as_str())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn take_device_authorization(
+        &self,
+        device_code_hash: &str,
+    ) -> Result<Option<DeviceAuthorization>> {
+        let authorization = sqlx::query_as(
+            "DELETE FROM oauth_device_authorizations WHERE device_code_hash = $1 RETURNING *",
+        )
+        .bind(device_code_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(authorization)
+    }
+
+    async fn store_upstream_login(&self, login: &UpstreamLogin) -> Result<()> {
+        sqlx::query("DELETE FROM upstream_logins WHERE expires_at < $1")
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO upstream_logins
+            (state_hash, provider, nonce, code_verifier, authorize_params, expires_at)
+            VALUES
+            ($1, $2, $3, $4, $5, $6)"#,
+        )
+        .bind(&login.state_hash)
+        .bind(&login.provider)
+        .bind(&login.nonce)
+        .bind(&login.code_verifier)
+        .bind(&login.authorize_params)
+        .bind(login.expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn take_upstream_login(&self, state_hash: &str) -> Result<Option<UpstreamLogin>> {
+        let login = sqlx::query_as("DELETE FROM upstream_logins WHERE state_hash = $1 RETURNING *")
+            .bind(state_hash)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(login)
+    }
+
+    async fn revoke_token(&self, jti: &Uuid, expires_at: DateTime<Utc>) -> Result<()> {
+        sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < $1")
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        sqlx::query(
+            "INSERT INTO revoked_tokens (jti, expires_at) VALUES ($1, $2) ON CONFLICT (jti) DO NOTHING",
+        )
+        .bind(jti)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn is_token_revoked(&self, jti: &Uuid) -> Result<bool> {
+        let revoked: Option<(Uuid,)> =
+            sqlx::query_as("SELECT jti FROM revoked_tokens WHERE jti = $1")
+                .bind(jti)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(revoked.is_some())
+    }
+
+    async fn revoke_user_tokens(&self, user_id: &Uuid, issued_before: DateTime<Utc>) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO revoked_user_tokens (user_id, issued_before) VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE SET issued_before = excluded.issued_before"#,
+        )
+        .bind(user_id)
+        .bind(issued_before)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_user_tokens_revoked_before(
+        &self,
+        user_id: &Uuid,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let revoked: Option<(DateTime<Utc>,)> =
+            sqlx::query_as("SELECT issued_before FROM revoked_user_tokens WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(revoked.map(|(issued_before,)| issued_before))
+    }
+
+    async fn record_dpop_proof(&self, jti_hash: &str, expires_at: DateTime<Utc>) -> Result<bool> {
+        sqlx::query("DELETE FROM dpop_proofs WHERE expires_at < $1")
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        let result = sqlx::query(
+            "INSERT INTO dpop_proofs (jti_hash, expires_at) VALUES ($1, $2) ON CONFLICT (jti_hash) DO NOTHING",
+        )
+        .bind(jti_hash)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+}
@@ -0,0 +1,206 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::SqliteExecutor;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::errors::user::UserRepoError;
+use crate::models::user::User;
+use crate::repositories::database::connect_with_retry;
+use crate::repositories::database::DatabaseProbe;
+use crate::repositories::database::PoolStats;
+use crate::repositories::migrations::migration_status;
+use crate::repositories::migrations::MigrationStatus;
+use crate::repositories::migrations::SchemaMigrator;
+use crate::repositories::user::UserRepo;
+use crate::settings::DatabaseSettings;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+pub struct UserRepoSqlite {
+    pub(super) pool: SqlitePool,
+    max_connections: u32,
+}
+
+impl UserRepoSqlite {
+    pub async fn init(settings: &DatabaseSettings) -> Result<Self> {
+        let db_url = &settings.url;
+        let options = SqliteConnectOptions::from_str(db_url)?.create_if_missing(true);
+        let idle_timeout = (settings.idle_timeout_secs > 0)
+            .then(|| Duration::from_secs(settings.idle_timeout_secs));
+        // Every connection to an in-memory database gets its own database, so keep a single
+        // connection alive for the lifetime of the pool
+        let in_memory = db_url.contains(":memory:") || db_url.contains("mode=memory");
+        let max_connections = if in_memory {
+            1
+        } else {
+            settings.max_connections
+        };
+        // `PoolOptions` is not `Clone`, so build it anew for every attempt
+        let pool_options = || {
+            let pool_options = SqlitePoolOptions::new()
+                .max_connections(max_connections)
+                .acquire_timeout(Duration::from_secs(settings.acquire_timeout_secs));
+            if in_memory {
+                pool_options.idle_timeout(None).max_lifetime(None)
+            } else {
+                pool_options
+                    .min_connections(settings.min_connections)
+                    .idle_timeout(idle_timeout)
+            }
+        };
+        let pool = connect_with_retry(settings, || async {
+            Ok(pool_options().connect_with(options.clone()).await?)
+        })
+        .await?;
+        Ok(Self {
+            pool,
+            max_connections,
+        })
+    }
+}
+
+#[async_trait]
+impl DatabaseProbe for UserRepoSqlite {
+    async fn ping(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max_size: self.max_connections,
+        }
+    }
+}
+
+#[async_trait]
+impl SchemaMigrator for UserRepoSqlite {
+    async fn migrate_up(&self) -> Result<()> {
+        MIGRATOR.run(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn migrate_down(&self, target: i64) -> Result<()> {
+        MIGRATOR.undo(&self.pool, target).await?;
+        Ok(())
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>> {
+        let mut conn = self.pool.acquire().await?;
+        migration_status(&MIGRATOR, &mut *conn).await
+    }
+}
+
+#[async_trait]
+impl UserRepo for UserRepoSqlite {
+    async fn create_user(&self, user: &User) -> Result<()> {
+        insert_user(&self.pool, user).await
+    }
+
+    async fn get_user_by_id(&self, user_id: &Uuid) -> Result<User> {
+        let user = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(user)
+    }
+
+    async fn update_user_by_id(&self, user_id: &Uuid, new_user: &User) -> Result<bool> {
+        update_user(&self.pool, user_id, new_user).await
+    }
+
+    async fn delete_user_by_id(&self, user_id: &Uuid) -> Result<bool> {
+        delete_user(&self.pool, user_id).await
+    }
+
+    async fn contains_user_with_username(&self, username: &str) -> Result<bool> {
+        let user = sqlx::query("SELECT id FROM users WHERE LOWER(username) = LOWER($1)")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(user.is_some())
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>> {
+        let user = sqlx::query_as("SELECT * FROM users WHERE LOWER(username) = LOWER($1)")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(user)
+    }
+
+    async fn get_password_by_id(&self, user_id: &Uuid) -> Result<String> {
+        let (password_hash,) = sqlx::query_as("SELECT password_hash FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(password_hash)
+    }
+}
+
+pub(crate) async fn insert_user<'e>(executor: impl SqliteExecutor<'e>, user: &User) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO users
+        (id, username, password_hash, email, created_at, last_login)
+        VALUES
+        ($1, $2, $3, $4, $5, $6)"#,
+    )
+    .bind(user.id)
+    .bind(&user.username)
+    .bind(&user.password_hash)
+    .bind(&user.email)
+    .bind(user.created_at)
+    .bind(user.last_login)
+    .execute(executor)
+    .await
+    .map_err(|err| match err {
+        sqlx::Error::Database(db_err) if db_err.message().contains("users_username_key") => {
+            UserRepoError::UsernameConflict(user.username.clone()).into()
+        }
+        err => anyhow::Error::from(err),
+    })?;
+    Ok(())
+}
+
+pub(crate) async fn update_user<'e>(
+    executor: impl SqliteExecutor<'e>,
+    user_id: &Uuid,
+    new_user: &User,
+) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE users SET
+        username = $2, password_hash = $3, email = $4, created_at = $5, last_login = $6
+        WHERE id = $1"#,
+    )
+    .bind(user_id)
+    .bind(&new_user.username)
+    .bind(&new_user.password_hash)
+    .bind(&new_user.email)
+    .bind(new_user.created_at)
+    .bind(new_user.last_login)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub(crate) async fn delete_user<'e>(
+    executor: impl SqliteExecutor<'e>,
+    user_id: &Uuid,
+) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(user_id)
+        .execute(executor)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
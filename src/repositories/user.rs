@@ -8,8 +8,12 @@ use crate::models::user::User;
 pub trait UserRepo: Send + Sync + 'static {
     async fn create_user(&self, user: &User) -> Result<()>;
     async fn get_user_by_id(&self, user_id: &Uuid) -> Result<User>;
-    async fn update_user_by_id(&self, user_id: &Uuid, new_user: &User) -> Result<()>;
-    async fn delete_user_by_id(&self, user_id: &Uuid) -> Result<()>;
+    /// Returns `false` if there is no user with the given ID.
+    async fn update_user_by_id(&self, user_id: &Uuid, new_user: &User) -> Result<bool>;
+    /// Returns `false` if there is no user with the given ID.
+    async fn delete_user_by_id(&self, user_id: &Uuid) -> Result<bool>;
     async fn contains_user_with_username(&self, username: &str) -> Result<bool>;
+    /// Usernames are matched case-insensitively.
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>>;
     async fn get_password_by_id(&self, user_id: &Uuid) -> Result<String>;
 }
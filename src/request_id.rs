@@ -0,0 +1,79 @@
+use std::fmt;
+use std::future::Future;
+
+use actix_web::dev::Service;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::HeaderName;
+use actix_web::http::header::HeaderValue;
+use actix_web::HttpMessage;
+use uuid::Uuid;
+
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest `X-Request-Id` accepted from clients, longer ones are replaced by a generated ID.
+const MAX_LEN: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Reuses the ID given by the client if it is reasonably short and printable, so it can be
+    /// correlated with the logs of upstream services.
+    fn from_request(req: &ServiceRequest) -> Self {
+        req.headers()
+            .get(&REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| {
+                !id.is_empty()
+                    && id.len() <= MAX_LEN
+                    && id
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+            })
+            .map(|id| Self(id.to_string()))
+            .unwrap_or_else(|| Self(Uuid::new_v4().to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// ID of the request being handled by the current task, if any.
+pub fn current() -> Option<RequestId> {
+    CURRENT.try_with(|id| id.clone()).ok()
+}
+
+/// Middleware for `App::wrap_fn` assigning every request an ID. The ID is stored in the request
+/// extensions, available through `current()` while the request is handled, and returned in the
+/// `X-Request-Id` response header.
+pub fn assign_request_id<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let request_id = RequestId::from_request(&req);
+    req.extensions_mut().insert(request_id.clone());
+    let header = HeaderValue::from_str(request_id.as_str()).ok();
+    let fut = CURRENT.sync_scope(request_id.clone(), || srv.call(req));
+    CURRENT.scope(request_id, async move {
+        let mut res = fut.await?;
+        if let Some(header) = header {
+            res.headers_mut().insert(REQUEST_ID_HEADER.clone(), header);
+        }
+        Ok(res)
+    })
+}
@@ -0,0 +1,101 @@
+use actix_web::web::Data;
+use actix_web::web::Json;
+use actix_web::web::Query;
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::auth::AdminAuth;
+use crate::errors::user::log_err;
+use crate::errors::user::UserServiceError;
+use crate::errors::user::UserServiceResult;
+use crate::models::audit::AuditChainReport;
+use crate::models::audit::AuditEvent;
+use crate::models::audit::AuditEventRespDto;
+use crate::models::audit::AuditQuery;
+use crate::models::audit::ChainVerifier;
+use crate::models::audit::NewAuditEvent;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::audit::UserChange;
+use crate::request_id;
+
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 1000;
+
+/// Appends the event, tagged with the current request ID. Callers fail the audited operation if
+/// it can't be recorded.
+pub async fn record(audit_repo: &dyn AuditRepo, event: NewAuditEvent) -> Result<AuditEvent> {
+    audit_repo.append_event(&tagged(event)).await
+}
+
+/// Changes the user along with recording the event, so neither happens without the other.
+pub async fn change_user(
+    audit_repo: &dyn AuditRepo,
+    change: UserChange<'_>,
+    event: NewAuditEvent,
+) -> Result<bool> {
+    audit_repo.change_user(change, &tagged(event)).await
+}
+
+fn tagged(mut event: NewAuditEvent) -> NewAuditEvent {
+    if let (Some(request_id), Value::Object(details)) = (request_id::current(), &mut event.details)
+    {
+        details.insert("request_id".into(), request_id.to_string().into());
+    }
+    event
+}
+
+/// Walks the whole chain from the first event up to its head. The head is read first, events
+/// appended meanwhile are left for the next verification.
+pub async fn verify_chain(audit_repo: &dyn AuditRepo) -> Result<AuditChainReport> {
+    let head = audit_repo.get_head().await?;
+    let last_seq = head.as_ref().map_or(i64::MAX, |head| head.seq);
+    let mut verifier = ChainVerifier::default();
+    let mut query = AuditQuery {
+        limit: Some(MAX_LIMIT),
+        ..Default::default()
+    };
+    loop {
+        let events = audit_repo.list_events(&query).await?;
+        for event in events.iter().take_while(|event| event.seq <= last_seq) {
+            if !verifier.check(event) {
+                return Ok(verifier.report());
+            }
+        }
+        match events.last() {
+            Some(last) if events.len() == MAX_LIMIT as usize && last.seq < last_seq => {
+                query.after_seq = Some(last.seq)
+            }
+            _ => break,
+        }
+    }
+    verifier.check_head(head.as_ref());
+    Ok(verifier.report())
+}
+
+pub async fn get_audit_events(
+    _admin: AdminAuth,
+    audit_repo: Data<dyn AuditRepo>,
+    query: Query<AuditQuery>,
+) -> UserServiceResult<Vec<AuditEventRespDto>> {
+    let query = AuditQuery {
+        limit: Some(query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT)),
+        ..query.into_inner()
+    };
+    let events = audit_repo
+        .list_events(&query)
+        .await
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    Ok(Json(events.into_iter().map(Into::into).collect()))
+}
+
+pub async fn get_audit_chain_verification(
+    _admin: AdminAuth,
+    audit_repo: Data<dyn AuditRepo>,
+) -> UserServiceResult<AuditChainReport> {
+    let report = verify_chain(audit_repo.as_ref())
+        .await
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    Ok(Json(report))
+}
@@ -0,0 +1,298 @@
+use actix_web::http::header::CacheControl;
+use actix_web::http::header::CacheDirective;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::http::header::PRAGMA;
+use actix_web::web::Data;
+use actix_web::web::Json;
+use actix_web::web::Path;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use chrono::Duration;
+use chrono::Utc;
+use ring::constant_time::verify_slices_are_equal;
+
+use crate::auth::AdminAuth;
+use crate::crypto::random_token;
+use crate::crypto::PasswordHasher;
+use crate::errors::oauth::OAuthError;
+use crate::errors::user::log_err;
+use crate::errors::user::UserServiceError;
+use crate::errors::user::UserServiceResult;
+use crate::models::oauth::ClientRegistrationDto;
+use crate::models::oauth::ClientUpdateDto;
+use crate::models::oauth::DynamicRegistrationReqDto;
+use crate::models::oauth::DynamicRegistrationRespDto;
+use crate::models::oauth::OAuthClient;
+use crate::models::oauth::RedirectUris;
+use crate::models::oauth::RegisteredClientDto;
+use crate::models::oauth::SecretRotationReqDto;
+use crate::repositories::oauth::OAuthRepo;
+use crate::services::exchange::DELEGATE_PERMISSION;
+use crate::services::exchange::IMPERSONATE_PERMISSION;
+use crate::services::oauth::checked_client_settings;
+use crate::services::oauth::register_client;
+use crate::settings::OAuthSettings;
+
+/// Secrets rotated out are accepted for 30 days at most
+pub const MAX_SECRET_OVERLAP_SECS: u64 = 30 * 86400;
+/// Token endpoint authentication methods of dynamically registered clients, the default first.
+/// The method isn't stored, so only those the token endpoint takes from every client are offered.
+const AUTH_METHODS: &[&str] = &["client_secret_basic", "none"];
+
+pub async fn get_clients(
+    _admin: AdminAuth,
+    oauth_repo: Data<dyn OAuthRepo>,
+) -> UserServiceResult<Vec<OAuthClient>> {
+    let clients = oauth_repo
+        .list_clients()
+        .await
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    Ok(Json(clients))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn post_client(
+    _admin: AdminAuth,
+    oauth_repo: Data<dyn OAuthRepo>,
+    passwd_hasher: Data<PasswordHasher>,
+    registration: Json<ClientRegistrationDto>,
+) -> UserServiceResult<RegisteredClientDto> {
+    let client = register_client(
+        oauth_repo.as_ref(),
+        &passwd_hasher,
+        registration.into_inner(),
+    )
+    .await?;
+    tracing::info!(client_id = %client.client.id, "OAuth client registered");
+    Ok(Json(client))
+}
+
+pub async fn get_client(
+    _admin: AdminAuth,
+    oauth_repo: Data<dyn OAuthRepo>,
+    client_id: Path<String>,
+) -> UserServiceResult<OAuthClient> {
+    Ok(Json(find_client(oauth_repo.as_ref(), &client_id).await?))
+}
+
+/// Replaces the settings of the client, checked as on registration.
+#[tracing::instrument(skip(_admin, oauth_repo, update))]
+pub async fn put_client(
+    _admin: AdminAuth,
+    oauth_repo: Data<dyn OAuthRepo>,
+    client_id: Path<String>,
+    update: Json<ClientUpdateDto>,
+) -> UserServiceResult<OAuthClient> {
+    let client = find_client(oauth_repo.as_ref(), &client_id).await?;
+    let update = update.into_inner();
+    let grant_types = checked_client_settings(
+        &update.redirect_uris,
+        &update.allowed_scopes,
+        &update.grant_types,
+        client.is_confidential(),
+    )?;
+    let client = OAuthClient {
+        name: update.name,
+        redirect_uris: RedirectUris(update.redirect_uris),
+        allowed_scopes: update.allowed_scopes.join(" "),
+        grant_types,
+        ..client
+    };
+    save_client(oauth_repo.as_ref(), &client).await?;
+    tracing::info!("OAuth client updated");
+    Ok(Json(client))
+}
+
+/// Deletes the client, with its pending authorization codes and device authorizations. Tokens
+/// already issued to it stay valid until they expire or are revoked.
+#[tracing::instrument(skip(_admin, oauth_repo))]
+pub async fn delete_client(
+    _admin: AdminAuth,
+    oauth_repo: Data<dyn OAuthRepo>,
+    client_id: Path<String>,
+) -> UserServiceResult<()> {
+    let deleted = oauth_repo
+        .delete_client(&client_id)
+        .await
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    if !deleted {
+        return Err(UserServiceError::NoClientForId(client_id.into_inner()));
+    }
+    tracing::info!("OAuth client deleted");
+    Ok(Json(()))
+}
+
+/// Issues a new secret to a confidential client. The previous one is still accepted for the
+/// overlap period, for the client to be redeployed with the new one.
+#[tracing::instrument(skip(_admin, oauth_repo, passwd_hasher, oauth_settings, rotation))]
+pub async fn post_client_secret(
+    _admin: AdminAuth,
+    oauth_repo: Data<dyn OAuthRepo>,
+    passwd_hasher: Data<PasswordHasher>,
+    oauth_settings: Data<OAuthSettings>,
+    client_id: Path<String>,
+    rotation: Json<SecretRotationReqDto>,
+) -> UserServiceResult<RegisteredClientDto> {
+    let client = find_client(oauth_repo.as_ref(), &client_id).await?;
+    let overlap_secs = rotation
+        .overlap_secs
+        .unwrap_or(oauth_settings.secret_rotation_overlap_secs);
+    if overlap_secs > MAX_SECRET_OVERLAP_SECS {
+        return Err(UserServiceError::InvalidClientFields(format!(
+            "The overlap can't exceed {} seconds",
+            MAX_SECRET_OVERLAP_SECS
+        )));
+    }
+    let previous_secret_hash = match client.secret_hash.clone() {
+        Some(secret_hash) => secret_hash,
+        None => {
+            return Err(UserServiceError::InvalidClientFields(
+                "Public clients have no secret".into(),
+            ))
+        }
+    };
+    let client_secret = random_token();
+    let secret_hash = passwd_hasher
+        .hash_password(&client_secret)
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    let (previous_secret_hash, previous_secret_expires_at) = match overlap_secs {
+        0 => (None, None),
+        _ => (
+            Some(previous_secret_hash),
+            Some(Utc::now() + Duration::seconds(overlap_secs as i64)),
+        ),
+    };
+    let client = OAuthClient {
+        secret_hash: Some(secret_hash),
+        previous_secret_hash,
+        previous_secret_expires_at,
+        ..client
+    };
+    save_client(oauth_repo.as_ref(), &client).await?;
+    tracing::info!(overlap_secs, "OAuth client secret rotated");
+    Ok(Json(RegisteredClientDto {
+        client,
+        client_secret: Some(client_secret),
+    }))
+}
+
+/// RFC 7591 dynamic registration, for the holders of the initial access token.
+#[tracing::instrument(skip_all)]
+pub async fn post_register(
+    req: HttpRequest,
+    oauth_repo: Data<dyn OAuthRepo>,
+    passwd_hasher: Data<PasswordHasher>,
+    oauth_settings: Data<OAuthSettings>,
+    metadata: Json<DynamicRegistrationReqDto>,
+) -> Result<HttpResponse, OAuthError> {
+    let expected = oauth_settings
+        .initial_access_token
+        .as_deref()
+        .ok_or_else(|| {
+            OAuthError::InvalidToken("Dynamic client registration is disabled".into())
+        })?;
+    let given = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !given.is_some_and(|given| {
+        verify_slices_are_equal(expected.as_bytes(), given.trim().as_bytes()).is_ok()
+    }) {
+        return Err(OAuthError::InvalidToken(
+            "Missing or invalid initial access token".into(),
+        ));
+    }
+
+    let metadata = metadata.into_inner();
+    let auth_method = metadata
+        .token_endpoint_auth_method
+        .as_deref()
+        .unwrap_or(AUTH_METHODS[0]);
+    if !AUTH_METHODS.contains(&auth_method) {
+        return Err(OAuthError::InvalidClientMetadata(format!(
+            "Unsupported token_endpoint_auth_method: {}",
+            auth_method
+        )));
+    }
+    let allowed_scopes: Vec<String> = metadata
+        .scope
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(Into::into)
+        .collect();
+    // Powerful enough to be granted by administrators only
+    if let Some(scope) = allowed_scopes
+        .iter()
+        .find(|&scope| scope == DELEGATE_PERMISSION || scope == IMPERSONATE_PERMISSION)
+    {
+        return Err(OAuthError::InvalidClientMetadata(format!(
+            "The {} scope can't be registered dynamically",
+            scope
+        )));
+    }
+    let registration = ClientRegistrationDto {
+        name: metadata.client_name.unwrap_or_default(),
+        redirect_uris: metadata.redirect_uris,
+        allowed_scopes,
+        confidential: auth_method != "none",
+        grant_types: metadata
+            .grant_types
+            .unwrap_or_else(|| vec!["authorization_code".into()]),
+    };
+    let RegisteredClientDto {
+        client,
+        client_secret,
+    } = register_client(oauth_repo.as_ref(), &passwd_hasher, registration).await?;
+    tracing::info!(client_id = %client.id, "OAuth client registered dynamically");
+    Ok(HttpResponse::Created()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .insert_header((PRAGMA, "no-cache"))
+        .json(DynamicRegistrationRespDto {
+            client_secret_expires_at: client_secret.as_ref().map(|_| 0),
+            client_secret,
+            client_id_issued_at: client.created_at.timestamp(),
+            grant_types: client
+                .grant_types
+                .split_whitespace()
+                .map(Into::into)
+                .collect(),
+            scope: client.allowed_scopes,
+            client_id: client.id,
+            client_name: client.name,
+            redirect_uris: client.redirect_uris.0,
+            token_endpoint_auth_method: auth_method.into(),
+        }))
+}
+
+async fn find_client(
+    oauth_repo: &dyn OAuthRepo,
+    client_id: &str,
+) -> Result<OAuthClient, UserServiceError> {
+    oauth_repo
+        .get_client(client_id)
+        .await
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?
+        .ok_or_else(|| UserServiceError::NoClientForId(client_id.to_string()))
+}
+
+async fn save_client(
+    oauth_repo: &dyn OAuthRepo,
+    client: &OAuthClient,
+) -> Result<(), UserServiceError> {
+    let updated = oauth_repo
+        .update_client(client)
+        .await
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    match updated {
+        true => Ok(()),
+        // Deleted meanwhile
+        false => Err(UserServiceError::NoClientForId(client.id.clone())),
+    }
+}
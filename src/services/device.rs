@@ -0,0 +1,374 @@
+use std::time::Duration;
+
+use actix_web::http::header::CacheControl;
+use actix_web::http::header::CacheDirective;
+use actix_web::http::StatusCode;
+use actix_web::web::Data;
+use actix_web::web::Form;
+use actix_web::web::Query;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use chrono::Utc;
+
+use crate::crypto::random_token;
+use crate::crypto::random_user_code;
+use crate::crypto::token_hash;
+use crate::crypto::PasswordHasher;
+use crate::crypto::TokenSigner;
+use crate::errors::oauth::OAuthError;
+use crate::errors::user::log_err;
+use crate::errors::user::UserServiceError;
+use crate::models::oauth::DeviceAuthorization;
+use crate::models::oauth::DeviceAuthorizationReqDto;
+use crate::models::oauth::DeviceAuthorizationRespDto;
+use crate::models::oauth::DeviceAuthorizationStatus;
+use crate::models::oauth::DeviceVerificationFormDto;
+use crate::models::oauth::DeviceVerificationParams;
+use crate::models::oauth::OAuthClient;
+use crate::models::oauth::TokenReqDto;
+use crate::models::token::AccessTokenRespDto;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::user::UserRepo;
+use crate::services::oauth::authenticate_client;
+use crate::services::oauth::escape_html;
+use crate::services::oauth::html_page;
+use crate::services::oauth::missing;
+use crate::services::token;
+use crate::settings::OAuthSettings;
+use crate::settings::TokenSettings;
+
+pub const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+/// Added to the polling interval of devices polling too fast, per RFC 8628
+const SLOW_DOWN_SECS: i64 = 5;
+
+/// Starts the authorization of a device, which shows the user code and polls for a token.
+#[tracing::instrument(skip_all)]
+pub async fn post_device_authorization(
+    req: HttpRequest,
+    oauth_repo: Data<dyn OAuthRepo>,
+    passwd_hasher: Data<PasswordHasher>,
+    token_settings: Data<TokenSettings>,
+    oauth_settings: Data<OAuthSettings>,
+    form: Form<DeviceAuthorizationReqDto>,
+) -> Result<HttpResponse, OAuthError> {
+    let client = authenticate_client(
+        oauth_repo.as_ref(),
+        &passwd_hasher,
+        &req,
+        form.client_id.as_deref(),
+        form.client_secret.as_deref(),
+    )
+    .await?;
+    if !client.allows_grant_type(DEVICE_CODE_GRANT_TYPE) {
+        return Err(OAuthError::UnauthorizedClient);
+    }
+    if let Some(scope) = &form.scope {
+        if !client.allows_scope(scope) {
+            return Err(OAuthError::InvalidScope(scope.clone()));
+        }
+    }
+
+    let device_code = random_token();
+    let user_code = random_user_code();
+    let ttl = oauth_settings.device_code_ttl_secs as i64;
+    let interval = oauth_settings.device_poll_interval_secs as i64;
+    oauth_repo
+        .store_device_authorization(&DeviceAuthorization {
+            device_code_hash: token_hash(&device_code),
+            user_code: user_code.clone(),
+            client_id: client.id,
+            scope: form.scope.clone(),
+            expires_at: Utc::now() + chrono::Duration::seconds(ttl),
+            interval_secs: interval,
+            last_polled_at: None,
+            status: DeviceAuthorizationStatus::Pending,
+            user_id: None,
+        })
+        .await
+        .map_err(log_err)
+        .map_err(|_| OAuthError::ServerError)?;
+
+    let verification_uri = format!(
+        "{}/oauth/device",
+        token_settings.issuer.trim_end_matches('/')
+    );
+    let user_code = display_user_code(&user_code);
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(DeviceAuthorizationRespDto {
+            device_code,
+            verification_uri_complete: format!("{}?user_code={}", verification_uri, user_code),
+            verification_uri,
+            user_code,
+            expires_in: ttl,
+            interval,
+        }))
+}
+
+/// Asks for the user code, then for the user to sign in and approve the client.
+#[tracing::instrument(skip_all)]
+pub async fn get_device(
+    oauth_repo: Data<dyn OAuthRepo>,
+    params: Query<DeviceVerificationParams>,
+) -> HttpResponse {
+    let user_code = match params.user_code.as_deref() {
+        Some(user_code) => user_code,
+        None => return user_code_page(StatusCode::OK, None),
+    };
+    match pending_authorization(oauth_repo.as_ref(), user_code).await {
+        Ok((authorization, client)) => approval_page(StatusCode::OK, &authorization, &client, None),
+        Err(page) => page,
+    }
+}
+
+/// Approves or denies the device once the user signed in.
+#[tracing::instrument(skip_all)]
+pub async fn post_device(
+    user_repo: Data<dyn UserRepo>,
+    audit_repo: Data<dyn AuditRepo>,
+    oauth_repo: Data<dyn OAuthRepo>,
+    passwd_hasher: Data<PasswordHasher>,
+    form: Form<DeviceVerificationFormDto>,
+) -> HttpResponse {
+    let (authorization, client) =
+        match pending_authorization(oauth_repo.as_ref(), &form.user_code).await {
+            Ok(pending) => pending,
+            Err(page) => return page,
+        };
+    let status = match form.action.as_str() {
+        "approve" => DeviceAuthorizationStatus::Approved,
+        "deny" => DeviceAuthorizationStatus::Denied,
+        _ => return message_page(StatusCode::BAD_REQUEST, "Invalid request"),
+    };
+    let user = match token::verify_credentials(
+        user_repo.as_ref(),
+        audit_repo.as_ref(),
+        &passwd_hasher,
+        &form.username,
+        &form.password,
+    )
+    .await
+    {
+        Ok(user) => user,
+        Err(UserServiceError::InvalidCredentials) => {
+            return approval_page(
+                StatusCode::UNAUTHORIZED,
+                &authorization,
+                &client,
+                Some("Invalid username or password"),
+            )
+        }
+        Err(_) => return message_page(StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong"),
+    };
+    match oauth_repo
+        .decide_device_authorization(&authorization.user_code, status, &user.id)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return user_code_page(StatusCode::BAD_REQUEST, Some("Unknown or expired code"))
+        }
+        Err(err) => {
+            log_err(err);
+            return message_page(StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong");
+        }
+    }
+    tracing::info!(client_id = %client.id, user_id = %user.id, status = status.as_str(), "Device authorization decided");
+    match status {
+        DeviceAuthorizationStatus::Approved => {
+            message_page(StatusCode::OK, "Device connected, you can return to it")
+        }
+        _ => message_page(StatusCode::OK, "Request denied"),
+    }
+}
+
+/// Polls for the token of a device. Until the user decides, devices are told to wait, and to
+/// slow down if they poll more often than their interval.
+pub async fn redeem_device_code(
+    user_repo: &dyn UserRepo,
+    oauth_repo: &dyn OAuthRepo,
+    token_signer: &TokenSigner,
+    access_token_ttl: Duration,
+    client: &OAuthClient,
+    form: &TokenReqDto,
+) -> Result<AccessTokenRespDto, OAuthError> {
+    let device_code = form
+        .device_code
+        .as_deref()
+        .ok_or_else(|| missing("device_code"))?;
+    let device_code_hash = token_hash(device_code);
+    let unknown = || OAuthError::InvalidGrant("Unknown or already redeemed device code".into());
+    let authorization = oauth_repo
+        .get_device_authorization(&device_code_hash)
+        .await
+        .map_err(log_err)
+        .map_err(|_| OAuthError::ServerError)?
+        .ok_or_else(unknown)?;
+    if authorization.client_id != client.id {
+        return Err(OAuthError::InvalidGrant(
+            "Device code was issued to another client".into(),
+        ));
+    }
+
+    let now = Utc::now();
+    let take = || async {
+        oauth_repo
+            .take_device_authorization(&device_code_hash)
+            .await
+            .map_err(log_err)
+            .map_err(|_| OAuthError::ServerError)?
+            .ok_or_else(unknown)
+    };
+    if authorization.expires_at < now {
+        take().await?;
+        return Err(OAuthError::ExpiredToken);
+    }
+    match authorization.status {
+        DeviceAuthorizationStatus::Pending => {
+            let too_fast = authorization.last_polled_at.is_some_and(|last_polled_at| {
+                now < last_polled_at + chrono::Duration::seconds(authorization.interval_secs)
+            });
+            let interval = match too_fast {
+                true => authorization.interval_secs + SLOW_DOWN_SECS,
+                false => authorization.interval_secs,
+            };
+            oauth_repo
+                .record_device_poll(&device_code_hash, now, interval)
+                .await
+                .map_err(log_err)
+                .map_err(|_| OAuthError::ServerError)?;
+            Err(match too_fast {
+                true => OAuthError::SlowDown(interval),
+                false => OAuthError::AuthorizationPending,
+            })
+        }
+        DeviceAuthorizationStatus::Denied => {
+            take().await?;
+            Err(OAuthError::AccessDenied)
+        }
+        DeviceAuthorizationStatus::Approved => {
+            let authorization = take().await?;
+            let user_id = authorization.user_id.ok_or(OAuthError::ServerError)?;
+            let user = user_repo
+                .get_user_by_id(&user_id)
+                .await
+                .map_err(|_| OAuthError::InvalidGrant("User no longer exists".into()))?;
+            Ok(token::issue_access_token(
+                token_signer,
+                access_token_ttl,
+                &user,
+                Some(&client.id),
+                authorization.scope.as_deref(),
+            )?)
+        }
+    }
+}
+
+/// Uppercase and without separator or spaces, as stored.
+pub fn normalize_user_code(user_code: &str) -> String {
+    user_code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Halves separated by a dash, `BCDF-GHJK`.
+fn display_user_code(user_code: &str) -> String {
+    let (first, second) = user_code.split_at(user_code.len() / 2);
+    format!("{}-{}", first, second)
+}
+
+/// The pending, unexpired authorization of the code and its client, or the page to show instead.
+async fn pending_authorization(
+    oauth_repo: &dyn OAuthRepo,
+    user_code: &str,
+) -> Result<(DeviceAuthorization, OAuthClient), HttpResponse> {
+    let internal_error =
+        |_| message_page(StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong");
+    let authorization = oauth_repo
+        .get_device_authorization_by_user_code(&normalize_user_code(user_code))
+        .await
+        .map_err(log_err)
+        .map_err(internal_error)?
+        .filter(|authorization| {
+            authorization.status == DeviceAuthorizationStatus::Pending
+                && authorization.expires_at >= Utc::now()
+        });
+    let authorization = match authorization {
+        Some(authorization) => authorization,
+        None => {
+            return Err(user_code_page(
+                StatusCode::BAD_REQUEST,
+                Some("Unknown or expired code"),
+            ))
+        }
+    };
+    let client = oauth_repo
+        .get_client(&authorization.client_id)
+        .await
+        .map_err(log_err)
+        .map_err(internal_error)?
+        .ok_or_else(|| user_code_page(StatusCode::BAD_REQUEST, Some("Unknown or expired code")))?;
+    Ok((authorization, client))
+}
+
+fn error_paragraph(error: Option<&str>) -> String {
+    error
+        .map(|error| format!(r#"<p class="error">{}</p>"#, escape_html(error)))
+        .unwrap_or_default()
+}
+
+fn user_code_page(status: StatusCode, error: Option<&str>) -> HttpResponse {
+    let content = format!(
+        r#"<h1>Connect a device</h1>
+{}
+<form method="get" action="device">
+<input name="user_code" placeholder="Code shown on the device" autocomplete="off" required autofocus>
+<input type="submit" value="Continue">
+</form>"#,
+        error_paragraph(error)
+    );
+    html_page(status, "Connect a device", &content)
+}
+
+fn approval_page(
+    status: StatusCode,
+    authorization: &DeviceAuthorization,
+    client: &OAuthClient,
+    error: Option<&str>,
+) -> HttpResponse {
+    let scope = authorization
+        .scope
+        .as_deref()
+        .map(|scope| format!("<p>Access requested: {}</p>", escape_html(scope)))
+        .unwrap_or_default();
+    let content = format!(
+        r#"<h1>Connect {} to your account</h1>
+<p>Only continue if your device shows the code <strong>{}</strong>.</p>
+{}
+{}
+<form method="post" action="device">
+<input type="hidden" name="user_code" value="{}">
+<input name="username" placeholder="Username" autocomplete="username" required autofocus>
+<input name="password" type="password" placeholder="Password" autocomplete="current-password" required>
+<button name="action" value="approve">Approve</button>
+<button name="action" value="deny">Deny</button>
+</form>"#,
+        escape_html(&client.name),
+        display_user_code(&authorization.user_code),
+        scope,
+        error_paragraph(error),
+        authorization.user_code
+    );
+    html_page(status, "Connect a device", &content)
+}
+
+fn message_page(status: StatusCode, message: &str) -> HttpResponse {
+    html_page(
+        status,
+        "Connect a device",
+        &format!("<p>{}</p>", escape_html(message)),
+    )
+}
@@ -0,0 +1,136 @@
+use actix_web::HttpRequest;
+use chrono::Duration;
+use chrono::Utc;
+use jsonwebtoken::Algorithm;
+use jsonwebtoken::DecodingKey;
+use jsonwebtoken::Validation;
+use url::Url;
+
+use crate::crypto::public_jwk_thumbprint;
+use crate::crypto::sha256_base64url;
+use crate::crypto::token_hash;
+use crate::crypto::TokenSigner;
+use crate::errors::oauth::OAuthError;
+use crate::errors::user::log_err;
+use crate::models::token::DpopProofClaims;
+use crate::repositories::oauth::OAuthRepo;
+use crate::settings::TokenSettings;
+
+/// Request header carrying the proof, and the token type of DPoP-bound tokens
+pub const DPOP_HEADER: &str = "DPoP";
+pub const DPOP_NONCE_HEADER: &str = "DPoP-Nonce";
+pub const DPOP_SIGNING_ALGS: &[&str] = &["EdDSA", "ES256", "ES384", "RS256", "PS256"];
+const DPOP_PROOF_TYPE: &str = "dpop+jwt";
+
+/// The DPoP proof of the request if any, which may carry only one.
+pub fn proof_header(req: &HttpRequest) -> Result<Option<&str>, OAuthError> {
+    let mut proofs = req.headers().get_all(DPOP_HEADER);
+    match (proofs.next(), proofs.next()) {
+        (None, _) => Ok(None),
+        (Some(proof), None) => proof
+            .to_str()
+            .map(Some)
+            .map_err(|_| OAuthError::InvalidDpopProof("Malformed DPoP proof".into())),
+        (Some(_), Some(_)) => Err(OAuthError::InvalidDpopProof(
+            "Only one DPoP proof is allowed".into(),
+        )),
+    }
+}
+
+/// Checks the proof was made for this request, and for the `access_token` presented with it if
+/// any, and hasn't been used before. Returns the thumbprint of the key it was signed with.
+#[tracing::instrument(skip_all)]
+pub async fn verify_proof(
+    oauth_repo: &dyn OAuthRepo,
+    token_signer: &TokenSigner,
+    token_settings: &TokenSettings,
+    req: &HttpRequest,
+    proof: &str,
+    access_token: Option<&str>,
+) -> Result<String, OAuthError> {
+    let invalid = |description: &str| OAuthError::InvalidDpopProof(description.into());
+    let header = jsonwebtoken::decode_header(proof).map_err(|_| invalid("Malformed DPoP proof"))?;
+    if header.typ.as_deref() != Some(DPOP_PROOF_TYPE) {
+        return Err(invalid("DPoP proofs must be of type dpop+jwt"));
+    }
+    // Asymmetric algorithms only, the key of the proof being public
+    if !matches!(
+        header.alg,
+        Algorithm::EdDSA
+            | Algorithm::ES256
+            | Algorithm::ES384
+            | Algorithm::RS256
+            | Algorithm::PS256
+    ) {
+        return Err(invalid("Unsupported DPoP proof algorithm"));
+    }
+    let jwk = header
+        .jwk
+        .ok_or_else(|| invalid("The DPoP proof has no jwk header"))?;
+    let jkt =
+        public_jwk_thumbprint(&jwk).map_err(|_| invalid("The DPoP proof key isn't public"))?;
+    let key = DecodingKey::from_jwk(&jwk).map_err(|_| invalid("Invalid DPoP proof key"))?;
+    let mut validation = Validation::new(header.alg);
+    validation.validate_exp = false;
+    validation.required_spec_claims.clear();
+    let claims = jsonwebtoken::decode::<DpopProofClaims>(proof, &key, &validation)
+        .map_err(|_| invalid("Invalid DPoP proof signature or claims"))?
+        .claims;
+
+    if claims.htm != req.method().as_str() {
+        return Err(invalid("The DPoP proof is for another method"));
+    }
+    let without_query = |uri: &str| {
+        Url::parse(uri).ok().map(|mut url| {
+            url.set_query(None);
+            url.set_fragment(None);
+            url
+        })
+    };
+    let uri = without_query(&request_uri(token_signer, req));
+    if uri.is_none() || without_query(&claims.htu) != uri {
+        return Err(invalid("The DPoP proof is for another URI"));
+    }
+    let now = Utc::now();
+    let max_age = Duration::seconds(token_settings.dpop_proof_max_age_secs as i64);
+    if claims.iat.abs_diff(now.timestamp()) > token_settings.dpop_proof_max_age_secs {
+        return Err(invalid("The DPoP proof is too old or from the future"));
+    }
+    if let Some(access_token) = access_token {
+        if claims.ath != Some(sha256_base64url(access_token.as_bytes())) {
+            return Err(invalid("The DPoP proof is for another access token"));
+        }
+    }
+    if token_settings.dpop_nonce_required
+        && !claims
+            .nonce
+            .as_deref()
+            .is_some_and(|nonce| token_signer.verify_dpop_nonce(nonce, now.timestamp()))
+    {
+        return Err(OAuthError::UseDpopNonce(
+            token_signer.dpop_nonce(now.timestamp()),
+        ));
+    }
+    // Remembered as long as its `iat` is acceptable, which is at most twice the max age
+    let fresh = oauth_repo
+        .record_dpop_proof(
+            &token_hash(&format!("{}:{}", jkt, claims.jti)),
+            now + max_age * 2,
+        )
+        .await
+        .map_err(log_err)
+        .map_err(|_| OAuthError::ServerError)?;
+    if !fresh {
+        return Err(invalid("The DPoP proof was already used"));
+    }
+    Ok(jkt)
+}
+
+/// URI of the request as seen by clients: the path under the issuer, the public URL of the service.
+pub fn request_uri(token_signer: &TokenSigner, req: &HttpRequest) -> String {
+    format!(
+        "{}{}",
+        token_signer.issuer().trim_end_matches('/'),
+        req.path()
+    )
+}
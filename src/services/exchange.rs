@@ -0,0 +1,285 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::crypto::TokenSigner;
+use crate::errors::oauth::OAuthError;
+use crate::errors::user::log_err;
+use crate::models::audit::AuditEventType;
+use crate::models::audit::NewAuditEventBuilder;
+use crate::models::oauth::OAuthClient;
+use crate::models::oauth::TokenReqDto;
+use crate::models::token::AccessTokenClaims;
+use crate::models::token::AccessTokenRespDto;
+use crate::models::token::Actor;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::user::UserRepo;
+use crate::services::audit;
+use crate::services::oauth::missing;
+use crate::services::token;
+
+pub const TOKEN_EXCHANGE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+pub const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+/// Our access tokens are JWTs, so either type identifies them
+pub const JWT_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:jwt";
+/// Scope a confidential client must be allowed to exchange tokens of users for its own
+pub const DELEGATE_PERMISSION: &str = "exchange:delegate";
+/// Scope a confidential client must be allowed to swap tokens of staff for tokens of other users
+pub const IMPERSONATE_PERMISSION: &str = "exchange:impersonate";
+
+/// RFC 8693 token exchange, as done by the token endpoint for an authenticated client.
+pub struct TokenExchange<'a> {
+    pub user_repo: &'a dyn UserRepo,
+    pub audit_repo: &'a dyn AuditRepo,
+    pub oauth_repo: &'a dyn OAuthRepo,
+    pub token_signer: &'a TokenSigner,
+    pub access_token_ttl: Duration,
+    /// Users allowed to act as others
+    pub impersonators: &'a [Uuid],
+    /// Thumbprint of the key of the DPoP proof of the request, if it gave one
+    pub dpop_jkt: Option<&'a str>,
+}
+
+impl TokenExchange<'_> {
+    /// Delegates with a `subject_token`, or impersonates the `requested_subject` with the
+    /// `actor_token` of a staff user. Exchanged tokens never outlive or outscope the token they
+    /// come from.
+    #[tracing::instrument(skip_all, fields(client_id = %client.id))]
+    pub async fn exchange(
+        &self,
+        client: &OAuthClient,
+        form: &TokenReqDto,
+    ) -> Result<AccessTokenRespDto, OAuthError> {
+        if let Some(requested) = form.requested_token_type.as_deref() {
+            if requested != ACCESS_TOKEN_TYPE {
+                return Err(OAuthError::InvalidRequest(
+                    "Only access tokens can be requested".into(),
+                ));
+            }
+        }
+        if let Some(audience) = form.audience.as_deref() {
+            if audience.is_empty() || audience.contains(char::is_whitespace) {
+                return Err(OAuthError::InvalidTarget(audience.into()));
+            }
+        }
+        let mut token = match (
+            form.subject_token.as_deref(),
+            form.requested_subject.as_deref(),
+        ) {
+            (Some(subject_token), None) => self.delegate(client, form, subject_token).await?,
+            (None, Some(requested_subject)) => {
+                self.impersonate(client, form, requested_subject).await?
+            }
+            (None, None) => return Err(missing("subject_token")),
+            (Some(_), Some(_)) => {
+                return Err(OAuthError::InvalidRequest(
+                    "Give either subject_token or requested_subject".into(),
+                ))
+            }
+        };
+        token.issued_token_type = Some(ACCESS_TOKEN_TYPE.into());
+        Ok(token)
+    }
+
+    /// Token of the same user for the client to call `audience` with, the client being the actor.
+    async fn delegate(
+        &self,
+        client: &OAuthClient,
+        form: &TokenReqDto,
+        subject_token: &str,
+    ) -> Result<AccessTokenRespDto, OAuthError> {
+        if !client.is_confidential() || !client.allows_scope(DELEGATE_PERMISSION) {
+            return Err(OAuthError::UnauthorizedClient);
+        }
+        if form.actor_token.is_some() {
+            return Err(OAuthError::InvalidRequest(
+                "The client is the actor of delegated tokens".into(),
+            ));
+        }
+        let subject = self
+            .verify(
+                subject_token,
+                form.subject_token_type.as_deref(),
+                "subject_token_type",
+            )
+            .await?
+            .ok_or_else(|| {
+                OAuthError::InvalidGrant("Invalid, expired or revoked subject token".into())
+            })?;
+        self.check_binding(&subject, "subject token")?;
+        let audience = form.audience.clone().or_else(|| subject.aud.clone());
+        if subject.aud.is_some() && audience != subject.aud {
+            return Err(OAuthError::InvalidTarget(audience.unwrap_or_default()));
+        }
+        let scope = narrowed_scope(subject.scope.as_deref(), form.scope.as_deref())?;
+        let now = Utc::now().timestamp();
+        Ok(token::sign_claims(
+            self.token_signer,
+            &AccessTokenClaims {
+                iss: self.token_signer.issuer().into(),
+                iat: now,
+                exp: subject
+                    .exp
+                    .min(now + self.access_token_ttl.as_secs() as i64),
+                jti: Uuid::new_v4(),
+                client_id: Some(client.id.clone()),
+                scope,
+                aud: audience,
+                act: Some(Actor {
+                    sub: client.id.clone(),
+                    username: None,
+                    act: subject.act.map(Box::new),
+                }),
+                // Still bound to the same key, which the client proved it holds
+                ..subject
+            },
+        )?)
+    }
+
+    /// Token of another user with the staff user as actor, which is always audited.
+    async fn impersonate(
+        &self,
+        client: &OAuthClient,
+        form: &TokenReqDto,
+        requested_subject: &str,
+    ) -> Result<AccessTokenRespDto, OAuthError> {
+        if !client.is_confidential() || !client.allows_scope(IMPERSONATE_PERMISSION) {
+            return Err(OAuthError::UnauthorizedClient);
+        }
+        let actor_token = form
+            .actor_token
+            .as_deref()
+            .ok_or_else(|| missing("actor_token"))?;
+        let actor = self
+            .verify(
+                actor_token,
+                form.actor_token_type.as_deref(),
+                "actor_token_type",
+            )
+            .await?
+            .ok_or_else(|| {
+                OAuthError::InvalidGrant("Invalid, expired or revoked actor token".into())
+            })?;
+        self.check_binding(&actor, "actor token")?;
+        // Like for our own API, tokens restricted to a downstream service don't identify staff here
+        if actor
+            .aud
+            .as_deref()
+            .is_some_and(|aud| aud != self.token_signer.issuer())
+        {
+            return Err(OAuthError::InvalidGrant(
+                "The actor token is for another audience".into(),
+            ));
+        }
+        // Only staff users acting for themselves, not through earlier exchanges
+        let actor_id = Uuid::parse_str(&actor.sub)
+            .ok()
+            .filter(|id| {
+                actor.username.is_some() && actor.act.is_none() && self.impersonators.contains(id)
+            })
+            .ok_or_else(|| {
+                OAuthError::InvalidGrant("The actor isn't allowed to impersonate users".into())
+            })?;
+        let user_id = Uuid::parse_str(requested_subject)
+            .map_err(|_| OAuthError::InvalidRequest("Invalid requested_subject".into()))?;
+        let user = self
+            .user_repo
+            .get_user_by_id(&user_id)
+            .await
+            .map_err(|_| OAuthError::InvalidGrant("Unknown requested_subject".into()))?;
+        let scope = narrowed_scope(actor.scope.as_deref(), form.scope.as_deref())?;
+        let now = Utc::now().timestamp();
+        let claims = AccessTokenClaims {
+            iss: self.token_signer.issuer().into(),
+            sub: user.id.to_string(),
+            username: Some(user.username),
+            iat: now,
+            exp: actor.exp.min(now + self.access_token_ttl.as_secs() as i64),
+            jti: Uuid::new_v4(),
+            client_id: Some(client.id.clone()),
+            scope,
+            aud: form.audience.clone(),
+            act: Some(Actor {
+                sub: actor.sub,
+                username: actor.username,
+                act: None,
+            }),
+            cnf: actor.cnf,
+        };
+        let token = token::sign_claims(self.token_signer, &claims)?;
+        audit::record(
+            self.audit_repo,
+            NewAuditEventBuilder::default()
+                .event_type(AuditEventType::UserImpersonated)
+                .actor_id(actor_id)
+                .subject_id(user.id)
+                .details(json!({
+                    "client_id": client.id,
+                    "scope": claims.scope,
+                    "audience": claims.aud,
+                    "jti": claims.jti,
+                    "expires_at": claims.exp,
+                }))
+                .build()
+                .map_err(log_err)
+                .map_err(|_| OAuthError::ServerError)?,
+        )
+        .await
+        .map_err(log_err)
+        .map_err(|_| OAuthError::ServerError)?;
+        Ok(token)
+    }
+
+    /// Tokens bound to a key are only exchanged with a DPoP proof of that key.
+    fn check_binding(&self, claims: &AccessTokenClaims, token: &str) -> Result<(), OAuthError> {
+        match &claims.cnf {
+            Some(cnf) if self.dpop_jkt != Some(cnf.jkt.as_str()) => {
+                Err(OAuthError::InvalidDpopProof(format!(
+                    "The {} needs a DPoP proof of its key",
+                    token
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn verify(
+        &self,
+        token: &str,
+        token_type: Option<&str>,
+        token_type_param: &str,
+    ) -> Result<Option<AccessTokenClaims>, OAuthError> {
+        match token_type {
+            Some(ACCESS_TOKEN_TYPE | JWT_TOKEN_TYPE) => {}
+            Some(token_type) => {
+                return Err(OAuthError::InvalidRequest(format!(
+                    "Unsupported {}: {}",
+                    token_type_param, token_type
+                )))
+            }
+            None => return Err(missing(token_type_param)),
+        }
+        Ok(token::verify_access_token(self.token_signer, self.oauth_repo, token).await?)
+    }
+}
+
+/// The `requested` scope if it's within the `held` one, the `held` one if none was requested.
+fn narrowed_scope(
+    held: Option<&str>,
+    requested: Option<&str>,
+) -> Result<Option<String>, OAuthError> {
+    let Some(requested) = requested else {
+        return Ok(held.map(Into::into));
+    };
+    let held: Vec<_> = held.unwrap_or_default().split_whitespace().collect();
+    match requested
+        .split_whitespace()
+        .all(|scope| held.contains(&scope))
+    {
+        true => Ok(Some(requested.into())),
+        false => Err(OAuthError::InvalidScope(requested.into())),
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::Duration;
+use std::time::Instant;
+
+use actix_web::http::StatusCode;
+use actix_web::rt::time::timeout;
+use actix_web::web::Data;
+use actix_web::HttpResponse;
+use anyhow::Result;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::crypto::TokenSigner;
+use crate::models::health::HealthCheckDto;
+use crate::models::health::HealthRespDto;
+use crate::models::health::HealthStatus;
+use crate::repositories::database::DatabaseProbe;
+use crate::repositories::migrations::SchemaMigrator;
+
+/// Upper bound for a single readiness check, so a hanging database fails the probe instead of
+/// stalling it.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+pub const CHECK_FAILED: &str = "Check failed";
+pub const CHECK_TIMED_OUT: &str = "Check timed out";
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses((status = 200, description = "The process is serving requests", body = HealthRespDto))
+)]
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(HealthRespDto {
+        status: HealthStatus::Ok,
+        checks: BTreeMap::new(),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "Every check passed", body = HealthRespDto),
+        (status = 503, description = "A check failed", body = HealthRespDto),
+    )
+)]
+pub async fn readyz(
+    probe: Data<dyn DatabaseProbe>,
+    migrator: Data<dyn SchemaMigrator>,
+    token_signer: Data<TokenSigner>,
+) -> HttpResponse {
+    let checks = BTreeMap::from([
+        ("database".to_string(), run_check(probe.ping()).await),
+        (
+            "migrations".to_string(),
+            run_check(migrator.check_schema()).await,
+        ),
+        (
+            "signing_keys".to_string(),
+            run_check(async { check_signing_keys(&token_signer) }).await,
+        ),
+    ]);
+
+    let (status, status_code) = if checks.values().all(|c| c.status == HealthStatus::Ok) {
+        (HealthStatus::Ok, StatusCode::OK)
+    } else {
+        (HealthStatus::Fail, StatusCode::SERVICE_UNAVAILABLE)
+    };
+    HttpResponse::build(status_code).json(HealthRespDto { status, checks })
+}
+
+async fn run_check(check: impl Future<Output = Result<()>>) -> HealthCheckDto {
+    let start = Instant::now();
+    let result = timeout(CHECK_TIMEOUT, check).await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+    // The probe is unauthenticated, the details of failures are only logged
+    let error = match result {
+        Ok(Ok(())) => {
+            return HealthCheckDto {
+                status: HealthStatus::Ok,
+                latency_ms,
+                error: None,
+            }
+        }
+        Ok(Err(err)) => {
+            log::warn!("Readiness check failed: {:#}", err);
+            CHECK_FAILED
+        }
+        Err(_) => {
+            log::warn!("Readiness check timed out after {:?}", CHECK_TIMEOUT);
+            CHECK_TIMED_OUT
+        }
+    };
+    HealthCheckDto {
+        status: HealthStatus::Fail,
+        latency_ms,
+        error: Some(error.to_string()),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProbeClaims {
+    iss: String,
+    exp: i64,
+}
+
+/// Round-trips a token through the signer to make sure the keys are usable.
+fn check_signing_keys(token_signer: &TokenSigner) -> Result<()> {
+    let token = token_signer.sign(&ProbeClaims {
+        iss: token_signer.issuer().to_string(),
+        exp: Utc::now().timestamp() + 60,
+    })?;
+    token_signer.verify::<ProbeClaims>(&token)?;
+    Ok(())
+}
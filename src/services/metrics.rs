@@ -0,0 +1,19 @@
+use actix_web::web::Data;
+use actix_web::HttpResponse;
+
+use crate::errors::user::log_err;
+use crate::metrics::METRICS;
+use crate::repositories::database::DatabaseProbe;
+
+pub async fn metrics(probe: Data<dyn DatabaseProbe>) -> HttpResponse {
+    METRICS.set_pool_stats(probe.pool_stats());
+    match METRICS.encode() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(body),
+        Err(err) => {
+            log_err(err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
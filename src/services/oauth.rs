@@ -0,0 +1,863 @@
+use std::time::Duration;
+
+use actix_web::http::header::CacheControl;
+use actix_web::http::header::CacheDirective;
+use actix_web::http::header::ContentType;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::http::header::LOCATION;
+use actix_web::http::header::PRAGMA;
+use actix_web::http::StatusCode;
+use actix_web::web::Data;
+use actix_web::web::Form;
+use actix_web::web::Query;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use chrono::TimeZone;
+use chrono::Utc;
+use ring::constant_time::verify_slices_are_equal;
+use ring::digest;
+use url::Host;
+use url::Url;
+use uuid::Uuid;
+
+use crate::crypto::random_token;
+use crate::crypto::token_hash;
+use crate::crypto::PasswordHasher;
+use crate::crypto::TokenSigner;
+use crate::errors::oauth::AuthorizeError;
+use crate::errors::oauth::ClientRegistrationError;
+use crate::errors::oauth::OAuthError;
+use crate::errors::user::log_err;
+use crate::errors::user::UserServiceError;
+use crate::models::oauth::AuthorizationCode;
+use crate::models::oauth::AuthorizeParams;
+use crate::models::oauth::ClientRegistrationDto;
+use crate::models::oauth::ClientTokenReqDto;
+use crate::models::oauth::LoginFormDto;
+use crate::models::oauth::OAuthClient;
+use crate::models::oauth::RedirectUris;
+use crate::models::oauth::RegisteredClientDto;
+use crate::models::oauth::TokenReqDto;
+use crate::models::token::AccessTokenRespDto;
+use crate::models::user::User;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::user::UserRepo;
+use crate::services::device;
+use crate::services::device::DEVICE_CODE_GRANT_TYPE;
+use crate::services::dpop;
+use crate::services::dpop::DPOP_NONCE_HEADER;
+use crate::services::exchange::TokenExchange;
+use crate::services::exchange::TOKEN_EXCHANGE_GRANT_TYPE;
+use crate::services::oidc;
+use crate::services::token;
+use crate::settings::OAuthSettings;
+use crate::settings::TokenSettings;
+use crate::settings::UpstreamProviderSettings;
+
+/// PKCE is mandatory, and only with the SHA-256 method
+pub const CODE_CHALLENGE_METHOD: &str = "S256";
+/// Grants of the token endpoint
+pub const GRANT_TYPES: &[&str] = &[
+    "authorization_code",
+    "client_credentials",
+    DEVICE_CODE_GRANT_TYPE,
+    TOKEN_EXCHANGE_GRANT_TYPE,
+];
+
+/// Shows the login form for a valid authorization request.
+#[tracing::instrument(skip(oauth_repo, oauth_settings))]
+pub async fn get_authorize(
+    oauth_repo: Data<dyn OAuthRepo>,
+    oauth_settings: Data<OAuthSettings>,
+    params: Query<AuthorizeParams>,
+) -> Result<HttpResponse, AuthorizeError> {
+    let request = check_authorize_request(oauth_repo.as_ref(), &params).await?;
+    Ok(login_page(
+        &params,
+        &request.client,
+        &oauth_settings.upstream_providers,
+        None,
+    ))
+}
+
+/// Logs the user in and redirects back to the client with an authorization code.
+#[tracing::instrument(skip_all)]
+pub async fn post_authorize(
+    user_repo: Data<dyn UserRepo>,
+    audit_repo: Data<dyn AuditRepo>,
+    oauth_repo: Data<dyn OAuthRepo>,
+    passwd_hasher: Data<PasswordHasher>,
+    oauth_settings: Data<OAuthSettings>,
+    form: Form<LoginFormDto>,
+) -> Result<HttpResponse, AuthorizeError> {
+    let LoginFormDto {
+        params,
+        username,
+        password,
+    } = form.into_inner();
+    let request = check_authorize_request(oauth_repo.as_ref(), &params).await?;
+    let user = match token::verify_credentials(
+        user_repo.as_ref(),
+        audit_repo.as_ref(),
+        &passwd_hasher,
+        &username,
+        &password,
+    )
+    .await
+    {
+        Ok(user) => user,
+        Err(UserServiceError::InvalidCredentials) => {
+            return Ok(login_page(
+                &params,
+                &request.client,
+                &oauth_settings.upstream_providers,
+                Some("Invalid username or password"),
+            ))
+        }
+        Err(err) => return Err(request.reject(err.into())),
+    };
+    redirect_with_code(
+        oauth_repo.as_ref(),
+        &oauth_settings,
+        request,
+        &params,
+        &user,
+    )
+    .await
+}
+
+/// Completes the authorization request of the signed in user, redirecting back to the client
+/// with an authorization code.
+pub async fn redirect_with_code(
+    oauth_repo: &dyn OAuthRepo,
+    oauth_settings: &OAuthSettings,
+    request: AuthorizeRequest,
+    params: &AuthorizeParams,
+    user: &User,
+) -> Result<HttpResponse, AuthorizeError> {
+    let auth_time = Utc::now();
+    let code = random_token();
+    let ttl = chrono::Duration::seconds(oauth_settings.authorization_code_ttl_secs as i64);
+    oauth_repo
+        .store_authorization_code(&AuthorizationCode {
+            code_hash: token_hash(&code),
+            client_id: request.client.id.clone(),
+            user_id: user.id,
+            redirect_uri: request.redirect_uri.to_string(),
+            scope: params.scope.clone(),
+            code_challenge: request.code_challenge.clone(),
+            expires_at: auth_time + ttl,
+            nonce: params.nonce.clone(),
+            auth_time,
+        })
+        .await
+        .map_err(log_err)
+        .map_err(|_| request.reject(OAuthError::ServerError))?;
+
+    let mut location = request.redirect_uri;
+    location.query_pairs_mut().append_pair("code", &code);
+    if let Some(state) = &request.state {
+        location.query_pairs_mut().append_pair("state", state);
+    }
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, location.as_str()))
+        .finish())
+}
+
+#[tracing::instrument(skip_all)]
+#[allow(clippy::too_many_arguments)]
+pub async fn post_token(
+    req: HttpRequest,
+    user_repo: Data<dyn UserRepo>,
+    audit_repo: Data<dyn AuditRepo>,
+    oauth_repo: Data<dyn OAuthRepo>,
+    passwd_hasher: Data<PasswordHasher>,
+    token_signer: Data<TokenSigner>,
+    token_settings: Data<TokenSettings>,
+    oauth_settings: Data<OAuthSettings>,
+    form: Form<TokenReqDto>,
+) -> Result<HttpResponse, OAuthError> {
+    let grant_type = form
+        .grant_type
+        .as_deref()
+        .ok_or_else(|| missing("grant_type"))?;
+    if !GRANT_TYPES.contains(&grant_type) {
+        return Err(OAuthError::UnsupportedGrantType(grant_type.to_string()));
+    }
+    let client = authenticate_client(
+        oauth_repo.as_ref(),
+        &passwd_hasher,
+        &req,
+        form.client_id.as_deref(),
+        form.client_secret.as_deref(),
+    )
+    .await?;
+    if !client.allows_grant_type(grant_type) {
+        return Err(OAuthError::UnauthorizedClient);
+    }
+    // Checked before redeeming anything, so a bad proof doesn't use up codes
+    let jkt = match dpop::proof_header(&req)? {
+        Some(proof) => Some(
+            dpop::verify_proof(
+                oauth_repo.as_ref(),
+                &token_signer,
+                &token_settings,
+                &req,
+                proof,
+                None,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    let access_token_ttl = Duration::from_secs(token_settings.access_token_ttl_secs);
+    let token = match grant_type {
+        "authorization_code" => {
+            redeem_authorization_code(
+                user_repo.as_ref(),
+                oauth_repo.as_ref(),
+                &token_signer,
+                access_token_ttl,
+                &client,
+                &form,
+            )
+            .await?
+        }
+        "client_credentials" => {
+            if !client.is_confidential() {
+                return Err(OAuthError::UnauthorizedClient);
+            }
+            let scope = granted_scope(&client, form.scope.as_deref())?;
+            token::issue_client_token(
+                &token_signer,
+                access_token_ttl,
+                &client.id,
+                scope.as_deref(),
+            )?
+        }
+        DEVICE_CODE_GRANT_TYPE => {
+            device::redeem_device_code(
+                user_repo.as_ref(),
+                oauth_repo.as_ref(),
+                &token_signer,
+                access_token_ttl,
+                &client,
+                &form,
+            )
+            .await?
+        }
+        TOKEN_EXCHANGE_GRANT_TYPE => {
+            TokenExchange {
+                user_repo: user_repo.as_ref(),
+                audit_repo: audit_repo.as_ref(),
+                oauth_repo: oauth_repo.as_ref(),
+                token_signer: &token_signer,
+                access_token_ttl,
+                impersonators: &oauth_settings.impersonators,
+                dpop_jkt: jkt.as_deref(),
+            }
+            .exchange(&client, &form)
+            .await?
+        }
+        _ => unreachable!("Grant type checked above"),
+    };
+    let token = match jkt {
+        Some(jkt) => token::bind_to_key(&token_signer, token, jkt)?,
+        None => token,
+    };
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .insert_header((PRAGMA, "no-cache"));
+    if token_settings.dpop_nonce_required {
+        response.insert_header((
+            DPOP_NONCE_HEADER,
+            token_signer.dpop_nonce(Utc::now().timestamp()),
+        ));
+    }
+    Ok(response.json(token))
+}
+
+/// RFC 7662 introspection, for resource servers authenticating as confidential clients.
+#[tracing::instrument(skip_all)]
+pub async fn post_introspect(
+    req: HttpRequest,
+    oauth_repo: Data<dyn OAuthRepo>,
+    passwd_hasher: Data<PasswordHasher>,
+    token_signer: Data<TokenSigner>,
+    form: Form<ClientTokenReqDto>,
+) -> Result<HttpResponse, OAuthError> {
+    let client = authenticate_client(
+        oauth_repo.as_ref(),
+        &passwd_hasher,
+        &req,
+        form.client_id.as_deref(),
+        form.client_secret.as_deref(),
+    )
+    .await?;
+    if !client.is_confidential() {
+        return Err(OAuthError::UnauthorizedClient);
+    }
+    let token = form.token.as_deref().ok_or_else(|| missing("token"))?;
+    let introspection = token::introspect(&token_signer, oauth_repo.as_ref(), token).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(introspection))
+}
+
+/// RFC 7009 revocation of an access token by the client it was issued to. Tokens which are
+/// invalid, expired or already revoked are accepted as is.
+#[tracing::instrument(skip_all)]
+pub async fn post_revoke(
+    req: HttpRequest,
+    oauth_repo: Data<dyn OAuthRepo>,
+    passwd_hasher: Data<PasswordHasher>,
+    token_signer: Data<TokenSigner>,
+    form: Form<ClientTokenReqDto>,
+) -> Result<HttpResponse, OAuthError> {
+    let client = authenticate_client(
+        oauth_repo.as_ref(),
+        &passwd_hasher,
+        &req,
+        form.client_id.as_deref(),
+        form.client_secret.as_deref(),
+    )
+    .await?;
+    let token = form.token.as_deref().ok_or_else(|| missing("token"))?;
+    let claims = match token::verify_access_token(&token_signer, oauth_repo.as_ref(), token).await?
+    {
+        Some(claims) => claims,
+        None => return Ok(HttpResponse::Ok().finish()),
+    };
+    if claims.client_id.as_deref() != Some(client.id.as_str()) {
+        return Err(OAuthError::InvalidRequest(
+            "The token was issued to another client".into(),
+        ));
+    }
+    let expires_at = Utc
+        .timestamp_opt(claims.exp, 0)
+        .single()
+        .ok_or(OAuthError::ServerError)?;
+    oauth_repo
+        .revoke_token(&claims.jti, expires_at)
+        .await
+        .map_err(log_err)
+        .map_err(|_| OAuthError::ServerError)?;
+    tracing::info!(jti = %claims.jti, client_id = %client.id, "Access token revoked");
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Identifies the client of a request to the token endpoints. Confidential clients authenticate
+/// with their secret, in the `Authorization: Basic` header or the form, public clients only give
+/// their ID.
+pub async fn authenticate_client(
+    oauth_repo: &dyn OAuthRepo,
+    passwd_hasher: &PasswordHasher,
+    req: &HttpRequest,
+    form_client_id: Option<&str>,
+    form_client_secret: Option<&str>,
+) -> Result<OAuthClient, OAuthError> {
+    let (client_id, client_secret) = match basic_credentials(req)? {
+        Some((client_id, client_secret)) => {
+            if form_client_secret.is_some() || form_client_id.is_some_and(|id| id != client_id) {
+                return Err(OAuthError::InvalidRequest(
+                    "Client credentials given more than once".into(),
+                ));
+            }
+            (client_id, Some(client_secret))
+        }
+        None => (
+            form_client_id
+                .ok_or_else(|| missing("client_id"))?
+                .to_string(),
+            form_client_secret.map(Into::into),
+        ),
+    };
+    let client = oauth_repo
+        .get_client(&client_id)
+        .await
+        .map_err(log_err)
+        .map_err(|_| OAuthError::ServerError)?
+        .ok_or(OAuthError::InvalidClient)?;
+    let verify = |secret: &str, secret_hash: &str| {
+        passwd_hasher
+            .verify_password(secret, secret_hash)
+            .map_err(log_err)
+            .unwrap_or(false)
+    };
+    let authenticated = match (&client.secret_hash, &client_secret) {
+        (Some(secret_hash), Some(secret)) => {
+            verify(secret, secret_hash)
+                // Until the end of the overlap of the last rotation
+                || client
+                    .previous_secret_hash
+                    .as_deref()
+                    .zip(client.previous_secret_expires_at)
+                    .is_some_and(|(previous_hash, expires_at)| {
+                        expires_at > Utc::now() && verify(secret, previous_hash)
+                    })
+        }
+        (None, None) => true,
+        _ => false,
+    };
+    if !authenticated {
+        return Err(OAuthError::InvalidClient);
+    }
+    Ok(client)
+}
+
+/// RFC 6749 `client_secret_basic` credentials, if given.
+fn basic_credentials(req: &HttpRequest) -> Result<Option<(String, String)>, OAuthError> {
+    let encoded = match req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+    {
+        Some(encoded) => encoded,
+        None => return Ok(None),
+    };
+    // IDs and secrets are issued URL safe, so their form encoding is left as is
+    let decoded = base64::decode(encoded.trim())
+        .ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+    match decoded
+        .as_deref()
+        .and_then(|decoded| decoded.split_once(':'))
+    {
+        Some((client_id, client_secret)) => {
+            Ok(Some((client_id.to_string(), client_secret.to_string())))
+        }
+        None => Err(OAuthError::InvalidClient),
+    }
+}
+
+/// The requested scopes if all are allowed, by default every allowed one.
+fn granted_scope(
+    client: &OAuthClient,
+    requested: Option<&str>,
+) -> Result<Option<String>, OAuthError> {
+    match requested {
+        Some(scope) if !client.allows_scope(scope) => {
+            Err(OAuthError::InvalidScope(scope.to_string()))
+        }
+        Some(scope) => Ok(Some(scope.to_string())),
+        None if client.allowed_scopes.is_empty() => Ok(None),
+        None => Ok(Some(client.allowed_scopes.clone())),
+    }
+}
+
+/// Exchanges a code for an access token, once, given the verifier of its PKCE challenge.
+pub async fn redeem_authorization_code(
+    user_repo: &dyn UserRepo,
+    oauth_repo: &dyn OAuthRepo,
+    token_signer: &TokenSigner,
+    access_token_ttl: Duration,
+    client: &OAuthClient,
+    form: &TokenReqDto,
+) -> Result<AccessTokenRespDto, OAuthError> {
+    let code = form.code.as_deref().ok_or_else(|| missing("code"))?;
+    let redirect_uri = form
+        .redirect_uri
+        .as_deref()
+        .ok_or_else(|| missing("redirect_uri"))?;
+    let code_verifier = form
+        .code_verifier
+        .as_deref()
+        .ok_or_else(|| missing("code_verifier"))?;
+    if !is_code_verifier(code_verifier) {
+        return Err(OAuthError::InvalidRequest("Malformed code_verifier".into()));
+    }
+
+    // Taken before any other check, a code presented with wrong parameters is burnt
+    let code = oauth_repo
+        .take_authorization_code(&token_hash(code))
+        .await
+        .map_err(log_err)
+        .map_err(|_| OAuthError::ServerError)?
+        .ok_or_else(|| {
+            OAuthError::InvalidGrant("Unknown or already redeemed authorization code".into())
+        })?;
+    if code.expires_at < Utc::now() {
+        return Err(OAuthError::InvalidGrant(
+            "Authorization code expired".into(),
+        ));
+    }
+    if code.client_id != client.id {
+        return Err(OAuthError::InvalidGrant(
+            "Authorization code was issued to another client".into(),
+        ));
+    }
+    if code.redirect_uri != redirect_uri {
+        return Err(OAuthError::InvalidGrant(
+            "redirect_uri differs from the authorization request".into(),
+        ));
+    }
+    let challenge = pkce_s256(code_verifier);
+    if verify_slices_are_equal(challenge.as_bytes(), code.code_challenge.as_bytes()).is_err() {
+        return Err(OAuthError::InvalidGrant(
+            "code_verifier doesn't match the code_challenge".into(),
+        ));
+    }
+
+    let user = user_repo
+        .get_user_by_id(&code.user_id)
+        .await
+        .map_err(|_| OAuthError::InvalidGrant("User no longer exists".into()))?;
+    let mut token = token::issue_access_token(
+        token_signer,
+        access_token_ttl,
+        &user,
+        Some(&client.id),
+        code.scope.as_deref(),
+    )?;
+    if oidc::has_scope(code.scope.as_deref(), oidc::OPENID_SCOPE) {
+        token.id_token = Some(
+            oidc::issue_id_token(
+                token_signer,
+                access_token_ttl,
+                &user,
+                &client.id,
+                code.nonce.as_deref(),
+                code.auth_time,
+                code.scope.as_deref(),
+            )
+            .map_err(log_err)
+            .map_err(|_| OAuthError::ServerError)?,
+        );
+    }
+    Ok(token)
+}
+
+/// Registers a client. The secret of confidential clients is generated and returned this once.
+pub async fn register_client(
+    oauth_repo: &dyn OAuthRepo,
+    passwd_hasher: &PasswordHasher,
+    registration: ClientRegistrationDto,
+) -> Result<RegisteredClientDto, ClientRegistrationError> {
+    let grant_types = checked_client_settings(
+        &registration.redirect_uris,
+        &registration.allowed_scopes,
+        &registration.grant_types,
+        registration.confidential,
+    )?;
+    let client_secret = registration.confidential.then(random_token);
+    let client = OAuthClient {
+        id: Uuid::new_v4().simple().to_string(),
+        name: registration.name,
+        redirect_uris: RedirectUris(registration.redirect_uris),
+        created_at: Utc::now(),
+        secret_hash: client_secret
+            .as_deref()
+            .map(|secret| passwd_hasher.hash_password(secret))
+            .transpose()?,
+        allowed_scopes: registration.allowed_scopes.join(" "),
+        grant_types,
+        previous_secret_hash: None,
+        previous_secret_expires_at: None,
+    };
+    oauth_repo.create_client(&client).await?;
+    Ok(RegisteredClientDto {
+        client,
+        client_secret,
+    })
+}
+
+/// Checks the settings of a client and returns its space separated grant types. Redirect URIs
+/// must be fragment free `https` URLs, or `http` ones on the loopback interface for native apps
+/// (RFC 8252). Without grant types, the client gets every grant it can
+/// use: the device grant, the authorization code grant given redirect URIs, and the client
+/// credentials and token exchange grants if confidential.
+pub fn checked_client_settings(
+    redirect_uris: &[String],
+    allowed_scopes: &[String],
+    grant_types: &[String],
+    confidential: bool,
+) -> Result<String, ClientRegistrationError> {
+    let invalid = |description: String| Err(ClientRegistrationError::InvalidMetadata(description));
+    for redirect_uri in redirect_uris {
+        match Url::parse(redirect_uri) {
+            Ok(url) if url.fragment().is_none() && is_secure_redirect(&url) => {}
+            _ => {
+                return Err(ClientRegistrationError::InvalidRedirectUri(
+                    redirect_uri.clone(),
+                ))
+            }
+        }
+    }
+    for scope in allowed_scopes {
+        if !is_scope_token(scope) {
+            return invalid(format!("Invalid scope: {:?}", scope));
+        }
+    }
+    if grant_types.is_empty() {
+        let inferred: Vec<&str> = GRANT_TYPES
+            .iter()
+            .copied()
+            .filter(|&grant_type| match grant_type {
+                "authorization_code" => !redirect_uris.is_empty(),
+                "client_credentials" | TOKEN_EXCHANGE_GRANT_TYPE => confidential,
+                _ => true,
+            })
+            .collect();
+        return Ok(inferred.join(" "));
+    }
+    let mut checked: Vec<&str> = Vec::new();
+    for grant_type in grant_types {
+        match grant_type.as_str() {
+            grant_type if !GRANT_TYPES.contains(&grant_type) => {
+                return invalid(format!("Unsupported grant type: {}", grant_type))
+            }
+            "authorization_code" if redirect_uris.is_empty() => {
+                return invalid("The authorization_code grant needs a redirect URI".into())
+            }
+            "client_credentials" | TOKEN_EXCHANGE_GRANT_TYPE if !confidential => {
+                return invalid(format!(
+                    "The {} grant needs a confidential client",
+                    grant_type
+                ))
+            }
+            grant_type if checked.contains(&grant_type) => {}
+            grant_type => checked.push(grant_type),
+        }
+    }
+    Ok(checked.join(" "))
+}
+
+fn is_secure_redirect(url: &Url) -> bool {
+    match (url.scheme(), url.host()) {
+        ("https", Some(_)) => true,
+        ("http", Some(Host::Domain(domain))) => domain == "localhost",
+        ("http", Some(Host::Ipv4(ip))) => ip.is_loopback(),
+        ("http", Some(Host::Ipv6(ip))) => ip.is_loopback(),
+        _ => false,
+    }
+}
+
+/// Authorization request whose client and redirect URI are valid.
+pub struct AuthorizeRequest {
+    pub client: OAuthClient,
+    pub redirect_uri: Url,
+    pub state: Option<String>,
+    pub code_challenge: String,
+}
+
+impl AuthorizeRequest {
+    pub fn reject(&self, error: OAuthError) -> AuthorizeError {
+        AuthorizeError::Redirect {
+            redirect_uri: self.redirect_uri.clone(),
+            state: self.state.clone(),
+            error,
+        }
+    }
+}
+
+pub async fn check_authorize_request(
+    oauth_repo: &dyn OAuthRepo,
+    params: &AuthorizeParams,
+) -> Result<AuthorizeRequest, AuthorizeError> {
+    let client_id = params
+        .client_id
+        .as_deref()
+        .ok_or_else(|| missing("client_id"))?;
+    let client = oauth_repo
+        .get_client(client_id)
+        .await
+        .map_err(log_err)
+        .map_err(|_| OAuthError::ServerError)?
+        .ok_or_else(|| OAuthError::InvalidRequest("Unknown client".into()))?;
+    let redirect_uri = params
+        .redirect_uri
+        .as_deref()
+        .ok_or_else(|| missing("redirect_uri"))?;
+    if !client.redirect_uris.contains(redirect_uri) {
+        return Err(OAuthError::InvalidRequest(
+            "redirect_uri is not registered for the client".into(),
+        )
+        .into());
+    }
+    let redirect_uri = Url::parse(redirect_uri)
+        .map_err(|_| OAuthError::InvalidRequest("Invalid redirect_uri".into()))?;
+
+    let mut request = AuthorizeRequest {
+        client,
+        redirect_uri,
+        state: params.state.clone(),
+        code_challenge: String::new(),
+    };
+    match params.response_type.as_deref() {
+        Some("code") => {}
+        Some(other) => {
+            return Err(request.reject(OAuthError::UnsupportedResponseType(other.into())))
+        }
+        None => return Err(request.reject(missing("response_type"))),
+    }
+    if !request.client.allows_grant_type("authorization_code") {
+        return Err(request.reject(OAuthError::UnauthorizedClient));
+    }
+    if params.code_challenge_method.as_deref() != Some(CODE_CHALLENGE_METHOD) {
+        return Err(request.reject(OAuthError::InvalidRequest(format!(
+            "PKCE is required, with code_challenge_method={}",
+            CODE_CHALLENGE_METHOD
+        ))));
+    }
+    match params.code_challenge.as_deref() {
+        Some(challenge) if is_s256_challenge(challenge) => {
+            request.code_challenge = challenge.to_string()
+        }
+        _ => {
+            return Err(request.reject(OAuthError::InvalidRequest(
+                "Missing or malformed code_challenge".into(),
+            )))
+        }
+    }
+    if let Some(scope) = &params.scope {
+        if !request.client.allows_scope(scope) {
+            return Err(request.reject(OAuthError::InvalidScope(scope.clone())));
+        }
+    }
+    Ok(request)
+}
+
+pub fn missing(param: &str) -> OAuthError {
+    OAuthError::InvalidRequest(format!("Missing {}", param))
+}
+
+/// RFC 7636 `S256` challenge of a verifier.
+pub fn pkce_s256(code_verifier: &str) -> String {
+    base64::encode_config(
+        digest::digest(&digest::SHA256, code_verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+fn is_code_verifier(s: &str) -> bool {
+    (43..=128).contains(&s.len())
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._~".contains(&b))
+}
+
+/// RFC 6749 `scope-token`: printable ASCII but space, `"` and `\`.
+fn is_scope_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| (0x21..=0x7e).contains(&b) && b != b'"' && b != b'\\')
+}
+
+/// Base64url encoded SHA-256 digest.
+fn is_s256_challenge(s: &str) -> bool {
+    s.len() == 43
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+fn login_page(
+    params: &AuthorizeParams,
+    client: &OAuthClient,
+    providers: &[UpstreamProviderSettings],
+    error: Option<&str>,
+) -> HttpResponse {
+    let fields: Vec<(&str, &String)> = [
+        ("response_type", &params.response_type),
+        ("client_id", &params.client_id),
+        ("redirect_uri", &params.redirect_uri),
+        ("scope", &params.scope),
+        ("state", &params.state),
+        ("code_challenge", &params.code_challenge),
+        ("code_challenge_method", &params.code_challenge_method),
+        ("nonce", &params.nonce),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| value.as_ref().map(|value| (name, value)))
+    .collect();
+    let hidden: String = fields
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                r#"<input type="hidden" name="{}" value="{}">"#,
+                name,
+                escape_html(value)
+            )
+        })
+        .collect();
+    let error = error
+        .map(|error| format!(r#"<p class="error">{}</p>"#, escape_html(error)))
+        .unwrap_or_default();
+    // The authorization request is carried over to the upstream sign-in
+    let upstream_query = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(&fields)
+        .finish();
+    let upstream: String = providers
+        .iter()
+        .map(|provider| {
+            format!(
+                r#"<p><a href="upstream/{}/login?{}">Sign in with {}</a></p>"#,
+                provider.id,
+                escape_html(&upstream_query),
+                escape_html(&provider.name)
+            )
+        })
+        .collect();
+    let content = format!(
+        r#"<h1>Sign in to {}</h1>
+{}
+<form method="post" action="authorize">
+{}
+<input name="username" placeholder="Username" autocomplete="username" required autofocus>
+<input name="password" type="password" placeholder="Password" autocomplete="current-password" required>
+<input type="submit" value="Sign in">
+</form>
+{}"#,
+        escape_html(&client.name),
+        error,
+        hidden,
+        upstream
+    );
+    let status = if error.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::UNAUTHORIZED
+    };
+    html_page(status, "Sign in", &content)
+}
+
+/// Page of the endpoints users interact with, sent uncached and unframed.
+pub fn html_page(status: StatusCode, title: &str, content: &str) -> HttpResponse {
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>{}</title>
+<style>body{{font-family:sans-serif;max-width:20em;margin:4em auto}}input{{display:block;width:100%;margin:.5em 0}}.error{{color:#b00}}</style>
+</head>
+<body>
+{}
+</body>
+</html>"#,
+        escape_html(title),
+        content
+    );
+    HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        // The forms must not be framed by another site to trick users into signing in
+        .insert_header(("X-Frame-Options", "DENY"))
+        .insert_header((
+            "Content-Security-Policy",
+            "default-src 'none'; style-src 'unsafe-inline'; frame-ancestors 'none'",
+        ))
+        .body(body)
+}
+
+pub fn escape_html(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#x27;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use actix_web::web::Data;
+use actix_web::HttpResponse;
+use chrono::DateTime;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::auth::BearerToken;
+use crate::crypto::TokenSigner;
+use crate::errors::oauth::OAuthError;
+use crate::models::oidc::IdTokenClaims;
+use crate::models::oidc::JwkDto;
+use crate::models::oidc::JwksDto;
+use crate::models::oidc::OpenIdConfigurationDto;
+use crate::models::oidc::UserClaims;
+use crate::models::oidc::UserInfoDto;
+use crate::models::user::User;
+use crate::repositories::user::UserRepo;
+use crate::services::dpop::DPOP_SIGNING_ALGS;
+use crate::services::oauth::CODE_CHALLENGE_METHOD;
+use crate::services::oauth::GRANT_TYPES;
+
+/// Scope requesting an ID token, and giving access to the userinfo endpoint
+pub const OPENID_SCOPE: &str = "openid";
+pub const PROFILE_SCOPE: &str = "profile";
+pub const EMAIL_SCOPE: &str = "email";
+
+pub async fn get_openid_configuration(token_signer: Data<TokenSigner>) -> HttpResponse {
+    HttpResponse::Ok().json(openid_configuration(token_signer.issuer()))
+}
+
+pub async fn get_jwks(token_signer: Data<TokenSigner>) -> HttpResponse {
+    HttpResponse::Ok().json(JwksDto {
+        keys: vec![JwkDto {
+            kty: "OKP".into(),
+            crv: "Ed25519".into(),
+            x: base64::encode_config(token_signer.public_key(), base64::URL_SAFE_NO_PAD),
+            kid: token_signer.kid().into(),
+            alg: "EdDSA".into(),
+            use_: "sig".into(),
+        }],
+    })
+}
+
+/// Claims of the user the access token was issued for, limited to its scopes.
+#[tracing::instrument(skip_all)]
+pub async fn get_userinfo(
+    user_repo: Data<dyn UserRepo>,
+    BearerToken(claims): BearerToken,
+) -> Result<HttpResponse, OAuthError> {
+    if !has_scope(claims.scope.as_deref(), OPENID_SCOPE) {
+        return Err(OAuthError::InsufficientScope(OPENID_SCOPE.into()));
+    }
+    // Tokens of the client credentials grant have a client rather than a user as subject
+    let user_id = Uuid::parse_str(&claims.sub)
+        .ok()
+        .filter(|_| claims.username.is_some())
+        .ok_or_else(|| OAuthError::InvalidToken("The access token has no user".into()))?;
+    let user = user_repo
+        .get_user_by_id(&user_id)
+        .await
+        .map_err(|_| OAuthError::InvalidToken("User no longer exists".into()))?;
+    Ok(HttpResponse::Ok().json(UserInfoDto {
+        sub: user.id.to_string(),
+        user: user_claims(&user, claims.scope.as_deref()),
+    }))
+}
+
+/// Endpoints are published under the issuer, which must be the public URL of the service.
+pub fn openid_configuration(issuer: &str) -> OpenIdConfigurationDto {
+    let base = issuer.trim_end_matches('/');
+    let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+    OpenIdConfigurationDto {
+        issuer: issuer.into(),
+        authorization_endpoint: format!("{}/oauth/authorize", base),
+        token_endpoint: format!("{}/oauth/token", base),
+        device_authorization_endpoint: format!("{}/oauth/device_authorization", base),
+        userinfo_endpoint: format!("{}/userinfo", base),
+        registration_endpoint: format!("{}/oauth/register", base),
+        jwks_uri: format!("{}/.well-known/jwks.json", base),
+        scopes_supported: strings(&[OPENID_SCOPE, PROFILE_SCOPE, EMAIL_SCOPE]),
+        response_types_supported: strings(&["code"]),
+        grant_types_supported: strings(GRANT_TYPES),
+        subject_types_supported: strings(&["public"]),
+        id_token_signing_alg_values_supported: strings(&["EdDSA"]),
+        token_endpoint_auth_methods_supported: strings(&[
+            "client_secret_basic",
+            "client_secret_post",
+            "none",
+        ]),
+        code_challenge_methods_supported: strings(&[CODE_CHALLENGE_METHOD]),
+        dpop_signing_alg_values_supported: strings(DPOP_SIGNING_ALGS),
+        claims_supported: strings(&[
+            "iss",
+            "sub",
+            "aud",
+            "iat",
+            "exp",
+            "auth_time",
+            "nonce",
+            "preferred_username",
+            "email",
+            "email_verified",
+        ]),
+    }
+}
+
+/// ID token of the user for the client, carrying the claims of the granted scopes.
+pub fn issue_id_token(
+    token_signer: &TokenSigner,
+    ttl: Duration,
+    user: &User,
+    client_id: &str,
+    nonce: Option<&str>,
+    auth_time: DateTime<Utc>,
+    scope: Option<&str>,
+) -> anyhow::Result<String> {
+    let now = Utc::now().timestamp();
+    token_signer.sign(&IdTokenClaims {
+        iss: token_signer.issuer().into(),
+        sub: user.id.to_string(),
+        aud: client_id.into(),
+        iat: now,
+        exp: now + ttl.as_secs() as i64,
+        auth_time: auth_time.timestamp(),
+        nonce: nonce.map(Into::into),
+        user: user_claims(user, scope),
+    })
+}
+
+pub fn has_scope(scope: Option<&str>, wanted: &str) -> bool {
+    scope.is_some_and(|scope| scope.split_whitespace().any(|scope| scope == wanted))
+}
+
+fn user_claims(user: &User, scope: Option<&str>) -> UserClaims {
+    let mut claims = UserClaims::default();
+    if has_scope(scope, PROFILE_SCOPE) {
+        claims.preferred_username = Some(user.username.clone());
+    }
+    if has_scope(scope, EMAIL_SCOPE) {
+        if let Some(email) = &user.email {
+            claims.email = Some(email.clone());
+            // Addresses are taken as given at sign up, nothing verifies them
+            claims.email_verified = Some(false);
+        }
+    }
+    claims
+}
@@ -0,0 +1,34 @@
+use actix_web::web::Json;
+use utoipa::openapi::OpenApi as OpenApiDoc;
+use utoipa::OpenApi;
+
+use crate::services::health;
+use crate::services::user;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Auth UService", description = "Authentication micro service"),
+    paths(
+        user::get_user_by_id,
+        user::post_user,
+        user::delete_user,
+        health::healthz,
+        health::readyz,
+    ),
+    tags(
+        (name = "users", description = "User accounts"),
+        (name = "health", description = "Liveness and readiness probes"),
+    )
+)]
+pub struct ApiDoc;
+
+pub async fn openapi_json() -> Json<OpenApiDoc> {
+    Json(ApiDoc::openapi())
+}
+
+/// Swagger UI browsing the document at `/openapi.json`, only served by debug builds.
+#[cfg(debug_assertions)]
+pub fn swagger_ui() -> utoipa_swagger_ui::SwaggerUi {
+    utoipa_swagger_ui::SwaggerUi::new("/swagger-ui/{_:.*}")
+        .config(utoipa_swagger_ui::Config::from("/openapi.json"))
+}
@@ -0,0 +1,260 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::crypto::PasswordHasher;
+use crate::crypto::TokenSigner;
+use crate::errors::user::log_err;
+use crate::errors::user::UserServiceError;
+use crate::metrics::METRICS;
+use crate::models::audit::AuditEventType;
+use crate::models::audit::NewAuditEventBuilder;
+use crate::models::token::AccessTokenClaims;
+use crate::models::token::AccessTokenRespDto;
+use crate::models::token::Confirmation;
+use crate::models::token::IntrospectionRespDto;
+use crate::models::user::User;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::user::UserRepo;
+use crate::services::audit;
+use crate::services::dpop::DPOP_HEADER;
+
+/// Checks the password of the user and issues an access token.
+pub async fn authenticate(
+    user_repo: &dyn UserRepo,
+    audit_repo: &dyn AuditRepo,
+    passwd_hasher: &PasswordHasher,
+    token_signer: &TokenSigner,
+    access_token_ttl: Duration,
+    username: &str,
+    password_raw: &str,
+) -> Result<AccessTokenRespDto, UserServiceError> {
+    let user =
+        verify_credentials(user_repo, audit_repo, passwd_hasher, username, password_raw).await?;
+    issue_access_token(token_signer, access_token_ttl, &user, None, None)
+}
+
+/// Checks the password of the user, updating their last login. Failures are counted and audited,
+/// and don't tell unknown usernames apart from wrong passwords.
+#[tracing::instrument(skip_all, fields(username = %username))]
+pub async fn verify_credentials(
+    user_repo: &dyn UserRepo,
+    audit_repo: &dyn AuditRepo,
+    passwd_hasher: &PasswordHasher,
+    username: &str,
+    password_raw: &str,
+) -> Result<User, UserServiceError> {
+    let user = user_repo
+        .get_user_by_username(username)
+        .await
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    let verified = match &user {
+        Some(user) => passwd_hasher
+            .verify_password(password_raw, &user.password_hash)
+            .map_err(log_err)
+            .unwrap_or(false),
+        None => {
+            // Spend as long as for a known user, so timing doesn't reveal which usernames exist
+            let _ = passwd_hasher.hash_password(password_raw);
+            false
+        }
+    };
+    METRICS.record_login(verified);
+
+    let mut user = match user {
+        Some(user) if verified => user,
+        user => {
+            let mut event = NewAuditEventBuilder::default();
+            event
+                .event_type(AuditEventType::LoginFailed)
+                .details(json!({ "username": username }));
+            if let Some(user) = user {
+                event.subject_id(user.id);
+            }
+            audit::record(
+                audit_repo,
+                event
+                    .build()
+                    .map_err(log_err)
+                    .map_err(|_| UserServiceError::UnknownInternal)?,
+            )
+            .await
+            .map_err(log_err)
+            .map_err(|_| UserServiceError::UnknownInternal)?;
+            return Err(UserServiceError::InvalidCredentials);
+        }
+    };
+
+    user.last_login = Some(Utc::now());
+    user_repo
+        .update_user_by_id(&user.id, &user)
+        .await
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    audit::record(
+        audit_repo,
+        NewAuditEventBuilder::default()
+            .event_type(AuditEventType::LoginSucceeded)
+            .actor_id(user.id)
+            .subject_id(user.id)
+            .build()
+            .map_err(log_err)
+            .map_err(|_| UserServiceError::UnknownInternal)?,
+    )
+    .await
+    .map_err(log_err)
+    .map_err(|_| UserServiceError::UnknownInternal)?;
+    Ok(user)
+}
+
+/// Token acting on behalf of the user, through the client if any.
+pub fn issue_access_token(
+    token_signer: &TokenSigner,
+    access_token_ttl: Duration,
+    user: &User,
+    client_id: Option<&str>,
+    scope: Option<&str>,
+) -> Result<AccessTokenRespDto, UserServiceError> {
+    sign_access_token(
+        token_signer,
+        access_token_ttl,
+        user.id.to_string(),
+        Some(user.username.clone()),
+        client_id,
+        scope,
+    )
+}
+
+/// Token of the client acting for itself, which is its subject.
+pub fn issue_client_token(
+    token_signer: &TokenSigner,
+    access_token_ttl: Duration,
+    client_id: &str,
+    scope: Option<&str>,
+) -> Result<AccessTokenRespDto, UserServiceError> {
+    sign_access_token(
+        token_signer,
+        access_token_ttl,
+        client_id.to_string(),
+        None,
+        Some(client_id),
+        scope,
+    )
+}
+
+fn sign_access_token(
+    token_signer: &TokenSigner,
+    access_token_ttl: Duration,
+    sub: String,
+    username: Option<String>,
+    client_id: Option<&str>,
+    scope: Option<&str>,
+) -> Result<AccessTokenRespDto, UserServiceError> {
+    let now = Utc::now().timestamp();
+    sign_claims(
+        token_signer,
+        &AccessTokenClaims {
+            iss: token_signer.issuer().to_string(),
+            sub,
+            username,
+            iat: now,
+            exp: now + access_token_ttl.as_secs() as i64,
+            jti: Uuid::new_v4(),
+            client_id: client_id.map(Into::into),
+            scope: scope.map(Into::into),
+            aud: None,
+            act: None,
+            cnf: None,
+        },
+    )
+}
+
+/// Signs claims built by the caller, for tokens the helpers above don't cover.
+pub fn sign_claims(
+    token_signer: &TokenSigner,
+    claims: &AccessTokenClaims,
+) -> Result<AccessTokenRespDto, UserServiceError> {
+    let access_token = token_signer
+        .sign(claims)
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    Ok(AccessTokenRespDto {
+        access_token,
+        token_type: "Bearer".into(),
+        expires_in: claims.exp - claims.iat,
+        scope: claims.scope.clone(),
+        id_token: None,
+        issued_token_type: None,
+    })
+}
+
+/// Binds a token just issued to the key of the DPoP proof of the token request, rather than
+/// threading the key through every grant.
+pub fn bind_to_key(
+    token_signer: &TokenSigner,
+    token: AccessTokenRespDto,
+    jkt: String,
+) -> Result<AccessTokenRespDto, UserServiceError> {
+    let mut claims: AccessTokenClaims = token_signer
+        .verify(&token.access_token)
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    claims.cnf = Some(Confirmation { jkt });
+    Ok(AccessTokenRespDto {
+        token_type: DPOP_HEADER.into(),
+        id_token: token.id_token,
+        issued_token_type: token.issued_token_type,
+        ..sign_claims(token_signer, &claims)?
+    })
+}
+
+/// Claims of `token` if it's an access token signed by us, unexpired and not revoked.
+pub async fn verify_access_token(
+    token_signer: &TokenSigner,
+    oauth_repo: &dyn OAuthRepo,
+    token: &str,
+) -> Result<Option<AccessTokenClaims>, UserServiceError> {
+    let claims = match token_signer.verify::<AccessTokenClaims>(token) {
+        Ok(claims) => claims,
+        Err(_) => return Ok(None),
+    };
+    let revoked = oauth_repo
+        .is_token_revoked(&claims.jti)
+        .await
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    if revoked {
+        return Ok(None);
+    }
+    // All the tokens of a user are revoked when their password changes. `iat` is in seconds, like
+    // the cutoff, so tokens issued within the second of the change are kept, rather than revoking
+    // those issued right after it.
+    if let Ok(user_id) = Uuid::try_parse(&claims.sub) {
+        let revoked_before = oauth_repo
+            .get_user_tokens_revoked_before(&user_id)
+            .await
+            .map_err(log_err)
+            .map_err(|_| UserServiceError::UnknownInternal)?;
+        if revoked_before.is_some_and(|before| claims.iat < before.timestamp()) {
+            return Ok(None);
+        }
+    }
+    Ok(Some(claims))
+}
+
+/// Tokens which are malformed, expired, revoked or not signed by us are reported inactive.
+pub async fn introspect(
+    token_signer: &TokenSigner,
+    oauth_repo: &dyn OAuthRepo,
+    token: &str,
+) -> Result<IntrospectionRespDto, UserServiceError> {
+    let claims = verify_access_token(token_signer, oauth_repo, token).await?;
+    Ok(IntrospectionRespDto {
+        active: claims.is_some(),
+        claims,
+    })
+}
@@ -0,0 +1,706 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
+
+use actix_web::cookie::Cookie;
+use actix_web::cookie::SameSite;
+use actix_web::http::header::LOCATION;
+use actix_web::web::Data;
+use actix_web::web::Json;
+use actix_web::web::Path;
+use actix_web::web::Query;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use chrono::Utc;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::Algorithm;
+use jsonwebtoken::DecodingKey;
+use jsonwebtoken::Validation;
+use ring::constant_time::verify_slices_are_equal;
+use serde_json::json;
+use url::Url;
+use uuid::Uuid;
+
+use crate::auth::AdminAuth;
+use crate::crypto::random_token;
+use crate::crypto::token_hash;
+use crate::crypto::PasswordHasher;
+use crate::crypto::TokenSigner;
+use crate::errors::oauth::AuthorizeError;
+use crate::errors::oauth::OAuthError;
+use crate::errors::user::log_err;
+use crate::errors::user::UserRepoError;
+use crate::errors::user::UserServiceError;
+use crate::errors::user::UserServiceResult;
+use crate::models::audit::AuditEventType;
+use crate::models::audit::NewAuditEventBuilder;
+use crate::models::identity::LinkIdentityReqDto;
+use crate::models::identity::LinkedIdentity;
+use crate::models::oauth::AuthorizeParams;
+use crate::models::oauth::UpstreamCallbackParams;
+use crate::models::oauth::UpstreamLogin;
+use crate::models::oidc::UpstreamConfigurationDto;
+use crate::models::oidc::UpstreamIdTokenClaims;
+use crate::models::oidc::UpstreamTokenRespDto;
+use crate::models::user::User;
+use crate::models::user::UserBuilder;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::identity::IdentityRepo;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::user::UserRepo;
+use crate::services::audit;
+use crate::services::oauth::check_authorize_request;
+use crate::services::oauth::pkce_s256;
+use crate::services::oauth::redirect_with_code;
+use crate::services::oauth::CODE_CHALLENGE_METHOD;
+use crate::services::user::find_user;
+use crate::settings::OAuthSettings;
+use crate::settings::UpstreamProviderSettings;
+
+/// Time given to the user to sign in at the provider
+pub const UPSTREAM_LOGIN_TTL_SECS: i64 = 600;
+/// Binds the callback to the browser which started the sign-in
+pub const UPSTREAM_STATE_COOKIE: &str = "upstream_state";
+const UPSTREAM_COOKIE_PATH: &str = "/oauth/upstream";
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Discovery documents and keys are fetched again after this long, or sooner for an unknown key
+const METADATA_TTL: Duration = Duration::from_secs(3600);
+const USERNAME_MAX_LEN: usize = 30;
+
+/// Discovery document and keys of a provider.
+struct ProviderMetadata {
+    configuration: UpstreamConfigurationDto,
+    jwks: JwkSet,
+    fetched_at: Instant,
+}
+
+/// Client of the upstream OpenID Connect providers, caching their metadata.
+pub struct UpstreamClient {
+    http: reqwest::Client,
+    metadata: RwLock<HashMap<String, Arc<ProviderMetadata>>>,
+}
+
+impl UpstreamClient {
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            http: reqwest::Client::builder().timeout(HTTP_TIMEOUT).build()?,
+            metadata: RwLock::new(HashMap::new()),
+        })
+    }
+
+    async fn metadata(
+        &self,
+        provider: &UpstreamProviderSettings,
+        refresh: bool,
+    ) -> anyhow::Result<Arc<ProviderMetadata>> {
+        if !refresh {
+            let cached = self
+                .metadata
+                .read()
+                .map_err(|_| anyhow!("Poisoned metadata cache"))?
+                .get(&provider.id)
+                .filter(|metadata| metadata.fetched_at.elapsed() < METADATA_TTL)
+                .cloned();
+            if let Some(metadata) = cached {
+                return Ok(metadata);
+            }
+        }
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            provider.issuer.trim_end_matches('/')
+        );
+        let configuration: UpstreamConfigurationDto = self
+            .http
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("Invalid discovery document at {}", url))?;
+        // Per OpenID Connect Discovery, the document must be for the configured issuer
+        if configuration.issuer != provider.issuer {
+            bail!(
+                "Discovery document of {} is for issuer {}",
+                provider.issuer,
+                configuration.issuer
+            );
+        }
+        let jwks: JwkSet = self
+            .http
+            .get(&configuration.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("Invalid JWK set at {}", configuration.jwks_uri))?;
+        let metadata = Arc::new(ProviderMetadata {
+            configuration,
+            jwks,
+            fetched_at: Instant::now(),
+        });
+        self.metadata
+            .write()
+            .map_err(|_| anyhow!("Poisoned metadata cache"))?
+            .insert(provider.id.clone(), metadata.clone());
+        Ok(metadata)
+    }
+
+    /// Redeems the code issued by the provider, and returns the claims of the checked ID token.
+    pub async fn sign_in(
+        &self,
+        provider: &UpstreamProviderSettings,
+        redirect_uri: &str,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> anyhow::Result<UpstreamIdTokenClaims> {
+        let metadata = self.metadata(provider, false).await?;
+        let mut request = self.http.post(&metadata.configuration.token_endpoint);
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("code_verifier", code_verifier),
+        ];
+        match &provider.client_secret {
+            // client_secret_basic, with both parts form encoded as RFC 6749 requires
+            Some(client_secret) => {
+                let encode = |s: &str| -> String {
+                    url::form_urlencoded::byte_serialize(s.as_bytes()).collect()
+                };
+                request =
+                    request.basic_auth(encode(&provider.client_id), Some(encode(client_secret)))
+            }
+            None => form.push(("client_id", &provider.client_id)),
+        }
+        let response = request.form(&form).send().await?;
+        if !response.status().is_success() {
+            bail!(
+                "Token request to {} failed with {}: {}",
+                provider.id,
+                response.status(),
+                response.text().await.unwrap_or_default()
+            );
+        }
+        let id_token = response
+            .json::<UpstreamTokenRespDto>()
+            .await?
+            .id_token
+            .ok_or_else(|| anyhow!("No ID token from {}", provider.id))?;
+
+        let header = jsonwebtoken::decode_header(&id_token)?;
+        // Asymmetric algorithms only, HMAC keys being client secrets
+        if !matches!(
+            header.alg,
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512
+                | Algorithm::ES256
+                | Algorithm::ES384
+                | Algorithm::EdDSA
+        ) {
+            bail!("Unsupported ID token algorithm {:?}", header.alg);
+        }
+        // The provider may have rotated its keys since they were fetched
+        let mut metadata = metadata;
+        let mut refreshed = false;
+        let jwk = loop {
+            let jwk = match &header.kid {
+                Some(kid) => metadata.jwks.find(kid).cloned(),
+                None if metadata.jwks.keys.len() == 1 => metadata.jwks.keys.first().cloned(),
+                None => None,
+            };
+            match jwk {
+                Some(jwk) => break jwk,
+                None if !refreshed => {
+                    metadata = self.metadata(provider, true).await?;
+                    refreshed = true;
+                }
+                None => bail!("No key of {} for kid {:?}", provider.id, header.kid),
+            }
+        };
+        let key = DecodingKey::from_jwk(&jwk)?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&provider.issuer]);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims =
+            jsonwebtoken::decode::<UpstreamIdTokenClaims>(&id_token, &key, &validation)?.claims;
+        let nonce_matches = claims.nonce.as_deref().is_some_and(|claimed| {
+            verify_slices_are_equal(claimed.as_bytes(), nonce.as_bytes()).is_ok()
+        });
+        if !nonce_matches {
+            bail!("The ID token of {} is for another nonce", provider.id);
+        }
+        Ok(claims)
+    }
+}
+
+/// Starts the sign-in at an upstream provider, for an authorization request to complete once
+/// back.
+#[tracing::instrument(skip(oauth_repo, oauth_settings, upstream, token_signer))]
+pub async fn get_upstream_login(
+    oauth_repo: Data<dyn OAuthRepo>,
+    oauth_settings: Data<OAuthSettings>,
+    upstream: Data<UpstreamClient>,
+    token_signer: Data<TokenSigner>,
+    provider: Path<String>,
+    params: Query<AuthorizeParams>,
+) -> Result<HttpResponse, AuthorizeError> {
+    let request = check_authorize_request(oauth_repo.as_ref(), &params).await?;
+    let provider = match find_provider(&oauth_settings, &provider) {
+        Some(provider) => provider,
+        None => {
+            return Err(request.reject(OAuthError::InvalidRequest(
+                "Unknown upstream provider".into(),
+            )))
+        }
+    };
+    let metadata = upstream
+        .metadata(provider, false)
+        .await
+        .map_err(log_err)
+        .map_err(|_| request.reject(OAuthError::ServerError))?;
+    let mut location = Url::parse(&metadata.configuration.authorization_endpoint)
+        .map_err(log_err)
+        .map_err(|_| request.reject(OAuthError::ServerError))?;
+
+    let state = random_token();
+    let nonce = random_token();
+    let code_verifier = random_token();
+    let authorize_params = serde_json::to_string(&params.into_inner())
+        .map_err(log_err)
+        .map_err(|_| request.reject(OAuthError::ServerError))?;
+    oauth_repo
+        .store_upstream_login(&UpstreamLogin {
+            state_hash: token_hash(&state),
+            provider: provider.id.clone(),
+            nonce: nonce.clone(),
+            code_verifier: code_verifier.clone(),
+            authorize_params,
+            expires_at: Utc::now() + chrono::Duration::seconds(UPSTREAM_LOGIN_TTL_SECS),
+        })
+        .await
+        .map_err(log_err)
+        .map_err(|_| request.reject(OAuthError::ServerError))?;
+
+    location
+        .query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &provider.client_id)
+        .append_pair("redirect_uri", &callback_uri(&token_signer, provider))
+        .append_pair("scope", &provider.scopes.join(" "))
+        .append_pair("state", &state)
+        .append_pair("nonce", &nonce)
+        .append_pair("code_challenge", &pkce_s256(&code_verifier))
+        .append_pair("code_challenge_method", CODE_CHALLENGE_METHOD);
+    let cookie = Cookie::build(UPSTREAM_STATE_COOKIE, state)
+        .path(UPSTREAM_COOKIE_PATH)
+        .http_only(true)
+        .secure(token_signer.issuer().starts_with("https://"))
+        // Lax, for the cookie to come back with the redirect from the provider
+        .same_site(SameSite::Lax)
+        .max_age(actix_web::cookie::time::Duration::seconds(
+            UPSTREAM_LOGIN_TTL_SECS,
+        ))
+        .finish();
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, location.as_str()))
+        .cookie(cookie)
+        .finish())
+}
+
+/// Completes the sign-in at an upstream provider, provisioning the user on their first one, and
+/// resumes the authorization request.
+#[tracing::instrument(skip_all, fields(provider = %provider))]
+#[allow(clippy::too_many_arguments)]
+pub async fn get_upstream_callback(
+    req: HttpRequest,
+    user_repo: Data<dyn UserRepo>,
+    identity_repo: Data<dyn IdentityRepo>,
+    audit_repo: Data<dyn AuditRepo>,
+    oauth_repo: Data<dyn OAuthRepo>,
+    passwd_hasher: Data<PasswordHasher>,
+    oauth_settings: Data<OAuthSettings>,
+    upstream: Data<UpstreamClient>,
+    token_signer: Data<TokenSigner>,
+    provider: Path<String>,
+    callback: Query<UpstreamCallbackParams>,
+) -> Result<HttpResponse, AuthorizeError> {
+    let invalid_state = || OAuthError::InvalidRequest("Invalid or expired sign-in state".into());
+    let state = callback.state.as_deref().ok_or_else(invalid_state)?;
+    // Checked against the cookie, so the callback can't be completed in another browser
+    let cookie = req
+        .cookie(UPSTREAM_STATE_COOKIE)
+        .ok_or_else(invalid_state)?;
+    if verify_slices_are_equal(cookie.value().as_bytes(), state.as_bytes()).is_err() {
+        return Err(invalid_state().into());
+    }
+    let login = oauth_repo
+        .take_upstream_login(&token_hash(state))
+        .await
+        .map_err(log_err)
+        .map_err(|_| OAuthError::ServerError)?
+        .filter(|login| login.provider == *provider && login.expires_at > Utc::now())
+        .ok_or_else(invalid_state)?;
+    let provider = find_provider(&oauth_settings, &login.provider).ok_or_else(invalid_state)?;
+    let params: AuthorizeParams = serde_json::from_str(&login.authorize_params)
+        .map_err(log_err)
+        .map_err(|_| OAuthError::ServerError)?;
+    // The client may have changed meanwhile
+    let request = check_authorize_request(oauth_repo.as_ref(), &params).await?;
+
+    if let Some(error) = &callback.error {
+        tracing::info!(
+            error = %error,
+            description = ?callback.error_description,
+            "Upstream sign-in failed"
+        );
+        return Err(request.reject(OAuthError::AccessDenied));
+    }
+    let code = match callback.code.as_deref() {
+        Some(code) => code,
+        None => return Err(request.reject(OAuthError::AccessDenied)),
+    };
+    let claims = match upstream
+        .sign_in(
+            provider,
+            &callback_uri(&token_signer, provider),
+            code,
+            &login.code_verifier,
+            &login.nonce,
+        )
+        .await
+    {
+        Ok(claims) => claims,
+        Err(err) => {
+            tracing::warn!(error = ?err, "Upstream sign-in rejected");
+            return Err(request.reject(OAuthError::AccessDenied));
+        }
+    };
+
+    let identity = identity_repo
+        .get_identity(&provider.id, &claims.sub)
+        .await
+        .map_err(log_err)
+        .map_err(|_| request.reject(OAuthError::ServerError))?;
+    let user = match identity {
+        Some(identity) => user_repo
+            .get_user_by_id(&identity.user_id)
+            .await
+            .map_err(log_err)
+            .map_err(|_| request.reject(OAuthError::ServerError))?,
+        None => provision_user(
+            user_repo.as_ref(),
+            identity_repo.as_ref(),
+            audit_repo.as_ref(),
+            &passwd_hasher,
+            provider,
+            &claims,
+        )
+        .await
+        .map_err(|err| request.reject(err.into()))?,
+    };
+    let user = record_login(user_repo.as_ref(), audit_repo.as_ref(), provider, user)
+        .await
+        .map_err(|err| request.reject(err.into()))?;
+    tracing::info!(user_id = %user.id, "Signed in upstream");
+
+    let mut response = redirect_with_code(
+        oauth_repo.as_ref(),
+        &oauth_settings,
+        request,
+        &params,
+        &user,
+    )
+    .await?;
+    let mut removal = Cookie::named(UPSTREAM_STATE_COOKIE);
+    removal.set_path(UPSTREAM_COOKIE_PATH);
+    response
+        .add_removal_cookie(&removal)
+        .map_err(log_err)
+        .map_err(|_| OAuthError::ServerError)?;
+    Ok(response)
+}
+
+/// Identities linked to the user.
+pub async fn get_user_identities(
+    _admin: AdminAuth,
+    user_repo: Data<dyn UserRepo>,
+    identity_repo: Data<dyn IdentityRepo>,
+    user_id: Path<String>,
+) -> UserServiceResult<Vec<LinkedIdentity>> {
+    let user = find_user(user_repo.as_ref(), &user_id).await?;
+    let identities = identity_repo
+        .list_identities(&user.id)
+        .await
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    Ok(Json(identities))
+}
+
+/// Links an identity to the user, for them to sign in with it.
+#[tracing::instrument(skip(_admin, user_repo, identity_repo, oauth_settings))]
+pub async fn post_user_identity(
+    _admin: AdminAuth,
+    user_repo: Data<dyn UserRepo>,
+    identity_repo: Data<dyn IdentityRepo>,
+    oauth_settings: Data<OAuthSettings>,
+    user_id: Path<String>,
+    link: Json<LinkIdentityReqDto>,
+) -> UserServiceResult<LinkedIdentity> {
+    let user = find_user(user_repo.as_ref(), &user_id).await?;
+    let link = link.into_inner();
+    if find_provider(&oauth_settings, &link.provider).is_none() {
+        return Err(UserServiceError::InvalidIdentityFields(format!(
+            "Unknown upstream provider: {}",
+            link.provider
+        )));
+    }
+    if link.subject.is_empty() {
+        return Err(UserServiceError::InvalidIdentityFields(
+            "The subject can't be empty".into(),
+        ));
+    }
+    let identity = LinkedIdentity {
+        provider: link.provider,
+        subject: link.subject,
+        user_id: user.id,
+        email: link.email,
+        created_at: Utc::now(),
+    };
+    link_identity(identity_repo.as_ref(), &identity).await?;
+    tracing::info!("Identity linked");
+    Ok(Json(identity))
+}
+
+/// Unlinks an identity from the user. Signing in with it again provisions a new user.
+#[tracing::instrument(skip(_admin, identity_repo))]
+pub async fn delete_user_identity(
+    _admin: AdminAuth,
+    identity_repo: Data<dyn IdentityRepo>,
+    path: Path<(String, String, String)>,
+) -> UserServiceResult<()> {
+    let (user_id, provider, subject) = path.into_inner();
+    let user_id =
+        Uuid::parse_str(&user_id).map_err(|_| UserServiceError::InvalidId(user_id.clone()))?;
+    let unlinked = identity_repo
+        .unlink_identity(&user_id, &provider, &subject)
+        .await
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    if !unlinked {
+        return Err(UserServiceError::NoIdentityForUser(format!(
+            "{}/{}",
+            provider, subject
+        )));
+    }
+    tracing::info!("Identity unlinked");
+    Ok(Json(()))
+}
+
+fn find_provider<'a>(
+    oauth_settings: &'a OAuthSettings,
+    id: &str,
+) -> Option<&'a UpstreamProviderSettings> {
+    oauth_settings
+        .upstream_providers
+        .iter()
+        .find(|provider| provider.id == id)
+}
+
+fn callback_uri(token_signer: &TokenSigner, provider: &UpstreamProviderSettings) -> String {
+    format!(
+        "{}/oauth/upstream/{}/callback",
+        token_signer.issuer().trim_end_matches('/'),
+        provider.id
+    )
+}
+
+/// Creates the user of an identity signing in for the first time. The user has no usable
+/// password, until an administrator sets one. Should a concurrent first sign-in of the identity
+/// provision its user first, that user is returned instead.
+pub async fn provision_user(
+    user_repo: &dyn UserRepo,
+    identity_repo: &dyn IdentityRepo,
+    audit_repo: &dyn AuditRepo,
+    passwd_hasher: &PasswordHasher,
+    provider: &UpstreamProviderSettings,
+    claims: &UpstreamIdTokenClaims,
+) -> Result<User, UserServiceError> {
+    let username = available_username(user_repo, &username_hint(claims)).await?;
+    let password_hash = passwd_hasher
+        .hash_password(&random_token())
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    let mut user = UserBuilder::default()
+        .id(Uuid::new_v4())
+        .username(username)
+        .password_hash(password_hash)
+        .created_at(Utc::now())
+        .build()
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    // Addresses not known to be verified could be anyone's
+    if claims.email_verified == Some(true) {
+        user.email = claims.email.clone();
+    }
+    let identity = LinkedIdentity {
+        provider: provider.id.clone(),
+        subject: claims.sub.clone(),
+        user_id: user.id,
+        email: claims.email.clone(),
+        created_at: Utc::now(),
+    };
+    // Together, so a concurrent first sign-in can't leave a user without its identity
+    match identity_repo
+        .create_user_with_identity(&user, &identity)
+        .await
+    {
+        Ok(()) => {}
+        Err(err) if matches!(err.downcast_ref(), Some(UserRepoError::IdentityConflict(_))) => {
+            let linked = identity_repo
+                .get_identity(&provider.id, &claims.sub)
+                .await
+                .map_err(log_err)
+                .map_err(|_| UserServiceError::UnknownInternal)?
+                // Unlinked since, so no longer the user of the sign-in
+                .ok_or(UserServiceError::IdentityTaken)?;
+            return user_repo
+                .get_user_by_id(&linked.user_id)
+                .await
+                .map_err(log_err)
+                .map_err(|_| UserServiceError::UnknownInternal);
+        }
+        Err(err) => return Err(repo_error(err)),
+    }
+    audit::record(
+        audit_repo,
+        NewAuditEventBuilder::default()
+            .event_type(AuditEventType::UserCreated)
+            .subject_id(user.id)
+            .details(json!({ "username": user.username, "provider": provider.id }))
+            .build()
+            .map_err(log_err)
+            .map_err(|_| UserServiceError::UnknownInternal)?,
+    )
+    .await
+    .map_err(log_err)
+    .map_err(|_| UserServiceError::UnknownInternal)?;
+    tracing::info!(user_id = %user.id, "User provisioned");
+    Ok(user)
+}
+
+async fn record_login(
+    user_repo: &dyn UserRepo,
+    audit_repo: &dyn AuditRepo,
+    provider: &UpstreamProviderSettings,
+    mut user: User,
+) -> Result<User, UserServiceError> {
+    user.last_login = Some(Utc::now());
+    user_repo
+        .update_user_by_id(&user.id, &user)
+        .await
+        .map_err(log_err)
+        .map_err(|_| UserServiceError::UnknownInternal)?;
+    audit::record(
+        audit_repo,
+        NewAuditEventBuilder::default()
+            .event_type(AuditEventType::LoginSucceeded)
+            .actor_id(user.id)
+            .subject_id(user.id)
+            .details(json!({ "provider": provider.id }))
+            .build()
+            .map_err(log_err)
+            .map_err(|_| UserServiceError::UnknownInternal)?,
+    )
+    .await
+    .map_err(log_err)
+    .map_err(|_| UserServiceError::UnknownInternal)?;
+    Ok(user)
+}
+
+async fn link_identity(
+    identity_repo: &dyn IdentityRepo,
+    identity: &LinkedIdentity,
+) -> Result<(), UserServiceError> {
+    identity_repo
+        .link_identity(identity)
+        .await
+        .map_err(repo_error)
+}
+
+fn repo_error(err: anyhow::Error) -> UserServiceError {
+    match err.downcast_ref::<UserRepoError>() {
+        Some(UserRepoError::UsernameConflict(_)) => UserServiceError::UsernameTaken,
+        Some(UserRepoError::IdentityConflict(_)) => UserServiceError::IdentityTaken,
+        None => {
+            log_err(err);
+            UserServiceError::UnknownInternal
+        }
+    }
+}
+
+/// Username from the claims, restricted to the characters and length of usernames.
+fn username_hint(claims: &UpstreamIdTokenClaims) -> String {
+    let candidates = [
+        claims.preferred_username.as_deref(),
+        claims
+            .email
+            .as_deref()
+            .and_then(|email| email.split('@').next()),
+        claims.name.as_deref(),
+    ];
+    candidates
+        .into_iter()
+        .flatten()
+        .map(|candidate| {
+            candidate
+                .chars()
+                .map(|c| if c.is_whitespace() { '.' } else { c })
+                .filter(|c| c.is_ascii_alphanumeric() || "._-".contains(*c))
+                .take(USERNAME_MAX_LEN)
+                .collect::<String>()
+        })
+        .find(|username| username.len() >= 3)
+        .unwrap_or_else(|| "user".into())
+}
+
+/// The username, or the first free one with a numeric suffix.
+async fn available_username(
+    user_repo: &dyn UserRepo,
+    username: &str,
+) -> Result<String, UserServiceError> {
+    for n in 1..100 {
+        let candidate = match n {
+            1 => username.to_string(),
+            _ => {
+                let suffix = format!("-{}", n);
+                let prefix: String = username
+                    .chars()
+                    .take(USERNAME_MAX_LEN - suffix.len())
+                    .collect();
+                prefix + &suffix
+            }
+        };
+        if !user_repo
+            .contains_user_with_username(&candidate)
+            .await
+            .map_err(log_err)
+            .map_err(|_| UserServiceError::UnknownInternal)?
+        {
+            return Ok(candidate);
+        }
+    }
+    Err(UserServiceError::UsernameTaken)
+}
@@ -1,48 +1,161 @@
 use actix_web::web::Data;
 use actix_web::web::Json;
 use actix_web::web::Path;
+use chrono::SubsecRound;
 use chrono::Utc;
+use serde_json::json;
+use serde_json::Value;
 use uuid::Uuid;
 use validator::Validate;
 
 use crate::crypto::PasswordHasher;
 use crate::errors::user::log_err;
+use crate::errors::user::ProblemDetailsDto;
+use crate::errors::user::UserRepoError;
 use crate::errors::user::UserServiceError;
 use crate::errors::user::UserServiceResult;
+use crate::models::audit::AuditEventType;
+use crate::models::audit::NewAuditEventBuilder;
+use crate::models::user::User;
 use crate::models::user::UserBuilder;
 use crate::models::user::UserCreateReqDto;
 use crate::models::user::UserGetRespDto;
+use crate::models::user::UserUpdateReqDto;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::audit::UserChange;
+use crate::repositories::oauth::OAuthRepo;
 use crate::repositories::user::UserRepo;
+use crate::services::audit;
 
+#[utoipa::path(
+    get,
+    path = "/v1/users/{user_id}",
+    tag = "users",
+    params(("user_id" = Uuid, Path)),
+    responses(
+        (status = 200, body = UserGetRespDto),
+        (
+            status = 400,
+            description = "Invalid ID",
+            body = ProblemDetailsDto,
+            content_type = "application/problem+json",
+        ),
+        (
+            status = 404,
+            description = "No user for the ID",
+            body = ProblemDetailsDto,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+#[tracing::instrument(skip(user_repo))]
 pub async fn get_user_by_id(
     user_repo: Data<dyn UserRepo>,
     user_id: Path<String>,
 ) -> UserServiceResult<UserGetRespDto> {
-    let user_id_str = user_id.into_inner();
-    let user_id = Uuid::try_parse(&user_id_str)
-        .map_err(|_| UserServiceError::InvalidId(user_id_str.clone()))?;
-    let user = user_repo
-        .get_user_by_id(&user_id)
-        .await
-        .map_err(|_| UserServiceError::NoUserForId(user_id_str))?;
-    let user = UserGetRespDto::from(user);
-    Ok(Json(user))
+    let user = find_user(user_repo.as_ref(), &user_id).await?;
+    Ok(Json(UserGetRespDto::from(user)))
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/users",
+    tag = "users",
+    request_body = UserCreateReqDto,
+    responses(
+        (status = 200, description = "ID of the new user", body = Uuid),
+        (
+            status = 400,
+            description = "Invalid fields or username taken",
+            body = ProblemDetailsDto,
+            content_type = "application/problem+json",
+        ),
+        (
+            status = 500,
+            body = ProblemDetailsDto,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+#[tracing::instrument(skip_all)]
 pub async fn post_user(
     user_repo: Data<dyn UserRepo>,
+    audit_repo: Data<dyn AuditRepo>,
     passwd_hasher: Data<PasswordHasher>,
     user: Json<UserCreateReqDto>,
 ) -> UserServiceResult<Uuid> {
-    user.0
-        .validate()
+    let user_id = create_user(
+        user_repo.as_ref(),
+        audit_repo.as_ref(),
+        &passwd_hasher,
+        user.into_inner(),
+    )
+    .await?;
+    Ok(Json(user_id))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/users/{user_id}",
+    tag = "users",
+    params(("user_id" = Uuid, Path)),
+    responses(
+        (status = 200, body = ()),
+        (
+            status = 400,
+            description = "Invalid ID",
+            body = ProblemDetailsDto,
+            content_type = "application/problem+json",
+        ),
+        (
+            status = 404,
+            description = "No user for the ID",
+            body = ProblemDetailsDto,
+            content_type = "application/problem+json",
+        ),
+        (
+            status = 500,
+            body = ProblemDetailsDto,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+#[tracing::instrument(skip(audit_repo))]
+pub async fn delete_user(
+    audit_repo: Data<dyn AuditRepo>,
+    user_id: Path<String>,
+) -> UserServiceResult<()> {
+    remove_user(audit_repo.as_ref(), &user_id).await?;
+    Ok(Json(()))
+}
+
+// Operations shared by the REST handlers and the gRPC services
+
+pub fn parse_user_id(user_id: &str) -> Result<Uuid, UserServiceError> {
+    Uuid::try_parse(user_id).map_err(|_| UserServiceError::InvalidId(user_id.to_string()))
+}
+
+pub async fn find_user(user_repo: &dyn UserRepo, user_id: &str) -> Result<User, UserServiceError> {
+    user_repo
+        .get_user_by_id(&parse_user_id(user_id)?)
+        .await
+        .map_err(|_| UserServiceError::NoUserForId(user_id.to_string()))
+}
+
+pub async fn create_user(
+    user_repo: &dyn UserRepo,
+    audit_repo: &dyn AuditRepo,
+    passwd_hasher: &PasswordHasher,
+    user: UserCreateReqDto,
+) -> Result<Uuid, UserServiceError> {
+    user.validate()
         .map_err(UserServiceError::InvalidUserFields)?;
 
     let UserCreateReqDto {
         username,
         password_raw,
         email,
-    } = user.0;
+    } = user;
 
     if user_repo
         .contains_user_with_username(&username)
@@ -69,24 +182,106 @@ pub async fn post_user(
         .map_err(|_| UserServiceError::UnknownInternal)?;
     user.email = email;
 
-    user_repo
-        .create_user(&user)
-        .await
-        .map_err(log_err)
-        .map_err(|_| UserServiceError::UnknownInternal)?;
-    Ok(Json(user_id))
+    record(
+        audit_repo,
+        UserChange::Create(&user),
+        AuditEventType::UserCreated,
+        user_id,
+        json!({ "username": user.username }),
+    )
+    .await
+    .map_err(|err| match err.downcast_ref::<UserRepoError>() {
+        Some(UserRepoError::UsernameConflict(_)) => UserServiceError::UsernameTaken,
+        _ => {
+            log_err(err);
+            UserServiceError::UnknownInternal
+        }
+    })?;
+    Ok(user_id)
 }
 
-pub async fn delete_user(
-    user_repo: Data<dyn UserRepo>,
-    user_id: Path<String>,
-) -> UserServiceResult<()> {
-    let user_id_str = user_id.into_inner();
-    let user_id = Uuid::try_parse(&user_id_str)
-        .map_err(|_| UserServiceError::InvalidId(user_id_str.clone()))?;
-    user_repo
-        .delete_user_by_id(&user_id)
-        .await
-        .map(Json)
-        .map_err(|_| UserServiceError::NoUserForId(user_id_str))
+pub async fn update_user(
+    user_repo: &dyn UserRepo,
+    audit_repo: &dyn AuditRepo,
+    oauth_repo: &dyn OAuthRepo,
+    passwd_hasher: &PasswordHasher,
+    user_id: &str,
+    update: UserUpdateReqDto,
+) -> Result<User, UserServiceError> {
+    update
+        .validate()
+        .map_err(UserServiceError::InvalidUserFields)?;
+    let mut user = find_user(user_repo, user_id).await?;
+    if let Some(email) = update.email {
+        user.email = Some(email);
+    }
+    if let Some(password_raw) = &update.password_raw {
+        user.password_hash = passwd_hasher
+            .hash_password(password_raw)
+            .map_err(log_err)
+            .map_err(|_| UserServiceError::UnknownInternal)?;
+    }
+    let updated = match update.password_raw {
+        Some(_) => {
+            // Sessions opened with the former password end with it, revoked first so a failure
+            // leaves the former password in place rather than its sessions open
+            oauth_repo
+                .revoke_user_tokens(&user.id, Utc::now().trunc_subsecs(0))
+                .await
+                .map_err(log_err)
+                .map_err(|_| UserServiceError::UnknownInternal)?;
+            record(
+                audit_repo,
+                UserChange::Update(&user),
+                AuditEventType::PasswordChanged,
+                user.id,
+                json!({}),
+            )
+            .await
+        }
+        None => user_repo.update_user_by_id(&user.id, &user).await,
+    }
+    .map_err(log_err)
+    .map_err(|_| UserServiceError::UnknownInternal)?;
+    if !updated {
+        return Err(UserServiceError::NoUserForId(user_id.to_string()));
+    }
+    Ok(user)
+}
+
+pub async fn remove_user(
+    audit_repo: &dyn AuditRepo,
+    user_id: &str,
+) -> Result<(), UserServiceError> {
+    let id = parse_user_id(user_id)?;
+    if !record(
+        audit_repo,
+        UserChange::Delete(&id),
+        AuditEventType::UserDeleted,
+        id,
+        json!({}),
+    )
+    .await
+    .map_err(log_err)
+    .map_err(|_| UserServiceError::UnknownInternal)?
+    {
+        return Err(UserServiceError::NoUserForId(user_id.to_string()));
+    }
+    Ok(())
+}
+
+/// Applies the change to the user with its audit event, in one transaction.
+async fn record(
+    audit_repo: &dyn AuditRepo,
+    change: UserChange<'_>,
+    event_type: AuditEventType,
+    subject_id: Uuid,
+    details: Value,
+) -> anyhow::Result<bool> {
+    let event = NewAuditEventBuilder::default()
+        .event_type(event_type)
+        .subject_id(subject_id)
+        .details(details)
+        .build()?;
+    audit::change_user(audit_repo, change, event).await
 }
@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::Context;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Utc;
+use config::Config;
+use config::Environment;
+use config::File;
+use serde::Deserialize;
+use serde::Serialize;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+use validator::Validate;
+use validator::ValidationError;
+
+pub const DEFAULT_CONFIG_FILE: &str = "auth-uservice.toml";
+pub const ENV_PREFIX: &str = "AUTH_USERVICE";
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    #[validate]
+    pub server: ServerSettings,
+    #[validate]
+    pub database: DatabaseSettings,
+    #[validate]
+    pub argon2: Argon2Settings,
+    #[validate]
+    pub token: TokenSettings,
+    #[validate]
+    pub log: LogSettings,
+    #[validate]
+    pub tracing: TracingSettings,
+    #[validate]
+    pub admin: AdminSettings,
+    #[validate]
+    pub api: ApiSettings,
+    #[validate]
+    pub oauth: OAuthSettings,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    #[validate(custom = "validate_socket_addr")]
+    pub bind_address: String,
+    #[validate(custom = "validate_socket_addr")]
+    pub grpc_bind_address: String,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            bind_address: "0.0.0.0:8000".into(),
+            grpc_bind_address: "127.0.0.1:50051".into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Validate)]
+#[serde(default, deny_unknown_fields)]
+#[validate(schema(function = "validate_database_settings"))]
+pub struct DatabaseSettings {
+    #[validate(length(min = 1))]
+    pub url: String,
+    #[validate(range(min = 1, max = 1000))]
+    pub max_connections: u32,
+    pub min_connections: u32,
+    #[validate(range(min = 1))]
+    pub acquire_timeout_secs: u64,
+    /// Idle connections above `min_connections` are closed after this long, 0 keeps them open
+    pub idle_timeout_secs: u64,
+    /// Postgres only, 0 disables the timeout
+    pub statement_timeout_ms: u64,
+    /// Attempts to connect again when the database is not reachable on startup
+    pub connect_retries: u32,
+    /// Delay before the first retry, doubled on every attempt up to `connect_backoff_max_ms`
+    #[validate(range(min = 1))]
+    pub connect_backoff_ms: u64,
+    #[validate(range(min = 1))]
+    pub connect_backoff_max_ms: u64,
+}
+
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        Self {
+            url: "postgres://127.0.0.1:5432/users_db".into(),
+            max_connections: 5,
+            min_connections: 0,
+            acquire_timeout_secs: 30,
+            idle_timeout_secs: 600,
+            statement_timeout_ms: 30_000,
+            connect_retries: 5,
+            connect_backoff_ms: 500,
+            connect_backoff_max_ms: 10_000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct Argon2Settings {
+    /// Memory cost in KiB
+    #[validate(range(min = 8))]
+    pub mem_cost: u32,
+    #[validate(range(min = 1))]
+    pub time_cost: u32,
+    #[validate(range(min = 1, max = 64))]
+    pub lanes: u32,
+    #[validate(range(min = 16, max = 128))]
+    pub hash_length: u32,
+}
+
+impl Default for Argon2Settings {
+    fn default() -> Self {
+        Self {
+            mem_cost: 1024,
+            time_cost: 3,
+            lanes: 4,
+            hash_length: 32,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenSettings {
+    #[validate(url)]
+    pub issuer: String,
+    #[validate(range(min = 1))]
+    pub access_token_ttl_secs: u64,
+    /// PKCS#8 PEM file with the Ed25519 token signing key, an ephemeral key is generated if unset
+    pub signing_key_file: Option<PathBuf>,
+    /// How far the `iat` of DPoP proofs may be from now, either way
+    #[validate(range(min = 1, max = 300))]
+    pub dpop_proof_max_age_secs: u64,
+    /// Whether DPoP proofs must carry a nonce from the `DPoP-Nonce` response header
+    pub dpop_nonce_required: bool,
+}
+
+impl Default for TokenSettings {
+    fn default() -> Self {
+        Self {
+            issuer: "http://localhost:8000".into(),
+            access_token_ttl_secs: 900,
+            signing_key_file: None,
+            dpop_proof_max_age_secs: 60,
+            dpop_nonce_required: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings {
+    /// Filter directives, e.g. `info` or `info,sqlx=warn`
+    #[validate(custom = "validate_log_filter")]
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: "info".into(),
+            format: LogFormat::Json,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One JSON object per line
+    Json,
+    /// Human readable, for local development
+    Text,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracingSettings {
+    /// OTLP/HTTP collector base URL, e.g. `http://localhost:4318`, spans are not exported if unset
+    #[validate(url)]
+    pub otlp_endpoint: Option<String>,
+    #[validate(length(min = 1))]
+    pub service_name: String,
+}
+
+impl Default for TracingSettings {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: env!("CARGO_PKG_NAME").into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminSettings {
+    /// Bearer token granting access to the `/admin` endpoints, which are disabled if unset
+    #[validate(length(min = 32))]
+    pub api_token: Option<String>,
+}
+
+/// Deprecation of the unversioned routes, 2026-10-18T00:00:00Z, which `legacy_sunset` follows
+pub const LEGACY_DEPRECATED_SINCE: i64 = 1_792_281_600;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiSettings {
+    /// Also serve the deprecated unversioned routes (`/users` besides `/v1/users`)
+    pub legacy_routes: bool,
+    /// Announced end of the unversioned routes, in RFC 3339
+    pub legacy_sunset: Option<DateTime<Utc>>,
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        Self {
+            legacy_routes: true,
+            legacy_sunset: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Validate)]
+#[serde(default, deny_unknown_fields)]
+#[validate(schema(function = "validate_oauth_settings"))]
+pub struct OAuthSettings {
+    /// Authorization codes must be redeemed within this long
+    #[validate(range(min = 1, max = 600))]
+    pub authorization_code_ttl_secs: u64,
+    /// Device codes must be approved and redeemed within this long
+    #[validate(range(min = 1, max = 1800))]
+    pub device_code_ttl_secs: u64,
+    /// Seconds devices wait between polls of the token endpoint
+    #[validate(range(min = 1, max = 60))]
+    pub device_poll_interval_secs: u64,
+    /// IDs of the users, such as support staff, allowed to impersonate others by token exchange
+    pub impersonators: Vec<Uuid>,
+    /// Bearer token of `POST /oauth/register`, dynamic client registration is disabled if unset
+    #[validate(length(min = 32))]
+    pub initial_access_token: Option<String>,
+    /// By default, how long the previous secret of a client is still accepted after a rotation
+    #[validate(range(max = 2_592_000))]
+    pub secret_rotation_overlap_secs: u64,
+    /// OpenID Connect providers users may sign in with, offered on the login page
+    #[validate]
+    pub upstream_providers: Vec<UpstreamProviderSettings>,
+}
+
+/// Upstream OpenID Connect provider, such as Google Workspace or Azure AD. Its redirect URI is
+/// `{token.issuer}/oauth/upstream/{id}/callback`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamProviderSettings {
+    /// Identifies the provider in URLs and linked identities, e.g. `google`
+    #[validate(custom = "validate_provider_id")]
+    pub id: String,
+    /// Shown on the login page
+    #[validate(length(min = 1))]
+    pub name: String,
+    /// Serves the discovery document at `{issuer}/.well-known/openid-configuration`
+    #[validate(url)]
+    pub issuer: String,
+    #[validate(length(min = 1))]
+    pub client_id: String,
+    /// Sent with `client_secret_basic`, the service is a public client of the provider if unset
+    pub client_secret: Option<String>,
+    #[serde(default = "default_upstream_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_upstream_scopes() -> Vec<String> {
+    vec!["openid".into(), "profile".into(), "email".into()]
+}
+
+impl Default for OAuthSettings {
+    fn default() -> Self {
+        Self {
+            authorization_code_ttl_secs: 60,
+            device_code_ttl_secs: 600,
+            device_poll_interval_secs: 5,
+            impersonators: Vec::new(),
+            initial_access_token: None,
+            secret_rotation_overlap_secs: 86400,
+            upstream_providers: Vec::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Layers, from lowest to highest precedence: defaults, the TOML config file, `AUTH_USERVICE_*`
+    /// environment variables (`AUTH_USERVICE_DATABASE__URL` sets `database.url`) and `overrides`.
+    pub fn load(config_file: Option<&Path>, overrides: &[(&str, String)]) -> Result<Self> {
+        Self::load_from(config_file, None, overrides)
+    }
+
+    pub fn load_from(
+        config_file: Option<&Path>,
+        env: Option<HashMap<String, String>>,
+        overrides: &[(&str, String)],
+    ) -> Result<Self> {
+        let file = match config_file {
+            Some(path) => File::from(path).required(true),
+            None => File::with_name(DEFAULT_CONFIG_FILE).required(false),
+        };
+        let mut builder = Config::builder()
+            .add_source(Config::try_from(&Self::default())?)
+            .add_source(file)
+            .add_source(
+                Environment::with_prefix(ENV_PREFIX)
+                    .prefix_separator("_")
+                    .separator("__")
+                    .try_parsing(true)
+                    .source(env),
+            );
+        for (key, value) in overrides {
+            builder = builder.set_override(*key, value.as_str())?;
+        }
+        let settings: Self = builder
+            .build()?
+            .try_deserialize()
+            .context("Failed to load configuration")?;
+        settings.validate().context("Invalid configuration")?;
+        Ok(settings)
+    }
+}
+
+fn validate_socket_addr(addr: &str) -> Result<(), ValidationError> {
+    SocketAddr::from_str(addr)
+        .map(|_| ())
+        .map_err(|_| ValidationError::new("socket_addr"))
+}
+
+fn validate_database_settings(settings: &DatabaseSettings) -> Result<(), ValidationError> {
+    if settings.min_connections > settings.max_connections {
+        return Err(ValidationError::new("min_connections_above_max"));
+    }
+    if settings.connect_backoff_ms > settings.connect_backoff_max_ms {
+        return Err(ValidationError::new("connect_backoff_above_max"));
+    }
+    Ok(())
+}
+
+fn validate_oauth_settings(settings: &OAuthSettings) -> Result<(), ValidationError> {
+    let providers = &settings.upstream_providers;
+    for (i, provider) in providers.iter().enumerate() {
+        if providers[..i].iter().any(|other| other.id == provider.id) {
+            return Err(ValidationError::new("duplicate_upstream_provider_id"));
+        }
+    }
+    Ok(())
+}
+
+fn validate_provider_id(id: &str) -> Result<(), ValidationError> {
+    let valid = (1..=32).contains(&id.len())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-');
+    if valid {
+        Ok(())
+    } else {
+        Err(ValidationError::new("provider_id"))
+    }
+}
+
+/// Crates logging through the subscriber, which bare directives such as `sqlx` may name
+const LOG_TARGETS: &[&str] = &[
+    "auth_uservice",
+    "actix_server",
+    "actix_web",
+    "h2",
+    "hyper",
+    "opentelemetry",
+    "reqwest",
+    "sqlx",
+    "tonic",
+    "tower",
+];
+
+/// Parsed as the filter will be. A bare directive must be a level or a known target, as the
+/// parser takes any word for a target, which would turn logging off.
+fn validate_log_filter(filter: &str) -> Result<(), ValidationError> {
+    let known = filter.split(',').all(|directive| {
+        directive.contains(['=', '['])
+            || LevelFilter::from_str(directive).is_ok()
+            || LOG_TARGETS.contains(&directive.split("::").next().unwrap_or_default())
+    });
+    match EnvFilter::try_new(filter) {
+        Ok(_) if known => Ok(()),
+        _ => Err(ValidationError::new("log_filter")),
+    }
+}
@@ -0,0 +1,319 @@
+use std::fmt;
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::HeaderMap;
+use actix_web::HttpMessage;
+use anyhow::Result;
+use chrono::SecondsFormat;
+use chrono::Utc;
+use opentelemetry::global;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::runtime;
+use opentelemetry_sdk::trace;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::Resource;
+use serde_json::Map;
+use serde_json::Value;
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing::Event;
+use tracing::Span;
+use tracing::Subscriber;
+use tracing_actix_web::DefaultRootSpanBuilder;
+use tracing_actix_web::RootSpanBuilder;
+use tracing_log::LogTracer;
+use tracing_log::NormalizeEvent;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::FmtContext;
+use tracing_subscriber::fmt::FormatEvent;
+use tracing_subscriber::fmt::FormatFields;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::Layer;
+use tracing_subscriber::Registry;
+
+use crate::request_id;
+use crate::request_id::RequestId;
+use crate::settings::LogFormat;
+use crate::settings::LogSettings;
+use crate::settings::TracingSettings;
+
+/// Fields whose values never make it into the logs, whatever their level.
+pub const REDACTED_FIELDS: &[&str] = &[
+    "password",
+    "password_raw",
+    "password_hash",
+    "secret",
+    "client_secret",
+    "token",
+    "access_token",
+    "refresh_token",
+    "code_verifier",
+    "authorization",
+];
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Installs the global subscriber writing logs in the configured format, forwards `log` records
+/// to it, and exports spans over OTLP if a collector is configured. The W3C `traceparent`
+/// propagator is installed to continue traces of incoming requests.
+///
+/// Dropping the returned provider flushes the spans not exported yet.
+pub fn init(log: &LogSettings, tracing: &TracingSettings) -> Result<Option<TracerProvider>> {
+    LogTracer::init()?;
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let provider = match &tracing.otlp_endpoint {
+        Some(endpoint) => Some(tracer_provider(endpoint, &tracing.service_name)?),
+        None => None,
+    };
+    let subscriber = Registry::default()
+        .with(EnvFilter::try_new(&log.level)?)
+        .with(log_layer(log.format, std::io::stdout))
+        .with(provider.as_ref().map(otel_layer));
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(provider)
+}
+
+pub fn log_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .event_format(JsonFormat)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .fmt_fields(TextFields)
+            .with_writer(writer)
+            .boxed(),
+    }
+}
+
+pub fn tracer_provider(endpoint: &str, service_name: &str) -> Result<TracerProvider> {
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(endpoint)
+        .build_span_exporter()?;
+    // The current-thread runtime exports from a dedicated thread, so flushing never waits on
+    // the actix workers
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::TokioCurrentThread)
+        .with_config(trace::config().with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            service_name.to_string(),
+        )])))
+        .build())
+}
+
+pub fn otel_layer<S>(provider: &TracerProvider) -> impl Layer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+}
+
+/// Formats events as one JSON object per line, with the ID of the request being handled.
+pub struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        // Events forwarded from `log` carry their real target in the `log.*` fields
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+
+        let mut line = Map::new();
+        line.insert(
+            "timestamp".into(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Micros, true)
+                .into(),
+        );
+        line.insert("level".into(), metadata.level().as_str().into());
+        line.insert("target".into(), metadata.target().into());
+        if let Some(span) = ctx.lookup_current() {
+            line.insert("span".into(), span.name().into());
+        }
+        if let Some(request_id) = request_id::current() {
+            line.insert("request_id".into(), request_id.as_str().into());
+        }
+        event.record(&mut JsonVisitor(&mut line));
+
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl JsonVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        let name = field.name();
+        if name.starts_with("log.") {
+            return;
+        }
+        let value = if REDACTED_FIELDS.contains(&name) {
+            REDACTED.into()
+        } else {
+            value
+        };
+        self.0.insert(name.into(), value);
+    }
+}
+
+impl Visit for JsonVisitor<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{:?}", value).into());
+    }
+}
+
+/// Formats fields as `name=value` pairs, like the default formatter, redacting secrets.
+pub struct TextFields;
+
+impl<'writer> FormatFields<'writer> for TextFields {
+    fn format_fields<R: RecordFields>(&self, writer: Writer<'writer>, fields: R) -> fmt::Result {
+        let mut visitor = TextVisitor {
+            writer,
+            seen: false,
+            result: Ok(()),
+        };
+        fields.record(&mut visitor);
+        visitor.result
+    }
+}
+
+struct TextVisitor<'a> {
+    writer: Writer<'a>,
+    seen: bool,
+    result: fmt::Result,
+}
+
+impl Visit for TextVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let name = field.name();
+        if self.result.is_err() || name.starts_with("log.") {
+            return;
+        }
+        let delimiter = if self.seen { " " } else { "" };
+        self.seen = true;
+        self.result = if name == "message" {
+            write!(self.writer, "{}{:?}", delimiter, value)
+        } else if REDACTED_FIELDS.contains(&name) {
+            write!(self.writer, "{}{}={}", delimiter, name, REDACTED)
+        } else {
+            write!(self.writer, "{}{}={:?}", delimiter, name, value)
+        };
+    }
+}
+
+/// Root span of every request, tagged with the request ID and continuing the trace given in the
+/// `traceparent` header. Also logs every completed request.
+pub struct RequestSpan;
+
+struct RequestStart(Instant);
+
+impl RootSpanBuilder for RequestSpan {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        request
+            .extensions_mut()
+            .insert(RequestStart(Instant::now()));
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(RequestId::to_string)
+            .unwrap_or_default();
+        let route = request
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".into());
+        let span = tracing::info_span!(
+            "HTTP request",
+            otel.name = %format!("{} {}", request.method(), route),
+            otel.kind = "server",
+            otel.status_code = tracing::field::Empty,
+            http.method = %request.method(),
+            http.route = %route,
+            http.target = %request.uri().path(),
+            http.status_code = tracing::field::Empty,
+            exception.message = tracing::field::Empty,
+            exception.details = tracing::field::Empty,
+            request_id = %request_id,
+        );
+        let parent = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(request.headers()))
+        });
+        span.set_parent(parent);
+        span
+    }
+
+    fn on_request_end<B: MessageBody>(
+        span: Span,
+        outcome: &Result<ServiceResponse<B>, actix_web::Error>,
+    ) {
+        if let Ok(res) = outcome {
+            let latency_ms = res
+                .request()
+                .extensions()
+                .get::<RequestStart>()
+                .map(|start| start.0.elapsed().as_secs_f64() * 1000.0);
+            tracing::info!(
+                method = %res.request().method(),
+                path = %res.request().path(),
+                status = res.status().as_u16(),
+                latency_ms,
+                "Request completed"
+            );
+        }
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
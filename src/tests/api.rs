@@ -0,0 +1,102 @@
+use actix_web::http::header::LINK;
+use actix_web::http::StatusCode;
+use actix_web::test;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::App;
+use anyhow::Result;
+use chrono::TimeZone;
+use chrono::Utc;
+
+use crate::api::version;
+use crate::api::version::DEPRECATION;
+use crate::api::version::SUNSET;
+use crate::services::health::healthz;
+use crate::settings::ApiSettings;
+use crate::tests::mock::user_repo::InjectableMockUserRepo;
+use crate::tests::mock::user_repo::MockUserRepoNoDb;
+
+#[actix_web::test]
+async fn test_versioned_routes() -> Result<()> {
+    let (users, user_repo) = MockUserRepoNoDb.init().await?;
+    let settings = ApiSettings {
+        legacy_sunset: Some(Utc.with_ymd_and_hms(2027, 4, 1, 0, 0, 0).unwrap()),
+        ..Default::default()
+    };
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::from(user_repo))
+            .route("/healthz", web::get().to(healthz))
+            .configure(|cfg| version::configure(cfg, &settings)),
+    )
+    .await;
+    let path = format!("/users/{}", users[0].id);
+
+    // Test the current version is not marked deprecated
+    let req = test::TestRequest::get()
+        .uri(&format!("/v1{}", path))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get(&DEPRECATION).is_none());
+
+    // Test legacy routes still work, pointing to their successor
+    let req = test::TestRequest::get().uri(&path).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get(&DEPRECATION).unwrap(), "@1792281600");
+    assert_eq!(
+        resp.headers().get(&SUNSET).unwrap(),
+        "Thu, 01 Apr 2027 00:00:00 GMT"
+    );
+    assert_eq!(
+        resp.headers().get(LINK).unwrap().to_str()?,
+        format!("</v1{}>; rel=\"successor-version\"", path)
+    );
+
+    // Test unversioned routes are not caught by the legacy routes
+    let req = test::TestRequest::get().uri("/healthz").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get(&DEPRECATION).is_none());
+
+    // Test only routes predating v1 are served at the root, unknown paths aren't marked
+    for uri in ["/admin/clients", "/unknown"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{} was served", uri);
+        assert!(resp.headers().get(&DEPRECATION).is_none());
+        assert!(resp.headers().get(LINK).is_none());
+    }
+
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_legacy_routes_disabled() -> Result<()> {
+    let (users, user_repo) = MockUserRepoNoDb.init().await?;
+    let settings = ApiSettings {
+        legacy_routes: false,
+        ..Default::default()
+    };
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::from(user_repo))
+            .configure(|cfg| version::configure(cfg, &settings)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/users/{}", users[0].id))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::NOT_FOUND
+    );
+    let req = test::TestRequest::get()
+        .uri(&format!("/v1/users/{}", users[0].id))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    Ok(())
+}
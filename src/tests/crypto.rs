@@ -0,0 +1,118 @@
+use std::io::Write;
+
+use anyhow::Result;
+use chrono::Utc;
+use jsonwebtoken::jwk::Jwk;
+use ring::rand::SystemRandom;
+use ring::signature::Ed25519KeyPair;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::crypto::public_jwk_thumbprint;
+use crate::crypto::TokenSigner;
+use crate::settings::TokenSettings;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct Claims {
+    iss: String,
+    sub: String,
+    exp: i64,
+}
+
+fn claims(iss: &str) -> Claims {
+    Claims {
+        iss: iss.into(),
+        sub: "alice".into(),
+        exp: Utc::now().timestamp() + 60,
+    }
+}
+
+#[test]
+fn test_token_signer_from_key_file() -> Result<()> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    let pem = pem::encode(&pem::Pem {
+        tag: "PRIVATE KEY".into(),
+        contents: pkcs8.as_ref().to_vec(),
+    });
+    let path = std::env::temp_dir().join(format!("signing_key_{}.pem", uuid::Uuid::new_v4()));
+    std::fs::File::create(&path)?.write_all(pem.as_bytes())?;
+    let settings = TokenSettings {
+        signing_key_file: Some(path.clone()),
+        ..Default::default()
+    };
+
+    // The same key file always yields the same key ID
+    let signer = TokenSigner::from_settings(&settings)?;
+    let reloaded = TokenSigner::from_settings(&settings)?;
+    std::fs::remove_file(&path)?;
+    assert_eq!(signer.kid(), reloaded.kid());
+
+    let token = signer.sign(&claims(signer.issuer()))?;
+    assert_eq!(reloaded.verify::<Claims>(&token)?, claims(signer.issuer()));
+    Ok(())
+}
+
+#[test]
+fn test_token_signer_rejects_foreign_tokens() -> Result<()> {
+    let signer = TokenSigner::from_settings(&TokenSettings::default())?;
+    let other = TokenSigner::from_settings(&TokenSettings::default())?;
+    assert_ne!(signer.kid(), other.kid());
+
+    let token = other.sign(&claims(other.issuer()))?;
+    assert!(signer.verify::<Claims>(&token).is_err());
+
+    let token = signer.sign(&claims("https://evil.example.com"))?;
+    assert!(signer.verify::<Claims>(&token).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_token_signer_invalid_key_file() {
+    let path = std::env::temp_dir().join(format!("signing_key_{}.pem", uuid::Uuid::new_v4()));
+    std::fs::write(&path, "not a key").unwrap();
+    let settings = TokenSettings {
+        signing_key_file: Some(path.clone()),
+        ..Default::default()
+    };
+    assert!(TokenSigner::from_settings(&settings).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_public_jwk_thumbprint() -> Result<()> {
+    // Example of RFC 7638 section 3.1
+    let jwk: Jwk = serde_json::from_value(serde_json::json!({
+        "kty": "RSA",
+        "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+        "e": "AQAB",
+        "alg": "RS256",
+        "kid": "2011-04-29"
+    }))?;
+    assert_eq!(
+        public_jwk_thumbprint(&jwk)?,
+        "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+    );
+    let symmetric: Jwk = serde_json::from_value(serde_json::json!({
+        "kty": "oct",
+        "k": "c2VjcmV0"
+    }))?;
+    assert!(public_jwk_thumbprint(&symmetric).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_dpop_nonces() -> Result<()> {
+    let signer = TokenSigner::from_settings(&TokenSettings::default())?;
+    let other_signer = TokenSigner::from_settings(&TokenSettings::default())?;
+    let now = Utc::now().timestamp();
+    let nonce = signer.dpop_nonce(now);
+
+    assert!(signer.verify_dpop_nonce(&nonce, now));
+    assert!(signer.verify_dpop_nonce(&nonce, now + 300));
+    assert!(!signer.verify_dpop_nonce(&nonce, now + 600));
+    assert!(!other_signer.verify_dpop_nonce(&nonce, now));
+    assert!(!signer.verify_dpop_nonce("garbage", now));
+
+    Ok(())
+}
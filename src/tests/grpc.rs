@@ -0,0 +1,411 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Channel;
+use tonic::Code;
+use tonic::Request;
+use tonic_reflection::pb::server_reflection_client::ServerReflectionClient;
+use tonic_reflection::pb::server_reflection_request::MessageRequest;
+use tonic_reflection::pb::server_reflection_response::MessageResponse;
+use tonic_reflection::pb::ServerReflectionRequest;
+use uuid::Uuid;
+
+use crate::crypto::PasswordHasher;
+use crate::crypto::TokenSigner;
+use crate::grpc::pb;
+use crate::grpc::pb::auth_service_client::AuthServiceClient;
+use crate::grpc::pb::user_service_client::UserServiceClient;
+use crate::grpc::GrpcState;
+use crate::grpc::ERROR_CODE_METADATA;
+use crate::grpc::MANAGE_ACCOUNT_SCOPE;
+use crate::models::audit::AuditEventType;
+use crate::models::audit::AuditQuery;
+use crate::models::token::AccessTokenClaims;
+use crate::models::token::Actor;
+use crate::repositories::audit::AuditRepo;
+use crate::services::token;
+use crate::settings::AdminSettings;
+use crate::settings::TokenSettings;
+use crate::tests::mock::audit_repo::MockAuditRepo;
+use crate::tests::mock::oauth_repo::MockOAuthRepo;
+use crate::tests::mock::user_repo::InjectableMockUserRepo;
+use crate::tests::mock::user_repo::MockUserRepoNoDb;
+
+const ADMIN_TOKEN: &str = "0123456789abcdef0123456789abcdef";
+
+/// The message, with `authorization: Bearer` metadata.
+fn with_bearer<T>(message: T, token: &str) -> Result<Request<T>> {
+    let mut request = Request::new(message);
+    request
+        .metadata_mut()
+        .insert("authorization", format!("Bearer {}", token).parse()?);
+    Ok(request)
+}
+
+/// Serves the gRPC API on an ephemeral port, returning a channel to it.
+async fn serve(state: GrpcState) -> Result<Channel> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let router = state.router()?;
+    actix_web::rt::spawn(router.serve_with_incoming(TcpListenerStream::new(listener)));
+    Ok(Channel::from_shared(format!("http://{}", addr))?
+        .connect()
+        .await?)
+}
+
+#[actix_web::test]
+async fn test_grpc_users_and_auth() -> Result<()> {
+    let (_, user_repo) = MockUserRepoNoDb.init().await?;
+    let audit_repo: Arc<dyn AuditRepo> = Arc::new(MockAuditRepo::with_users(user_repo.clone()));
+    let channel = serve(GrpcState {
+        user_repo: user_repo.clone(),
+        audit_repo: audit_repo.clone(),
+        oauth_repo: Arc::new(MockOAuthRepo::default()),
+        passwd_hasher: Arc::new(PasswordHasher::default()),
+        token_signer: Arc::new(TokenSigner::from_settings(&TokenSettings::default())?),
+        access_token_ttl: Duration::from_secs(60),
+        admin_settings: Arc::new(AdminSettings {
+            api_token: Some(ADMIN_TOKEN.into()),
+        }),
+    })
+    .await?;
+    let mut users = UserServiceClient::new(channel.clone());
+    let mut auth = AuthServiceClient::new(channel);
+
+    // Test user creation is reserved to the admin
+    let create = pb::CreateUserRequest {
+        username: "Grpc".into(),
+        password: "password1".into(),
+        email: Some("grpc@example.com".into()),
+    };
+    let status = users.create_user(create.clone()).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    let status = users
+        .create_user(with_bearer(create.clone(), "wrong")?)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    // Test user creation, through the shared repository
+    let id = users
+        .create_user(with_bearer(create, ADMIN_TOKEN)?)
+        .await?
+        .into_inner()
+        .id;
+    let id = Uuid::try_parse(&id)?;
+    assert_eq!(user_repo.get_user_by_id(&id).await?.username, "Grpc");
+    let user = users
+        .get_user(pb::GetUserRequest { id: id.to_string() })
+        .await?
+        .into_inner();
+    assert_eq!(user.email.as_deref(), Some("grpc@example.com"));
+
+    // Test errors map to status codes
+    let status = users
+        .create_user(with_bearer(
+            pb::CreateUserRequest {
+                username: "grpc".into(),
+                password: "password1".into(),
+                email: None,
+            },
+            ADMIN_TOKEN,
+        )?)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::AlreadyExists);
+    assert_eq!(
+        status.metadata().get(ERROR_CODE_METADATA).unwrap(),
+        "username_taken"
+    );
+    let status = users
+        .create_user(with_bearer(
+            pb::CreateUserRequest {
+                username: "Short".into(),
+                password: "sEcr3t!".into(),
+                email: None,
+            },
+            ADMIN_TOKEN,
+        )?)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(status.message(), "Invalid user fields");
+    let status = users
+        .get_user(pb::GetUserRequest {
+            id: "invalid".into(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    // Test authentication and introspection
+    let status = auth
+        .authenticate(pb::AuthenticateRequest {
+            username: "Grpc".into(),
+            password: "wrong-password".into(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    let status = auth
+        .authenticate(pb::AuthenticateRequest {
+            username: "Nobody".into(),
+            password: "password1".into(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    let token = auth
+        .authenticate(pb::AuthenticateRequest {
+            username: "grpc".into(),
+            password: "password1".into(),
+        })
+        .await?
+        .into_inner();
+    assert_eq!(token.token_type, "Bearer");
+    assert_eq!(token.expires_in, 60);
+    let introspection = auth
+        .introspect_token(pb::IntrospectTokenRequest {
+            token: token.access_token.clone(),
+        })
+        .await?
+        .into_inner();
+    assert!(introspection.active);
+    assert_eq!(introspection.sub, Some(id.to_string()));
+    assert_eq!(introspection.username.as_deref(), Some("Grpc"));
+    let introspection = auth
+        .introspect_token(pb::IntrospectTokenRequest {
+            token: "not.a.token".into(),
+        })
+        .await?
+        .into_inner();
+    assert!(!introspection.active && introspection.sub.is_none());
+    assert!(user_repo.get_user_by_id(&id).await?.last_login.is_some());
+
+    // Test users can only update themselves
+    let update = pb::UpdateUserRequest {
+        id: id.to_string(),
+        email: None,
+        password: Some("password2".into()),
+    };
+    let status = users.update_user(update.clone()).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    let status = users
+        .update_user(with_bearer(
+            pb::UpdateUserRequest {
+                id: Uuid::new_v4().to_string(),
+                ..update.clone()
+            },
+            &token.access_token,
+        )?)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    // Test updating the password, which revokes the tokens issued in earlier seconds
+    actix_web::rt::time::sleep(Duration::from_secs(1)).await;
+    users
+        .update_user(with_bearer(update, &token.access_token)?)
+        .await?;
+    let introspection = auth
+        .introspect_token(pb::IntrospectTokenRequest {
+            token: token.access_token.clone(),
+        })
+        .await?
+        .into_inner();
+    assert!(!introspection.active);
+    auth.authenticate(pb::AuthenticateRequest {
+        username: "Grpc".into(),
+        password: "password2".into(),
+    })
+    .await?;
+
+    // Test deletion
+    let delete = pb::DeleteUserRequest { id: id.to_string() };
+    let status = users.delete_user(delete.clone()).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    users.delete_user(with_bearer(delete, ADMIN_TOKEN)?).await?;
+    let status = users
+        .get_user(pb::GetUserRequest { id: id.to_string() })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+
+    let event_types: Vec<_> = audit_repo
+        .list_events(&AuditQuery::default())
+        .await?
+        .into_iter()
+        .map(|event| event.event_type)
+        .collect();
+    assert_eq!(
+        event_types,
+        [
+            AuditEventType::UserCreated,
+            AuditEventType::LoginFailed,
+            AuditEventType::LoginFailed,
+            AuditEventType::LoginSucceeded,
+            AuditEventType::PasswordChanged,
+            AuditEventType::LoginSucceeded,
+            AuditEventType::UserDeleted,
+        ]
+    );
+
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_grpc_account_management_needs_direct_tokens() -> Result<()> {
+    let (users, user_repo) = MockUserRepoNoDb.init().await?;
+    let token_signer = Arc::new(TokenSigner::from_settings(&TokenSettings::default())?);
+    let ttl = Duration::from_secs(60);
+    let channel = serve(GrpcState {
+        user_repo: user_repo.clone(),
+        audit_repo: Arc::new(MockAuditRepo::with_users(user_repo.clone())),
+        oauth_repo: Arc::new(MockOAuthRepo::default()),
+        passwd_hasher: Arc::new(PasswordHasher::default()),
+        token_signer: token_signer.clone(),
+        access_token_ttl: ttl,
+        admin_settings: Arc::new(AdminSettings {
+            api_token: Some(ADMIN_TOKEN.into()),
+        }),
+    })
+    .await?;
+    let mut client = UserServiceClient::new(channel);
+    let user = &users[0];
+    let direct = token::issue_access_token(&token_signer, ttl, user, None, None)?;
+    let claims = token::verify_access_token(
+        &token_signer,
+        &MockOAuthRepo::default(),
+        &direct.access_token,
+    )
+    .await?
+    .unwrap();
+    let exchanged = |act| {
+        token::sign_claims(
+            &token_signer,
+            &AccessTokenClaims {
+                jti: Uuid::new_v4(),
+                client_id: Some("gateway".into()),
+                act: Some(act),
+                ..claims.clone()
+            },
+        )
+    };
+    let delegated = exchanged(Actor {
+        sub: "gateway".into(),
+        username: None,
+        act: None,
+    })?;
+    let impersonated = exchanged(Actor {
+        sub: users[1].id.to_string(),
+        username: Some(users[1].username.clone()),
+        act: None,
+    })?;
+    let third_party = token::issue_access_token(
+        &token_signer,
+        ttl,
+        user,
+        Some("third-party"),
+        Some("profile"),
+    )?;
+    let update = pb::UpdateUserRequest {
+        id: user.id.to_string(),
+        email: Some("derek@example.com".into()),
+        password: None,
+    };
+    let change_password = pb::UpdateUserRequest {
+        password: Some("password2".into()),
+        ..update.clone()
+    };
+    let delete = pb::DeleteUserRequest {
+        id: user.id.to_string(),
+    };
+
+    // Test tokens of services or staff acting for the user are refused
+    for token in [&delegated, &impersonated] {
+        let status = client
+            .update_user(with_bearer(update.clone(), &token.access_token)?)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        let status = client
+            .delete_user(with_bearer(delete.clone(), &token.access_token)?)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+
+    // Test clients without the scope only make harmless changes
+    client
+        .update_user(with_bearer(update, &third_party.access_token)?)
+        .await?;
+    let status = client
+        .update_user(with_bearer(
+            change_password.clone(),
+            &third_party.access_token,
+        )?)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+    let status = client
+        .delete_user(with_bearer(delete.clone(), &third_party.access_token)?)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+    assert!(user_repo.get_user_by_id(&user.id).await.is_ok());
+
+    // Test clients granted the scope manage the account
+    let manager = token::issue_access_token(
+        &token_signer,
+        ttl,
+        user,
+        Some("third-party"),
+        Some(MANAGE_ACCOUNT_SCOPE),
+    )?;
+    client
+        .delete_user(with_bearer(delete, &manager.access_token)?)
+        .await?;
+    assert!(user_repo.get_user_by_id(&user.id).await.is_err());
+
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_grpc_reflection() -> Result<()> {
+    let (_, user_repo) = MockUserRepoNoDb.init().await?;
+    let channel = serve(GrpcState {
+        user_repo: user_repo.clone(),
+        audit_repo: Arc::new(MockAuditRepo::with_users(user_repo)),
+        oauth_repo: Arc::new(MockOAuthRepo::default()),
+        passwd_hasher: Arc::new(PasswordHasher::default()),
+        token_signer: Arc::new(TokenSigner::from_settings(&TokenSettings::default())?),
+        access_token_ttl: Duration::from_secs(60),
+        admin_settings: Arc::new(AdminSettings {
+            api_token: Some(ADMIN_TOKEN.into()),
+        }),
+    })
+    .await?;
+
+    let request = ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(MessageRequest::ListServices(String::new())),
+    };
+    let mut responses = ServerReflectionClient::new(channel)
+        .server_reflection_info(tokio_stream::once(request))
+        .await?
+        .into_inner();
+    let response = responses.message().await?.unwrap();
+    let services: Vec<_> = match response.message_response {
+        Some(MessageResponse::ListServicesResponse(list)) => {
+            list.service.into_iter().map(|s| s.name).collect()
+        }
+        other => panic!("Unexpected reflection response: {:?}", other),
+    };
+    assert!(services.contains(&"auth_uservice.v1.UserService".to_string()));
+    assert!(services.contains(&"auth_uservice.v1.AuthService".to_string()));
+
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use std::env;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::ChildStdin;
+use std::process::Command;
+use std::process::Stdio;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use sqlx::Connection;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+/// Postgres server shared by every test in the process: `TEST_DATABASE_URL` if set, otherwise an
+/// ephemeral server created with `initdb` (looked up in `PG_BIN_DIR` or `PATH`).
+static TEST_SERVER: Lazy<Result<TestServer, String>> =
+    Lazy::new(|| match env::var("TEST_DATABASE_URL") {
+        Ok(url) => Ok(TestServer { url, _guard: None }),
+        Err(_) => TestServer::start_ephemeral().map_err(|err| format!("{:#}", err)),
+    });
+
+struct TestServer {
+    url: String,
+    /// The server shuts down and removes its data directory once this is closed, which happens
+    /// when the test process exits.
+    _guard: Option<ChildStdin>,
+}
+
+impl TestServer {
+    fn start_ephemeral() -> Result<Self> {
+        let data_dir =
+            env::temp_dir().join(format!("auth-uservice-test-pg-{}", std::process::id()));
+        let path = match env::var_os("PG_BIN_DIR") {
+            Some(bin_dir) => {
+                let mut paths = vec![PathBuf::from(bin_dir)];
+                paths.extend(env::split_paths(&env::var_os("PATH").unwrap_or_default()));
+                env::join_paths(paths)?
+            }
+            None => env::var_os("PATH").unwrap_or_default(),
+        };
+        let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+        let script = r#"
+            initdb -D "$1" -U postgres --auth=trust --no-sync >/dev/null &&
+            pg_ctl -D "$1" -o "-p $2 -k $1 -c listen_addresses=127.0.0.1 -F" -w start >/dev/null &&
+            echo ready &&
+            read _
+            pg_ctl -D "$1" -m immediate stop >/dev/null 2>&1
+            rm -rf "$1"
+        "#;
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .arg("sh")
+            .arg(&data_dir)
+            .arg(port.to_string())
+            .env("PATH", &path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("Failed to spawn shell for the test Postgres server")?;
+
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().context("No stdout for test Postgres")?)
+            .read_line(&mut line)?;
+        if line.trim() != "ready" {
+            bail!("Failed to start a test Postgres server with initdb/pg_ctl");
+        }
+        Ok(Self {
+            url: format!("postgres://postgres@127.0.0.1:{}/postgres", port),
+            _guard: child.stdin.take(),
+        })
+    }
+}
+
+/// Creates a fresh schema and returns a URL whose connections use it as their `search_path`, so
+/// tests sharing a server never see each other's tables.
+pub async fn isolated_schema_url() -> Result<String> {
+    let server = TEST_SERVER.as_ref().map_err(|err| {
+        anyhow!(
+            "No Postgres available for tests, set TEST_DATABASE_URL or install Postgres: {}",
+            err
+        )
+    })?;
+    let schema = format!("test_{}", Uuid::new_v4().simple());
+    let mut conn = PgConnection::connect(&server.url).await?;
+    sqlx::query(&format!("CREATE SCHEMA {}", schema))
+        .execute(&mut conn)
+        .await?;
+    conn.close().await?;
+
+    let separator = if server.url.contains('?') { '&' } else { '?' };
+    Ok(format!(
+        "{}{}options[search_path]={}",
+        server.url, separator, schema
+    ))
+}
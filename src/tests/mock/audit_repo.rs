@@ -0,0 +1,175 @@
+use std::sync::Arc;
+
+use anyhow::bail;
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use crate::models::audit::AuditEvent;
+use crate::models::audit::AuditHead;
+use crate::models::audit::AuditQuery;
+use crate::models::audit::NewAuditEvent;
+use crate::models::audit::GENESIS_HASH;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::audit::UserChange;
+use crate::repositories::migrations::SchemaMigrator;
+use crate::repositories::psql::user::UserRepoDb;
+#[cfg(feature = "sqlite")]
+use crate::repositories::sqlite::user::UserRepoSqlite;
+use crate::repositories::user::UserRepo;
+use crate::settings::DatabaseSettings;
+use crate::tests::harness::psql::isolated_schema_url;
+use crate::tests::mock::user_repo::MockUserRepo;
+
+/// Events, and the head kept apart from them like the databases do. Audited changes are applied
+/// to the given user repository.
+pub struct MockAuditRepo(
+    pub Mutex<Vec<AuditEvent>>,
+    pub Mutex<Option<AuditHead>>,
+    pub Arc<dyn UserRepo>,
+);
+
+impl MockAuditRepo {
+    pub fn with_users(user_repo: Arc<dyn UserRepo>) -> Self {
+        Self(Default::default(), Default::default(), user_repo)
+    }
+}
+
+impl Default for MockAuditRepo {
+    fn default() -> Self {
+        Self::with_users(Arc::new(MockUserRepo::default()))
+    }
+}
+
+#[async_trait]
+impl AuditRepo for MockAuditRepo {
+    async fn append_event(&self, event: &NewAuditEvent) -> Result<AuditEvent> {
+        let mut events = self.0.lock().await;
+        let prev_hash = events.last().map_or(GENESIS_HASH, |e| &e.hash);
+        let event = AuditEvent {
+            seq: events.len() as i64 + 1,
+            ..AuditEvent::seal(event, prev_hash)
+        };
+        events.push(event.clone());
+        *self.1.lock().await = Some(AuditHead {
+            seq: event.seq,
+            hash: event.hash.clone(),
+        });
+        Ok(event)
+    }
+
+    async fn change_user(&self, change: UserChange<'_>, event: &NewAuditEvent) -> Result<bool> {
+        let changed = match change {
+            UserChange::Create(user) => self.2.create_user(user).await.map(|_| true)?,
+            UserChange::Update(user) => self.2.update_user_by_id(&user.id, user).await?,
+            UserChange::Delete(user_id) => self.2.delete_user_by_id(user_id).await?,
+        };
+        if changed {
+            self.append_event(event).await?;
+        }
+        Ok(changed)
+    }
+
+    async fn get_head(&self) -> Result<Option<AuditHead>> {
+        Ok(Some(self.1.lock().await.clone().unwrap_or(AuditHead {
+            seq: 0,
+            hash: GENESIS_HASH.to_string(),
+        })))
+    }
+
+    async fn list_events(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>> {
+        Ok(self
+            .0
+            .lock()
+            .await
+            .iter()
+            .filter(|e| query.event_type.is_none_or(|t| e.event_type == t))
+            .filter(|e| query.actor_id.is_none_or(|id| e.actor_id == Some(id)))
+            .filter(|e| query.subject_id.is_none_or(|id| e.subject_id == Some(id)))
+            .filter(|e| e.seq > query.after_seq.unwrap_or(0))
+            .take(query.limit.map_or(usize::MAX, |l| l as usize))
+            .cloned()
+            .collect())
+    }
+}
+
+/// Audit log which can't be written to.
+pub struct FailingAuditRepo;
+
+#[async_trait]
+impl AuditRepo for FailingAuditRepo {
+    async fn append_event(&self, _event: &NewAuditEvent) -> Result<AuditEvent> {
+        bail!("Audit log unavailable")
+    }
+
+    async fn change_user(&self, _change: UserChange<'_>, _event: &NewAuditEvent) -> Result<bool> {
+        bail!("Audit log unavailable")
+    }
+
+    async fn get_head(&self) -> Result<Option<AuditHead>> {
+        Ok(None)
+    }
+
+    async fn list_events(&self, _query: &AuditQuery) -> Result<Vec<AuditEvent>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Audited changes to users go to the user repository of the same backend.
+#[async_trait]
+pub trait InjectableMockAuditRepo: Sync {
+    async fn init_with_users(&self) -> Result<(Arc<dyn UserRepo>, Arc<dyn AuditRepo>)>;
+
+    async fn init(&self) -> Result<Arc<dyn AuditRepo>> {
+        Ok(self.init_with_users().await?.1)
+    }
+}
+
+pub struct MockAuditRepoNoDb;
+
+#[async_trait]
+impl InjectableMockAuditRepo for MockAuditRepoNoDb {
+    async fn init_with_users(&self) -> Result<(Arc<dyn UserRepo>, Arc<dyn AuditRepo>)> {
+        let user_repo: Arc<dyn UserRepo> = Arc::new(MockUserRepo::default());
+        Ok((
+            user_repo.clone(),
+            Arc::new(MockAuditRepo::with_users(user_repo)),
+        ))
+    }
+}
+
+pub struct MockAuditRepoPsqlDb;
+
+#[async_trait]
+impl InjectableMockAuditRepo for MockAuditRepoPsqlDb {
+    async fn init_with_users(&self) -> Result<(Arc<dyn UserRepo>, Arc<dyn AuditRepo>)> {
+        let repo = Arc::new(
+            UserRepoDb::init(&DatabaseSettings {
+                url: isolated_schema_url().await?,
+                ..Default::default()
+            })
+            .await?,
+        );
+        repo.migrate_up().await?;
+        Ok((repo.clone(), repo))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub struct MockAuditRepoSqliteDb;
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl InjectableMockAuditRepo for MockAuditRepoSqliteDb {
+    async fn init_with_users(&self) -> Result<(Arc<dyn UserRepo>, Arc<dyn AuditRepo>)> {
+        let repo = Arc::new(
+            UserRepoSqlite::init(&DatabaseSettings {
+                url: "sqlite::memory:".into(),
+                ..Default::default()
+            })
+            .await?,
+        );
+        repo.migrate_up().await?;
+        Ok((repo.clone(), repo))
+    }
+}
@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::errors::user::UserRepoError;
+use crate::models::identity::LinkedIdentity;
+use crate::models::user::User;
+use crate::repositories::identity::IdentityRepo;
+use crate::repositories::migrations::SchemaMigrator;
+use crate::repositories::psql::user::UserRepoDb;
+#[cfg(feature = "sqlite")]
+use crate::repositories::sqlite::user::UserRepoSqlite;
+use crate::repositories::user::UserRepo;
+use crate::settings::DatabaseSettings;
+use crate::tests::harness::psql::isolated_schema_url;
+use crate::tests::mock::user_repo::MockUserRepo;
+
+/// Users are created in the given user repository.
+#[derive(Default)]
+pub struct MockIdentityRepo(pub Mutex<Vec<LinkedIdentity>>, pub Arc<MockUserRepo>);
+
+#[async_trait]
+impl IdentityRepo for MockIdentityRepo {
+    async fn link_identity(&self, identity: &LinkedIdentity) -> Result<()> {
+        let mut identities = self.0.lock().await;
+        check_unlinked(&identities, identity)?;
+        identities.push(identity.clone());
+        Ok(())
+    }
+
+    async fn create_user_with_identity(
+        &self,
+        user: &User,
+        identity: &LinkedIdentity,
+    ) -> Result<()> {
+        let mut identities = self.0.lock().await;
+        check_unlinked(&identities, identity)?;
+        self.1.create_user(user).await?;
+        identities.push(identity.clone());
+        Ok(())
+    }
+
+    async fn get_identity(&self, provider: &str, subject: &str) -> Result<Option<LinkedIdentity>> {
+        Ok(self
+            .0
+            .lock()
+            .await
+            .iter()
+            .find(|identity| identity.provider == provider && identity.subject == subject)
+            .cloned())
+    }
+
+    async fn list_identities(&self, user_id: &Uuid) -> Result<Vec<LinkedIdentity>> {
+        let mut identities: Vec<_> = self
+            .0
+            .lock()
+            .await
+            .iter()
+            .filter(|identity| identity.user_id == *user_id)
+            .cloned()
+            .collect();
+        identities.sort_by(|a, b| {
+            (a.created_at, &a.provider, &a.subject).cmp(&(b.created_at, &b.provider, &b.subject))
+        });
+        Ok(identities)
+    }
+
+    async fn unlink_identity(&self, user_id: &Uuid, provider: &str, subject: &str) -> Result<bool> {
+        let mut identities = self.0.lock().await;
+        let count = identities.len();
+        identities.retain(|identity| {
+            identity.user_id != *user_id
+                || identity.provider != provider
+                || identity.subject != subject
+        });
+        Ok(identities.len() < count)
+    }
+}
+
+fn check_unlinked(identities: &[LinkedIdentity], identity: &LinkedIdentity) -> Result<()> {
+    if identities
+        .iter()
+        .any(|other| other.provider == identity.provider && other.subject == identity.subject)
+    {
+        return Err(UserRepoError::IdentityConflict(format!(
+            "{}/{}",
+            identity.provider, identity.subject
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Identities reference users, so both repositories come from the same backend.
+#[async_trait]
+pub trait InjectableMockIdentityRepo {
+    async fn init(&self) -> Result<(Arc<dyn UserRepo>, Arc<dyn IdentityRepo>)>;
+}
+
+pub struct MockIdentityRepoNoDb;
+
+#[async_trait]
+impl InjectableMockIdentityRepo for MockIdentityRepoNoDb {
+    async fn init(&self) -> Result<(Arc<dyn UserRepo>, Arc<dyn IdentityRepo>)> {
+        let user_repo = Arc::new(MockUserRepo::default());
+        Ok((
+            user_repo.clone(),
+            Arc::new(MockIdentityRepo(Default::default(), user_repo)),
+        ))
+    }
+}
+
+pub struct MockIdentityRepoPsqlDb;
+
+#[async_trait]
+impl InjectableMockIdentityRepo for MockIdentityRepoPsqlDb {
+    async fn init(&self) -> Result<(Arc<dyn UserRepo>, Arc<dyn IdentityRepo>)> {
+        let repo = Arc::new(
+            UserRepoDb::init(&DatabaseSettings {
+                url: isolated_schema_url().await?,
+                ..Default::default()
+            })
+            .await?,
+        );
+        repo.migrate_up().await?;
+        Ok((repo.clone(), repo))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub struct MockIdentityRepoSqliteDb;
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl InjectableMockIdentityRepo for MockIdentityRepoSqliteDb {
+    async fn init(&self) -> Result<(Arc<dyn UserRepo>, Arc<dyn IdentityRepo>)> {
+        let repo = Arc::new(
+            UserRepoSqlite::init(&DatabaseSettings {
+                url: "sqlite::memory:".into(),
+                ..Default::default()
+            })
+            .await?,
+        );
+        repo.migrate_up().await?;
+        Ok((repo.clone(), repo))
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::DateTime;
+use chrono::Utc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::models::oauth::AuthorizationCode;
+use crate::models::oauth::DeviceAuthorization;
+use crate::models::oauth::DeviceAuthorizationStatus;
+use crate::models::oauth::OAuthClient;
+use crate::models::oauth::UpstreamLogin;
+use crate::repositories::migrations::SchemaMigrator;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::psql::user::UserRepoDb;
+#[cfg(feature = "sqlite")]
+use crate::repositories::sqlite::user::UserRepoSqlite;
+use crate::settings::DatabaseSettings;
+use crate::tests::harness::psql::isolated_schema_url;
+
+#[derive(Default)]
+pub struct MockOAuthRepo {
+    pub clients: Mutex<Vec<OAuthClient>>,
+    pub codes: Mutex<HashMap<String, AuthorizationCode>>,
+    pub device_authorizations: Mutex<HashMap<String, DeviceAuthorization>>,
+    pub upstream_logins: Mutex<HashMap<String, UpstreamLogin>>,
+    pub revoked_tokens: Mutex<HashMap<Uuid, DateTime<Utc>>>,
+    pub revoked_user_tokens: Mutex<HashMap<Uuid, DateTime<Utc>>>,
+    pub dpop_proofs: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+#[async_trait]
+impl OAuthRepo for MockOAuthRepo {
+    async fn create_client(&self, client: &OAuthClient) -> Result<()> {
+        let mut clients = self.clients.lock().await;
+        if clients.iter().any(|other| other.id == client.id) {
+            return Err(anyhow!("Client creation failed!"));
+        }
+        clients.push(client.clone());
+        Ok(())
+    }
+
+    async fn update_client(&self, client: &OAuthClient) -> Result<bool> {
+        let mut clients = self.clients.lock().await;
+        Ok(
+            match clients.iter_mut().find(|other| other.id == client.id) {
+                Some(other) => {
+                    *other = OAuthClient {
+                        created_at: other.created_at,
+                        ..client.clone()
+                    };
+                    true
+                }
+                None => false,
+            },
+        )
+    }
+
+    async fn delete_client(&self, client_id: &str) -> Result<bool> {
+        let mut clients = self.clients.lock().await;
+        let count = clients.len();
+        clients.retain(|client| client.id != client_id);
+        if clients.len() == count {
+            return Ok(false);
+        }
+        self.codes
+            .lock()
+            .await
+            .retain(|_, code| code.client_id != client_id);
+        self.device_authorizations
+            .lock()
+            .await
+            .retain(|_, authorization| authorization.client_id != client_id);
+        Ok(true)
+    }
+
+    async fn get_client(&self, client_id: &str) -> Result<Option<OAuthClient>> {
+        Ok(self
+            .clients
+            .lock()
+            .await
+            .iter()
+            .find(|client| client.id == client_id)
+            .cloned())
+    }
+
+    async fn list_clients(&self) -> Result<Vec<OAuthClient>> {
+        Ok(self.clients.lock().await.clone())
+    }
+
+    async fn store_authorization_code(&self, code: &AuthorizationCode) -> Result<()> {
+        let mut codes = self.codes.lock().await;
+        let now = Utc::now();
+        codes.retain(|_, code| code.expires_at >= now);
+        codes.insert(code.code_hash.clone(), code.clone());
+        Ok(())
+    }
+
+    async fn take_authorization_code(&self, code_hash: &str) -> Result<Option<AuthorizationCode>> {
+        Ok(self.codes.lock().await.remove(code_hash))
+    }
+
+    async fn store_device_authorization(&self, authorization: &DeviceAuthorization) -> Result<()> {
+        let mut authorizations = self.device_authorizations.lock().await;
+        let now = Utc::now();
+        authorizations.retain(|_, authorization| authorization.expires_at >= now);
+        if authorizations
+            .values()
+            .any(|other| other.user_code == authorization.user_code)
+        {
+            return Err(anyhow!("Device authorization creation failed!"));
+        }
+        authorizations.insert(
+            authorization.device_code_hash.clone(),
+            authorization.clone(),
+        );
+        Ok(())
+    }
+
+    async fn get_device_authorization(
+        &self,
+        device_code_hash: &str,
+    ) -> Result<Option<DeviceAuthorization>> {
+        Ok(self
+            .device_authorizations
+            .lock()
+            .await
+            .get(device_code_hash)
+            .cloned())
+    }
+
+    async fn get_device_authorization_by_user_code(
+        &self,
+        user_code: &str,
+    ) -> Result<Option<DeviceAuthorization>> {
+        Ok(self
+            .device_authorizations
+            .lock()
+            .await
+            .values()
+            .find(|authorization| authorization.user_code == user_code)
+            .cloned())
+    }
+
+    async fn record_device_poll(
+        &self,
+        device_code_hash: &str,
+        polled_at: DateTime<Utc>,
+        interval_secs: i64,
+    ) -> Result<()> {
+        if let Some(authorization) = self
+            .device_authorizations
+            .lock()
+            .await
+            .get_mut(device_code_hash)
+        {
+            authorization.last_polled_at = Some(polled_at);
+            authorization.interval_secs = interval_secs;
+        }
+        Ok(())
+    }
+
+    async fn decide_device_authorization(
+        &self,
+        user_code: &str,
+        status: DeviceAuthorizationStatus,
+        user_id: &Uuid,
+    ) -> Result<bool> {
+        let mut authorizations = self.device_authorizations.lock().await;
+        match authorizations.values_mut().find(|authorization| {
+            authorization.user_code == user_code
+                && authorization.status == DeviceAuthorizationStatus::Pending
+        }) {
+            Some(authorization) => {
+                authorization.status = status;
+                authorization.user_id = Some(*user_id);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn take_device_authorization(
+        &self,
+        device_code_hash: &str,
+    ) -> Result<Option<DeviceAuthorization>> {
+        Ok(self
+            .device_authorizations
+            .lock()
+            .await
+            .remove(device_code_hash))
+    }
+
+    async fn store_upstream_login(&self, login: &UpstreamLogin) -> Result<()> {
+        let mut logins = self.upstream_logins.lock().await;
+        let now = Utc::now();
+        logins.retain(|_, login| login.expires_at >= now);
+        logins.insert(login.state_hash.clone(), login.clone());
+        Ok(())
+    }
+
+    async fn take_upstream_login(&self, state_hash: &str) -> Result<Option<UpstreamLogin>> {
+        Ok(self.upstream_logins.lock().await.remove(state_hash))
+    }
+
+    async fn revoke_token(&self, jti: &Uuid, expires_at: DateTime<Utc>) -> Result<()> {
+        let mut revoked_tokens = self.revoked_tokens.lock().await;
+        let now = Utc::now();
+        revoked_tokens.retain(|_, expires_at| *expires_at >= now);
+        revoked_tokens.insert(*jti, expires_at);
+        Ok(())
+    }
+
+    async fn is_token_revoked(&self, jti: &Uuid) -> Result<bool> {
+        Ok(self.revoked_tokens.lock().await.contains_key(jti))
+    }
+
+    async fn revoke_user_tokens(&self, user_id: &Uuid, issued_before: DateTime<Utc>) -> Result<()> {
+        self.revoked_user_tokens
+            .lock()
+            .await
+            .insert(*user_id, issued_before);
+        Ok(())
+    }
+
+    async fn get_user_tokens_revoked_before(
+        &self,
+        user_id: &Uuid,
+    ) -> Result<Option<DateTime<Utc>>> {
+        Ok(self.revoked_user_tokens.lock().await.get(user_id).copied())
+    }
+
+    async fn record_dpop_proof(&self, jti_hash: &str, expires_at: DateTime<Utc>) -> Result<bool> {
+        let mut dpop_proofs = self.dpop_proofs.lock().await;
+        let now = Utc::now();
+        dpop_proofs.retain(|_, expires_at| *expires_at >= now);
+        if dpop_proofs.contains_key(jti_hash) {
+            return Ok(false);
+        }
+        dpop_proofs.insert(jti_hash.to_string(), expires_at);
+        Ok(true)
+    }
+}
+
+#[async_trait]
+pub trait InjectableMockOAuthRepo {
+    async fn init(&self) -> Result<Arc<dyn OAuthRepo>>;
+}
+
+pub struct MockOAuthRepoNoDb;
+
+#[async_trait]
+impl InjectableMockOAuthRepo for MockOAuthRepoNoDb {
+    async fn init(&self) -> Result<Arc<dyn OAuthRepo>> {
+        Ok(Arc::new(MockOAuthRepo::default()))
+    }
+}
+
+pub struct MockOAuthRepoPsqlDb;
+
+#[async_trait]
+impl InjectableMockOAuthRepo for MockOAuthRepoPsqlDb {
+    async fn init(&self) -> Result<Arc<dyn OAuthRepo>> {
+        let repo = UserRepoDb::init(&DatabaseSettings {
+            url: isolated_schema_url().await?,
+            ..Default::default()
+        })
+        .await?;
+        repo.migrate_up().await?;
+        Ok(Arc::new(repo))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub struct MockOAuthRepoSqliteDb;
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl InjectableMockOAuthRepo for MockOAuthRepoSqliteDb {
+    async fn init(&self) -> Result<Arc<dyn OAuthRepo>> {
+        let repo = UserRepoSqlite::init(&DatabaseSettings {
+            url: "sqlite::memory:".into(),
+            ..Default::default()
+        })
+        .await?;
+        repo.migrate_up().await?;
+        Ok(Arc::new(repo))
+    }
+}
@@ -1,23 +1,29 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
 use async_trait::async_trait;
+use chrono::SubsecRound;
+use chrono::Utc;
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
+use crate::errors::user::UserRepoError;
 use crate::models::user::User;
+use crate::models::user::UserBuilder;
+use crate::repositories::migrations::SchemaMigrator;
+use crate::repositories::psql::user::UserRepoDb;
+#[cfg(feature = "sqlite")]
+use crate::repositories::sqlite::user::UserRepoSqlite;
 use crate::repositories::user::UserRepo;
+use crate::settings::DatabaseSettings;
+use crate::tests::harness::psql::isolated_schema_url;
 
+#[derive(Default)]
 pub struct MockUserRepo(pub Mutex<HashMap<Uuid, User>>);
 
-impl Default for MockUserRepo {
-    fn default() -> Self {
-        Self(Default::default())
-    }
-}
-
 impl From<Vec<User>> for MockUserRepo {
     fn from(v: Vec<User>) -> Self {
         Self(Mutex::new(HashMap::from_iter(
@@ -29,7 +35,14 @@ impl From<Vec<User>> for MockUserRepo {
 #[async_trait]
 impl UserRepo for MockUserRepo {
     async fn create_user(&self, user: &User) -> Result<()> {
-        if self.0.lock().await.insert(user.id, user.clone()).is_none() {
+        let mut users = self.0.lock().await;
+        if users
+            .values()
+            .any(|other| other.username.to_lowercase() == user.username.to_lowercase())
+        {
+            return Err(UserRepoError::UsernameConflict(user.username.clone()).into());
+        }
+        if users.insert(user.id, user.clone()).is_none() {
             Ok(())
         } else {
             Err(anyhow!("User creation failed!"))
@@ -45,23 +58,21 @@ impl UserRepo for MockUserRepo {
             .context("No user with given ID")
     }
 
-    async fn update_user_by_id(&self, user_id: &Uuid, new_user: &User) -> Result<()> {
-        *self
-            .0
-            .lock()
-            .await
-            .get_mut(user_id)
-            .context("No user with given ID")? = new_user.clone();
-        Ok(())
+    async fn update_user_by_id(&self, user_id: &Uuid, new_user: &User) -> Result<bool> {
+        Ok(match self.0.lock().await.get_mut(user_id) {
+            Some(user) => {
+                *user = User {
+                    id: *user_id,
+                    ..new_user.clone()
+                };
+                true
+            }
+            None => false,
+        })
     }
 
-    async fn delete_user_by_id(&self, user_id: &Uuid) -> Result<()> {
-        self.0
-            .lock()
-            .await
-            .remove(user_id)
-            .map(|_| ())
-            .context("Failed to delete user with given ID")
+    async fn delete_user_by_id(&self, user_id: &Uuid) -> Result<bool> {
+        Ok(self.0.lock().await.remove(user_id).is_some())
     }
 
     async fn contains_user_with_username(&self, username: &str) -> Result<bool> {
@@ -71,7 +82,17 @@ impl UserRepo for MockUserRepo {
             .await
             .values()
             .map(|user| &user.username)
-            .any(|other_username| other_username == username))
+            .any(|other_username| other_username.to_lowercase() == username.to_lowercase()))
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>> {
+        Ok(self
+            .0
+            .lock()
+            .await
+            .values()
+            .find(|user| user.username.to_lowercase() == username.to_lowercase())
+            .cloned())
     }
 
     async fn get_password_by_id(&self, user_id: &Uuid) -> Result<String> {
@@ -83,3 +104,90 @@ impl UserRepo for MockUserRepo {
             .context("No user with given ID")
     }
 }
+
+#[async_trait]
+pub trait InjectableMockUserRepo {
+    async fn init(&self) -> Result<(Vec<User>, Arc<dyn UserRepo>)>;
+}
+
+pub struct MockUserRepoNoDb;
+
+#[async_trait]
+impl InjectableMockUserRepo for MockUserRepoNoDb {
+    async fn init(&self) -> Result<(Vec<User>, Arc<dyn UserRepo>)> {
+        let user_vec = sample_users()?;
+        let user_repo = Arc::new(MockUserRepo::from(user_vec.clone()));
+
+        Ok((user_vec, user_repo))
+    }
+}
+
+pub struct MockUserRepoPsqlDb;
+
+#[async_trait]
+impl InjectableMockUserRepo for MockUserRepoPsqlDb {
+    async fn init(&self) -> Result<(Vec<User>, Arc<dyn UserRepo>)> {
+        let user_repo = UserRepoDb::init(&DatabaseSettings {
+            url: isolated_schema_url().await?,
+            ..Default::default()
+        })
+        .await?;
+        let user_vec = sample_users()?;
+        user_repo.migrate_up().await?;
+        for user in user_vec.iter() {
+            user_repo.create_user(user).await?;
+        }
+
+        let user_repo = Arc::new(user_repo);
+        Ok((user_vec, user_repo))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub struct MockUserRepoSqliteDb;
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl InjectableMockUserRepo for MockUserRepoSqliteDb {
+    async fn init(&self) -> Result<(Vec<User>, Arc<dyn UserRepo>)> {
+        let user_repo = UserRepoSqlite::init(&DatabaseSettings {
+            url: "sqlite::memory:".into(),
+            ..Default::default()
+        })
+        .await?;
+        let user_vec = sample_users()?;
+        user_repo.migrate_up().await?;
+        for user in user_vec.iter() {
+            user_repo.create_user(user).await?;
+        }
+
+        let user_repo = Arc::new(user_repo);
+        Ok((user_vec, user_repo))
+    }
+}
+
+fn sample_users() -> Result<Vec<User>> {
+    // Databases store timestamps with less than nanosecond precision
+    let now = Utc::now().trunc_subsecs(0);
+    Ok(vec![
+        UserBuilder::default()
+            .id(Uuid::new_v4())
+            .username("Alice")
+            .password_hash("phash1234")
+            .build()?,
+        UserBuilder::default()
+            .id(Uuid::new_v4())
+            .username("Bob")
+            .password_hash("hunter2")
+            .email("bobmaster@email.com")
+            .build()?,
+        UserBuilder::default()
+            .id(Uuid::new_v4())
+            .username("Carl")
+            .password_hash("blahblah")
+            .email("carl@email.com")
+            .created_at(now)
+            .last_login(now)
+            .build()?,
+    ])
+}
@@ -0,0 +1,346 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use rstest::*;
+use serde_json::json;
+use sqlx::PgPool;
+#[cfg(feature = "sqlite")]
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::errors::user::UserRepoError;
+use crate::models::audit::AuditEventType;
+use crate::models::audit::AuditQuery;
+use crate::models::audit::NewAuditEventBuilder;
+use crate::models::audit::GENESIS_HASH;
+use crate::models::user::UserBuilder;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::audit::UserChange;
+use crate::repositories::migrations::SchemaMigrator;
+use crate::repositories::psql::user::UserRepoDb;
+#[cfg(feature = "sqlite")]
+use crate::repositories::sqlite::user::UserRepoSqlite;
+use crate::repositories::user::UserRepo;
+use crate::services::audit::verify_chain;
+use crate::settings::DatabaseSettings;
+use crate::tests::harness::psql::isolated_schema_url;
+use crate::tests::mock::audit_repo::InjectableMockAuditRepo;
+use crate::tests::mock::audit_repo::MockAuditRepoNoDb;
+use crate::tests::mock::audit_repo::MockAuditRepoPsqlDb;
+#[cfg(feature = "sqlite")]
+use crate::tests::mock::audit_repo::MockAuditRepoSqliteDb;
+
+#[rstest]
+#[case::no_db(Arc::new(MockAuditRepoNoDb))]
+#[case::psql_db(Arc::new(MockAuditRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockAuditRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_append_and_list_events(
+    #[case] testable_repo: Arc<dyn InjectableMockAuditRepo>,
+) -> Result<()> {
+    let audit_repo = testable_repo.init().await?;
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+    for (event_type, subject_id) in [
+        (AuditEventType::UserCreated, alice),
+        (AuditEventType::UserCreated, bob),
+        (AuditEventType::UserDeleted, alice),
+    ] {
+        let event = NewAuditEventBuilder::default()
+            .event_type(event_type)
+            .subject_id(subject_id)
+            .details(json!({ "request_id": "req-1" }))
+            .build()?;
+        audit_repo.append_event(&event).await?;
+    }
+
+    // Test events are read back in order, each linked to the previous one
+    let events = audit_repo.list_events(&AuditQuery::default()).await?;
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0].prev_hash, GENESIS_HASH);
+    for pair in events.windows(2) {
+        assert!(pair[0].seq < pair[1].seq);
+        assert_eq!(pair[1].prev_hash, pair[0].hash);
+    }
+    for event in &events {
+        assert_eq!(
+            event.hash,
+            event.compute_hash(),
+            "Event {} read back differs",
+            event.seq
+        );
+    }
+
+    // Test filters
+    let query = AuditQuery {
+        subject_id: Some(alice),
+        ..Default::default()
+    };
+    assert_eq!(audit_repo.list_events(&query).await?.len(), 2);
+    let query = AuditQuery {
+        event_type: Some(AuditEventType::UserDeleted),
+        ..Default::default()
+    };
+    let deleted = audit_repo.list_events(&query).await?;
+    assert_eq!(deleted.len(), 1);
+    assert_eq!(deleted[0].subject_id, Some(alice));
+    let query = AuditQuery {
+        after_seq: Some(events[0].seq),
+        limit: Some(1),
+        ..Default::default()
+    };
+    assert_eq!(audit_repo.list_events(&query).await?, &events[1..2]);
+
+    // Test the head is moved to the latest event
+    let head = audit_repo.get_head().await?.unwrap();
+    assert_eq!(
+        (head.seq, head.hash),
+        (events[2].seq, events[2].hash.clone())
+    );
+    let report = verify_chain(audit_repo.as_ref()).await?;
+    assert!(report.valid);
+    assert_eq!(report.events_checked, 3);
+
+    Ok(())
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockAuditRepoNoDb))]
+#[case::psql_db(Arc::new(MockAuditRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockAuditRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_concurrent_appends_keep_chain(
+    #[case] testable_repo: Arc<dyn InjectableMockAuditRepo>,
+) -> Result<()> {
+    let audit_repo = testable_repo.init().await?;
+    let event = NewAuditEventBuilder::default()
+        .event_type(AuditEventType::LoginFailed)
+        .build()?;
+    let appends: Vec<_> = (0..16)
+        .map(|_| {
+            let (audit_repo, event) = (audit_repo.clone(), event.clone());
+            actix_web::rt::spawn(async move { audit_repo.append_event(&event).await })
+        })
+        .collect();
+    for append in appends {
+        append.await??;
+    }
+
+    let report = verify_chain(audit_repo.as_ref()).await?;
+    assert!(report.valid);
+    assert_eq!(report.events_checked, 16);
+
+    Ok(())
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockAuditRepoNoDb))]
+#[case::psql_db(Arc::new(MockAuditRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockAuditRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_audited_user_changes(
+    #[case] testable_repo: Arc<dyn InjectableMockAuditRepo>,
+) -> Result<()> {
+    let (user_repo, audit_repo) = testable_repo.init_with_users().await?;
+    let mut user = UserBuilder::default()
+        .id(Uuid::new_v4())
+        .username("derek")
+        .password_hash("derekhash")
+        .build()?;
+    let event = |event_type| {
+        NewAuditEventBuilder::default()
+            .event_type(event_type)
+            .subject_id(user.id)
+            .build()
+    };
+    let event_count =
+        || async { anyhow::Ok(audit_repo.list_events(&AuditQuery::default()).await?.len()) };
+
+    // Test changes are kept with their event
+    let created = event(AuditEventType::UserCreated)?;
+    assert!(
+        audit_repo
+            .change_user(UserChange::Create(&user), &created)
+            .await?
+    );
+    assert_eq!(user_repo.get_user_by_id(&user.id).await?.username, "derek");
+    assert_eq!(event_count().await?, 1);
+    user.password_hash = "newhash".into();
+    let changed = event(AuditEventType::PasswordChanged)?;
+    assert!(
+        audit_repo
+            .change_user(UserChange::Update(&user), &changed)
+            .await?
+    );
+    assert_eq!(user_repo.get_password_by_id(&user.id).await?, "newhash");
+    assert_eq!(event_count().await?, 2);
+
+    // Test failed or missed changes have no event
+    let err = audit_repo
+        .change_user(
+            UserChange::Create(
+                &UserBuilder::default()
+                    .id(Uuid::new_v4())
+                    .username("Derek")
+                    .password_hash("otherhash")
+                    .build()?,
+            ),
+            &created,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(UserRepoError::UsernameConflict(_))
+    ));
+    let deleted = event(AuditEventType::UserDeleted)?;
+    assert!(
+        !audit_repo
+            .change_user(UserChange::Delete(&Uuid::new_v4()), &deleted)
+            .await?
+    );
+    assert_eq!(event_count().await?, 2);
+
+    assert!(
+        audit_repo
+            .change_user(UserChange::Delete(&user.id), &deleted)
+            .await?
+    );
+    assert!(user_repo.get_user_by_id(&user.id).await.is_err());
+    assert_eq!(event_count().await?, 3);
+    assert!(verify_chain(audit_repo.as_ref()).await?.valid);
+
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_psql_user_change_rolled_back_without_event() -> Result<()> {
+    let url = isolated_schema_url().await?;
+    let repo = UserRepoDb::init(&DatabaseSettings {
+        url: url.clone(),
+        ..Default::default()
+    })
+    .await?;
+    repo.migrate_up().await?;
+    let pool = PgPool::connect(&url).await?;
+    // The event can't be appended once the head is gone
+    sqlx::query("DROP TABLE audit_head").execute(&pool).await?;
+    let user = UserBuilder::default()
+        .id(Uuid::new_v4())
+        .username("derek")
+        .password_hash("derekhash")
+        .build()?;
+    let event = NewAuditEventBuilder::default()
+        .event_type(AuditEventType::UserCreated)
+        .subject_id(user.id)
+        .build()?;
+
+    assert!(repo
+        .change_user(UserChange::Create(&user), &event)
+        .await
+        .is_err());
+    assert!(!repo.contains_user_with_username("derek").await?);
+    assert!(repo.list_events(&AuditQuery::default()).await?.is_empty());
+
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+#[actix_web::test]
+async fn test_sqlite_user_change_rolled_back_without_event() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("audit-{}.db", Uuid::new_v4()));
+    let url = format!("sqlite://{}", path.display());
+    let repo = UserRepoSqlite::init(&DatabaseSettings {
+        url: url.clone(),
+        ..Default::default()
+    })
+    .await?;
+    repo.migrate_up().await?;
+    let pool = SqlitePool::connect(&url).await?;
+    sqlx::query("DROP TABLE audit_head").execute(&pool).await?;
+    pool.close().await;
+    let user = UserBuilder::default()
+        .id(Uuid::new_v4())
+        .username("derek")
+        .password_hash("derekhash")
+        .build()?;
+    let event = NewAuditEventBuilder::default()
+        .event_type(AuditEventType::UserCreated)
+        .subject_id(user.id)
+        .build()?;
+
+    assert!(repo
+        .change_user(UserChange::Create(&user), &event)
+        .await
+        .is_err());
+    assert!(!repo.contains_user_with_username("derek").await?);
+    assert!(repo.list_events(&AuditQuery::default()).await?.is_empty());
+    std::fs::remove_file(path)?;
+
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_psql_events_are_append_only() -> Result<()> {
+    let url = isolated_schema_url().await?;
+    let repo = UserRepoDb::init(&DatabaseSettings {
+        url: url.clone(),
+        ..Default::default()
+    })
+    .await?;
+    repo.migrate_up().await?;
+    let pool = PgPool::connect(&url).await?;
+    let event = NewAuditEventBuilder::default()
+        .event_type(AuditEventType::UserCreated)
+        .build()?;
+    repo.append_event(&event).await?;
+
+    for statement in [
+        "UPDATE audit_events SET details = '{}'",
+        "DELETE FROM audit_events",
+        "TRUNCATE audit_events",
+    ] {
+        assert!(
+            sqlx::query(statement).execute(&pool).await.is_err(),
+            "{} was allowed",
+            statement
+        );
+    }
+    assert_eq!(repo.list_events(&AuditQuery::default()).await?.len(), 1);
+
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+#[actix_web::test]
+async fn test_sqlite_events_are_append_only() -> Result<()> {
+    // In-memory databases can't be shared with a second pool
+    let path = std::env::temp_dir().join(format!("audit-{}.db", Uuid::new_v4()));
+    let url = format!("sqlite://{}", path.display());
+    let repo = UserRepoSqlite::init(&DatabaseSettings {
+        url: url.clone(),
+        ..Default::default()
+    })
+    .await?;
+    repo.migrate_up().await?;
+    let pool = SqlitePool::connect(&url).await?;
+    let event = NewAuditEventBuilder::default()
+        .event_type(AuditEventType::UserCreated)
+        .build()?;
+    repo.append_event(&event).await?;
+
+    for statement in [
+        "UPDATE audit_events SET details = '{}'",
+        "DELETE FROM audit_events",
+    ] {
+        assert!(
+            sqlx::query(statement).execute(&pool).await.is_err(),
+            "{} was allowed",
+            statement
+        );
+    }
+    assert_eq!(repo.list_events(&AuditQuery::default()).await?.len(), 1);
+    pool.close().await;
+    std::fs::remove_file(path)?;
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::cell::Cell;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use rstest::rstest;
+
+use crate::repositories::database::connect_with_retry;
+use crate::repositories::database::DatabaseProbe;
+use crate::repositories::psql::user::UserRepoDb;
+#[cfg(feature = "sqlite")]
+use crate::repositories::sqlite::user::UserRepoSqlite;
+use crate::settings::DatabaseSettings;
+use crate::tests::harness::psql::isolated_schema_url;
+
+fn retry_settings(connect_retries: u32) -> DatabaseSettings {
+    DatabaseSettings {
+        connect_retries,
+        connect_backoff_ms: 1,
+        connect_backoff_max_ms: 2,
+        ..Default::default()
+    }
+}
+
+#[rstest]
+#[case::first_attempt(0, 0, true)]
+#[case::after_retries(3, 5, true)]
+#[case::retries_exhausted(3, 2, false)]
+#[actix_web::test]
+async fn test_connect_with_retry(
+    #[case] failures: u32,
+    #[case] connect_retries: u32,
+    #[case] expect_ok: bool,
+) {
+    let attempts = Cell::new(0);
+    let result = connect_with_retry(&retry_settings(connect_retries), || {
+        attempts.set(attempts.get() + 1);
+        let attempt = attempts.get();
+        async move {
+            if attempt > failures {
+                Ok(attempt)
+            } else {
+                Err(anyhow!("connection refused"))
+            }
+        }
+    })
+    .await;
+
+    assert_eq!(result.is_ok(), expect_ok);
+    let expected_attempts = if expect_ok {
+        failures + 1
+    } else {
+        connect_retries + 1
+    };
+    assert_eq!(attempts.get(), expected_attempts);
+}
+
+#[rstest]
+#[case::psql_db(false)]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(true))]
+#[actix_web::test]
+async fn test_database_probe(#[case] sqlite: bool) -> Result<()> {
+    let probe: Box<dyn DatabaseProbe> = if sqlite {
+        #[cfg(feature = "sqlite")]
+        {
+            Box::new(
+                UserRepoSqlite::init(&DatabaseSettings {
+                    url: "sqlite::memory:".into(),
+                    ..Default::default()
+                })
+                .await?,
+            )
+        }
+        #[cfg(not(feature = "sqlite"))]
+        unreachable!()
+    } else {
+        Box::new(
+            UserRepoDb::init(&DatabaseSettings {
+                url: isolated_schema_url().await?,
+                max_connections: 3,
+                ..Default::default()
+            })
+            .await?,
+        )
+    };
+
+    probe.ping().await?;
+    let stats = probe.pool_stats();
+    assert!(stats.size >= 1);
+    assert!(stats.idle <= stats.size);
+    assert!(stats.size <= stats.max_size);
+    assert_eq!(stats.max_size, if sqlite { 1 } else { 3 });
+
+    Ok(())
+}
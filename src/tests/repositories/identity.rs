@@ -0,0 +1,162 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::Duration;
+use chrono::SubsecRound;
+use chrono::Utc;
+use rstest::*;
+use uuid::Uuid;
+
+use crate::errors::user::UserRepoError;
+use crate::models::identity::LinkedIdentity;
+use crate::models::user::UserBuilder;
+use crate::tests::mock::identity_repo::InjectableMockIdentityRepo;
+use crate::tests::mock::identity_repo::MockIdentityRepoNoDb;
+use crate::tests::mock::identity_repo::MockIdentityRepoPsqlDb;
+#[cfg(feature = "sqlite")]
+use crate::tests::mock::identity_repo::MockIdentityRepoSqliteDb;
+
+#[rstest]
+#[case::no_db(Arc::new(MockIdentityRepoNoDb))]
+#[case::psql_db(Arc::new(MockIdentityRepoPsqlDb))]
+#[cfg_attr(
+    feature = "sqlite",
+    case::sqlite_db(Arc::new(MockIdentityRepoSqliteDb))
+)]
+#[actix_web::test]
+async fn test_linked_identities(
+    #[case] testable_repo: Arc<dyn InjectableMockIdentityRepo>,
+) -> Result<()> {
+    let (user_repo, identity_repo) = testable_repo.init().await?;
+    let user = UserBuilder::default()
+        .id(Uuid::new_v4())
+        .username("derek")
+        .password_hash("derekhash")
+        .build()?;
+    user_repo.create_user(&user).await?;
+    let google = LinkedIdentity {
+        provider: "google".into(),
+        subject: "110169484474386276334".into(),
+        user_id: user.id,
+        email: Some("derek@example.com".into()),
+        // Databases store timestamps with less than nanosecond precision
+        created_at: (Utc::now() - Duration::seconds(60)).trunc_subsecs(0),
+    };
+    let corp = LinkedIdentity {
+        provider: "corp".into(),
+        subject: "derek".into(),
+        email: None,
+        created_at: Utc::now().trunc_subsecs(0),
+        ..google.clone()
+    };
+
+    // Test a user can have several identities, listed by link date
+    identity_repo.link_identity(&corp).await?;
+    identity_repo.link_identity(&google).await?;
+    assert_eq!(
+        identity_repo.list_identities(&user.id).await?,
+        vec![google.clone(), corp.clone()]
+    );
+    assert_eq!(
+        identity_repo
+            .get_identity("google", &google.subject)
+            .await?,
+        Some(google.clone())
+    );
+    assert_eq!(
+        identity_repo.get_identity("corp", &google.subject).await?,
+        None
+    );
+    assert!(identity_repo
+        .list_identities(&Uuid::new_v4())
+        .await?
+        .is_empty());
+
+    // Test an identity can be linked to one user only
+    let err = identity_repo
+        .link_identity(&LinkedIdentity {
+            user_id: Uuid::new_v4(),
+            ..google.clone()
+        })
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(UserRepoError::IdentityConflict(_))
+    ));
+
+    // Test users are created with their identity, or not at all
+    let provisioned = UserBuilder::default()
+        .id(Uuid::new_v4())
+        .username("deb")
+        .password_hash("debhash")
+        .build()?;
+    let err = identity_repo
+        .create_user_with_identity(
+            &provisioned,
+            &LinkedIdentity {
+                user_id: provisioned.id,
+                ..google.clone()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(UserRepoError::IdentityConflict(_))
+    ));
+    assert!(!user_repo.contains_user_with_username("deb").await?);
+    let deb = LinkedIdentity {
+        provider: "corp".into(),
+        subject: "deb".into(),
+        user_id: provisioned.id,
+        ..corp.clone()
+    };
+    let err = identity_repo
+        .create_user_with_identity(
+            &UserBuilder::default()
+                .id(provisioned.id)
+                .username("Derek")
+                .password_hash("derekhash")
+                .build()?,
+            &deb,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(UserRepoError::UsernameConflict(_))
+    ));
+    assert_eq!(identity_repo.get_identity("corp", "deb").await?, None);
+    identity_repo
+        .create_user_with_identity(&provisioned, &deb)
+        .await?;
+    assert_eq!(
+        user_repo.get_user_by_id(&provisioned.id).await?.username,
+        "deb"
+    );
+    assert_eq!(
+        identity_repo.list_identities(&provisioned.id).await?,
+        vec![deb]
+    );
+
+    // Test identities are unlinked from their user only
+    assert!(
+        !identity_repo
+            .unlink_identity(&Uuid::new_v4(), "google", &google.subject)
+            .await?
+    );
+    assert!(
+        identity_repo
+            .unlink_identity(&user.id, "google", &google.subject)
+            .await?
+    );
+    assert_eq!(
+        identity_repo
+            .get_identity("google", &google.subject)
+            .await?,
+        None
+    );
+
+    Ok(())
+}
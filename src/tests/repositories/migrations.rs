@@ -0,0 +1,132 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use anyhow::Result;
+use sqlx::migrate::AppliedMigration;
+use sqlx::migrate::Migrator;
+
+use crate::repositories::migrations::compute_migration_status;
+use crate::repositories::migrations::ensure_schema_current;
+use crate::repositories::migrations::MigrationState;
+use crate::repositories::migrations::SchemaMigrator;
+use crate::repositories::psql::user::UserRepoDb;
+use crate::repositories::psql::user::MIGRATOR as PSQL_MIGRATOR;
+#[cfg(feature = "sqlite")]
+use crate::repositories::sqlite::user::MIGRATOR as SQLITE_MIGRATOR;
+use crate::settings::DatabaseSettings;
+use crate::tests::harness::psql::isolated_schema_url;
+
+fn applied(migrator: &Migrator, count: usize) -> Vec<AppliedMigration> {
+    migrator
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .take(count)
+        .map(|m| AppliedMigration {
+            version: m.version,
+            checksum: m.checksum.clone(),
+        })
+        .collect()
+}
+
+#[test]
+fn test_migrations_are_reversible() {
+    let migrators = [
+        &PSQL_MIGRATOR,
+        #[cfg(feature = "sqlite")]
+        &SQLITE_MIGRATOR,
+    ];
+    for migrator in migrators {
+        let (down, up): (Vec<_>, Vec<_>) = migrator
+            .iter()
+            .partition(|m| m.migration_type.is_down_migration());
+        assert!(!up.is_empty(), "No migrations embedded");
+        let down_versions: HashSet<_> = down.iter().map(|m| m.version).collect();
+        for m in up {
+            assert!(
+                m.migration_type.is_reversible(),
+                "Migration {} is not reversible",
+                m.version
+            );
+            assert!(
+                down_versions.contains(&m.version),
+                "Migration {} has no down script",
+                m.version
+            );
+        }
+    }
+}
+
+#[test]
+fn test_schema_status() {
+    let migrator = &PSQL_MIGRATOR;
+    let total = applied(migrator, usize::MAX).len();
+
+    // Test fully migrated schema
+    let status = compute_migration_status(migrator, &applied(migrator, total));
+    assert!(status.iter().all(|m| m.state == MigrationState::Applied));
+    assert!(ensure_schema_current(&status).is_ok());
+
+    // Test unmigrated schema
+    let status = compute_migration_status(migrator, &[]);
+    assert!(status.iter().all(|m| m.state == MigrationState::Pending));
+    assert!(ensure_schema_current(&status).is_err());
+
+    // Test partially migrated schema
+    let status = compute_migration_status(migrator, &applied(migrator, total - 1));
+    assert_eq!(
+        status.last().map(|m| m.state),
+        Some(MigrationState::Pending)
+    );
+    assert!(ensure_schema_current(&status).is_err());
+
+    // Test applied migrations which were edited since
+    let mut modified = applied(migrator, total);
+    modified[0].checksum = Cow::Owned(vec![0; 48]);
+    let status = compute_migration_status(migrator, &modified);
+    assert_eq!(status[0].state, MigrationState::Modified);
+    assert!(ensure_schema_current(&status).is_err());
+
+    // Test schema newer than the binary
+    let mut newer = applied(migrator, total);
+    newer.push(AppliedMigration {
+        version: i64::MAX,
+        checksum: Cow::Owned(vec![]),
+    });
+    let status = compute_migration_status(migrator, &newer);
+    assert_eq!(
+        status.last().map(|m| m.state),
+        Some(MigrationState::Unknown)
+    );
+    assert!(ensure_schema_current(&status).is_err());
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_migrations_match_across_backends() {
+    let versions = |migrator: &Migrator| {
+        migrator
+            .iter()
+            .map(|m| (m.version, m.migration_type.is_down_migration()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(versions(&PSQL_MIGRATOR), versions(&SQLITE_MIGRATOR));
+}
+
+#[actix_web::test]
+async fn test_psql_migrations_roundtrip() -> Result<()> {
+    let migrator = UserRepoDb::init(&DatabaseSettings {
+        url: isolated_schema_url().await?,
+        ..Default::default()
+    })
+    .await?;
+
+    assert!(migrator.check_schema().await.is_err());
+    migrator.migrate_up().await?;
+    migrator.check_schema().await?;
+    migrator.migrate_down(0).await?;
+    assert!(migrator.check_schema().await.is_err());
+    migrator.migrate_up().await?;
+    migrator.check_schema().await?;
+
+    Ok(())
+}
@@ -0,0 +1,352 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::Duration;
+use chrono::SubsecRound;
+use chrono::Utc;
+use rstest::*;
+use uuid::Uuid;
+
+use crate::crypto::token_hash;
+use crate::models::oauth::AuthorizationCode;
+use crate::models::oauth::DeviceAuthorization;
+use crate::models::oauth::DeviceAuthorizationStatus;
+use crate::models::oauth::OAuthClient;
+use crate::models::oauth::RedirectUris;
+use crate::models::oauth::UpstreamLogin;
+use crate::tests::mock::oauth_repo::InjectableMockOAuthRepo;
+use crate::tests::mock::oauth_repo::MockOAuthRepoNoDb;
+use crate::tests::mock::oauth_repo::MockOAuthRepoPsqlDb;
+#[cfg(feature = "sqlite")]
+use crate::tests::mock::oauth_repo::MockOAuthRepoSqliteDb;
+
+fn sample_client() -> OAuthClient {
+    OAuthClient {
+        id: Uuid::new_v4().simple().to_string(),
+        name: "Web app".into(),
+        redirect_uris: RedirectUris(vec![
+            "https://app.example.com/callback".into(),
+            "com.example.app:/callback".into(),
+        ]),
+        // Databases store timestamps with less than nanosecond precision
+        created_at: Utc::now().trunc_subsecs(0),
+        secret_hash: Some("secret-hash".into()),
+        allowed_scopes: "profile email".into(),
+        grant_types: "authorization_code client_credentials".into(),
+        previous_secret_hash: None,
+        previous_secret_expires_at: None,
+    }
+}
+
+fn sample_code(client_id: &str, code: &str, expires_in: Duration) -> AuthorizationCode {
+    AuthorizationCode {
+        code_hash: token_hash(code),
+        client_id: client_id.into(),
+        user_id: Uuid::new_v4(),
+        redirect_uri: "https://app.example.com/callback".into(),
+        scope: Some("profile".into()),
+        code_challenge: "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM".into(),
+        expires_at: (Utc::now() + expires_in).trunc_subsecs(0),
+        nonce: Some("n-0S6_WzA2Mj".into()),
+        auth_time: Utc::now().trunc_subsecs(0),
+    }
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockOAuthRepoNoDb))]
+#[case::psql_db(Arc::new(MockOAuthRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockOAuthRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_clients(#[case] testable_repo: Arc<dyn InjectableMockOAuthRepo>) -> Result<()> {
+    let oauth_repo = testable_repo.init().await?;
+    let client = sample_client();
+    oauth_repo.create_client(&client).await?;
+
+    assert_eq!(
+        oauth_repo.get_client(&client.id).await?,
+        Some(client.clone())
+    );
+    assert_eq!(oauth_repo.get_client("unknown").await?, None);
+    assert_eq!(oauth_repo.list_clients().await?, vec![client.clone()]);
+    assert!(oauth_repo.create_client(&client).await.is_err());
+
+    // Test updates replace everything but the ID and creation date
+    let updated = OAuthClient {
+        name: "Renamed app".into(),
+        redirect_uris: RedirectUris(vec!["https://new.example.com/callback".into()]),
+        secret_hash: Some("new-secret-hash".into()),
+        allowed_scopes: "profile".into(),
+        grant_types: "authorization_code".into(),
+        previous_secret_hash: Some("secret-hash".into()),
+        previous_secret_expires_at: Some((Utc::now() + Duration::hours(1)).trunc_subsecs(0)),
+        ..client.clone()
+    };
+    assert!(oauth_repo.update_client(&updated).await?);
+    assert_eq!(oauth_repo.get_client(&client.id).await?, Some(updated));
+    let unknown = sample_client();
+    assert!(!oauth_repo.update_client(&unknown).await?);
+
+    // Test deleting a client drops the codes issued to it
+    let code = sample_code(&client.id, "valid", Duration::seconds(60));
+    oauth_repo.store_authorization_code(&code).await?;
+    assert!(oauth_repo.delete_client(&client.id).await?);
+    assert!(!oauth_repo.delete_client(&client.id).await?);
+    assert_eq!(oauth_repo.get_client(&client.id).await?, None);
+    assert_eq!(
+        oauth_repo.take_authorization_code(&code.code_hash).await?,
+        None
+    );
+
+    Ok(())
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockOAuthRepoNoDb))]
+#[case::psql_db(Arc::new(MockOAuthRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockOAuthRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_authorization_codes(
+    #[case] testable_repo: Arc<dyn InjectableMockOAuthRepo>,
+) -> Result<()> {
+    let oauth_repo = testable_repo.init().await?;
+    let client = sample_client();
+    oauth_repo.create_client(&client).await?;
+    let expired = sample_code(&client.id, "expired", Duration::seconds(-1));
+    let code = sample_code(&client.id, "valid", Duration::seconds(60));
+    oauth_repo.store_authorization_code(&expired).await?;
+    oauth_repo.store_authorization_code(&code).await?;
+
+    // Test codes can only be taken once
+    assert_eq!(
+        oauth_repo.take_authorization_code(&code.code_hash).await?,
+        Some(code.clone())
+    );
+    assert_eq!(
+        oauth_repo.take_authorization_code(&code.code_hash).await?,
+        None
+    );
+
+    // Test expired codes are dropped when storing new ones
+    assert_eq!(
+        oauth_repo
+            .take_authorization_code(&expired.code_hash)
+            .await?,
+        None
+    );
+
+    Ok(())
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockOAuthRepoNoDb))]
+#[case::psql_db(Arc::new(MockOAuthRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockOAuthRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_device_authorizations(
+    #[case] testable_repo: Arc<dyn InjectableMockOAuthRepo>,
+) -> Result<()> {
+    let oauth_repo = testable_repo.init().await?;
+    let client = sample_client();
+    oauth_repo.create_client(&client).await?;
+    let authorization = DeviceAuthorization {
+        device_code_hash: token_hash("device-code"),
+        user_code: "BCDFGHJK".into(),
+        client_id: client.id.clone(),
+        scope: Some("profile".into()),
+        expires_at: (Utc::now() + Duration::seconds(600)).trunc_subsecs(0),
+        interval_secs: 5,
+        last_polled_at: None,
+        status: DeviceAuthorizationStatus::Pending,
+        user_id: None,
+    };
+    oauth_repo
+        .store_device_authorization(&authorization)
+        .await?;
+    assert!(oauth_repo
+        .store_device_authorization(&DeviceAuthorization {
+            device_code_hash: token_hash("other-device-code"),
+            ..authorization.clone()
+        })
+        .await
+        .is_err());
+
+    assert_eq!(
+        oauth_repo
+            .get_device_authorization(&authorization.device_code_hash)
+            .await?,
+        Some(authorization.clone())
+    );
+    assert_eq!(
+        oauth_repo
+            .get_device_authorization_by_user_code("BCDFGHJK")
+            .await?,
+        Some(authorization.clone())
+    );
+    assert_eq!(
+        oauth_repo
+            .get_device_authorization_by_user_code("ZZZZZZZZ")
+            .await?,
+        None
+    );
+
+    // Test polls are recorded, and only pending authorizations are decided
+    let polled_at = Utc::now().trunc_subsecs(0);
+    oauth_repo
+        .record_device_poll(&authorization.device_code_hash, polled_at, 10)
+        .await?;
+    let user_id = Uuid::new_v4();
+    assert!(
+        oauth_repo
+            .decide_device_authorization("BCDFGHJK", DeviceAuthorizationStatus::Approved, &user_id)
+            .await?
+    );
+    assert!(
+        !oauth_repo
+            .decide_device_authorization("BCDFGHJK", DeviceAuthorizationStatus::Denied, &user_id)
+            .await?
+    );
+    let decided = DeviceAuthorization {
+        interval_secs: 10,
+        last_polled_at: Some(polled_at),
+        status: DeviceAuthorizationStatus::Approved,
+        user_id: Some(user_id),
+        ..authorization.clone()
+    };
+
+    // Test authorizations can only be taken once
+    assert_eq!(
+        oauth_repo
+            .take_device_authorization(&authorization.device_code_hash)
+            .await?,
+        Some(decided)
+    );
+    assert_eq!(
+        oauth_repo
+            .take_device_authorization(&authorization.device_code_hash)
+            .await?,
+        None
+    );
+
+    Ok(())
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockOAuthRepoNoDb))]
+#[case::psql_db(Arc::new(MockOAuthRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockOAuthRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_revoked_tokens(
+    #[case] testable_repo: Arc<dyn InjectableMockOAuthRepo>,
+) -> Result<()> {
+    let oauth_repo = testable_repo.init().await?;
+    let (jti, other_jti) = (Uuid::new_v4(), Uuid::new_v4());
+    let expires_at = (Utc::now() + Duration::seconds(60)).trunc_subsecs(0);
+
+    assert!(!oauth_repo.is_token_revoked(&jti).await?);
+    oauth_repo.revoke_token(&jti, expires_at).await?;
+    assert!(oauth_repo.is_token_revoked(&jti).await?);
+    assert!(!oauth_repo.is_token_revoked(&other_jti).await?);
+
+    // Test revoking twice is fine, and the entries of expired tokens are dropped
+    oauth_repo.revoke_token(&jti, expires_at).await?;
+    oauth_repo
+        .revoke_token(&other_jti, Utc::now() - Duration::seconds(1))
+        .await?;
+    oauth_repo.revoke_token(&Uuid::new_v4(), expires_at).await?;
+    assert!(oauth_repo.is_token_revoked(&jti).await?);
+    assert!(!oauth_repo.is_token_revoked(&other_jti).await?);
+
+    // Test every token of a user can be revoked at once, the latest cutoff winning
+    let (user_id, other_user_id) = (Uuid::new_v4(), Uuid::new_v4());
+    assert_eq!(
+        oauth_repo.get_user_tokens_revoked_before(&user_id).await?,
+        None
+    );
+    oauth_repo
+        .revoke_user_tokens(&user_id, expires_at - Duration::seconds(60))
+        .await?;
+    oauth_repo.revoke_user_tokens(&user_id, expires_at).await?;
+    assert_eq!(
+        oauth_repo.get_user_tokens_revoked_before(&user_id).await?,
+        Some(expires_at)
+    );
+    assert_eq!(
+        oauth_repo
+            .get_user_tokens_revoked_before(&other_user_id)
+            .await?,
+        None
+    );
+
+    Ok(())
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockOAuthRepoNoDb))]
+#[case::psql_db(Arc::new(MockOAuthRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockOAuthRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_dpop_proofs(#[case] testable_repo: Arc<dyn InjectableMockOAuthRepo>) -> Result<()> {
+    let oauth_repo = testable_repo.init().await?;
+    let expires_at = (Utc::now() + Duration::seconds(60)).trunc_subsecs(0);
+
+    assert!(oauth_repo.record_dpop_proof("proof-1", expires_at).await?);
+    assert!(!oauth_repo.record_dpop_proof("proof-1", expires_at).await?);
+    assert!(oauth_repo.record_dpop_proof("proof-2", expires_at).await?);
+
+    // Test expired proofs are forgotten
+    oauth_repo
+        .record_dpop_proof("proof-3", Utc::now() - Duration::seconds(1))
+        .await?;
+    assert!(oauth_repo.record_dpop_proof("proof-3", expires_at).await?);
+    assert!(!oauth_repo.record_dpop_proof("proof-2", expires_at).await?);
+
+    Ok(())
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockOAuthRepoNoDb))]
+#[case::psql_db(Arc::new(MockOAuthRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockOAuthRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_upstream_logins(
+    #[case] testable_repo: Arc<dyn InjectableMockOAuthRepo>,
+) -> Result<()> {
+    let oauth_repo = testable_repo.init().await?;
+    let login = UpstreamLogin {
+        state_hash: token_hash("state"),
+        provider: "corp".into(),
+        nonce: "nonce".into(),
+        code_verifier: "verifier".into(),
+        authorize_params: r#"{"client_id":"web"}"#.into(),
+        expires_at: (Utc::now() + Duration::seconds(600)).trunc_subsecs(0),
+    };
+    oauth_repo.store_upstream_login(&login).await?;
+
+    // Test logins are taken once
+    assert_eq!(
+        oauth_repo.take_upstream_login(&login.state_hash).await?,
+        Some(login.clone())
+    );
+    assert_eq!(
+        oauth_repo.take_upstream_login(&login.state_hash).await?,
+        None
+    );
+
+    // Test expired logins are dropped
+    oauth_repo
+        .store_upstream_login(&UpstreamLogin {
+            state_hash: token_hash("expired"),
+            expires_at: Utc::now() - Duration::seconds(1),
+            ..login.clone()
+        })
+        .await?;
+    oauth_repo.store_upstream_login(&login).await?;
+    assert_eq!(
+        oauth_repo
+            .take_upstream_login(&token_hash("expired"))
+            .await?,
+        None
+    );
+
+    Ok(())
+}
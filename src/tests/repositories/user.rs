@@ -0,0 +1,236 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use rstest::*;
+use uuid::Uuid;
+
+use crate::errors::user::UserRepoError;
+use crate::models::user::UserBuilder;
+use crate::tests::mock::user_repo::InjectableMockUserRepo;
+use crate::tests::mock::user_repo::MockUserRepoNoDb;
+use crate::tests::mock::user_repo::MockUserRepoPsqlDb;
+#[cfg(feature = "sqlite")]
+use crate::tests::mock::user_repo::MockUserRepoSqliteDb;
+
+#[rstest]
+#[case::no_db(Arc::new(MockUserRepoNoDb))]
+#[case::psql_db(Arc::new(MockUserRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockUserRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_create_user(#[case] testable_repo: Arc<dyn InjectableMockUserRepo>) -> Result<()> {
+    let (user_vec, user_repo) = testable_repo.init().await?;
+
+    // Test existing users can be read back
+    for user in user_vec {
+        assert_eq!(
+            user_repo.get_user_by_id(&user.id).await?,
+            user,
+            "User {} read back differs from the one created",
+            user.id
+        );
+    }
+
+    // Test a new user can be created and read back
+    let user = UserBuilder::default()
+        .id(Uuid::new_v4())
+        .username("Derek")
+        .password_hash("derekhash")
+        .build()?;
+    user_repo.create_user(&user).await?;
+    assert_eq!(user_repo.get_user_by_id(&user.id).await?, user);
+
+    // Test reading a nonexistent user fails
+    assert!(user_repo.get_user_by_id(&Uuid::new_v4()).await.is_err());
+
+    Ok(())
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockUserRepoNoDb))]
+#[case::psql_db(Arc::new(MockUserRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockUserRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_update_user_by_id(
+    #[case] testable_repo: Arc<dyn InjectableMockUserRepo>,
+) -> Result<()> {
+    let (user_vec, user_repo) = testable_repo.init().await?;
+
+    // Test updating an existing user reports a row was affected
+    let id = user_vec[0].id;
+    let new_user = UserBuilder::default()
+        .id(id)
+        .username("Alicia")
+        .password_hash("newhash")
+        .email("alicia@email.com")
+        .build()?;
+    assert!(
+        user_repo.update_user_by_id(&id, &new_user).await?,
+        "Update of existing user {} reported no row affected",
+        id
+    );
+    assert_eq!(user_repo.get_user_by_id(&id).await?, new_user);
+
+    // Test updating a nonexistent user reports no row was affected
+    let missing_id = Uuid::new_v4();
+    assert!(
+        !user_repo.update_user_by_id(&missing_id, &new_user).await?,
+        "Update of nonexistent user reported a row affected"
+    );
+    assert!(user_repo.get_user_by_id(&missing_id).await.is_err());
+
+    Ok(())
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockUserRepoNoDb))]
+#[case::psql_db(Arc::new(MockUserRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockUserRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_delete_user_by_id(
+    #[case] testable_repo: Arc<dyn InjectableMockUserRepo>,
+) -> Result<()> {
+    let (user_vec, user_repo) = testable_repo.init().await?;
+
+    // Test deleting an existing user reports a row was affected
+    let id = user_vec[0].id;
+    assert!(
+        user_repo.delete_user_by_id(&id).await?,
+        "Delete of existing user {} reported no row affected",
+        id
+    );
+    assert!(user_repo.get_user_by_id(&id).await.is_err());
+
+    // Test deleting it again reports no row was affected
+    assert!(
+        !user_repo.delete_user_by_id(&id).await?,
+        "Delete of already deleted user {} reported a row affected",
+        id
+    );
+
+    // Test other users are untouched
+    for user in &user_vec[1..] {
+        assert!(user_repo.get_user_by_id(&user.id).await.is_ok());
+    }
+
+    Ok(())
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockUserRepoNoDb))]
+#[case::psql_db(Arc::new(MockUserRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockUserRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_contains_user_with_username(
+    #[case] testable_repo: Arc<dyn InjectableMockUserRepo>,
+) -> Result<()> {
+    let (user_vec, user_repo) = testable_repo.init().await?;
+
+    for user in user_vec {
+        assert!(
+            user_repo
+                .contains_user_with_username(&user.username)
+                .await?,
+            "Username {} not found",
+            user.username
+        );
+    }
+    assert!(!user_repo.contains_user_with_username("Nobody").await?);
+
+    Ok(())
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockUserRepoNoDb))]
+#[case::psql_db(Arc::new(MockUserRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockUserRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_get_user_by_username(
+    #[case] testable_repo: Arc<dyn InjectableMockUserRepo>,
+) -> Result<()> {
+    let (user_vec, user_repo) = testable_repo.init().await?;
+
+    for user in user_vec {
+        assert_eq!(
+            user_repo
+                .get_user_by_username(&user.username.to_uppercase())
+                .await?
+                .as_ref(),
+            Some(&user),
+        );
+    }
+    assert_eq!(user_repo.get_user_by_username("Nobody").await?, None);
+
+    Ok(())
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockUserRepoNoDb))]
+#[case::psql_db(Arc::new(MockUserRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockUserRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_get_password_by_id(
+    #[case] testable_repo: Arc<dyn InjectableMockUserRepo>,
+) -> Result<()> {
+    let (user_vec, user_repo) = testable_repo.init().await?;
+
+    for user in user_vec {
+        assert_eq!(
+            user_repo.get_password_by_id(&user.id).await?,
+            user.password_hash
+        );
+    }
+    assert!(user_repo.get_password_by_id(&Uuid::new_v4()).await.is_err());
+
+    Ok(())
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockUserRepoNoDb))]
+#[case::psql_db(Arc::new(MockUserRepoPsqlDb))]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(Arc::new(MockUserRepoSqliteDb)))]
+#[actix_web::test]
+async fn test_create_user_username_conflict(
+    #[case] testable_repo: Arc<dyn InjectableMockUserRepo>,
+) -> Result<()> {
+    let (_, user_repo) = testable_repo.init().await?;
+
+    // Test usernames are unique regardless of case
+    let user = UserBuilder::default()
+        .id(Uuid::new_v4())
+        .username("aLiCe")
+        .password_hash("otherhash")
+        .build()?;
+    let err = user_repo
+        .create_user(&user)
+        .await
+        .expect_err("Created user with a taken username");
+    assert!(matches!(
+        err.downcast_ref::<UserRepoError>(),
+        Some(UserRepoError::UsernameConflict(_))
+    ));
+    assert!(user_repo.get_user_by_id(&user.id).await.is_err());
+
+    // Test concurrent creations with the same username only let one through
+    let (user_a, user_b) = (
+        UserBuilder::default()
+            .id(Uuid::new_v4())
+            .username("Derek")
+            .password_hash("hash_a")
+            .build()?,
+        UserBuilder::default()
+            .id(Uuid::new_v4())
+            .username("derek")
+            .password_hash("hash_b")
+            .build()?,
+    );
+    let (res_a, res_b) = tokio::join!(
+        user_repo.create_user(&user_a),
+        user_repo.create_user(&user_b)
+    );
+    assert!(
+        res_a.is_ok() ^ res_b.is_ok(),
+        "Expected exactly one concurrent creation to succeed"
+    );
+
+    Ok(())
+}
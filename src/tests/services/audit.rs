@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::http::StatusCode;
+use actix_web::test;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::App;
+use anyhow::Result;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::models::audit::AuditChainReport;
+use crate::models::audit::AuditEventRespDto;
+use crate::models::audit::AuditEventType;
+use crate::models::audit::AuditHead;
+use crate::models::audit::ChainVerifier;
+use crate::models::audit::NewAuditEventBuilder;
+use crate::repositories::audit::AuditRepo;
+use crate::services::audit::get_audit_chain_verification;
+use crate::services::audit::get_audit_events;
+use crate::services::audit::verify_chain;
+use crate::settings::AdminSettings;
+use crate::tests::mock::audit_repo::MockAuditRepo;
+
+const ADMIN_TOKEN: &str = "0123456789abcdef0123456789abcdef";
+
+#[actix_web::test]
+async fn test_audit_endpoints() -> Result<()> {
+    let audit_repo = Arc::new(MockAuditRepo::default());
+    let subject_id = Uuid::new_v4();
+    for event_type in [AuditEventType::UserCreated, AuditEventType::UserDeleted] {
+        let event = NewAuditEventBuilder::default()
+            .event_type(event_type)
+            .subject_id(subject_id)
+            .build()?;
+        audit_repo.append_event(&event).await?;
+    }
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::<dyn AuditRepo>::from(audit_repo.clone() as Arc<_>))
+            .app_data(Data::new(AdminSettings {
+                api_token: Some(ADMIN_TOKEN.into()),
+            }))
+            .route("/admin/audit", web::get().to(get_audit_events))
+            .route(
+                "/admin/audit/verify",
+                web::get().to(get_audit_chain_verification),
+            ),
+    )
+    .await;
+
+    // Test the admin token is required
+    for token in [None, Some("wrong")] {
+        let mut req = test::TestRequest::get().uri("/admin/audit");
+        if let Some(token) = token {
+            req = req.insert_header((AUTHORIZATION, format!("Bearer {}", token)));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    // Test events are listed and filtered
+    let bearer = (AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN));
+    let req = test::TestRequest::get()
+        .uri("/admin/audit?event_type=user_deleted")
+        .insert_header(bearer.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let events: Vec<AuditEventRespDto> = test::read_body_json(resp).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event_type, AuditEventType::UserDeleted);
+    assert_eq!(events[0].subject_id, Some(subject_id));
+
+    // Test the chain verifies
+    let req = test::TestRequest::get()
+        .uri("/admin/audit/verify")
+        .insert_header(bearer.clone())
+        .to_request();
+    let report: AuditChainReport = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        report,
+        AuditChainReport {
+            valid: true,
+            events_checked: 2,
+            first_invalid_seq: None,
+        }
+    );
+
+    // Test tampering with a recorded event is detected
+    audit_repo.0.lock().await[0].details = json!({ "username": "mallory" }).to_string();
+    let req = test::TestRequest::get()
+        .uri("/admin/audit/verify")
+        .insert_header(bearer)
+        .to_request();
+    let report: AuditChainReport = test::call_and_read_body_json(&app, req).await;
+    assert!(!report.valid);
+    assert_eq!(report.first_invalid_seq, Some(1));
+
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_verify_chain_detects_removed_head() -> Result<()> {
+    let audit_repo = MockAuditRepo::default();
+    let event = NewAuditEventBuilder::default()
+        .event_type(AuditEventType::LoginFailed)
+        .build()?;
+    for _ in 0..3 {
+        audit_repo.append_event(&event).await?;
+    }
+    assert!(verify_chain(&audit_repo).await?.valid);
+
+    // Test events appended after the head was read are left for the next verification
+    let events = audit_repo.0.lock().await.clone();
+    let head = audit_repo.1.lock().await.replace(AuditHead {
+        seq: events[0].seq,
+        hash: events[0].hash.clone(),
+    });
+    let report = verify_chain(&audit_repo).await?;
+    assert!(report.valid);
+    assert_eq!(report.events_checked, 1);
+
+    // Test the remaining events link up, but no longer lead to the head
+    *audit_repo.1.lock().await = head;
+    audit_repo.0.lock().await.pop();
+    let report = verify_chain(&audit_repo).await?;
+    assert!(!report.valid);
+    assert_eq!(report.events_checked, 2);
+    assert_eq!(report.first_invalid_seq, Some(3));
+
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_admin_endpoints_disabled_without_token() {
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::<dyn AuditRepo>::from(
+                Arc::new(MockAuditRepo::default()) as Arc<_>,
+            ))
+            .app_data(Data::new(AdminSettings::default()))
+            .route("/admin/audit", web::get().to(get_audit_events)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/admin/audit")
+        .insert_header((AUTHORIZATION, "Bearer "))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn test_chain_verifier_detects_removed_event() -> Result<()> {
+    let audit_repo = MockAuditRepo::default();
+    let event = NewAuditEventBuilder::default()
+        .event_type(AuditEventType::LoginSucceeded)
+        .build()?;
+    for _ in 0..3 {
+        audit_repo.append_event(&event).await?;
+    }
+    let mut events = audit_repo.0.lock().await.clone();
+    events.remove(1);
+
+    let mut verifier = ChainVerifier::default();
+    assert!(events.iter().any(|event| !verifier.check(event)));
+    assert_eq!(verifier.report().first_invalid_seq, Some(3));
+
+    Ok(())
+}
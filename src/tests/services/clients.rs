@@ -0,0 +1,319 @@
+use std::sync::Arc;
+
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::http::StatusCode;
+use actix_web::test;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::App;
+use anyhow::Result;
+use serde_json::json;
+
+use crate::crypto::PasswordHasher;
+use crate::crypto::TokenSigner;
+use crate::errors::user::ProblemDetailsDto;
+use crate::models::oauth::DynamicRegistrationRespDto;
+use crate::models::oauth::OAuthClient;
+use crate::models::oauth::OAuthErrorDto;
+use crate::models::oauth::RegisteredClientDto;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::user::UserRepo;
+use crate::services::clients::delete_client;
+use crate::services::clients::get_client;
+use crate::services::clients::get_clients;
+use crate::services::clients::post_client;
+use crate::services::clients::post_client_secret;
+use crate::services::clients::post_register;
+use crate::services::clients::put_client;
+use crate::services::oauth::post_token;
+use crate::settings::AdminSettings;
+use crate::settings::OAuthSettings;
+use crate::settings::TokenSettings;
+use crate::tests::mock::audit_repo::MockAuditRepo;
+use crate::tests::mock::oauth_repo::MockOAuthRepo;
+use crate::tests::mock::user_repo::MockUserRepo;
+
+const ADMIN_TOKEN: &str = "0123456789abcdef0123456789abcdef";
+const INITIAL_ACCESS_TOKEN: &str = "fedcba9876543210fedcba9876543210";
+
+#[actix_web::test]
+async fn test_admin_client_management() -> Result<()> {
+    let oauth_repo: Arc<dyn OAuthRepo> = Arc::new(MockOAuthRepo::default());
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::from(
+                Arc::new(MockUserRepo::default()) as Arc<dyn UserRepo>
+            ))
+            .app_data(Data::from(
+                Arc::new(MockAuditRepo::default()) as Arc<dyn AuditRepo>
+            ))
+            .app_data(Data::from(oauth_repo.clone()))
+            .app_data(Data::new(PasswordHasher::default()))
+            .app_data(Data::new(TokenSigner::from_settings(
+                &TokenSettings::default(),
+            )?))
+            .app_data(Data::new(TokenSettings::default()))
+            .app_data(Data::new(OAuthSettings::default()))
+            .app_data(Data::new(AdminSettings {
+                api_token: Some(ADMIN_TOKEN.into()),
+            }))
+            .route("/oauth/token", web::post().to(post_token))
+            .route("/admin/clients", web::get().to(get_clients))
+            .route("/admin/clients", web::post().to(post_client))
+            .route("/admin/clients/{client_id}", web::get().to(get_client))
+            .route("/admin/clients/{client_id}", web::put().to(put_client))
+            .route(
+                "/admin/clients/{client_id}",
+                web::delete().to(delete_client),
+            )
+            .route(
+                "/admin/clients/{client_id}/secret",
+                web::post().to(post_client_secret),
+            ),
+    )
+    .await;
+    let bearer = (AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN));
+    let client_token = |client_id: &str, secret: &str| {
+        test::TestRequest::post()
+            .uri("/oauth/token")
+            .set_form([
+                ("grant_type", "client_credentials"),
+                ("client_id", client_id),
+                ("client_secret", secret),
+            ])
+            .to_request()
+    };
+
+    // Test the admin token is required
+    let req = test::TestRequest::get().uri("/admin/clients").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // Test clients are created with the grant types they can use
+    let req = test::TestRequest::post()
+        .uri("/admin/clients")
+        .insert_header(bearer.clone())
+        .set_json(
+            json!({"name": "Billing job", "allowed_scopes": ["users:read"], "confidential": true}),
+        )
+        .to_request();
+    let created: RegisteredClientDto = test::call_and_read_body_json(&app, req).await;
+    let client_id = created.client.id.clone();
+    let secret = created.client_secret.unwrap();
+    assert!(created.client.allows_grant_type("client_credentials"));
+    assert!(!created.client.allows_grant_type("authorization_code"));
+    let req = test::TestRequest::get()
+        .uri("/admin/clients")
+        .insert_header(bearer.clone())
+        .to_request();
+    let clients: Vec<OAuthClient> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(clients.len(), 1);
+    assert_eq!(clients[0].id, client_id);
+
+    // Test updates are checked, and restrict the grants of the client
+    let uri = format!("/admin/clients/{}", client_id);
+    let req = test::TestRequest::put()
+        .uri(&uri)
+        .insert_header(bearer.clone())
+        .set_json(json!({"name": "Billing job", "grant_types": ["authorization_code"]}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let problem: ProblemDetailsDto = test::read_body_json(resp).await;
+    assert_eq!(problem.code, "invalid_client_fields");
+    let req = test::TestRequest::put()
+        .uri(&uri)
+        .insert_header(bearer.clone())
+        .set_json(json!({
+            "name": "Billing sync",
+            "allowed_scopes": ["users:read"],
+            "grant_types": ["urn:ietf:params:oauth:grant-type:device_code"],
+        }))
+        .to_request();
+    let updated: OAuthClient = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(updated.name, "Billing sync");
+    let resp = test::call_service(&app, client_token(&client_id, &secret)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let error: OAuthErrorDto = test::read_body_json(resp).await;
+    assert_eq!(error.error, "unauthorized_client");
+    let req = test::TestRequest::put()
+        .uri(&uri)
+        .insert_header(bearer.clone())
+        .set_json(json!({"name": "Billing job", "allowed_scopes": ["users:read"]}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Test both secrets work during the overlap of a rotation, then only the new one
+    let rotate = |overlap_secs: u64| {
+        test::TestRequest::post()
+            .uri(&format!("{}/secret", uri))
+            .insert_header(bearer.clone())
+            .set_json(json!({ "overlap_secs": overlap_secs }))
+            .to_request()
+    };
+    let rotated: RegisteredClientDto = test::call_and_read_body_json(&app, rotate(3600)).await;
+    let new_secret = rotated.client_secret.unwrap();
+    assert!(rotated.client.previous_secret_expires_at.is_some());
+    for secret in [&secret, &new_secret] {
+        let resp = test::call_service(&app, client_token(&client_id, secret)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    let rotated: RegisteredClientDto = test::call_and_read_body_json(&app, rotate(0)).await;
+    let newest_secret = rotated.client_secret.unwrap();
+    for (secret, status) in [
+        (&new_secret, StatusCode::UNAUTHORIZED),
+        (&newest_secret, StatusCode::OK),
+    ] {
+        let resp = test::call_service(&app, client_token(&client_id, secret)).await;
+        assert_eq!(resp.status(), status);
+    }
+    let resp = test::call_service(&app, rotate(365 * 86400)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Test deleted clients are gone
+    let req = test::TestRequest::delete()
+        .uri(&uri)
+        .insert_header(bearer.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, client_token(&client_id, &newest_secret)).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let req = test::TestRequest::get()
+        .uri(&uri)
+        .insert_header(bearer.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let problem: ProblemDetailsDto = test::read_body_json(resp).await;
+    assert_eq!(problem.code, "no_client_for_id");
+
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_dynamic_client_registration() -> Result<()> {
+    let oauth_repo: Arc<dyn OAuthRepo> = Arc::new(MockOAuthRepo::default());
+    let app = |initial_access_token: Option<&str>| {
+        test::init_service(
+            App::new()
+                .app_data(Data::from(oauth_repo.clone()))
+                .app_data(Data::new(PasswordHasher::default()))
+                .app_data(Data::new(OAuthSettings {
+                    initial_access_token: initial_access_token.map(Into::into),
+                    ..Default::default()
+                }))
+                .route("/oauth/register", web::post().to(post_register)),
+        )
+    };
+    let register = |token: &str, metadata: serde_json::Value| {
+        test::TestRequest::post()
+            .uri("/oauth/register")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", token)))
+            .set_json(metadata)
+            .to_request()
+    };
+    let web_app = json!({
+        "client_name": "Web app",
+        "redirect_uris": ["https://app.example.com/callback"],
+        "scope": "openid profile",
+    });
+
+    // Test registration needs the initial access token, and is disabled without one
+    for (initial_access_token, token) in [
+        (None, INITIAL_ACCESS_TOKEN),
+        (Some(INITIAL_ACCESS_TOKEN), "wrong"),
+    ] {
+        let app = app(initial_access_token).await;
+        let resp = test::call_service(&app, register(token, web_app.clone())).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let error: OAuthErrorDto = test::read_body_json(resp).await;
+        assert_eq!(error.error, "invalid_token");
+    }
+    assert!(oauth_repo.list_clients().await?.is_empty());
+
+    // Test confidential clients with the authorization code grant by default
+    let app = app(Some(INITIAL_ACCESS_TOKEN)).await;
+    let resp = test::call_service(&app, register(INITIAL_ACCESS_TOKEN, web_app)).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let registered: DynamicRegistrationRespDto = test::read_body_json(resp).await;
+    assert!(registered.client_secret.is_some());
+    assert_eq!(registered.client_secret_expires_at, Some(0));
+    assert_eq!(registered.grant_types, vec!["authorization_code"]);
+    assert_eq!(registered.scope, "openid profile");
+    assert_eq!(registered.token_endpoint_auth_method, "client_secret_basic");
+    let client = oauth_repo.get_client(&registered.client_id).await?.unwrap();
+    assert!(client.is_confidential());
+    assert!(!client.allows_grant_type("client_credentials"));
+
+    // Test public clients
+    let metadata = json!({
+        "client_name": "Deploy CLI",
+        "grant_types": ["urn:ietf:params:oauth:grant-type:device_code"],
+        "token_endpoint_auth_method": "none",
+    });
+    let resp = test::call_service(&app, register(INITIAL_ACCESS_TOKEN, metadata)).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let registered: DynamicRegistrationRespDto = test::read_body_json(resp).await;
+    assert_eq!(registered.client_secret, None);
+    assert_eq!(registered.token_endpoint_auth_method, "none");
+
+    // Test native apps may be redirected over plain HTTP on the loopback interface only
+    for redirect_uri in [
+        "http://127.0.0.1:8400/callback",
+        "http://localhost/callback",
+        "http://[::1]:8400/callback",
+    ] {
+        let metadata =
+            json!({"redirect_uris": [redirect_uri], "token_endpoint_auth_method": "none"});
+        let resp = test::call_service(&app, register(INITIAL_ACCESS_TOKEN, metadata)).await;
+        assert_eq!(resp.status(), StatusCode::CREATED, "{}", redirect_uri);
+    }
+
+    // Test invalid metadata
+    for (metadata, error) in [
+        (
+            json!({"redirect_uris": ["https://app.example.com/#fragment"]}),
+            "invalid_redirect_uri",
+        ),
+        (
+            json!({"redirect_uris": ["http://app.example.com/callback"]}),
+            "invalid_redirect_uri",
+        ),
+        (
+            json!({"redirect_uris": ["javascript:alert(document.cookie)"]}),
+            "invalid_redirect_uri",
+        ),
+        (
+            json!({"redirect_uris": ["data:text/html,<script>alert(1)</script>"]}),
+            "invalid_redirect_uri",
+        ),
+        (json!({}), "invalid_client_metadata"),
+        (
+            json!({"grant_types": ["client_credentials"], "scope": "exchange:impersonate"}),
+            "invalid_client_metadata",
+        ),
+        (
+            json!({"grant_types": ["client_credentials"], "token_endpoint_auth_method": "none"}),
+            "invalid_client_metadata",
+        ),
+        (
+            json!({"grant_types": ["client_credentials"], "token_endpoint_auth_method": "private_key_jwt"}),
+            "invalid_client_metadata",
+        ),
+        (
+            json!({"grant_types": ["client_credentials"], "token_endpoint_auth_method": "client_secret_post"}),
+            "invalid_client_metadata",
+        ),
+    ] {
+        let resp = test::call_service(&app, register(INITIAL_ACCESS_TOKEN, metadata)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let dto: OAuthErrorDto = test::read_body_json(resp).await;
+        assert_eq!(dto.error, error);
+    }
+    assert_eq!(oauth_repo.list_clients().await?.len(), 5);
+
+    Ok(())
+}
@@ -0,0 +1,225 @@
+use std::sync::Arc;
+
+use actix_web::http::StatusCode;
+use actix_web::test;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::App;
+use anyhow::Result;
+use chrono::Duration;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::crypto::token_hash;
+use crate::crypto::PasswordHasher;
+use crate::crypto::TokenSigner;
+use crate::models::oauth::ClientRegistrationDto;
+use crate::models::oauth::DeviceAuthorization;
+use crate::models::oauth::DeviceAuthorizationRespDto;
+use crate::models::oauth::DeviceAuthorizationStatus;
+use crate::models::oauth::OAuthErrorDto;
+use crate::models::token::AccessTokenClaims;
+use crate::models::token::AccessTokenRespDto;
+use crate::models::user::UserBuilder;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::user::UserRepo;
+use crate::services::device::get_device;
+use crate::services::device::normalize_user_code;
+use crate::services::device::post_device;
+use crate::services::device::post_device_authorization;
+use crate::services::device::DEVICE_CODE_GRANT_TYPE;
+use crate::services::oauth::post_token;
+use crate::services::oauth::register_client;
+use crate::settings::OAuthSettings;
+use crate::settings::TokenSettings;
+use crate::tests::mock::audit_repo::InjectableMockAuditRepo;
+use crate::tests::mock::audit_repo::MockAuditRepoNoDb;
+use crate::tests::mock::oauth_repo::MockOAuthRepo;
+use crate::tests::mock::user_repo::MockUserRepo;
+
+#[actix_web::test]
+async fn test_device_authorization_grant() -> Result<()> {
+    let passwd_hasher = PasswordHasher::default();
+    let user_repo: Arc<dyn UserRepo> = Arc::new(MockUserRepo::default());
+    let user_id = Uuid::new_v4();
+    user_repo
+        .create_user(
+            &UserBuilder::default()
+                .id(user_id)
+                .username("derek")
+                .password_hash(passwd_hasher.hash_password("password1")?)
+                .created_at(Utc::now())
+                .build()?,
+        )
+        .await?;
+    let oauth_repo: Arc<dyn OAuthRepo> = Arc::new(MockOAuthRepo::default());
+    // CLI tools are public clients without redirect URIs
+    let registration = ClientRegistrationDto {
+        name: "<Deploy CLI>".into(),
+        allowed_scopes: vec!["users:read".into()],
+        ..Default::default()
+    };
+    let client = register_client(oauth_repo.as_ref(), &passwd_hasher, registration)
+        .await?
+        .client;
+    let token_signer = Arc::new(TokenSigner::from_settings(&TokenSettings::default())?);
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::from(user_repo))
+            .app_data(Data::from(MockAuditRepoNoDb.init().await?))
+            .app_data(Data::from(oauth_repo.clone()))
+            .app_data(Data::new(passwd_hasher))
+            .app_data(Data::from(token_signer.clone()))
+            .app_data(Data::new(TokenSettings::default()))
+            .app_data(Data::new(OAuthSettings::default()))
+            .route(
+                "/oauth/device_authorization",
+                web::post().to(post_device_authorization),
+            )
+            .route("/oauth/device", web::get().to(get_device))
+            .route("/oauth/device", web::post().to(post_device))
+            .route("/oauth/token", web::post().to(post_token)),
+    )
+    .await;
+    let start = |scope: &str| {
+        test::TestRequest::post()
+            .uri("/oauth/device_authorization")
+            .set_form([("client_id", client.id.as_str()), ("scope", scope)])
+            .to_request()
+    };
+    let poll = |device_code: &str| {
+        test::TestRequest::post()
+            .uri("/oauth/token")
+            .set_form([
+                ("grant_type", DEVICE_CODE_GRANT_TYPE),
+                ("device_code", device_code),
+                ("client_id", &client.id),
+            ])
+            .to_request()
+    };
+    let decide = |user_code: &str, password: &str, action: &str| {
+        test::TestRequest::post()
+            .uri("/oauth/device")
+            .set_form([
+                ("user_code", user_code),
+                ("username", "derek"),
+                ("password", password),
+                ("action", action),
+            ])
+            .to_request()
+    };
+    let body = |resp| async { String::from_utf8(test::read_body(resp).await.to_vec()).unwrap() };
+
+    // Test the device gets codes to show and poll with
+    let resp = test::call_service(&app, start("users:read")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let device: DeviceAuthorizationRespDto = test::read_body_json(resp).await;
+    assert_eq!(device.user_code.len(), 9);
+    assert_eq!(&device.user_code[4..5], "-");
+    assert_eq!(
+        device.verification_uri,
+        "http://localhost:8000/oauth/device"
+    );
+    assert_eq!(
+        device.verification_uri_complete,
+        format!("{}?user_code={}", device.verification_uri, device.user_code)
+    );
+    assert_eq!((device.expires_in, device.interval), (600, 5));
+    let resp = test::call_service(&app, start("admin")).await;
+    let dto: OAuthErrorDto = test::read_body_json(resp).await;
+    assert_eq!(dto.error, "invalid_scope");
+
+    // Test the device waits for the user, and is told to slow down when polling too fast
+    let poll_error = |device_code: String| {
+        let req = poll(&device_code);
+        let app = &app;
+        async move {
+            let resp = test::call_service(app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            test::read_body_json::<OAuthErrorDto, _>(resp).await.error
+        }
+    };
+    assert_eq!(
+        poll_error(device.device_code.clone()).await,
+        "authorization_pending"
+    );
+    assert_eq!(poll_error(device.device_code.clone()).await, "slow_down");
+    let stored = oauth_repo
+        .get_device_authorization(&token_hash(&device.device_code))
+        .await?
+        .unwrap();
+    assert_eq!(stored.interval_secs, 10);
+
+    // Test the user enters the code, in any case and without dash, then signs in to approve
+    let req = test::TestRequest::get().uri("/oauth/device").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let typed = device.user_code.to_lowercase().replace('-', "");
+    let req = test::TestRequest::get()
+        .uri(&format!("/oauth/device?user_code={}", typed))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let page = body(resp).await;
+    assert!(page.contains("Connect &lt;Deploy CLI&gt; to your account"));
+    assert!(page.contains(&device.user_code));
+    let req = test::TestRequest::get()
+        .uri("/oauth/device?user_code=BCDF-GHJK")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(body(resp).await.contains("Unknown or expired code"));
+    let resp = test::call_service(&app, decide(&typed, "wrong-password", "approve")).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = test::call_service(&app, decide(&typed, "password1", "approve")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(body(resp).await.contains("Device connected"));
+    let resp = test::call_service(&app, decide(&typed, "password1", "deny")).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Test the device gets a token of the user once, after waiting its interval
+    oauth_repo
+        .record_device_poll(
+            &token_hash(&device.device_code),
+            Utc::now() - Duration::seconds(10),
+            10,
+        )
+        .await?;
+    let resp = test::call_service(&app, poll(&device.device_code)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let token: AccessTokenRespDto = test::read_body_json(resp).await;
+    assert_eq!(token.scope.as_deref(), Some("users:read"));
+    let claims: AccessTokenClaims = token_signer.verify(&token.access_token)?;
+    assert_eq!(claims.sub, user_id.to_string());
+    assert_eq!(claims.client_id, Some(client.id.clone()));
+    assert_eq!(
+        poll_error(device.device_code.clone()).await,
+        "invalid_grant"
+    );
+
+    // Test denied and expired requests
+    let denied: DeviceAuthorizationRespDto =
+        test::call_and_read_body_json(&app, start("users:read")).await;
+    let resp = test::call_service(&app, decide(&denied.user_code, "password1", "deny")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(poll_error(denied.device_code).await, "access_denied");
+    oauth_repo
+        .store_device_authorization(&DeviceAuthorization {
+            device_code_hash: token_hash("expired-device-code"),
+            user_code: normalize_user_code("xxxx-xxxx"),
+            client_id: client.id.clone(),
+            scope: None,
+            expires_at: Utc::now() - Duration::seconds(1),
+            interval_secs: 5,
+            last_polled_at: None,
+            status: DeviceAuthorizationStatus::Pending,
+            user_id: None,
+        })
+        .await?;
+    assert_eq!(
+        poll_error("expired-device-code".into()).await,
+        "expired_token"
+    );
+
+    Ok(())
+}
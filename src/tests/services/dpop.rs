@@ -0,0 +1,338 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::http::header::WWW_AUTHENTICATE;
+use actix_web::http::StatusCode;
+use actix_web::test;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::App;
+use anyhow::Result;
+use chrono::Utc;
+use jsonwebtoken::jwk::AlgorithmParameters;
+use jsonwebtoken::jwk::CommonParameters;
+use jsonwebtoken::jwk::EllipticCurve;
+use jsonwebtoken::jwk::Jwk;
+use jsonwebtoken::jwk::OctetKeyPairParameters;
+use jsonwebtoken::jwk::OctetKeyPairType;
+use jsonwebtoken::Algorithm;
+use jsonwebtoken::EncodingKey;
+use jsonwebtoken::Header;
+use ring::rand::SystemRandom;
+use ring::signature::Ed25519KeyPair;
+use ring::signature::KeyPair;
+use uuid::Uuid;
+
+use crate::crypto::public_jwk_thumbprint;
+use crate::crypto::sha256_base64url;
+use crate::crypto::PasswordHasher;
+use crate::crypto::TokenSigner;
+use crate::models::oauth::ClientRegistrationDto;
+use crate::models::oauth::OAuthErrorDto;
+use crate::models::token::AccessTokenClaims;
+use crate::models::token::AccessTokenRespDto;
+use crate::models::token::DpopProofClaims;
+use crate::models::user::UserBuilder;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::user::UserRepo;
+use crate::services::dpop::DPOP_HEADER;
+use crate::services::dpop::DPOP_NONCE_HEADER;
+use crate::services::oauth::post_token;
+use crate::services::oauth::register_client;
+use crate::services::oidc::get_userinfo;
+use crate::services::token;
+use crate::settings::OAuthSettings;
+use crate::settings::TokenSettings;
+use crate::tests::mock::audit_repo::InjectableMockAuditRepo;
+use crate::tests::mock::audit_repo::MockAuditRepoNoDb;
+use crate::tests::mock::oauth_repo::MockOAuthRepo;
+use crate::tests::mock::user_repo::MockUserRepo;
+
+const TOKEN_URI: &str = "http://localhost:8000/oauth/token";
+const USERINFO_URI: &str = "http://localhost:8000/userinfo";
+
+/// Key pair of a client, signing its DPoP proofs.
+struct ClientKey {
+    encoding_key: EncodingKey,
+    jwk: Jwk,
+}
+
+impl ClientKey {
+    fn generate() -> Self {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        Self {
+            encoding_key: EncodingKey::from_ed_der(pkcs8.as_ref()),
+            jwk: Jwk {
+                common: CommonParameters::default(),
+                algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: base64::encode_config(
+                        key_pair.public_key().as_ref(),
+                        base64::URL_SAFE_NO_PAD,
+                    ),
+                }),
+            },
+        }
+    }
+
+    fn proof(
+        &self,
+        htm: &str,
+        htu: &str,
+        access_token: Option<&str>,
+        nonce: Option<&str>,
+    ) -> String {
+        self.proof_at(htm, htu, access_token, nonce, Utc::now().timestamp())
+    }
+
+    fn proof_at(
+        &self,
+        htm: &str,
+        htu: &str,
+        access_token: Option<&str>,
+        nonce: Option<&str>,
+        iat: i64,
+    ) -> String {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.typ = Some("dpop+jwt".into());
+        header.jwk = Some(self.jwk.clone());
+        let claims = DpopProofClaims {
+            jti: Uuid::new_v4().to_string(),
+            htm: htm.into(),
+            htu: htu.into(),
+            iat,
+            ath: access_token.map(|token| sha256_base64url(token.as_bytes())),
+            nonce: nonce.map(Into::into),
+        };
+        jsonwebtoken::encode(&header, &claims, &self.encoding_key).unwrap()
+    }
+}
+
+#[actix_web::test]
+async fn test_dpop_bound_tokens() -> Result<()> {
+    let passwd_hasher = PasswordHasher::default();
+    let user_repo: Arc<dyn UserRepo> = Arc::new(MockUserRepo::default());
+    let user_id = Uuid::new_v4();
+    user_repo
+        .create_user(
+            &UserBuilder::default()
+                .id(user_id)
+                .username("derek")
+                .password_hash(passwd_hasher.hash_password("password1")?)
+                .created_at(Utc::now())
+                .build()?,
+        )
+        .await?;
+    let user = user_repo.get_user_by_id(&user_id).await?;
+    let oauth_repo: Arc<dyn OAuthRepo> = Arc::new(MockOAuthRepo::default());
+    let registration = ClientRegistrationDto {
+        name: "Billing job".into(),
+        allowed_scopes: vec!["users:read".into()],
+        confidential: true,
+        ..Default::default()
+    };
+    let client = register_client(oauth_repo.as_ref(), &passwd_hasher, registration).await?;
+    let (client_id, secret) = (client.client.id, client.client_secret.unwrap());
+    let token_signer = Arc::new(TokenSigner::from_settings(&TokenSettings::default())?);
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::from(user_repo))
+            .app_data(Data::from(MockAuditRepoNoDb.init().await?))
+            .app_data(Data::from(oauth_repo))
+            .app_data(Data::new(passwd_hasher))
+            .app_data(Data::from(token_signer.clone()))
+            .app_data(Data::new(TokenSettings::default()))
+            .app_data(Data::new(OAuthSettings::default()))
+            .route("/oauth/token", web::post().to(post_token))
+            .route("/userinfo", web::get().to(get_userinfo)),
+    )
+    .await;
+    let key = ClientKey::generate();
+    let jkt = public_jwk_thumbprint(&key.jwk)?;
+    let token_request = |proof: &str| {
+        test::TestRequest::post()
+            .uri("/oauth/token")
+            .insert_header((DPOP_HEADER, proof))
+            .set_form([
+                ("grant_type", "client_credentials"),
+                ("client_id", &client_id),
+                ("client_secret", &secret),
+            ])
+            .to_request()
+    };
+
+    // Test the token endpoint binds tokens to the key of the proof
+    let resp = test::call_service(
+        &app,
+        token_request(&key.proof("POST", TOKEN_URI, None, None)),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let token: AccessTokenRespDto = test::read_body_json(resp).await;
+    assert_eq!(token.token_type, "DPoP");
+    let claims: AccessTokenClaims = token_signer.verify(&token.access_token)?;
+    assert_eq!(claims.cnf.unwrap().jkt, jkt);
+
+    // Test proofs for other requests, replayed, unsigned or out of date are rejected
+    let replayed = key.proof("POST", TOKEN_URI, None, None);
+    test::call_service(&app, token_request(&replayed)).await;
+    let forged = {
+        let proof = key.proof("POST", TOKEN_URI, None, None);
+        let (signed, _) = proof.rsplit_once('.').unwrap();
+        format!("{}.{}", signed, "AAAA")
+    };
+    for proof in [
+        key.proof("GET", TOKEN_URI, None, None),
+        key.proof("POST", USERINFO_URI, None, None),
+        replayed,
+        forged,
+        "garbage".to_string(),
+        key.proof_at("POST", TOKEN_URI, None, None, Utc::now().timestamp() - 3600),
+        key.proof_at("POST", TOKEN_URI, None, None, i64::MIN),
+        key.proof_at("POST", TOKEN_URI, None, None, i64::MAX),
+    ] {
+        let resp = test::call_service(&app, token_request(&proof)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let dto: OAuthErrorDto = test::read_body_json(resp).await;
+        assert_eq!(dto.error, "invalid_dpop_proof");
+    }
+
+    // Test resources need the token with a proof of the key it's bound to
+    let ttl = Duration::from_secs(60);
+    let user_token = token::issue_access_token(&token_signer, ttl, &user, None, Some("openid"))?;
+    let unbound = user_token.access_token.clone();
+    let bound = token::bind_to_key(&token_signer, user_token, jkt)?.access_token;
+    let userinfo = |scheme: &str, token: &str, proof: Option<&str>| {
+        let mut req = test::TestRequest::get()
+            .uri("/userinfo")
+            .insert_header((AUTHORIZATION, format!("{} {}", scheme, token)));
+        if let Some(proof) = proof {
+            req = req.insert_header((DPOP_HEADER, proof));
+        }
+        req.to_request()
+    };
+    let proof = key.proof("GET", USERINFO_URI, Some(&bound), None);
+    let resp = test::call_service(&app, userinfo("DPoP", &bound, Some(&proof))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let other_key = ClientKey::generate();
+    for (scheme, token, proof, challenge) in [
+        (
+            "DPoP",
+            &bound,
+            Some(proof),
+            r#"DPoP algs="EdDSA ES256 ES384 RS256 PS256", error="invalid_dpop_proof""#,
+        ),
+        (
+            "DPoP",
+            &bound,
+            Some(key.proof("GET", USERINFO_URI, None, None)),
+            r#"error="invalid_dpop_proof""#,
+        ),
+        (
+            "DPoP",
+            &bound,
+            Some(key.proof("GET", USERINFO_URI, Some(&unbound), None)),
+            r#"error="invalid_dpop_proof""#,
+        ),
+        (
+            "DPoP",
+            &bound,
+            Some(other_key.proof("GET", USERINFO_URI, Some(&bound), None)),
+            r#"error="invalid_dpop_proof""#,
+        ),
+        ("DPoP", &bound, None, r#"error="invalid_dpop_proof""#),
+        (
+            "Bearer",
+            &bound,
+            None,
+            r#"DPoP algs="EdDSA ES256 ES384 RS256 PS256", error="invalid_token""#,
+        ),
+        (
+            "DPoP",
+            &unbound,
+            Some(key.proof("GET", USERINFO_URI, Some(&unbound), None)),
+            r#"error="invalid_token""#,
+        ),
+    ] {
+        let resp = test::call_service(&app, userinfo(scheme, token, proof.as_deref())).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let header = resp.headers().get(WWW_AUTHENTICATE).unwrap().to_str()?;
+        assert!(header.starts_with("DPoP "), "{}", header);
+        assert!(header.contains(challenge), "{}", header);
+    }
+    let resp = test::call_service(&app, userinfo("Bearer", &unbound, None)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_dpop_nonces() -> Result<()> {
+    let passwd_hasher = PasswordHasher::default();
+    let oauth_repo: Arc<dyn OAuthRepo> = Arc::new(MockOAuthRepo::default());
+    let registration = ClientRegistrationDto {
+        name: "Billing job".into(),
+        allowed_scopes: vec!["users:read".into()],
+        confidential: true,
+        ..Default::default()
+    };
+    let client = register_client(oauth_repo.as_ref(), &passwd_hasher, registration).await?;
+    let (client_id, secret) = (client.client.id, client.client_secret.unwrap());
+    let token_settings = TokenSettings {
+        dpop_nonce_required: true,
+        ..Default::default()
+    };
+    let token_signer = Arc::new(TokenSigner::from_settings(&token_settings)?);
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::from(
+                Arc::new(MockUserRepo::default()) as Arc<dyn UserRepo>
+            ))
+            .app_data(Data::from(MockAuditRepoNoDb.init().await?))
+            .app_data(Data::from(oauth_repo))
+            .app_data(Data::new(passwd_hasher))
+            .app_data(Data::from(token_signer.clone()))
+            .app_data(Data::new(token_settings))
+            .app_data(Data::new(OAuthSettings::default()))
+            .route("/oauth/token", web::post().to(post_token)),
+    )
+    .await;
+    let key = ClientKey::generate();
+    let token_request = |nonce: Option<&str>| {
+        test::TestRequest::post()
+            .uri("/oauth/token")
+            .insert_header((DPOP_HEADER, key.proof("POST", TOKEN_URI, None, nonce)))
+            .set_form([
+                ("grant_type", "client_credentials"),
+                ("client_id", &client_id),
+                ("client_secret", &secret),
+            ])
+            .to_request()
+    };
+
+    // Test the client is told the nonce to use, then gets a token and the next nonce
+    for nonce in [None, Some("garbage"), Some("1.AAAA")] {
+        let resp = test::call_service(&app, token_request(nonce)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(resp.headers().contains_key(DPOP_NONCE_HEADER));
+        let dto: OAuthErrorDto = test::read_body_json(resp).await;
+        assert_eq!(dto.error, "use_dpop_nonce");
+    }
+    let resp = test::call_service(&app, token_request(None)).await;
+    let nonce = resp
+        .headers()
+        .get(DPOP_NONCE_HEADER)
+        .unwrap()
+        .to_str()?
+        .to_string();
+    let resp = test::call_service(&app, token_request(Some(&nonce))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().contains_key(DPOP_NONCE_HEADER));
+    let token: AccessTokenRespDto = test::read_body_json(resp).await;
+    assert_eq!(token.token_type, "DPoP");
+
+    Ok(())
+}
@@ -0,0 +1,422 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::http::header::WWW_AUTHENTICATE;
+use actix_web::http::StatusCode;
+use actix_web::test;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::App;
+use anyhow::Result;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::crypto::PasswordHasher;
+use crate::crypto::TokenSigner;
+use crate::errors::oauth::OAuthError;
+use crate::models::audit::AuditEventType;
+use crate::models::audit::AuditQuery;
+use crate::models::oauth::ClientRegistrationDto;
+use crate::models::oauth::OAuthErrorDto;
+use crate::models::oauth::TokenReqDto;
+use crate::models::token::AccessTokenClaims;
+use crate::models::token::AccessTokenRespDto;
+use crate::models::token::Actor;
+use crate::models::user::UserBuilder;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::user::UserRepo;
+use crate::services::exchange::TokenExchange;
+use crate::services::exchange::ACCESS_TOKEN_TYPE;
+use crate::services::exchange::DELEGATE_PERMISSION;
+use crate::services::exchange::IMPERSONATE_PERMISSION;
+use crate::services::exchange::TOKEN_EXCHANGE_GRANT_TYPE;
+use crate::services::oauth::post_token;
+use crate::services::oauth::register_client;
+use crate::services::oidc::get_userinfo;
+use crate::services::token;
+use crate::settings::OAuthSettings;
+use crate::settings::TokenSettings;
+use crate::tests::mock::audit_repo::FailingAuditRepo;
+use crate::tests::mock::audit_repo::MockAuditRepo;
+use crate::tests::mock::oauth_repo::MockOAuthRepo;
+use crate::tests::mock::user_repo::MockUserRepo;
+
+#[actix_web::test]
+async fn test_token_exchange() -> Result<()> {
+    let passwd_hasher = PasswordHasher::default();
+    let user_repo: Arc<dyn UserRepo> = Arc::new(MockUserRepo::default());
+    let (user_id, staff_id) = (Uuid::new_v4(), Uuid::new_v4());
+    for (id, username) in [(user_id, "derek"), (staff_id, "support")] {
+        user_repo
+            .create_user(
+                &UserBuilder::default()
+                    .id(id)
+                    .username(username)
+                    .password_hash(passwd_hasher.hash_password("password1")?)
+                    .created_at(Utc::now())
+                    .build()?,
+            )
+            .await?;
+    }
+    let user = user_repo.get_user_by_id(&user_id).await?;
+    let staff = user_repo.get_user_by_id(&staff_id).await?;
+    let oauth_repo: Arc<dyn OAuthRepo> = Arc::new(MockOAuthRepo::default());
+    let mut clients = vec![];
+    for (name, permission) in [
+        ("API gateway", DELEGATE_PERMISSION),
+        ("Support console", IMPERSONATE_PERMISSION),
+    ] {
+        let registration = ClientRegistrationDto {
+            name: name.into(),
+            allowed_scopes: vec!["users:read".into(), permission.into()],
+            confidential: true,
+            ..Default::default()
+        };
+        let client = register_client(oauth_repo.as_ref(), &passwd_hasher, registration).await?;
+        clients.push((client.client.id, client.client_secret.unwrap()));
+    }
+    let (gateway, console) = (&clients[0], &clients[1]);
+    let audit_repo = Arc::new(MockAuditRepo::default());
+    let token_signer = Arc::new(TokenSigner::from_settings(&TokenSettings::default())?);
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::from(user_repo))
+            .app_data(Data::from(audit_repo.clone() as Arc<dyn AuditRepo>))
+            .app_data(Data::from(oauth_repo.clone()))
+            .app_data(Data::new(passwd_hasher))
+            .app_data(Data::from(token_signer.clone()))
+            .app_data(Data::new(TokenSettings::default()))
+            .app_data(Data::new(OAuthSettings {
+                impersonators: vec![staff_id],
+                ..Default::default()
+            }))
+            .route("/oauth/token", web::post().to(post_token))
+            .route("/userinfo", web::get().to(get_userinfo)),
+    )
+    .await;
+    let exchange = |(client_id, secret): &(String, String), params: &[(&str, &str)]| {
+        let mut form = vec![
+            ("grant_type", TOKEN_EXCHANGE_GRANT_TYPE),
+            ("client_id", client_id.as_str()),
+            ("client_secret", secret.as_str()),
+        ];
+        form.extend_from_slice(params);
+        test::TestRequest::post()
+            .uri("/oauth/token")
+            .set_form(form)
+            .to_request()
+    };
+    let exchange_error = |req| {
+        let app = &app;
+        async move {
+            let resp = test::call_service(app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            test::read_body_json::<OAuthErrorDto, _>(resp).await.error
+        }
+    };
+
+    // Test the gateway narrows a token of the user to one service and scope, acting for them
+    let ttl = Duration::from_secs(60);
+    let user_token = token::issue_access_token(
+        &token_signer,
+        ttl,
+        &user,
+        None,
+        Some("users:read users:write"),
+    )?;
+    let user_token = user_token.access_token.as_str();
+    let delegated = [
+        subject(user_token).as_slice(),
+        &[("audience", "billing"), ("scope", "users:read")],
+    ]
+    .concat();
+    let resp = test::call_service(&app, exchange(gateway, &delegated)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let token: AccessTokenRespDto = test::read_body_json(resp).await;
+    assert_eq!(token.issued_token_type.as_deref(), Some(ACCESS_TOKEN_TYPE));
+    assert_eq!(token.scope.as_deref(), Some("users:read"));
+    let claims: AccessTokenClaims = token_signer.verify(&token.access_token)?;
+    let subject_claims: AccessTokenClaims = token_signer.verify(user_token)?;
+    assert_eq!(claims.sub, user_id.to_string());
+    assert_eq!(claims.aud.as_deref(), Some("billing"));
+    assert!(claims.exp <= subject_claims.exp);
+    assert_eq!(
+        claims.act,
+        Some(Actor {
+            sub: gateway.0.clone(),
+            username: None,
+            act: None,
+        })
+    );
+
+    // Test the exchanged token is only good for its audience, and may only be delegated further
+    let req = test::TestRequest::get()
+        .uri("/userinfo")
+        .insert_header((AUTHORIZATION, format!("Bearer {}", token.access_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert!(resp
+        .headers()
+        .get(WWW_AUTHENTICATE)
+        .unwrap()
+        .to_str()?
+        .contains("invalid_token"));
+    let billing_token = token.access_token.as_str();
+    let resp = test::call_service(&app, exchange(gateway, &subject(billing_token))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let rebased: AccessTokenRespDto = test::read_body_json(resp).await;
+    let claims: AccessTokenClaims = token_signer.verify(&rebased.access_token)?;
+    assert_eq!(claims.aud.as_deref(), Some("billing"));
+    assert_eq!(claims.act.unwrap().act.unwrap().sub, gateway.0);
+
+    // Test requests the gateway can't make
+    let widened = [
+        subject(billing_token).as_slice(),
+        &[("scope", "users:write")],
+    ]
+    .concat();
+    let retargeted = [subject(billing_token).as_slice(), &[("audience", "crm")]].concat();
+    let untyped = [("subject_token", user_token)];
+    let refresh = [
+        subject(user_token).as_slice(),
+        &[(
+            "requested_token_type",
+            "urn:ietf:params:oauth:token-type:refresh_token",
+        )],
+    ]
+    .concat();
+    for (client, params, error) in [
+        (gateway, widened.as_slice(), "invalid_scope"),
+        (gateway, &retargeted, "invalid_target"),
+        (gateway, &untyped, "invalid_request"),
+        (gateway, &refresh, "invalid_request"),
+        (gateway, &[], "invalid_request"),
+        (gateway, &subject("garbage"), "invalid_grant"),
+        (console, &subject(user_token), "unauthorized_client"),
+    ] {
+        assert_eq!(exchange_error(exchange(client, params)).await, error);
+    }
+
+    // Test support staff impersonate the user, which is audited
+    let staff_token =
+        token::issue_access_token(&token_signer, ttl, &staff, None, Some("users:read"))?;
+    let staff_token = staff_token.access_token.as_str();
+    let user_id_str = user_id.to_string();
+    let impersonate = |actor_token: &str, requested_subject: &str| {
+        exchange(
+            console,
+            &[
+                ("actor_token", actor_token),
+                ("actor_token_type", ACCESS_TOKEN_TYPE),
+                ("requested_subject", requested_subject),
+            ],
+        )
+    };
+    let resp = test::call_service(&app, impersonate(staff_token, &user_id_str)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let token: AccessTokenRespDto = test::read_body_json(resp).await;
+    assert_eq!(token.scope.as_deref(), Some("users:read"));
+    let claims: AccessTokenClaims = token_signer.verify(&token.access_token)?;
+    assert_eq!(claims.sub, user_id.to_string());
+    assert_eq!(claims.username.as_deref(), Some("derek"));
+    assert_eq!(
+        claims.act,
+        Some(Actor {
+            sub: staff_id.to_string(),
+            username: Some("support".into()),
+            act: None,
+        })
+    );
+    let events = audit_repo
+        .list_events(&AuditQuery {
+            event_type: Some(AuditEventType::UserImpersonated),
+            ..Default::default()
+        })
+        .await?;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].actor_id, Some(staff_id));
+    assert_eq!(events[0].subject_id, Some(user_id));
+    assert!(events[0].details.contains(&claims.jti.to_string()));
+
+    // Test only listed staff may impersonate, and only through a client allowed to
+    let staff_id_str = staff_id.to_string();
+    assert_eq!(
+        exchange_error(impersonate(user_token, &staff_id_str)).await,
+        "invalid_grant"
+    );
+    assert_eq!(
+        exchange_error(impersonate(staff_token, &Uuid::new_v4().to_string())).await,
+        "invalid_grant"
+    );
+    let req = exchange(
+        gateway,
+        &[
+            ("actor_token", staff_token),
+            ("actor_token_type", ACCESS_TOKEN_TYPE),
+            ("requested_subject", &user_id_str),
+        ],
+    );
+    assert_eq!(exchange_error(req).await, "unauthorized_client");
+
+    // Test staff tokens restricted to another service aren't accepted as actor tokens
+    let foreign = token::sign_claims(
+        &token_signer,
+        &AccessTokenClaims {
+            aud: Some("billing".into()),
+            jti: Uuid::new_v4(),
+            ..token_signer.verify(staff_token)?
+        },
+    )?;
+    assert_eq!(
+        exchange_error(impersonate(&foreign.access_token, &user_id_str)).await,
+        "invalid_grant"
+    );
+
+    // Test revoked tokens can't be exchanged
+    oauth_repo
+        .revoke_token(
+            &subject_claims.jti,
+            Utc::now() + chrono::Duration::minutes(1),
+        )
+        .await?;
+    assert_eq!(
+        exchange_error(exchange(gateway, &subject(user_token))).await,
+        "invalid_grant"
+    );
+
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_unaudited_impersonation_fails() -> Result<()> {
+    let passwd_hasher = PasswordHasher::default();
+    let user_repo = MockUserRepo::default();
+    let (user, staff) = (
+        UserBuilder::default()
+            .id(Uuid::new_v4())
+            .username("derek")
+            .build()?,
+        UserBuilder::default()
+            .id(Uuid::new_v4())
+            .username("support")
+            .build()?,
+    );
+    user_repo.create_user(&user).await?;
+    user_repo.create_user(&staff).await?;
+    let oauth_repo = MockOAuthRepo::default();
+    let registration = ClientRegistrationDto {
+        name: "Support console".into(),
+        allowed_scopes: vec![IMPERSONATE_PERMISSION.into()],
+        confidential: true,
+        ..Default::default()
+    };
+    let console = register_client(&oauth_repo, &passwd_hasher, registration)
+        .await?
+        .client;
+    let token_signer = TokenSigner::from_settings(&TokenSettings::default())?;
+    let ttl = Duration::from_secs(60);
+    let staff_token = token::issue_access_token(&token_signer, ttl, &staff, None, None)?;
+    let exchange = TokenExchange {
+        user_repo: &user_repo,
+        audit_repo: &FailingAuditRepo,
+        oauth_repo: &oauth_repo,
+        token_signer: &token_signer,
+        access_token_ttl: ttl,
+        impersonators: &[staff.id],
+        dpop_jkt: None,
+    };
+    let form = TokenReqDto {
+        actor_token: Some(staff_token.access_token),
+        actor_token_type: Some(ACCESS_TOKEN_TYPE.into()),
+        requested_subject: Some(user.id.to_string()),
+        ..Default::default()
+    };
+
+    // The token is never handed out without its audit event
+    let result = exchange.exchange(&console, &form).await;
+    assert!(matches!(result, Err(OAuthError::ServerError)));
+
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_bound_token_exchange() -> Result<()> {
+    let passwd_hasher = PasswordHasher::default();
+    let user_repo = MockUserRepo::default();
+    let (user, staff) = (
+        UserBuilder::default()
+            .id(Uuid::new_v4())
+            .username("derek")
+            .build()?,
+        UserBuilder::default()
+            .id(Uuid::new_v4())
+            .username("support")
+            .build()?,
+    );
+    user_repo.create_user(&user).await?;
+    user_repo.create_user(&staff).await?;
+    let oauth_repo = MockOAuthRepo::default();
+    let registration = ClientRegistrationDto {
+        name: "API gateway".into(),
+        allowed_scopes: vec![DELEGATE_PERMISSION.into(), IMPERSONATE_PERMISSION.into()],
+        confidential: true,
+        ..Default::default()
+    };
+    let client = register_client(&oauth_repo, &passwd_hasher, registration)
+        .await?
+        .client;
+    let token_signer = TokenSigner::from_settings(&TokenSettings::default())?;
+    let ttl = Duration::from_secs(60);
+    let jkt = "0ZcOCORZNYy-DWpqq30jZyJGHTN0d2HglBV3uiguA4I";
+    let bind = |user| -> Result<String> {
+        let token = token::issue_access_token(&token_signer, ttl, user, None, None)?;
+        Ok(token::bind_to_key(&token_signer, token, jkt.into())?.access_token)
+    };
+    let delegated = TokenReqDto {
+        subject_token: Some(bind(&user)?),
+        subject_token_type: Some(ACCESS_TOKEN_TYPE.into()),
+        ..Default::default()
+    };
+    let impersonated = TokenReqDto {
+        actor_token: Some(bind(&staff)?),
+        actor_token_type: Some(ACCESS_TOKEN_TYPE.into()),
+        requested_subject: Some(user.id.to_string()),
+        ..Default::default()
+    };
+    let audit_repo = MockAuditRepo::default();
+    let impersonators = [staff.id];
+    let exchange = |dpop_jkt| TokenExchange {
+        user_repo: &user_repo,
+        audit_repo: &audit_repo,
+        oauth_repo: &oauth_repo,
+        token_signer: &token_signer,
+        access_token_ttl: ttl,
+        impersonators: &impersonators,
+        dpop_jkt,
+    };
+
+    for form in [&delegated, &impersonated] {
+        // Test bound tokens aren't exchanged without a proof of their key
+        for dpop_jkt in [None, Some("j7qAbiZr7CW7i6D8ANpbCa4q8PxNHJI8XEdu0UeLPLg")] {
+            let result = exchange(dpop_jkt).exchange(&client, form).await;
+            assert!(matches!(result, Err(OAuthError::InvalidDpopProof(_))));
+        }
+
+        // Test exchanged tokens stay bound to the same key
+        let token = exchange(Some(jkt)).exchange(&client, form).await?;
+        let claims: AccessTokenClaims = token_signer.verify(&token.access_token)?;
+        assert_eq!(claims.cnf.unwrap().jkt, jkt);
+    }
+
+    Ok(())
+}
+
+fn subject(token: &str) -> [(&str, &str); 2] {
+    [
+        ("subject_token", token),
+        ("subject_token_type", ACCESS_TOKEN_TYPE),
+    ]
+}
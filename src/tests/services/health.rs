@@ -0,0 +1,129 @@
+use std::sync::Arc;
+
+use actix_web::http::StatusCode;
+use actix_web::test;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::App;
+use anyhow::bail;
+use anyhow::Result;
+use async_trait::async_trait;
+use rstest::*;
+
+use crate::crypto::TokenSigner;
+use crate::models::health::HealthRespDto;
+use crate::models::health::HealthStatus;
+use crate::repositories::backend::Repositories;
+use crate::repositories::database::DatabaseProbe;
+use crate::repositories::database::PoolStats;
+use crate::services::health::healthz;
+use crate::services::health::readyz;
+use crate::services::health::CHECK_FAILED;
+use crate::settings::DatabaseSettings;
+use crate::settings::TokenSettings;
+use crate::tests::harness::psql::isolated_schema_url;
+
+async fn psql_repos() -> Result<Repositories> {
+    Repositories::init(&DatabaseSettings {
+        url: isolated_schema_url().await?,
+        ..Default::default()
+    })
+    .await
+}
+
+#[cfg(feature = "sqlite")]
+async fn sqlite_repos() -> Result<Repositories> {
+    Repositories::init(&DatabaseSettings {
+        url: "sqlite::memory:".into(),
+        ..Default::default()
+    })
+    .await
+}
+
+async fn get_readyz(repos: Repositories) -> Result<(StatusCode, HealthRespDto)> {
+    let token_signer = Data::new(TokenSigner::from_settings(&TokenSettings::default())?);
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::from(repos.probe))
+            .app_data(Data::from(repos.migrator))
+            .app_data(token_signer)
+            .route("/readyz", web::get().to(readyz)),
+    )
+    .await;
+    let req = test::TestRequest::get().uri("/readyz").to_request();
+    let resp = test::call_service(&app, req).await;
+    let status = resp.status();
+    Ok((status, test::read_body_json(resp).await))
+}
+
+#[actix_web::test]
+async fn test_healthz() {
+    let app = test::init_service(App::new().route("/healthz", web::get().to(healthz))).await;
+    let req = test::TestRequest::get().uri("/healthz").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: HealthRespDto = test::read_body_json(resp).await;
+    assert_eq!(body.status, HealthStatus::Ok);
+}
+
+#[rstest]
+#[case::psql_db(psql_repos())]
+#[cfg_attr(feature = "sqlite", case::sqlite_db(sqlite_repos()))]
+#[actix_web::test]
+async fn test_readyz(
+    #[case] repos: impl std::future::Future<Output = Result<Repositories>>,
+) -> Result<()> {
+    let repos = repos.await?;
+
+    // Not ready until the schema is migrated
+    let (status, body) = get_readyz(repos.clone()).await?;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body.status, HealthStatus::Fail);
+    assert_eq!(body.checks["database"].status, HealthStatus::Ok);
+    assert_eq!(body.checks["migrations"].status, HealthStatus::Fail);
+    assert!(body.checks["migrations"].error.is_some());
+
+    repos.migrator.migrate_up().await?;
+    let (status, body) = get_readyz(repos).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.status, HealthStatus::Ok);
+    for name in ["database", "migrations", "signing_keys"] {
+        let check = &body.checks[name];
+        assert_eq!(check.status, HealthStatus::Ok, "Check {} failed", name);
+        assert!(check.latency_ms >= 0.0);
+    }
+
+    Ok(())
+}
+
+struct UnreachableDatabase;
+
+#[async_trait]
+impl DatabaseProbe for UnreachableDatabase {
+    async fn ping(&self) -> Result<()> {
+        bail!("Connection refused")
+    }
+
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats::default()
+    }
+}
+
+#[actix_web::test]
+async fn test_readyz_database_unreachable() -> Result<()> {
+    let repos = psql_repos().await?;
+    repos.migrator.migrate_up().await?;
+    let (status, body) = get_readyz(Repositories {
+        probe: Arc::new(UnreachableDatabase),
+        ..repos
+    })
+    .await?;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body.status, HealthStatus::Fail);
+    assert_eq!(body.checks["database"].status, HealthStatus::Fail);
+    // Test the cause is not disclosed
+    assert_eq!(body.checks["database"].error.as_deref(), Some(CHECK_FAILED));
+    assert_eq!(body.checks["migrations"].status, HealthStatus::Ok);
+
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use actix_web::http::StatusCode;
+use actix_web::test;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::App;
+use anyhow::Result;
+
+use crate::crypto::PasswordHasher;
+use crate::metrics::track_request;
+use crate::models::user::UserCreateReqDtoBuilder;
+use crate::repositories::backend::Repositories;
+use crate::services::metrics::metrics;
+use crate::services::user::get_user_by_id;
+use crate::services::user::post_user;
+use crate::settings::DatabaseSettings;
+use crate::tests::harness::psql::isolated_schema_url;
+
+#[actix_web::test]
+async fn test_metrics() -> Result<()> {
+    let repos = Repositories::init(&DatabaseSettings {
+        url: isolated_schema_url().await?,
+        max_connections: 4,
+        ..Default::default()
+    })
+    .await?;
+    repos.migrator.migrate_up().await?;
+    let app = test::init_service(
+        App::new()
+            .wrap_fn(track_request)
+            .app_data(Data::from(repos.user_repo))
+            .app_data(Data::from(repos.audit_repo))
+            .app_data(Data::from(repos.probe))
+            .app_data(Data::new(PasswordHasher::new()))
+            .route("/users/{user_id}", web::get().to(get_user_by_id))
+            .route("/users", web::post().to(post_user))
+            .route("/metrics", web::get().to(metrics)),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/users/invalid").to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+    let user = UserCreateReqDtoBuilder::default()
+        .username("Metered")
+        .password_raw("password123")
+        .build()?;
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .set_json(&user)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = String::from_utf8(test::read_body(resp).await.to_vec())?;
+    for expected in [
+        r#"http_requests_total{method="GET",route="/users/{user_id}",status="400"}"#,
+        r#"http_request_duration_seconds_count{method="POST",route="/users",status="200"}"#,
+        r#"password_hash_duration_seconds_count{operation="hash"}"#,
+        r#"db_query_duration_seconds_count{method="change_user"}"#,
+        r#"db_query_duration_seconds_count{method="contains_user_with_username"}"#,
+        r#"db_pool_connections{state="idle"}"#,
+        "db_pool_max_connections",
+    ] {
+        assert!(
+            body.contains(expected),
+            "Missing {} in:\n{}",
+            expected,
+            body
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,557 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::dev::Service;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::http::header::LOCATION;
+use actix_web::http::header::WWW_AUTHENTICATE;
+use actix_web::http::StatusCode;
+use actix_web::test;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::App;
+use anyhow::Result;
+use chrono::Utc;
+use url::Url;
+use uuid::Uuid;
+
+use crate::crypto::PasswordHasher;
+use crate::crypto::TokenSigner;
+use crate::models::oauth::ClientRegistrationDto;
+use crate::models::oauth::OAuthErrorDto;
+use crate::models::token::AccessTokenClaims;
+use crate::models::token::AccessTokenRespDto;
+use crate::models::token::IntrospectionRespDto;
+use crate::models::user::UserBuilder;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::user::UserRepo;
+use crate::services::oauth::get_authorize;
+use crate::services::oauth::pkce_s256;
+use crate::services::oauth::post_authorize;
+use crate::services::oauth::post_introspect;
+use crate::services::oauth::post_revoke;
+use crate::services::oauth::post_token;
+use crate::services::oauth::register_client;
+use crate::services::oidc::get_userinfo;
+use crate::services::token;
+use crate::settings::OAuthSettings;
+use crate::settings::TokenSettings;
+use crate::tests::mock::audit_repo::InjectableMockAuditRepo;
+use crate::tests::mock::audit_repo::MockAuditRepoNoDb;
+use crate::tests::mock::oauth_repo::MockOAuthRepo;
+use crate::tests::mock::user_repo::InjectableMockUserRepo;
+use crate::tests::mock::user_repo::MockUserRepo;
+use crate::tests::mock::user_repo::MockUserRepoNoDb;
+
+const REDIRECT_URI: &str = "https://app.example.com/callback";
+const CODE_VERIFIER: &str = "dBjftJeZ4CVP-mJ92K9qf3kUsDmHNjUYUWk1Dqt0xlX";
+
+fn query_pairs(location: &str) -> HashMap<String, String> {
+    Url::parse(location)
+        .unwrap()
+        .query_pairs()
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect()
+}
+
+fn authorize_uri(params: &[(&str, &str)]) -> String {
+    let mut url = Url::parse("http://localhost/oauth/authorize").unwrap();
+    url.query_pairs_mut().extend_pairs(params);
+    format!("{}?{}", url.path(), url.query().unwrap())
+}
+
+fn location_of(resp: &ServiceResponse) -> String {
+    resp.headers()
+        .get(LOCATION)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+#[actix_web::test]
+async fn test_authorization_code_flow() -> Result<()> {
+    let passwd_hasher = PasswordHasher::default();
+    let user_repo: Arc<dyn UserRepo> = Arc::new(MockUserRepo::default());
+    let user_id = Uuid::new_v4();
+    user_repo
+        .create_user(
+            &UserBuilder::default()
+                .id(user_id)
+                .username("Derek")
+                .password_hash(passwd_hasher.hash_password("password1")?)
+                .created_at(Utc::now())
+                .build()?,
+        )
+        .await?;
+    let oauth_repo: Arc<dyn OAuthRepo> = Arc::new(MockOAuthRepo::default());
+    let registration = ClientRegistrationDto {
+        name: "<Web app>".into(),
+        redirect_uris: vec![REDIRECT_URI.into()],
+        allowed_scopes: vec!["profile".into()],
+        ..Default::default()
+    };
+    let client = register_client(oauth_repo.as_ref(), &passwd_hasher, registration)
+        .await?
+        .client;
+    let token_signer = Arc::new(TokenSigner::from_settings(&TokenSettings::default())?);
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::from(user_repo.clone()))
+            .app_data(Data::from(MockAuditRepoNoDb.init().await?))
+            .app_data(Data::from(oauth_repo))
+            .app_data(Data::new(passwd_hasher))
+            .app_data(Data::from(token_signer.clone()))
+            .app_data(Data::new(TokenSettings::default()))
+            .app_data(Data::new(OAuthSettings::default()))
+            .route("/oauth/authorize", web::get().to(get_authorize))
+            .route("/oauth/authorize", web::post().to(post_authorize))
+            .route("/oauth/token", web::post().to(post_token)),
+    )
+    .await;
+    let challenge = pkce_s256(CODE_VERIFIER);
+    let authorize_params = [
+        ("response_type", "code"),
+        ("client_id", &client.id),
+        ("redirect_uri", REDIRECT_URI),
+        ("scope", "profile"),
+        ("state", "xyz\"><"),
+        ("code_challenge", &challenge),
+        ("code_challenge_method", "S256"),
+    ];
+
+    // Test the login form carries the request, escaped
+    let uri = authorize_uri(&authorize_params);
+    let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = String::from_utf8(test::read_body(resp).await.to_vec())?;
+    assert!(body.contains("Sign in to &lt;Web app&gt;"));
+    assert!(body.contains(r#"name="state" value="xyz&quot;&gt;&lt;""#));
+
+    // Test invalid clients and redirect URIs are reported without redirecting
+    for (client_id, redirect_uri, status, error) in [
+        (
+            "unknown",
+            REDIRECT_URI,
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+        ),
+        (
+            client.id.as_str(),
+            "https://evil.example.com/callback",
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+        ),
+    ] {
+        let params = [("client_id", client_id), ("redirect_uri", redirect_uri)];
+        let uri = authorize_uri(&params);
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(resp.status(), status);
+        assert!(resp.headers().get(LOCATION).is_none());
+        let dto: OAuthErrorDto = test::read_body_json(resp).await;
+        assert_eq!(dto.error, error);
+    }
+
+    // Test other errors are sent back to the client, PKCE is mandatory
+    let params: Vec<_> = authorize_params
+        .into_iter()
+        .filter(|(name, _)| !name.starts_with("code_challenge"))
+        .collect();
+    let uri = authorize_uri(&params);
+    let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    let location = location_of(&resp);
+    assert!(location.starts_with(REDIRECT_URI));
+    let redirect = query_pairs(&location);
+    assert_eq!(redirect["error"], "invalid_request");
+    assert_eq!(redirect["state"], "xyz\"><");
+    let mut params = authorize_params.to_vec();
+    params[3] = ("scope", "profile admin");
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri(&authorize_uri(&params))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(query_pairs(&location_of(&resp))["error"], "invalid_scope");
+
+    let login = |password: &'static str| {
+        let mut form = authorize_params.to_vec();
+        form.extend([("username", "derek"), ("password", password)]);
+        test::TestRequest::post()
+            .uri("/oauth/authorize")
+            .set_form(form)
+            .to_request()
+    };
+    let authorize = || async {
+        let resp = app.call(login("password1")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        let redirect = query_pairs(&location_of(&resp));
+        assert_eq!(redirect["state"], "xyz\"><");
+        redirect["code"].clone()
+    };
+    let redeem = |code: &str, code_verifier: &str| {
+        test::TestRequest::post()
+            .uri("/oauth/token")
+            .set_form([
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", REDIRECT_URI),
+                ("client_id", &client.id),
+                ("code_verifier", code_verifier),
+            ])
+            .to_request()
+    };
+
+    // Test wrong passwords show the form again
+    let resp = test::call_service(&app, login("wrong-password")).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let body = String::from_utf8(test::read_body(resp).await.to_vec())?;
+    assert!(body.contains("Invalid username or password"));
+
+    // Test a code presented with the wrong verifier is burnt
+    let code = authorize().await;
+    let wrong_verifier = "x".repeat(43);
+    for verifier in [wrong_verifier.as_str(), CODE_VERIFIER] {
+        let resp = test::call_service(&app, redeem(&code, verifier)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let dto: OAuthErrorDto = test::read_body_json(resp).await;
+        assert_eq!(dto.error, "invalid_grant");
+    }
+
+    // Test codes are redeemed once for a token of the user
+    let code = authorize().await;
+    let resp = test::call_service(&app, redeem(&code, CODE_VERIFIER)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get(CACHE_CONTROL).unwrap(), "no-store");
+    let token: AccessTokenRespDto = test::read_body_json(resp).await;
+    assert_eq!(token.scope.as_deref(), Some("profile"));
+    let claims: AccessTokenClaims = token_signer.verify(&token.access_token)?;
+    assert_eq!(claims.sub, user_id.to_string());
+    assert_eq!(claims.client_id, Some(client.id.clone()));
+    let resp = test::call_service(&app, redeem(&code, CODE_VERIFIER)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Test unsupported grants
+    let req = test::TestRequest::post()
+        .uri("/oauth/token")
+        .set_form([("grant_type", "password")])
+        .to_request();
+    let dto: OAuthErrorDto = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(dto.error, "unsupported_grant_type");
+
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_client_credentials_grant() -> Result<()> {
+    let passwd_hasher = PasswordHasher::default();
+    let oauth_repo: Arc<dyn OAuthRepo> = Arc::new(MockOAuthRepo::default());
+    let registration = ClientRegistrationDto {
+        name: "Billing job".into(),
+        allowed_scopes: vec!["users:read".into(), "users:write".into()],
+        confidential: true,
+        ..Default::default()
+    };
+    let service = register_client(oauth_repo.as_ref(), &passwd_hasher, registration).await?;
+    let secret = service.client_secret.clone().unwrap();
+    assert_ne!(service.client.secret_hash.as_deref(), Some(secret.as_str()));
+    let registration = ClientRegistrationDto {
+        name: "SPA".into(),
+        redirect_uris: vec![REDIRECT_URI.into()],
+        ..Default::default()
+    };
+    let public = register_client(oauth_repo.as_ref(), &passwd_hasher, registration).await?;
+    assert!(public.client_secret.is_none());
+    let token_signer = Arc::new(TokenSigner::from_settings(&TokenSettings::default())?);
+    let (_, user_repo) = MockUserRepoNoDb.init().await?;
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::from(user_repo))
+            .app_data(Data::from(MockAuditRepoNoDb.init().await?))
+            .app_data(Data::from(oauth_repo))
+            .app_data(Data::new(passwd_hasher))
+            .app_data(Data::from(token_signer.clone()))
+            .app_data(Data::new(TokenSettings::default()))
+            .app_data(Data::new(OAuthSettings::default()))
+            .route("/oauth/token", web::post().to(post_token)),
+    )
+    .await;
+    let basic = |client_id: &str, secret: &str| {
+        format!(
+            "Basic {}",
+            base64::encode(format!("{}:{}", client_id, secret))
+        )
+    };
+
+    // Test authentication with Basic credentials, granting every allowed scope by default
+    let req = test::TestRequest::post()
+        .uri("/oauth/token")
+        .insert_header((AUTHORIZATION, basic(&service.client.id, &secret)))
+        .set_form([("grant_type", "client_credentials")])
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let token: AccessTokenRespDto = test::read_body_json(resp).await;
+    assert_eq!(token.scope.as_deref(), Some("users:read users:write"));
+    let claims: AccessTokenClaims = token_signer.verify(&token.access_token)?;
+    assert_eq!(claims.sub, service.client.id);
+    assert_eq!(claims.client_id.as_ref(), Some(&service.client.id));
+    assert_eq!(claims.username, None);
+
+    // Test authentication with credentials in the form, narrowing the scope
+    let req = test::TestRequest::post()
+        .uri("/oauth/token")
+        .set_form([
+            ("grant_type", "client_credentials"),
+            ("client_id", &service.client.id),
+            ("client_secret", &secret),
+            ("scope", "users:read"),
+        ])
+        .to_request();
+    let token: AccessTokenRespDto = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(token.scope.as_deref(), Some("users:read"));
+
+    // Test failures
+    for (authorization, fields, status, error) in [
+        (
+            Some(basic(&service.client.id, "wrong-secret")),
+            vec![],
+            StatusCode::UNAUTHORIZED,
+            "invalid_client",
+        ),
+        (
+            None,
+            vec![("client_id", service.client.id.as_str())],
+            StatusCode::UNAUTHORIZED,
+            "invalid_client",
+        ),
+        (
+            Some(basic("unknown", &secret)),
+            vec![],
+            StatusCode::UNAUTHORIZED,
+            "invalid_client",
+        ),
+        (
+            Some(basic(&service.client.id, &secret)),
+            vec![("scope", "users:read admin")],
+            StatusCode::BAD_REQUEST,
+            "invalid_scope",
+        ),
+        (
+            None,
+            vec![("client_id", public.client.id.as_str())],
+            StatusCode::BAD_REQUEST,
+            "unauthorized_client",
+        ),
+    ] {
+        let mut req = test::TestRequest::post().uri("/oauth/token");
+        if let Some(authorization) = authorization {
+            req = req.insert_header((AUTHORIZATION, authorization));
+        }
+        let mut form = vec![("grant_type", "client_credentials")];
+        form.extend(fields);
+        let resp = test::call_service(&app, req.set_form(form).to_request()).await;
+        assert_eq!(resp.status(), status, "{}", error);
+        if status == StatusCode::UNAUTHORIZED {
+            assert!(resp.headers().contains_key(WWW_AUTHENTICATE));
+        }
+        let dto: OAuthErrorDto = test::read_body_json(resp).await;
+        assert_eq!(dto.error, error);
+    }
+
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_introspection_and_revocation() -> Result<()> {
+    let passwd_hasher = PasswordHasher::default();
+    let oauth_repo: Arc<dyn OAuthRepo> = Arc::new(MockOAuthRepo::default());
+    let registration = ClientRegistrationDto {
+        name: "Resource server".into(),
+        confidential: true,
+        ..Default::default()
+    };
+    let api = register_client(oauth_repo.as_ref(), &passwd_hasher, registration).await?;
+    let api_secret = api.client_secret.clone().unwrap();
+    let registration = ClientRegistrationDto {
+        name: "SPA".into(),
+        redirect_uris: vec![REDIRECT_URI.into()],
+        allowed_scopes: vec!["openid".into()],
+        ..Default::default()
+    };
+    let spa = register_client(oauth_repo.as_ref(), &passwd_hasher, registration)
+        .await?
+        .client;
+    let user_repo: Arc<dyn UserRepo> = Arc::new(MockUserRepo::default());
+    let user = UserBuilder::default()
+        .id(Uuid::new_v4())
+        .username("derek")
+        .created_at(Utc::now())
+        .build()?;
+    user_repo.create_user(&user).await?;
+    let token_signer = Arc::new(TokenSigner::from_settings(&TokenSettings::default())?);
+    let ttl = Duration::from_secs(60);
+    let spa_token =
+        token::issue_access_token(&token_signer, ttl, &user, Some(&spa.id), Some("openid"))?
+            .access_token;
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::from(user_repo))
+            .app_data(Data::from(oauth_repo))
+            .app_data(Data::new(passwd_hasher))
+            .app_data(Data::from(token_signer.clone()))
+            .route("/oauth/introspect", web::post().to(post_introspect))
+            .route("/oauth/revoke", web::post().to(post_revoke))
+            .route("/userinfo", web::get().to(get_userinfo)),
+    )
+    .await;
+    let introspect = |token: &str| {
+        test::TestRequest::post()
+            .uri("/oauth/introspect")
+            .set_form([
+                ("token", token),
+                ("client_id", &api.client.id),
+                ("client_secret", &api_secret),
+            ])
+            .to_request()
+    };
+    let revoke = |token: &str, client_id: &str| {
+        test::TestRequest::post()
+            .uri("/oauth/revoke")
+            .set_form([("token", token), ("client_id", client_id)])
+            .to_request()
+    };
+
+    // Test introspection reports the claims of valid tokens only
+    let resp = test::call_service(&app, introspect(&spa_token)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get(CACHE_CONTROL).unwrap(), "no-store");
+    let introspection: IntrospectionRespDto = test::read_body_json(resp).await;
+    assert!(introspection.active);
+    assert_eq!(
+        introspection.claims.unwrap().client_id,
+        Some(spa.id.clone())
+    );
+    let introspection: IntrospectionRespDto =
+        test::call_and_read_body_json(&app, introspect("garbage")).await;
+    assert_eq!(
+        introspection,
+        IntrospectionRespDto {
+            active: false,
+            claims: None
+        }
+    );
+
+    // Test introspection requires a confidential client
+    let req = test::TestRequest::post()
+        .uri("/oauth/introspect")
+        .set_form([("token", spa_token.as_str()), ("client_id", &spa.id)])
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let dto: OAuthErrorDto = test::read_body_json(resp).await;
+    assert_eq!(dto.error, "unauthorized_client");
+
+    // Test tokens are only revoked by their client, invalid ones are accepted
+    let resp = test::call_service(&app, revoke("garbage", &spa.id)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let req = test::TestRequest::post()
+        .uri("/oauth/revoke")
+        .insert_header((
+            AUTHORIZATION,
+            format!(
+                "Basic {}",
+                base64::encode(format!("{}:{}", api.client.id, api_secret))
+            ),
+        ))
+        .set_form([("token", spa_token.as_str())])
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let userinfo = || {
+        test::TestRequest::get()
+            .uri("/userinfo")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", spa_token)))
+            .to_request()
+    };
+    let resp = test::call_service(&app, userinfo()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Test revoked tokens are inactive and rejected wherever they are validated
+    for _ in 0..2 {
+        let resp = test::call_service(&app, revoke(&spa_token, &spa.id)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    let introspection: IntrospectionRespDto =
+        test::call_and_read_body_json(&app, introspect(&spa_token)).await;
+    assert!(!introspection.active);
+    let resp = test::call_service(&app, userinfo()).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_register_client_checks_fields() -> Result<()> {
+    let oauth_repo = MockOAuthRepo::default();
+    let passwd_hasher = PasswordHasher::default();
+    let valid = ClientRegistrationDto {
+        name: "App".into(),
+        redirect_uris: vec![REDIRECT_URI.into()],
+        ..Default::default()
+    };
+    for registration in [
+        ClientRegistrationDto {
+            redirect_uris: vec!["/relative".into()],
+            ..valid.clone()
+        },
+        ClientRegistrationDto {
+            redirect_uris: vec!["https://app.example.com/#fragment".into()],
+            ..valid.clone()
+        },
+        ClientRegistrationDto {
+            allowed_scopes: vec!["two scopes".into()],
+            ..valid.clone()
+        },
+        ClientRegistrationDto {
+            grant_types: vec!["password".into()],
+            ..valid.clone()
+        },
+        ClientRegistrationDto {
+            redirect_uris: vec![],
+            grant_types: vec!["authorization_code".into()],
+            ..valid.clone()
+        },
+        ClientRegistrationDto {
+            grant_types: vec!["client_credentials".into()],
+            ..valid.clone()
+        },
+    ] {
+        assert!(register_client(&oauth_repo, &passwd_hasher, registration)
+            .await
+            .is_err());
+    }
+    assert!(oauth_repo.list_clients().await?.is_empty());
+
+    // Test grant types are inferred from the redirect URIs and confidentiality if not given
+    let client = register_client(&oauth_repo, &passwd_hasher, valid.clone()).await?;
+    assert_eq!(
+        client.client.grant_types,
+        "authorization_code urn:ietf:params:oauth:grant-type:device_code"
+    );
+    let registration = ClientRegistrationDto {
+        redirect_uris: vec![],
+        confidential: true,
+        ..valid
+    };
+    let client = register_client(&oauth_repo, &passwd_hasher, registration).await?;
+    assert!(!client.client.allows_grant_type("authorization_code"));
+    assert!(client.client.allows_grant_type("client_credentials"));
+
+    Ok(())
+}
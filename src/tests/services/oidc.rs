@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::http::header::LOCATION;
+use actix_web::http::header::WWW_AUTHENTICATE;
+use actix_web::http::StatusCode;
+use actix_web::test;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::App;
+use anyhow::Result;
+use chrono::Utc;
+use url::Url;
+use uuid::Uuid;
+
+use crate::crypto::PasswordHasher;
+use crate::crypto::TokenSigner;
+use crate::models::oauth::ClientRegistrationDto;
+use crate::models::oidc::IdTokenClaims;
+use crate::models::oidc::JwksDto;
+use crate::models::oidc::OpenIdConfigurationDto;
+use crate::models::oidc::UserClaims;
+use crate::models::oidc::UserInfoDto;
+use crate::models::token::AccessTokenRespDto;
+use crate::models::user::UserBuilder;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::user::UserRepo;
+use crate::services::oauth::pkce_s256;
+use crate::services::oauth::post_authorize;
+use crate::services::oauth::post_token;
+use crate::services::oauth::register_client;
+use crate::services::oidc::get_jwks;
+use crate::services::oidc::get_openid_configuration;
+use crate::services::oidc::get_userinfo;
+use crate::services::token;
+use crate::settings::OAuthSettings;
+use crate::settings::TokenSettings;
+use crate::tests::mock::audit_repo::InjectableMockAuditRepo;
+use crate::tests::mock::audit_repo::MockAuditRepoNoDb;
+use crate::tests::mock::oauth_repo::MockOAuthRepo;
+use crate::tests::mock::user_repo::MockUserRepo;
+
+const REDIRECT_URI: &str = "https://wiki.example.com/callback";
+const CODE_VERIFIER: &str = "dBjftJeZ4CVP-mJ92K9qf3kUsDmHNjUYUWk1Dqt0xlX";
+
+#[actix_web::test]
+async fn test_openid_connect_flow() -> Result<()> {
+    let passwd_hasher = PasswordHasher::default();
+    let user_repo: Arc<dyn UserRepo> = Arc::new(MockUserRepo::default());
+    let user_id = Uuid::new_v4();
+    user_repo
+        .create_user(
+            &UserBuilder::default()
+                .id(user_id)
+                .username("Derek")
+                .password_hash(passwd_hasher.hash_password("password1")?)
+                .email("derek@example.com")
+                .created_at(Utc::now())
+                .build()?,
+        )
+        .await?;
+    let oauth_repo: Arc<dyn OAuthRepo> = Arc::new(MockOAuthRepo::default());
+    let registration = ClientRegistrationDto {
+        name: "Wiki".into(),
+        redirect_uris: vec![REDIRECT_URI.into()],
+        allowed_scopes: vec!["openid".into(), "profile".into(), "email".into()],
+        ..Default::default()
+    };
+    let client = register_client(oauth_repo.as_ref(), &passwd_hasher, registration)
+        .await?
+        .client;
+    let token_signer = Arc::new(TokenSigner::from_settings(&TokenSettings::default())?);
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::from(user_repo.clone()))
+            .app_data(Data::from(MockAuditRepoNoDb.init().await?))
+            .app_data(Data::from(oauth_repo))
+            .app_data(Data::new(passwd_hasher))
+            .app_data(Data::from(token_signer.clone()))
+            .app_data(Data::new(TokenSettings::default()))
+            .app_data(Data::new(OAuthSettings::default()))
+            .route("/oauth/authorize", web::post().to(post_authorize))
+            .route("/oauth/token", web::post().to(post_token))
+            .route(
+                "/.well-known/openid-configuration",
+                web::get().to(get_openid_configuration),
+            )
+            .route("/.well-known/jwks.json", web::get().to(get_jwks))
+            .route("/userinfo", web::get().to(get_userinfo)),
+    )
+    .await;
+
+    // Test discovery points at the endpoints and the signing key
+    let req = test::TestRequest::get()
+        .uri("/.well-known/openid-configuration")
+        .to_request();
+    let config: OpenIdConfigurationDto = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(config.issuer, "http://localhost:8000");
+    assert_eq!(config.userinfo_endpoint, "http://localhost:8000/userinfo");
+    assert_eq!(
+        config.jwks_uri,
+        "http://localhost:8000/.well-known/jwks.json"
+    );
+    assert!(config.scopes_supported.contains(&"openid".to_string()));
+    let req = test::TestRequest::get()
+        .uri("/.well-known/jwks.json")
+        .to_request();
+    let jwks: JwksDto = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(jwks.keys.len(), 1);
+    assert_eq!(jwks.keys[0].kid, token_signer.kid());
+    assert_eq!(jwks.keys[0].alg, "EdDSA");
+
+    // Test the ID token carries the nonce and the claims of the granted scopes
+    let challenge = pkce_s256(CODE_VERIFIER);
+    let login_time = Utc::now().timestamp();
+    let req = test::TestRequest::post()
+        .uri("/oauth/authorize")
+        .set_form([
+            ("response_type", "code"),
+            ("client_id", &client.id),
+            ("redirect_uri", REDIRECT_URI),
+            ("scope", "openid email"),
+            ("nonce", "n-0S6_WzA2Mj"),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+            ("username", "derek"),
+            ("password", "password1"),
+        ])
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    let location = Url::parse(resp.headers().get(LOCATION).unwrap().to_str()?)?;
+    let redirect: HashMap<_, _> = location.query_pairs().into_owned().collect();
+    let req = test::TestRequest::post()
+        .uri("/oauth/token")
+        .set_form([
+            ("grant_type", "authorization_code"),
+            ("code", &redirect["code"]),
+            ("redirect_uri", REDIRECT_URI),
+            ("client_id", &client.id),
+            ("code_verifier", CODE_VERIFIER),
+        ])
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let token: AccessTokenRespDto = test::read_body_json(resp).await;
+    let id_token = token.id_token.expect("ID token for the openid scope");
+    let claims: IdTokenClaims = token_signer.verify(&id_token)?;
+    assert_eq!(claims.sub, user_id.to_string());
+    assert_eq!(claims.aud, client.id);
+    assert_eq!(claims.nonce.as_deref(), Some("n-0S6_WzA2Mj"));
+    assert!((login_time..=Utc::now().timestamp()).contains(&claims.auth_time));
+    assert_eq!(
+        claims.user,
+        UserClaims {
+            preferred_username: None,
+            email: Some("derek@example.com".into()),
+            email_verified: Some(false),
+        }
+    );
+
+    // Test userinfo gives the same claims for the access token
+    let userinfo = |bearer: &str| {
+        test::TestRequest::get()
+            .uri("/userinfo")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", bearer)))
+            .to_request()
+    };
+    let resp = test::call_service(&app, userinfo(&token.access_token)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let info: UserInfoDto = test::read_body_json(resp).await;
+    assert_eq!(info.sub, user_id.to_string());
+    assert_eq!(info.user, claims.user);
+
+    // Test tokens without the openid scope, without a user or which aren't access tokens
+    let user = user_repo.get_user_by_id(&user_id).await?;
+    let ttl = Duration::from_secs(60);
+    let profile_token =
+        token::issue_access_token(&token_signer, ttl, &user, Some(&client.id), Some("profile"))?;
+    let client_token = token::issue_client_token(&token_signer, ttl, &client.id, Some("openid"))?;
+    for (bearer, status, error) in [
+        (
+            profile_token.access_token.as_str(),
+            StatusCode::FORBIDDEN,
+            "insufficient_scope",
+        ),
+        (
+            client_token.access_token.as_str(),
+            StatusCode::UNAUTHORIZED,
+            "invalid_token",
+        ),
+        (id_token.as_str(), StatusCode::UNAUTHORIZED, "invalid_token"),
+        ("garbage", StatusCode::UNAUTHORIZED, "invalid_token"),
+    ] {
+        let resp = test::call_service(&app, userinfo(bearer)).await;
+        assert_eq!(resp.status(), status);
+        let challenge = resp.headers().get(WWW_AUTHENTICATE).unwrap().to_str()?;
+        assert!(challenge.contains(&format!(r#"error="{}""#, error)));
+    }
+
+    Ok(())
+}
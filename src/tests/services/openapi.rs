@@ -0,0 +1,58 @@
+#[cfg(debug_assertions)]
+use actix_web::http::StatusCode;
+use actix_web::test;
+use actix_web::web;
+use actix_web::App;
+use serde_json::Value;
+
+use crate::services::openapi::openapi_json;
+#[cfg(debug_assertions)]
+use crate::services::openapi::swagger_ui;
+
+#[actix_web::test]
+async fn test_openapi_json() {
+    let app =
+        test::init_service(App::new().route("/openapi.json", web::get().to(openapi_json))).await;
+    let req = test::TestRequest::get().uri("/openapi.json").to_request();
+    let doc: Value = test::call_and_read_body_json(&app, req).await;
+
+    assert!(doc["openapi"].as_str().unwrap().starts_with("3."));
+    let user_path = &doc["paths"]["/v1/users/{user_id}"];
+    assert!(user_path["get"].is_object() && user_path["delete"].is_object());
+    assert_eq!(
+        user_path["get"]["responses"]["404"]["content"]["application/problem+json"]["schema"]
+            ["$ref"],
+        "#/components/schemas/ProblemDetailsDto"
+    );
+    assert!(doc["paths"]["/v1/users"]["post"]["requestBody"].is_object());
+    for route in ["/healthz", "/readyz"] {
+        assert!(doc["paths"][route]["get"].is_object(), "{} missing", route);
+    }
+
+    // Validation constraints are part of the schema
+    let schemas = &doc["components"]["schemas"];
+    let username = &schemas["UserCreateReqDto"]["properties"]["username"];
+    assert_eq!(username["minLength"], 3);
+    assert_eq!(username["maxLength"], 30);
+    let password = &schemas["UserCreateReqDto"]["properties"]["password_raw"];
+    assert_eq!(password["minLength"], 8);
+    assert_eq!(password["writeOnly"], true);
+    assert_eq!(
+        schemas["UserCreateReqDto"]["properties"]["email"]["format"],
+        "email"
+    );
+    assert_eq!(
+        schemas["UserCreateReqDto"]["required"],
+        serde_json::json!(["username", "password_raw"])
+    );
+}
+
+#[cfg(debug_assertions)]
+#[actix_web::test]
+async fn test_swagger_ui() {
+    let app = test::init_service(App::new().service(swagger_ui())).await;
+    let req = test::TestRequest::get()
+        .uri("/swagger-ui/index.html")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+}
@@ -0,0 +1,578 @@
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use actix_web::cookie::Cookie;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::http::header::LOCATION;
+use actix_web::http::StatusCode;
+use actix_web::test;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::web::Form;
+use actix_web::App;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::HttpServer;
+use anyhow::Result;
+use chrono::Utc;
+use jsonwebtoken::jwk::AlgorithmParameters;
+use jsonwebtoken::jwk::CommonParameters;
+use jsonwebtoken::jwk::EllipticCurve;
+use jsonwebtoken::jwk::EllipticCurveKeyParameters;
+use jsonwebtoken::jwk::EllipticCurveKeyType;
+use jsonwebtoken::jwk::Jwk;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::Algorithm;
+use jsonwebtoken::EncodingKey;
+use jsonwebtoken::Header;
+use ring::rand::SystemRandom;
+use ring::signature::EcdsaKeyPair;
+use ring::signature::KeyPair;
+use ring::signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+use rstest::*;
+use serde_json::json;
+use serde_json::Value;
+use url::Url;
+
+use crate::crypto::random_token;
+use crate::crypto::PasswordHasher;
+use crate::crypto::TokenSigner;
+use crate::errors::user::ProblemDetailsDto;
+use crate::models::audit::AuditEventType;
+use crate::models::audit::AuditQuery;
+use crate::models::identity::LinkedIdentity;
+use crate::models::oauth::ClientRegistrationDto;
+use crate::models::oauth::OAuthErrorDto;
+use crate::models::oidc::UpstreamIdTokenClaims;
+use crate::models::token::AccessTokenClaims;
+use crate::models::token::AccessTokenRespDto;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::identity::IdentityRepo;
+use crate::repositories::oauth::OAuthRepo;
+use crate::repositories::user::UserRepo;
+use crate::services::oauth::get_authorize;
+use crate::services::oauth::pkce_s256;
+use crate::services::oauth::post_token;
+use crate::services::oauth::register_client;
+use crate::services::upstream;
+use crate::services::upstream::delete_user_identity;
+use crate::services::upstream::get_upstream_callback;
+use crate::services::upstream::get_upstream_login;
+use crate::services::upstream::get_user_identities;
+use crate::services::upstream::post_user_identity;
+use crate::services::upstream::UpstreamClient;
+use crate::services::upstream::UPSTREAM_STATE_COOKIE;
+use crate::settings::AdminSettings;
+use crate::settings::OAuthSettings;
+use crate::settings::TokenSettings;
+use crate::settings::UpstreamProviderSettings;
+use crate::tests::mock::audit_repo::MockAuditRepo;
+use crate::tests::mock::identity_repo::InjectableMockIdentityRepo;
+use crate::tests::mock::identity_repo::MockIdentityRepo;
+use crate::tests::mock::identity_repo::MockIdentityRepoNoDb;
+use crate::tests::mock::identity_repo::MockIdentityRepoPsqlDb;
+#[cfg(feature = "sqlite")]
+use crate::tests::mock::identity_repo::MockIdentityRepoSqliteDb;
+use crate::tests::mock::oauth_repo::MockOAuthRepo;
+use crate::tests::mock::user_repo::MockUserRepo;
+
+const ADMIN_TOKEN: &str = "0123456789abcdef0123456789abcdef";
+const REDIRECT_URI: &str = "https://app.example.com/callback";
+const CODE_VERIFIER: &str = "dBjftJeZ4CVP-mJ92K9qf3kUsDmHNjUYUWk1Dqt0xlX";
+const UPSTREAM_CALLBACK_URI: &str = "http://localhost:8000/oauth/upstream/corp/callback";
+const IDP_CLIENT_ID: &str = "auth-uservice";
+const IDP_CLIENT_SECRET: &str = "idp-secret";
+
+/// OpenID provider signing its ID tokens with ES256, served on a local port.
+struct MockIdp {
+    issuer: String,
+    encoding_key: EncodingKey,
+    jwk: Jwk,
+    /// Codes issued to the users signing in, with their PKCE challenge and ID token claims
+    grants: Mutex<HashMap<String, (String, Value)>>,
+}
+
+impl MockIdp {
+    fn start() -> Result<Data<Self>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let pkcs8 =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+                .unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap();
+        // Uncompressed point, 0x04 || x || y
+        let point = key_pair.public_key().as_ref();
+        let idp = Data::new(Self {
+            issuer: format!("http://{}", listener.local_addr()?),
+            encoding_key: EncodingKey::from_ec_der(pkcs8.as_ref()),
+            jwk: Jwk {
+                common: CommonParameters {
+                    key_id: Some("idp-key".into()),
+                    ..Default::default()
+                },
+                algorithm: AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                    key_type: EllipticCurveKeyType::EC,
+                    curve: EllipticCurve::P256,
+                    x: base64::encode_config(&point[1..33], base64::URL_SAFE_NO_PAD),
+                    y: base64::encode_config(&point[33..], base64::URL_SAFE_NO_PAD),
+                }),
+            },
+            grants: Mutex::new(HashMap::new()),
+        });
+        let data = idp.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .route(
+                    "/.well-known/openid-configuration",
+                    web::get().to(idp_configuration),
+                )
+                .route("/jwks", web::get().to(idp_jwks))
+                .route("/token", web::post().to(idp_token))
+        })
+        .workers(1)
+        .listen(listener)?
+        .run();
+        actix_web::rt::spawn(server);
+        Ok(idp)
+    }
+
+    /// Signs the user in at the authorization endpoint the service redirected to, returning the
+    /// callback to follow. Claims left unset in `claims` are those of a valid ID token.
+    fn authorize(&self, location: &str, claims: Value) -> String {
+        let location = Url::parse(location).unwrap();
+        let query: HashMap<_, _> = location.query_pairs().into_owned().collect();
+        assert_eq!(
+            location.as_str().split('?').next(),
+            Some(format!("{}/authorize", self.issuer).as_str())
+        );
+        assert_eq!(query["response_type"], "code");
+        assert_eq!(query["client_id"], IDP_CLIENT_ID);
+        assert_eq!(query["redirect_uri"], UPSTREAM_CALLBACK_URI);
+        assert_eq!(query["scope"], "openid profile email");
+        assert_eq!(query["code_challenge_method"], "S256");
+        let mut id_token_claims = json!({
+            "iss": self.issuer,
+            "aud": IDP_CLIENT_ID,
+            "exp": Utc::now().timestamp() + 300,
+            "iat": Utc::now().timestamp(),
+            "nonce": query["nonce"],
+        });
+        for (name, value) in claims.as_object().unwrap() {
+            id_token_claims[name] = value.clone();
+        }
+        let code = random_token();
+        self.grants.lock().unwrap().insert(
+            code.clone(),
+            (query["code_challenge"].clone(), id_token_claims),
+        );
+        let mut callback = Url::parse(UPSTREAM_CALLBACK_URI).unwrap();
+        callback
+            .query_pairs_mut()
+            .append_pair("code", &code)
+            .append_pair("state", &query["state"]);
+        format!("{}?{}", callback.path(), callback.query().unwrap())
+    }
+}
+
+async fn idp_configuration(idp: Data<MockIdp>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "issuer": idp.issuer,
+        "authorization_endpoint": format!("{}/authorize", idp.issuer),
+        "token_endpoint": format!("{}/token", idp.issuer),
+        "jwks_uri": format!("{}/jwks", idp.issuer),
+    }))
+}
+
+async fn idp_jwks(idp: Data<MockIdp>) -> HttpResponse {
+    HttpResponse::Ok().json(JwkSet {
+        keys: vec![idp.jwk.clone()],
+    })
+}
+
+async fn idp_token(
+    idp: Data<MockIdp>,
+    req: HttpRequest,
+    form: Form<HashMap<String, String>>,
+) -> HttpResponse {
+    let invalid = |error: &str| HttpResponse::BadRequest().json(json!({ "error": error }));
+    let credentials = base64::encode(format!("{}:{}", IDP_CLIENT_ID, IDP_CLIENT_SECRET));
+    if req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        != Some(format!("Basic {}", credentials).as_str())
+    {
+        return invalid("invalid_client");
+    }
+    let grant = form
+        .get("code")
+        .and_then(|code| idp.grants.lock().unwrap().remove(code));
+    let (code_challenge, claims) = match grant {
+        Some(grant) => grant,
+        None => return invalid("invalid_grant"),
+    };
+    if form.get("redirect_uri").map(String::as_str) != Some(UPSTREAM_CALLBACK_URI)
+        || form
+            .get("code_verifier")
+            .map(|verifier| pkce_s256(verifier))
+            != Some(code_challenge)
+    {
+        return invalid("invalid_grant");
+    }
+    let header = Header {
+        kid: Some("idp-key".into()),
+        ..Header::new(Algorithm::ES256)
+    };
+    let id_token = jsonwebtoken::encode(&header, &claims, &idp.encoding_key).unwrap();
+    HttpResponse::Ok().json(json!({
+        "access_token": random_token(),
+        "token_type": "Bearer",
+        "id_token": id_token,
+    }))
+}
+
+fn location_of(resp: &ServiceResponse) -> String {
+    resp.headers()
+        .get(LOCATION)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+fn query_pairs(location: &str) -> HashMap<String, String> {
+    Url::parse(location)
+        .unwrap()
+        .query_pairs()
+        .into_owned()
+        .collect()
+}
+
+#[actix_web::test]
+async fn test_upstream_login() -> Result<()> {
+    let idp = MockIdp::start()?;
+    let passwd_hasher = PasswordHasher::default();
+    let mock_user_repo = Arc::new(MockUserRepo::default());
+    let identity_repo: Arc<dyn IdentityRepo> =
+        Arc::new(MockIdentityRepo(Default::default(), mock_user_repo.clone()));
+    let user_repo: Arc<dyn UserRepo> = mock_user_repo;
+    let oauth_repo: Arc<dyn OAuthRepo> = Arc::new(MockOAuthRepo::default());
+    let registration = ClientRegistrationDto {
+        name: "Web app".into(),
+        redirect_uris: vec![REDIRECT_URI.into()],
+        allowed_scopes: vec!["profile".into()],
+        ..Default::default()
+    };
+    let client = register_client(oauth_repo.as_ref(), &passwd_hasher, registration)
+        .await?
+        .client;
+    let token_signer = Arc::new(TokenSigner::from_settings(&TokenSettings::default())?);
+    let oauth_settings = OAuthSettings {
+        upstream_providers: vec![UpstreamProviderSettings {
+            id: "corp".into(),
+            name: "Corp <SSO>".into(),
+            issuer: idp.issuer.clone(),
+            client_id: IDP_CLIENT_ID.into(),
+            client_secret: Some(IDP_CLIENT_SECRET.into()),
+            scopes: vec!["openid".into(), "profile".into(), "email".into()],
+        }],
+        ..Default::default()
+    };
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::from(user_repo.clone()))
+            .app_data(Data::from(identity_repo.clone()))
+            .app_data(Data::from(
+                Arc::new(MockAuditRepo::default()) as Arc<dyn AuditRepo>
+            ))
+            .app_data(Data::from(oauth_repo))
+            .app_data(Data::new(passwd_hasher))
+            .app_data(Data::from(token_signer.clone()))
+            .app_data(Data::new(TokenSettings::default()))
+            .app_data(Data::new(oauth_settings))
+            .app_data(Data::new(UpstreamClient::new()?))
+            .app_data(Data::new(AdminSettings {
+                api_token: Some(ADMIN_TOKEN.into()),
+            }))
+            .route("/oauth/authorize", web::get().to(get_authorize))
+            .route("/oauth/token", web::post().to(post_token))
+            .route(
+                "/oauth/upstream/{provider}/login",
+                web::get().to(get_upstream_login),
+            )
+            .route(
+                "/oauth/upstream/{provider}/callback",
+                web::get().to(get_upstream_callback),
+            )
+            .route(
+                "/admin/users/{user_id}/identities",
+                web::get().to(get_user_identities),
+            )
+            .route(
+                "/admin/users/{user_id}/identities",
+                web::post().to(post_user_identity),
+            )
+            .route(
+                "/admin/users/{user_id}/identities/{provider}/{subject}",
+                web::delete().to(delete_user_identity),
+            ),
+    )
+    .await;
+    let challenge = pkce_s256(CODE_VERIFIER);
+    let mut authorize_params = Url::parse("http://localhost").unwrap();
+    authorize_params
+        .query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &client.id)
+        .append_pair("redirect_uri", REDIRECT_URI)
+        .append_pair("scope", "profile")
+        .append_pair("state", "xyz")
+        .append_pair("code_challenge", &challenge)
+        .append_pair("code_challenge_method", "S256");
+    let authorize_params = authorize_params.query().unwrap().to_string();
+    // Starts a sign-in at the provider, returning where the user is sent and the state cookie
+    let start_login = || async {
+        let uri = format!("/oauth/upstream/corp/login?{}", authorize_params);
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        let cookie = resp
+            .response()
+            .cookies()
+            .find(|cookie| cookie.name() == UPSTREAM_STATE_COOKIE)
+            .unwrap()
+            .into_owned();
+        assert!(cookie.http_only().unwrap_or(false));
+        (location_of(&resp), cookie)
+    };
+    let callback = |uri: &str, cookie: Option<&Cookie>| {
+        let mut req = test::TestRequest::get().uri(uri);
+        if let Some(cookie) = cookie {
+            req = req.cookie(cookie.clone());
+        }
+        req.to_request()
+    };
+    let redeem = |code: &str| {
+        test::TestRequest::post()
+            .uri("/oauth/token")
+            .set_form([
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", REDIRECT_URI),
+                ("client_id", &client.id),
+                ("code_verifier", CODE_VERIFIER),
+            ])
+            .to_request()
+    };
+    let jane = json!({
+        "sub": "248289761001",
+        "preferred_username": "Jane Doe",
+        "email": "jane@example.com",
+        "email_verified": true,
+    });
+
+    // Test the login page offers the provider, carrying the request over
+    let uri = format!("/oauth/authorize?{}", authorize_params);
+    let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+    let body = String::from_utf8(test::read_body(resp).await.to_vec())?;
+    assert!(body.contains("Sign in with Corp &lt;SSO&gt;"));
+    assert!(body.contains(r#"href="upstream/corp/login?response_type=code&amp;client_id="#));
+
+    // Test the first sign-in provisions a user with the identity linked
+    let (location, cookie) = start_login().await;
+    let resp = test::call_service(
+        &app,
+        callback(&idp.authorize(&location, jane.clone()), Some(&cookie)),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    let location = location_of(&resp);
+    assert!(location.starts_with(REDIRECT_URI));
+    let redirect = query_pairs(&location);
+    assert_eq!(redirect["state"], "xyz");
+    let resp = test::call_service(&app, redeem(&redirect["code"])).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let token: AccessTokenRespDto = test::read_body_json(resp).await;
+    let claims: AccessTokenClaims = token_signer.verify(&token.access_token)?;
+    let identity = identity_repo
+        .get_identity("corp", "248289761001")
+        .await?
+        .unwrap();
+    assert_eq!(claims.sub, identity.user_id.to_string());
+    let user = user_repo.get_user_by_id(&identity.user_id).await?;
+    assert_eq!(user.username, "Jane.Doe");
+    assert_eq!(user.email.as_deref(), Some("jane@example.com"));
+    assert!(user.last_login.is_some());
+
+    // Test the next sign-in is as the same user, and callbacks can't be replayed
+    let (location, cookie) = start_login().await;
+    let callback_uri = idp.authorize(&location, jane.clone());
+    let resp = test::call_service(&app, callback(&callback_uri, Some(&cookie))).await;
+    let redirect = query_pairs(&location_of(&resp));
+    let resp = test::call_service(&app, redeem(&redirect["code"])).await;
+    let token: AccessTokenRespDto = test::read_body_json(resp).await;
+    let claims: AccessTokenClaims = token_signer.verify(&token.access_token)?;
+    assert_eq!(claims.sub, user.id.to_string());
+    let resp = test::call_service(&app, callback(&callback_uri, Some(&cookie))).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Test another identity with the same name gets a user of its own, without the address it
+    // doesn't say is verified
+    let (location, cookie) = start_login().await;
+    let john =
+        json!({"sub": "1337", "preferred_username": "jane.doe", "email": "jane@example.com"});
+    let resp = test::call_service(
+        &app,
+        callback(&idp.authorize(&location, john), Some(&cookie)),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    let other = identity_repo.get_identity("corp", "1337").await?.unwrap();
+    assert_ne!(other.user_id, user.id);
+    let other_user = user_repo.get_user_by_id(&other.user_id).await?;
+    assert_eq!(other_user.username, "jane.doe-2");
+    assert_eq!(other_user.email, None);
+
+    // Test the callback is bound to the browser which started the sign-in
+    let (location, cookie) = start_login().await;
+    let callback_uri = idp.authorize(&location, jane.clone());
+    let other_cookie = Cookie::new(UPSTREAM_STATE_COOKIE, random_token());
+    for cookie in [None, Some(&other_cookie)] {
+        let resp = test::call_service(&app, callback(&callback_uri, cookie)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let dto: OAuthErrorDto = test::read_body_json(resp).await;
+        assert_eq!(dto.error, "invalid_request");
+    }
+    let resp = test::call_service(&app, callback(&callback_uri, Some(&cookie))).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    // Test ID tokens for another nonce, audience or issuer are rejected back to the client
+    for claims in [
+        json!({"sub": "248289761001", "nonce": "other"}),
+        json!({"sub": "248289761001", "aud": "other-client"}),
+        json!({"sub": "248289761001", "iss": "https://evil.example.com"}),
+        json!({"sub": "248289761001", "exp": Utc::now().timestamp() - 600}),
+    ] {
+        let (location, cookie) = start_login().await;
+        let resp = test::call_service(
+            &app,
+            callback(&idp.authorize(&location, claims), Some(&cookie)),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        let redirect = query_pairs(&location_of(&resp));
+        assert_eq!(redirect["error"], "access_denied");
+        assert_eq!(redirect["state"], "xyz");
+    }
+    // Test errors of the provider are sent back to the client too
+    let (location, cookie) = start_login().await;
+    let state = query_pairs(&location)["state"].clone();
+    let uri = format!(
+        "/oauth/upstream/corp/callback?error=access_denied&state={}",
+        state
+    );
+    let resp = test::call_service(&app, callback(&uri, Some(&cookie))).await;
+    assert_eq!(query_pairs(&location_of(&resp))["error"], "access_denied");
+
+    // Test unknown providers
+    let uri = format!("/oauth/upstream/other/login?{}", authorize_params);
+    let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+    assert_eq!(query_pairs(&location_of(&resp))["error"], "invalid_request");
+
+    // Test administrators link more identities to a user, and unlink them
+    let bearer = (AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN));
+    let identities_uri = format!("/admin/users/{}/identities", user.id);
+    let link = |provider: &str, subject: &str| {
+        test::TestRequest::post()
+            .uri(&identities_uri)
+            .insert_header(bearer.clone())
+            .set_json(json!({"provider": provider, "subject": subject}))
+            .to_request()
+    };
+    let linked: LinkedIdentity =
+        test::call_and_read_body_json(&app, link("corp", "jane-laptop")).await;
+    assert_eq!(linked.user_id, user.id);
+    for (provider, subject, code) in [
+        ("corp", "1337", "identity_taken"),
+        ("other", "jane", "invalid_identity_fields"),
+        ("corp", "", "invalid_identity_fields"),
+    ] {
+        let resp = test::call_service(&app, link(provider, subject)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let problem: ProblemDetailsDto = test::read_body_json(resp).await;
+        assert_eq!(problem.code, code);
+    }
+    let req = test::TestRequest::get()
+        .uri(&identities_uri)
+        .insert_header(bearer.clone())
+        .to_request();
+    let identities: Vec<LinkedIdentity> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(identities.len(), 2);
+    let unlink = || {
+        test::TestRequest::delete()
+            .uri(&format!("{}/corp/jane-laptop", identities_uri))
+            .insert_header(bearer.clone())
+            .to_request()
+    };
+    let resp = test::call_service(&app, unlink()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, unlink()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[rstest]
+#[case::no_db(Arc::new(MockIdentityRepoNoDb))]
+#[case::psql_db(Arc::new(MockIdentityRepoPsqlDb))]
+#[cfg_attr(
+    feature = "sqlite",
+    case::sqlite_db(Arc::new(MockIdentityRepoSqliteDb))
+)]
+#[actix_web::test]
+async fn test_concurrent_provisioning(
+    #[case] testable_repo: Arc<dyn InjectableMockIdentityRepo>,
+) -> Result<()> {
+    let (user_repo, identity_repo) = testable_repo.init().await?;
+    let audit_repo = MockAuditRepo::default();
+    let passwd_hasher = PasswordHasher::default();
+    let provider = UpstreamProviderSettings {
+        id: "corp".into(),
+        name: "Corp".into(),
+        issuer: "https://sso.example.com".into(),
+        client_id: IDP_CLIENT_ID.into(),
+        client_secret: None,
+        scopes: vec!["openid".into()],
+    };
+    let claims: UpstreamIdTokenClaims =
+        serde_json::from_value(json!({"sub": "248289761001", "preferred_username": "jane"}))?;
+    let provision = || {
+        upstream::provision_user(
+            user_repo.as_ref(),
+            identity_repo.as_ref(),
+            &audit_repo,
+            &passwd_hasher,
+            &provider,
+            &claims,
+        )
+    };
+
+    // Test the sign-in losing the race to provision the identity gets the user of the winner
+    let first = provision().await?;
+    let second = provision().await?;
+    assert_eq!(second.id, first.id);
+    assert_eq!(second.username, "jane");
+    assert!(!user_repo.contains_user_with_username("jane-2").await?);
+    let created = audit_repo
+        .list_events(&AuditQuery {
+            event_type: Some(AuditEventType::UserCreated),
+            ..Default::default()
+        })
+        .await?;
+    assert_eq!(created.len(), 1);
+
+    Ok(())
+}
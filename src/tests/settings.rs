@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::settings::Settings;
+
+#[test]
+fn test_settings_defaults() -> Result<()> {
+    let settings = Settings::load_from(None, Some(HashMap::new()), &[])?;
+    assert_eq!(settings, Settings::default());
+    Ok(())
+}
+
+#[test]
+fn test_settings_layering() -> Result<()> {
+    let config_path = std::env::temp_dir().join(format!(
+        "auth-uservice-test-{}.toml",
+        uuid::Uuid::new_v4().simple()
+    ));
+    std::fs::File::create(&config_path)?.write_all(
+        br#"
+        [server]
+        bind_address = "127.0.0.1:9000"
+
+        [database]
+        url = "postgres://file@db:5432/users_db"
+        max_connections = 10
+
+        [argon2]
+        time_cost = 5
+        "#,
+    )?;
+    let env = HashMap::from([
+        (
+            "AUTH_USERVICE_DATABASE__URL".to_string(),
+            "postgres://env@db:5432/users_db".to_string(),
+        ),
+        ("AUTH_USERVICE_ARGON2__LANES".to_string(), "2".to_string()),
+    ]);
+    let overrides = [("database.max_connections", "20".to_string())];
+
+    let settings = Settings::load_from(Some(&config_path), Some(env), &overrides);
+    std::fs::remove_file(&config_path)?;
+    let settings = settings?;
+
+    // File overrides defaults
+    assert_eq!(settings.server.bind_address, "127.0.0.1:9000");
+    assert_eq!(settings.argon2.time_cost, 5);
+    // Environment overrides file
+    assert_eq!(settings.database.url, "postgres://env@db:5432/users_db");
+    assert_eq!(settings.argon2.lanes, 2);
+    // Flags override environment and file
+    assert_eq!(settings.database.max_connections, 20);
+    // Untouched settings keep their defaults
+    assert_eq!(
+        settings.argon2.mem_cost,
+        Settings::default().argon2.mem_cost
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_settings_validation() {
+    let invalid = [
+        ("server.bind_address", "not an address"),
+        ("database.max_connections", "0"),
+        ("database.min_connections", "6"),
+        ("database.connect_backoff_ms", "20000"),
+        ("argon2.lanes", "0"),
+        ("token.issuer", "not a url"),
+        ("log.level", "verbose"),
+        ("log.level", "sqlx=verbose"),
+        ("log.level", "info,verbose"),
+        ("tracing.otlp_endpoint", "not a url"),
+        ("admin.api_token", "short"),
+        ("api.legacy_sunset", "next year"),
+    ];
+    for (key, value) in invalid {
+        assert!(
+            Settings::load_from(None, Some(HashMap::new()), &[(key, value.to_string())]).is_err(),
+            "Setting {} = {:?} was accepted",
+            key,
+            value
+        );
+    }
+    for filter in ["info,sqlx=warn", "info,sqlx", "warn,auth_uservice::grpc"] {
+        assert!(
+            Settings::load_from(None, Some(HashMap::new()), &[("log.level", filter.into())])
+                .is_ok()
+        );
+    }
+
+    // Unknown keys are rejected rather than silently ignored
+    let env = HashMap::from([(
+        "AUTH_USERVICE_DATABASE__PASWORD".to_string(),
+        "typo".to_string(),
+    )]);
+    assert!(Settings::load_from(None, Some(env), &[]).is_err());
+}
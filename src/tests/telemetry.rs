@@ -0,0 +1,357 @@
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use actix_web::test;
+use actix_web::web;
+use actix_web::web::Data;
+use actix_web::App;
+use anyhow::Result;
+use opentelemetry::global;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use rstest::*;
+use serde_json::Value;
+use tracing::subscriber::DefaultGuard;
+use tracing_actix_web::TracingLogger;
+use tracing_log::LogTracer;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+use uuid::Uuid;
+
+use crate::crypto::PasswordHasher;
+use crate::models::user::UserBuilder;
+use crate::models::user::UserCreateReqDtoBuilder;
+use crate::repositories::audit::AuditRepo;
+use crate::repositories::metered::MeteredAuditRepo;
+use crate::repositories::metered::MeteredUserRepo;
+use crate::repositories::user::UserRepo;
+use crate::request_id::assign_request_id;
+use crate::request_id::REQUEST_ID_HEADER;
+use crate::services::user::delete_user;
+use crate::services::user::get_user_by_id;
+use crate::services::user::post_user;
+use crate::settings::LogFormat;
+use crate::settings::TracingSettings;
+use crate::telemetry;
+use crate::telemetry::RequestSpan;
+use crate::telemetry::REDACTED;
+use crate::tests::mock::audit_repo::MockAuditRepo;
+use crate::tests::mock::user_repo::InjectableMockUserRepo;
+use crate::tests::mock::user_repo::MockUserRepoNoDb;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+/// Path and body of a request received by the collector
+type Export = (String, Vec<u8>);
+
+/// Stand-in for an OTLP/HTTP collector, sending every request it receives to the channel.
+fn start_collector() -> Result<(String, Receiver<Export>)> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let endpoint = format!("http://{}", listener.local_addr()?);
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let path = request_line
+                .split(' ')
+                .nth(1)
+                .unwrap_or_default()
+                .to_string();
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+            if tx.send((path, body)).is_err() {
+                return;
+            }
+        }
+    });
+    Ok((endpoint, rx))
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[actix_web::test]
+async fn test_spans_exported_over_otlp() -> Result<()> {
+    let (endpoint, exports) = start_collector()?;
+    let settings = TracingSettings {
+        otlp_endpoint: Some(endpoint),
+        ..Default::default()
+    };
+    let provider = telemetry::tracer_provider(
+        settings.otlp_endpoint.as_deref().unwrap(),
+        &settings.service_name,
+    )?;
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let _guard = tracing::subscriber::set_default(
+        Registry::default().with(telemetry::otel_layer(&provider)),
+    );
+
+    let (users, user_repo) = MockUserRepoNoDb.init().await?;
+    let user_repo: Arc<dyn UserRepo> = Arc::new(MeteredUserRepo(user_repo));
+    let audit_repo: Arc<dyn AuditRepo> = Arc::new(MeteredAuditRepo(Arc::new(
+        MockAuditRepo::with_users(user_repo.clone()),
+    )));
+    let app = test::init_service(
+        App::new()
+            .wrap(TracingLogger::<RequestSpan>::new())
+            .wrap_fn(assign_request_id)
+            .app_data(Data::from(user_repo))
+            .app_data(Data::from(audit_repo))
+            .app_data(Data::new(PasswordHasher::new()))
+            .route("/users/{user_id}", web::get().to(get_user_by_id))
+            .route("/users", web::post().to(post_user)),
+    )
+    .await;
+
+    // Continues the trace given by the caller
+    let req = test::TestRequest::get()
+        .uri(&format!("/users/{}", users[0].id))
+        .insert_header((
+            "traceparent",
+            format!("00-{}-00f067aa0ba902b7-01", TRACE_ID),
+        ))
+        .to_request();
+    test::call_service(&app, req).await;
+    let user = UserCreateReqDtoBuilder::default()
+        .username("Traced")
+        .password_raw("secret-password")
+        .build()?;
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .set_json(&user)
+        .to_request();
+    test::call_service(&app, req).await;
+
+    for result in provider.force_flush() {
+        result?;
+    }
+    let mut body = Vec::new();
+    while let Ok((path, export)) = exports.recv_timeout(Duration::from_secs(5)) {
+        assert_eq!(path, "/v1/traces");
+        body.extend(export);
+        if contains(&body, b"hash_password") {
+            break;
+        }
+    }
+
+    let trace_id: Vec<u8> = (0..TRACE_ID.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&TRACE_ID[i..i + 2], 16).unwrap())
+        .collect();
+    assert!(contains(&body, &trace_id), "traceparent was not propagated");
+    for span in [
+        "get_user_by_id",
+        "post_user",
+        "contains_user_with_username",
+        "change_user",
+        "hash_password",
+        "auth-uservice",
+    ] {
+        assert!(
+            contains(&body, span.as_bytes()),
+            "Span {} not exported",
+            span
+        );
+    }
+    assert!(!contains(&body, b"secret-password"));
+
+    Ok(())
+}
+
+#[derive(Clone, Default)]
+struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl LogBuffer {
+    fn lines(&self) -> Vec<Value> {
+        String::from_utf8(self.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Log line is not JSON"))
+            .collect()
+    }
+}
+
+fn capture_logs(format: LogFormat) -> (LogBuffer, DefaultGuard) {
+    let _ = LogTracer::init();
+    let logs = LogBuffer::default();
+    let writer = logs.clone();
+    let layer = telemetry::log_layer(format, move || writer.clone());
+    let guard = tracing::subscriber::set_default(Registry::default().with(layer));
+    (logs, guard)
+}
+
+#[actix_web::test]
+async fn test_json_logs_with_request_id() -> Result<()> {
+    let (logs, _guard) = capture_logs(LogFormat::Json);
+    let (_, user_repo) = MockUserRepoNoDb.init().await?;
+    let app = test::init_service(
+        App::new()
+            .wrap(TracingLogger::<RequestSpan>::new())
+            .wrap_fn(assign_request_id)
+            .app_data(Data::from(user_repo.clone()))
+            .app_data(Data::<dyn AuditRepo>::from(
+                Arc::new(MockAuditRepo::with_users(user_repo)) as Arc<_>,
+            ))
+            .route("/users/{user_id}", web::delete().to(delete_user)),
+    )
+    .await;
+    let uri = format!("/users/{}", Uuid::new_v4());
+
+    // The ID given by the client is kept
+    let req = test::TestRequest::delete()
+        .uri(&uri)
+        .insert_header((REQUEST_ID_HEADER.clone(), "req-123"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(resp.headers().get(&REQUEST_ID_HEADER).unwrap(), "req-123");
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["request_id"], "req-123");
+
+    let lines = logs.lines();
+    let messages: Vec<_> = lines.iter().map(|line| &line["message"]).collect();
+    assert!(messages.contains(&&Value::from("Sending error HTTP response")));
+    assert!(messages.contains(&&Value::from("Request completed")));
+    for line in &lines {
+        assert_eq!(
+            line["request_id"], "req-123",
+            "Line without request ID: {}",
+            line
+        );
+        assert!(line["timestamp"].is_string() && line["level"].is_string());
+    }
+
+    // Otherwise, and for IDs that are unsafe to log, one is generated
+    for header in [None, Some("bad\tid"), Some("x".repeat(200).as_str())] {
+        let mut req = test::TestRequest::delete().uri(&uri);
+        if let Some(header) = header {
+            req = req.insert_header((REQUEST_ID_HEADER.clone(), header));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        let request_id = resp
+            .headers()
+            .get(&REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()?
+            .to_string();
+        assert!(Uuid::try_parse(&request_id).is_ok());
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["request_id"], request_id.as_str());
+    }
+
+    Ok(())
+}
+
+#[rstest]
+#[case::json(LogFormat::Json)]
+#[case::text(LogFormat::Text)]
+#[actix_web::test]
+async fn test_secrets_never_logged(#[case] format: LogFormat) -> Result<()> {
+    let (logs, _guard) = capture_logs(format);
+    let (_, user_repo) = MockUserRepoNoDb.init().await?;
+    let app = test::init_service(
+        App::new()
+            .wrap(TracingLogger::<RequestSpan>::new())
+            .wrap_fn(assign_request_id)
+            .app_data(Data::from(user_repo.clone()))
+            .app_data(Data::<dyn AuditRepo>::from(
+                Arc::new(MockAuditRepo::with_users(user_repo)) as Arc<_>,
+            ))
+            .app_data(Data::new(PasswordHasher::new()))
+            .route("/users", web::post().to(post_user)),
+    )
+    .await;
+    let user = UserCreateReqDtoBuilder::default()
+        .username("Redacted")
+        .password_raw("hunter2-hunter2")
+        .build()?;
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .set_json(&user)
+        .to_request();
+    test::call_service(&app, req).await;
+    // Nor are the values rejected by validation
+    let too_short = UserCreateReqDtoBuilder::default()
+        .username("Redacted")
+        .password_raw("sEcr3t!")
+        .build()?;
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .set_json(&too_short)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let hash = PasswordHasher::new().hash_password(&user.password_raw)?;
+    let stored = UserBuilder::default()
+        .username("Redacted")
+        .password_hash(hash.clone())
+        .build()?;
+    tracing::info!(password_raw = %user.password_raw, password_hash = %hash, "Structured");
+    tracing::info!(user = ?stored, request = ?user, "Debug");
+    log::info!("Forwarded from log: {:?} {:?}", stored, user);
+
+    let output = String::from_utf8(logs.0.lock().unwrap().clone())?;
+    assert!(output.contains("Structured") && output.contains("Forwarded from log"));
+    assert!(output.contains("Sending error HTTP response"));
+    assert!(!output.contains(&user.password_raw), "{}", output);
+    assert!(!output.contains(&too_short.password_raw), "{}", output);
+    assert!(!output.contains(&hash), "{}", output);
+    if format == LogFormat::Text {
+        assert!(output.contains(&format!("password_raw={}", REDACTED)));
+        return Ok(());
+    }
+    for line in logs.lines() {
+        for field in ["password_raw", "password_hash"] {
+            if let Some(value) = line.get(field) {
+                assert_eq!(value, REDACTED);
+            }
+        }
+    }
+
+    Ok(())
+}